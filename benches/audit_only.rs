@@ -0,0 +1,51 @@
+#![feature(test)]
+
+//! Compares deserialising CADETS records through the untagged `TraceEvent` against parsing them
+//! as `AuditEvent` directly, as done when FBT records are skipped.
+//!
+//! The workload is an audit dominated trace with one FBT record in every hundred lines.
+//!
+//! Run with `cargo bench --bench audit_only`.
+
+extern crate test;
+
+use pvm::{
+    ingest::Mapped,
+    trace::cadets::{AuditEvent, TraceEvent},
+};
+use test::Bencher;
+
+const LINES: usize = 10_000;
+
+const AUDIT: &str = r#"{"event": "audit:event:aue_openat_rwtc:", "time": 1533046200000000000, "pid": 10, "ppid": 1, "tid": 100, "uid": 0, "exec": "bench", "retval": 3, "subjprocuuid": "3f7a9a54-94b8-11e8-8f3e-a1b2c3d4e5f6", "subjthruuid": "3f7a9a55-94b8-11e8-8f3e-a1b2c3d4e5f6", "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6", "ret_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/usr/lib/libc.so.7", "fd": 3}"#;
+
+const FBT: &str = r#"{"event": "fbt:kernel:cc_conn_init:", "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6", "time": 1533046200000000000, "so_uuid": "6c0b5c33-94b8-11e8-8f3e-a1b2c3d4e5f6", "lport": 22, "fport": 5000, "laddr": "10.0.0.1", "faddr": "10.0.0.2"}"#;
+
+fn trace() -> Vec<&'static str> {
+    (0..LINES)
+        .map(|n| if n % 100 == 0 { FBT } else { AUDIT })
+        .collect()
+}
+
+#[bench]
+fn untagged(b: &mut Bencher) {
+    let lines = trace();
+    b.iter(|| {
+        lines
+            .iter()
+            .filter_map(|l| serde_json::from_str::<TraceEvent>(l).ok())
+            .count()
+    });
+}
+
+#[bench]
+fn audit_only(b: &mut Bencher) {
+    let lines = trace();
+    b.iter(|| {
+        lines
+            .iter()
+            .filter(|l| AuditEvent::accept(l))
+            .filter_map(|l| serde_json::from_str::<AuditEvent>(l).ok())
+            .count()
+    });
+}
@@ -0,0 +1,80 @@
+#![feature(test)]
+
+//! Compares the hashed `LendingLibrary` node cache against the dense `NodeArena`.
+//!
+//! The workload mimics a trace where each event allocates a context ID and a node ID, touches a
+//! handful of recently created nodes and periodically releases an old one.
+//!
+//! Run with `cargo bench --features node-arena`.
+
+extern crate test;
+
+use lazy_static::lazy_static;
+use lending_library::LendingLibrary;
+use maplit::hashmap;
+use pvm::{
+    data::{
+        node_types::{ConcreteType, DataNode, PVMDataType::*},
+        ID,
+    },
+    ingest::arena::NodeArena,
+};
+use test::Bencher;
+use uuid::Uuid;
+
+const EVENTS: u64 = 10_000;
+const WINDOW: u64 = 64;
+
+lazy_static! {
+    static ref PROCESS: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "process",
+        props: hashmap!("cmdline" => true),
+    };
+}
+
+fn node(id: u64) -> DataNode {
+    DataNode::new(
+        Actor,
+        &PROCESS,
+        ID::new(id),
+        Uuid::nil(),
+        ID::new(id - 1),
+        None,
+    )
+}
+
+macro_rules! workload {
+    ($cache:expr) => {{
+        let cache = $cache;
+        let mut nodes = Vec::with_capacity(EVENTS as usize);
+        for ev in 0..EVENTS {
+            let id = ev * 2 + 2;
+            cache.insert(ID::new(id), node(id));
+            nodes.push(id);
+            for back in 1..4 {
+                if let Some(&prev) =
+                    nodes.get(nodes.len().saturating_sub(back * WINDOW as usize / 4))
+                {
+                    if let Some(mut n) = cache.lend(&ID::new(prev)) {
+                        n.meta.update("cmdline", "bench", ID::new(id - 1), true);
+                    }
+                }
+            }
+            if ev % 4 == 0 && ev >= WINDOW {
+                cache.remove(&ID::new(nodes[(ev - WINDOW) as usize]));
+            }
+        }
+        cache.len()
+    }};
+}
+
+#[bench]
+fn lending_library(b: &mut Bencher) {
+    b.iter(|| workload!(&mut LendingLibrary::<ID, DataNode>::new()));
+}
+
+#[bench]
+fn node_arena(b: &mut Bencher) {
+    b.iter(|| workload!(&mut NodeArena::<DataNode>::new()));
+}
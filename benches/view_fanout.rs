@@ -0,0 +1,145 @@
+#![feature(test)]
+
+//! Compares fanning events out to threaded view instances through the coordinator's shared ring
+//! against giving each instance a bounded channel of its own.
+//!
+//! Both pass every event from a producer through a dispatching thread to a handful of instances
+//! that only count what they are given, so what is measured is the cost of the fan-out itself.
+//!
+//! Run with `cargo bench --bench view_fanout`.
+
+extern crate test;
+
+use std::{
+    sync::{
+        mpsc::{channel, sync_channel},
+        Arc,
+    },
+    thread,
+};
+
+use pvm::{
+    data::{
+        node_types::{Name, NameNode, Node},
+        ID,
+    },
+    view::{
+        DBTr, InlineView, ParamDecl, View, ViewCoordinator, ViewError, ViewInst, ViewParams,
+        ViewStream,
+    },
+};
+use test::Bencher;
+
+const EVENTS: u64 = 10_000;
+const VIEWS: usize = 4;
+/// Events each channel holds, as many as the ring keeps
+const CAPACITY: usize = 1024;
+
+fn events() -> Vec<DBTr> {
+    (1..=EVENTS)
+        .map(|id| {
+            DBTr::CreateNode(Node::Name(NameNode::generate(
+                ID::new(id),
+                Name::Path(format!("/tmp/{}", id)),
+            )))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Count(u64);
+
+impl InlineView for Count {
+    fn process(&mut self, _: &DBTr) {
+        self.0 += 1;
+    }
+}
+
+#[derive(Debug)]
+struct CountView(usize);
+
+impl View for CountView {
+    fn new(id: usize) -> Self {
+        CountView(id)
+    }
+    fn id(&self) -> usize {
+        self.0
+    }
+    fn name(&self) -> &'static str {
+        "CountView"
+    }
+    fn desc(&self) -> &'static str {
+        "Counts the events it is passed."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        Ok(ViewInst {
+            id,
+            vtype: self.0,
+            params,
+            handle: thread::spawn(move || stream.run(&mut Count(0))),
+        })
+    }
+}
+
+#[bench]
+fn ring(b: &mut Bencher) {
+    let evts = events();
+    b.iter(|| {
+        let (send, recv) = channel();
+        let mut vc = ViewCoordinator::new(recv).unwrap();
+        vc.register_view_type::<CountView>().unwrap();
+        for _ in 0..VIEWS {
+            vc.create_view_with_name("CountView", ViewParams::new())
+                .unwrap();
+        }
+        for evt in &evts {
+            send.send(evt.clone()).unwrap();
+        }
+        drop(send);
+        vc.shutdown();
+    });
+}
+
+#[bench]
+fn sync_channels(b: &mut Bencher) {
+    let evts = events();
+    b.iter(|| {
+        let (send, recv) = channel::<DBTr>();
+        let (sends, handles): (Vec<_>, Vec<_>) = (0..VIEWS)
+            .map(|_| {
+                let (send, recv) = sync_channel::<Arc<DBTr>>(CAPACITY);
+                let handle = thread::spawn(move || {
+                    let mut count = Count(0);
+                    for evt in recv {
+                        count.process(&evt);
+                    }
+                });
+                (send, handle)
+            })
+            .unzip();
+        let dispatch = thread::spawn(move || {
+            for evt in recv {
+                let evt = Arc::new(evt);
+                for send in &sends {
+                    send.send(evt.clone()).unwrap();
+                }
+            }
+        });
+        for evt in &evts {
+            send.send(evt.clone()).unwrap();
+        }
+        drop(send);
+        dispatch.join().unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
@@ -1,6 +1,10 @@
 #[cfg(feature = "capi")]
 extern crate cbindgen;
 
+#[allow(dead_code)]
+#[path = "src/c_abi.rs"]
+mod c_abi;
+
 #[cfg(feature = "capi")]
 fn generate_with_lang(crate_dir: &str, lang: cbindgen::Language, out: &str) {
     let cfg = cbindgen::Config::from_root_or_default(std::path::Path::new(crate_dir));
@@ -34,6 +38,10 @@ fn main() {
     generate_with_lang(&crate_dir, cbindgen::Language::C, "src/include/pvm.h");
 
     generate_with_lang(&crate_dir, cbindgen::Language::Cxx, "src/include/pvm.hpp");
+
+    // for binding generators that would rather not load the library to call pvm_abi_describe
+    let abi = serde_json::to_string_pretty(&c_abi::abi_description()).unwrap();
+    std::fs::write("src/include/pvm_abi.json", abi).unwrap();
 }
 
 #[cfg(not(feature = "capi"))]
@@ -55,18 +55,19 @@ impl MetaStore {
         }
     }
 
+    /// Set the current value of a key, returns false if it already held that value
     pub fn update<K: Into<Cow<'static, str>>, T: ToString + ?Sized>(
         &mut self,
         key: K,
         val: &T,
         ctx: ID,
         heritable: bool,
-    ) {
+    ) -> bool {
         let cow_key = key.into();
         let str_val = val.to_string();
         if let Some(v) = self.cur(&cow_key) {
             if v == str_val {
-                return;
+                return false;
             }
         }
         let entry = (str_val, ctx);
@@ -75,6 +76,7 @@ impl MetaStore {
             .or_insert((heritable, Vec::new()))
             .1
             .push(entry);
+        true
     }
 
     pub fn cur(&self, key: &str) -> Option<&str> {
@@ -84,6 +86,11 @@ impl MetaStore {
             .map(|(v, _t)| &v[..])
     }
 
+    /// The context that set the current value of a key
+    pub fn cur_ctx(&self, key: &str) -> Option<ID> {
+        self.entries.get(key).map(|(_h, v)| v[v.len() - 1].1)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&str, &str, ID, bool)> {
         self.entries
             .iter()
@@ -97,3 +104,19 @@ impl MetaStore {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_reports_change() {
+        let mut meta = MetaStore::new();
+        assert!(meta.update("uid", "0", ID::new(1), true));
+        assert!(!meta.update("uid", &0, ID::new(2), true));
+        assert!(meta.update("uid", "1", ID::new(3), true));
+        assert_eq!(meta.iter().count(), 2);
+        assert_eq!(meta.cur("uid"), Some("1"));
+        assert_eq!(meta.cur_ctx("uid"), Some(ID::new(3)));
+    }
+}
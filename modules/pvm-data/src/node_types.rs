@@ -6,9 +6,11 @@ use std::{
 
 use crate::{meta_store::MetaStore, Enumerable, HasID, ID};
 
+use serde::Serializer;
+use serde_derive::Serialize;
 use uuid::Uuid;
 
-#[derive(Debug, Eq)]
+#[derive(Debug, Eq, Serialize)]
 pub struct ConcreteType {
     pub pvm_ty: PVMDataType,
     pub name: &'static str,
@@ -27,13 +29,21 @@ impl PartialEq for ConcreteType {
     }
 }
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Debug, Eq, Hash, PartialEq, Serialize)]
 pub struct ContextType {
     pub name: &'static str,
     pub props: Vec<&'static str>,
 }
 
-#[derive(Clone, Debug)]
+fn ser_data_ty<S: Serializer>(ty: &&'static ConcreteType, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(ty.name)
+}
+
+fn ser_ctx_ty<S: Serializer>(ty: &&'static ContextType, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(ty.name)
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub enum Node {
     Data(DataNode),
     Ctx(CtxNode),
@@ -87,9 +97,10 @@ impl Enumerable for SchemaNode {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CtxNode {
     id: ID,
+    #[serde(serialize_with = "ser_ctx_ty")]
     ty: &'static ContextType,
     pub cont: HashMap<&'static str, String>,
 }
@@ -122,7 +133,7 @@ impl HasID for CtxNode {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
 pub enum PVMDataType {
     Actor,
     Store,
@@ -151,9 +162,10 @@ impl PVMDataType {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct DataNode {
     pvm_ty: PVMDataType,
+    #[serde(serialize_with = "ser_data_ty")]
     ty: &'static ConcreteType,
     id: ID,
     uuid: Uuid,
@@ -207,6 +219,81 @@ impl DataNode {
     pub fn pvm_ty(&self) -> &PVMDataType {
         &self.pvm_ty
     }
+
+    /// A stable identifier for this node that does not depend on its per-run `ID`
+    ///
+    /// Computed with 64-bit FNV-1a over the PVM type, the concrete type name and the uuid, so
+    /// the same object produces the same fingerprint across separate ingests and builds.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint_with(&[])
+    }
+
+    /// As `fingerprint`, additionally covering the current value of each of the given metadata
+    /// keys in order
+    ///
+    /// Missing keys contribute a marker distinct from any value, so a node without a key does not
+    /// collide with one holding an empty value.
+    pub fn fingerprint_with(&self, keys: &[&str]) -> u64 {
+        let mut h = Fnv1a::identity(&self.pvm_ty.to_string(), self.ty.name, &self.uuid);
+        for key in keys {
+            h.field(key.as_bytes());
+            match self.meta.cur(key) {
+                Some(val) => h.field(val.as_bytes()),
+                None => h.write(&[0xfe]),
+            }
+        }
+        h.finish()
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The fingerprint `DataNode::fingerprint` gives a node with the given types and uuid
+///
+/// For use where only a serialised form of the node is available.
+pub fn data_fingerprint(pvm_ty: &str, ty: &str, uuid: &Uuid) -> u64 {
+    Fnv1a::identity(pvm_ty, ty, uuid).finish()
+}
+
+/// 64-bit FNV-1a hash of a byte string
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut h = Fnv1a::new();
+    h.write(bytes);
+    h.finish()
+}
+
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(FNV_OFFSET)
+    }
+
+    fn identity(pvm_ty: &str, ty: &str, uuid: &Uuid) -> Self {
+        let mut h = Fnv1a::new();
+        h.field(pvm_ty.as_bytes());
+        h.field(ty.as_bytes());
+        h.field(uuid.as_bytes());
+        h
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Write a length prefixed field so adjacent fields cannot run into each other
+    fn field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -215,7 +302,7 @@ pub enum Name {
     Net(String, u16),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum NameNode {
     Path(ID, String),
     Net(ID, String, u16),
@@ -239,7 +326,7 @@ impl NameNode {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum SchemaNode {
     Data(ID, &'static ConcreteType),
     Context(ID, &'static ContextType),
@@ -263,3 +350,42 @@ impl SchemaNode {
         SchemaNode::Data(id, val)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: u64, uuid: Uuid) -> DataNode {
+        let ty = Box::leak(Box::new(ConcreteType {
+            pvm_ty: PVMDataType::Store,
+            name: "file",
+            props: HashMap::new(),
+        }));
+        DataNode::new(PVMDataType::Store, ty, ID::new(id), uuid, ID::new(0), None)
+    }
+
+    #[test]
+    fn fnv1a_reference() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn fingerprint_ignores_id() {
+        let uuid = Uuid::parse_str("8a1b3e36-8e0a-11e8-8c50-a1b2c3d4e5f6").unwrap();
+        let a = file(1, uuid);
+        let b = file(7, uuid);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), file(1, Uuid::nil()).fingerprint());
+        assert_ne!(
+            a.fingerprint_with(&["mode"]),
+            a.fingerprint(),
+            "missing meta key must still contribute"
+        );
+        let mut c = file(9, uuid);
+        assert!(c.meta.update("mode", "644", ID::new(9), true));
+        assert_ne!(c.fingerprint_with(&["mode"]), a.fingerprint_with(&["mode"]));
+        assert_eq!(c.fingerprint(), a.fingerprint());
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{Denumerate, Enumerable, HasDst, HasID, HasSrc, RelGenerable, ID};
 
-#[derive(Clone, Copy, Debug)]
+use std::{fmt, str::FromStr};
+
+use serde_derive::Serialize;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
 pub enum PVMOps {
     Source,
     Sink,
@@ -9,21 +13,68 @@ pub enum PVMOps {
     Unknown,
 }
 
-#[derive(Clone, Debug)]
+impl fmt::Display for PVMOps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PVMOps::Source => "Source",
+                PVMOps::Sink => "Sink",
+                PVMOps::Connect => "Connect",
+                PVMOps::Version => "Version",
+                PVMOps::Unknown => "Unknown",
+            }
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParsePVMOpsError(String);
+
+impl fmt::Display for ParsePVMOpsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown PVM operation {:?}", self.0)
+    }
+}
+
+impl FromStr for PVMOps {
+    type Err = ParsePVMOpsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Source" => Ok(PVMOps::Source),
+            "Sink" => Ok(PVMOps::Sink),
+            "Connect" => Ok(PVMOps::Connect),
+            "Version" => Ok(PVMOps::Version),
+            "Unknown" => Ok(PVMOps::Unknown),
+            _ => Err(ParsePVMOpsError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Inf {
     id: ID,
     src: ID,
     dst: ID,
     pub pvm_op: PVMOps,
     pub ctx: ID,
-    pub byte_count: i64,
+    /// Bytes the actor read over this edge
+    pub bytes_read: u64,
+    /// Bytes the actor wrote over this edge
+    pub bytes_written: u64,
+    /// The trace event that created the edge, if recorded
+    pub event: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct InfInit {
     pub pvm_op: PVMOps,
     pub ctx: ID,
-    pub byte_count: i64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub event: Option<String>,
 }
 
 impl HasID for Inf {
@@ -54,24 +105,28 @@ impl RelGenerable for Inf {
             dst,
             pvm_op: init.pvm_op,
             ctx: init.ctx,
-            byte_count: init.byte_count,
+            bytes_read: init.bytes_read,
+            bytes_written: init.bytes_written,
+            event: init.event,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Named {
     id: ID,
     src: ID,
     dst: ID,
     pub start: ID,
     pub end: ID,
+    pub actor: Option<ID>,
 }
 
 #[derive(Debug)]
 pub struct NamedInit {
     pub start: ID,
     pub end: ID,
+    pub actor: Option<ID>,
 }
 
 impl HasID for Named {
@@ -102,11 +157,12 @@ impl RelGenerable for Named {
             dst,
             start: init.start,
             end: init.end,
+            actor: init.actor,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum Rel {
     Inf(Inf),
     Named(Named),
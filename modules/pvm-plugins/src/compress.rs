@@ -0,0 +1,194 @@
+//! Compressing file outputs as they are written
+//!
+//! A view reading the `compress` param writes `out/prov.trace` as `out/prov.trace.gz` or
+//! `out/prov.trace.zst`, which the usual command line tools decompress.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::write::GzEncoder;
+
+use crate::views::{ParamDecl, ViewError, ViewParams};
+
+/// The param read by `Compression::from_params`, for inclusion in a view's `params`
+pub const COMPRESS_PARAM: ParamDecl =
+    ParamDecl::str("compress", "Compress the output, either none, gzip or zstd").default("none");
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val {
+            "" | "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("must be none, gzip or zstd, not {}", val)),
+        }
+    }
+
+    pub fn from_params(params: &ViewParams) -> Result<Self, ViewError> {
+        Compression::parse(params.get_str(COMPRESS_PARAM.name).unwrap_or("none"))
+            .map_err(|e| ViewError::InvalidParam(COMPRESS_PARAM.name.to_string(), e))
+    }
+
+    /// The suffix added to the names of files written with this compression
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    pub fn path<P: AsRef<Path>>(self, path: P) -> PathBuf {
+        let mut name = path.as_ref().to_path_buf().into_os_string();
+        name.push(self.extension());
+        name.into()
+    }
+
+    pub fn wrap<W: Write>(self, w: W) -> io::Result<Encoder<W>> {
+        let inner = match self {
+            Compression::None => Inner::Plain(w),
+            Compression::Gzip => Inner::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Zstd => Inner::Zstd(zstd::Encoder::new(w, 0)?),
+        };
+        Ok(Encoder(Some(inner)))
+    }
+
+    /// Create the file for `path`, adding this compression's extension to the name
+    pub fn create<P: AsRef<Path>>(self, path: P) -> io::Result<Encoder<BufWriter<File>>> {
+        self.wrap(BufWriter::new(File::create(self.path(path))?))
+    }
+}
+
+enum Inner<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+/// A writer compressing into `W`
+///
+/// The compressed stream is only complete once the encoder is finished. Dropping it finishes
+/// it, call `finish` to see any error doing so.
+pub struct Encoder<W: Write>(Option<Inner<W>>);
+
+impl<W: Write> Encoder<W> {
+    fn inner(&mut self) -> &mut dyn Write {
+        match self.0.as_mut().unwrap() {
+            Inner::Plain(w) => w,
+            Inner::Gzip(w) => w,
+            Inner::Zstd(w) => w,
+        }
+    }
+
+    /// Write out the end of the compressed stream, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.0.take().unwrap() {
+            Inner::Plain(w) => Ok(w),
+            Inner::Gzip(w) => w.finish(),
+            Inner::Zstd(w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> fmt::Debug for Encoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compression = match self.0 {
+            Some(Inner::Plain(_)) | None => Compression::None,
+            Some(Inner::Gzip(_)) => Compression::Gzip,
+            Some(Inner::Zstd(_)) => Compression::Zstd,
+        };
+        f.debug_tuple("Encoder").field(&compression).finish()
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+impl<W: Write> Drop for Encoder<W> {
+    fn drop(&mut self) {
+        let res = match self.0.take() {
+            Some(Inner::Gzip(w)) => w.finish().map(drop),
+            Some(Inner::Zstd(w)) => w.finish().map(drop),
+            _ => Ok(()),
+        };
+        if let Err(e) = res {
+            eprintln!("Failed to finish compressed output: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    fn compress(c: Compression, data: &[u8]) -> Vec<u8> {
+        let mut w = c.wrap(Vec::new()).unwrap();
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = b"CreateNode(..)\n".repeat(100);
+        assert_eq!(compress(Compression::None, &data), data);
+
+        let gz = compress(Compression::Gzip, &data);
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(&gz[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+
+        let zst = compress(Compression::Zstd, &data);
+        assert!(zst.len() < data.len());
+        assert_eq!(zstd::decode_all(&zst[..]).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_unknown() {
+        let mut params = ViewParams::new();
+        params.insert_param("compress", "lz4");
+        match Compression::from_params(&params) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "compress"),
+            r => panic!("expected an invalid param, got {:?}", r),
+        }
+        params.insert_param("compress", "gz");
+        assert_eq!(
+            Compression::from_params(&params).unwrap(),
+            Compression::Gzip
+        );
+    }
+
+    #[test]
+    fn adds_extension() {
+        assert_eq!(
+            Compression::None.path("a/dbg.trace"),
+            Path::new("a/dbg.trace")
+        );
+        assert_eq!(
+            Compression::Zstd.path("a/dbg.trace"),
+            Path::new("a/dbg.trace.zst")
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Trace formats provided by plugins
+//!
+//! A plugin cannot implement `Mapped` for the engine: the PVM it maps into is defined in
+//! libpvm, and types from a separately built copy of that crate are not the host's. A plugin
+//! format instead translates its input into records of a format built into the engine, which
+//! maps them with the host's own settings such as the timestamp format and CADETS options.
+
+use std::error::Error;
+
+use serde_json::Value;
+
+/// Bumped whenever `TraceFormat` or `FormatRegistrar` change, part of `plugin_version`
+pub const FORMAT_API_VERSION: u32 = 1;
+
+/// A trace format translating its input into records of an engine format
+pub trait TraceFormat: Send + Sync {
+    /// The name of the engine format the translated records are in, e.g. `prov_json`
+    fn base(&self) -> &str;
+
+    /// Translate one line of input into any number of records of the base format
+    fn translate(&self, line: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>>;
+}
+
+/// The engine side of format registration, passed to `Plugin::formats`
+pub trait FormatRegistrar {
+    /// Register a format under a name, failing if the name is taken or the base format of
+    /// `fmt` is unknown
+    fn register(&mut self, name: &str, fmt: Box<dyn TraceFormat>) -> Result<(), Box<dyn Error>>;
+}
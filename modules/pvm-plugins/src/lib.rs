@@ -1,8 +1,17 @@
 pub extern crate pvm_views as views;
 
-use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+use std::{collections::hash_map::DefaultHasher, error::Error, hash::Hasher};
 
-use views::{data::version as data_version, version as views_version, ViewCoordinator};
+pub mod compress;
+pub mod format;
+pub mod output;
+pub mod rotate;
+pub mod rows;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+use format::{FormatRegistrar, FORMAT_API_VERSION};
+use views::{data::version as data_version, version as views_version, ViewCoordinator, ViewError};
 
 mod built_info {
     #![allow(dead_code)]
@@ -18,6 +27,7 @@ pub fn plugin_version() -> u64 {
     h.write(data_version().as_bytes());
     h.write(views_version().as_bytes());
     h.write(version().as_bytes());
+    h.write_u32(FORMAT_API_VERSION);
     h.finish()
 }
 
@@ -28,7 +38,13 @@ pub trait Plugin {
     fn build_version(&self) -> u64 {
         plugin_version()
     }
-    fn view_ops(&self, vc: &mut ViewCoordinator);
+    fn view_ops(&self, vc: &mut ViewCoordinator) -> Result<(), ViewError>;
+    /// Register the trace formats the plugin provides
+    ///
+    /// Each format translates into one of the engine's own formats, see `format`.
+    fn formats(&self, _reg: &mut dyn FormatRegistrar) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
 
 pub type PluginInit = unsafe extern "C" fn() -> *mut dyn Plugin;
@@ -50,8 +66,12 @@ macro_rules! define_plugin {
                 MyPlugin
             }
 
-            fn view_ops(&self, vc: &mut $crate::views::ViewCoordinator) {
-                $(vc.register_view_type::<$v>().unwrap();)*
+            fn view_ops(
+                &self,
+                vc: &mut $crate::views::ViewCoordinator,
+            ) -> Result<(), $crate::views::ViewError> {
+                $(vc.register_view_type::<$v>()?;)*
+                Ok(())
             }
         }
 
@@ -0,0 +1,168 @@
+//! Views writing their updates somewhere that can fail
+//!
+//! A full disk or a lost connection is only seen on the view's thread, where a panic would not
+//! surface until the coordinator shuts down. Such a view implements `Output` for what it writes
+//! to and runs a `Sender` of it, which reports the first failure through the instance's
+//! `ErrorSink` and discards every update after it.
+
+use std::fmt::{Debug, Display};
+
+use crate::views::{DBTr, ErrorSink, InlineView};
+
+/// What a view writes its updates to
+pub trait Output: Debug + Send {
+    type Error: Display;
+
+    fn write(&mut self, evt: &DBTr) -> Result<(), Self::Error>;
+
+    /// Write out anything buffered, see `InlineView::on_flush`
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Write out everything left once the stream has been shut down, see
+    /// `InlineView::on_shutdown`
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.flush()
+    }
+}
+
+/// Passes updates to an output until it fails, discarding any after that
+#[derive(Debug)]
+pub struct Sender<O> {
+    view: &'static str,
+    out: O,
+    errors: ErrorSink,
+    /// The number of updates discarded since a failure
+    discarded: Option<usize>,
+}
+
+impl<O: Output> Sender<O> {
+    /// Failures are reported as those of `view`, the name of the view writing to `out`
+    pub fn new(view: &'static str, out: O, errors: ErrorSink) -> Self {
+        Sender {
+            view,
+            out,
+            errors,
+            discarded: None,
+        }
+    }
+
+    pub fn output(&self) -> &O {
+        &self.out
+    }
+
+    fn check(&mut self, res: Result<(), O::Error>) {
+        if let Err(e) = res {
+            self.errors.report(format!(
+                "{} failed to write, discarding further updates: {}",
+                self.view, e
+            ));
+            self.discarded = Some(0);
+        }
+    }
+}
+
+impl<O: Output> InlineView for Sender<O> {
+    fn process(&mut self, evt: &DBTr) {
+        match self.discarded {
+            Some(ref mut n) => *n += 1,
+            None => {
+                let res = self.out.write(evt);
+                self.check(res);
+            }
+        }
+    }
+
+    fn on_flush(&mut self) {
+        if self.discarded.is_none() {
+            let res = self.out.flush();
+            self.check(res);
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        match self.discarded {
+            None => {
+                let res = self.out.finish();
+                self.check(res);
+            }
+            Some(0) => {}
+            Some(n) => self
+                .errors
+                .report(format!("{} discarded {} updates", self.view, n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io,
+        sync::{mpsc::channel, Arc},
+    };
+
+    use crate::{
+        testing::path,
+        views::{ViewMsg, ViewStream},
+    };
+
+    /// Fails every write after the first `limit`
+    #[derive(Debug, Default)]
+    struct Limited {
+        limit: usize,
+        written: usize,
+        finished: bool,
+    }
+
+    impl Output for Limited {
+        type Error = io::Error;
+
+        fn write(&mut self, _evt: &DBTr) -> io::Result<()> {
+            if self.written == self.limit {
+                return Err(io::Error::other("full"));
+            }
+            self.written += 1;
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    fn run(limit: usize, evts: usize) -> Sender<Limited> {
+        let out = Limited {
+            limit,
+            ..Limited::default()
+        };
+        let mut inst = Sender::new("TestView", out, ErrorSink::default());
+        let (send, recv) = channel();
+        for n in 0..evts {
+            send.send(ViewMsg::Event(Arc::new(path(n as u64, "/tmp"))))
+                .unwrap();
+        }
+        send.send(ViewMsg::Shutdown).unwrap();
+        ViewStream::new(recv).run(&mut inst);
+        inst
+    }
+
+    #[test]
+    fn finishes_output() {
+        let inst = run(10, 3);
+        assert_eq!(inst.output().written, 3);
+        assert!(inst.output().finished);
+        assert_eq!(inst.discarded, None);
+    }
+
+    #[test]
+    fn discards_after_failure() {
+        let inst = run(2, 5);
+        assert_eq!(inst.output().written, 2);
+        assert!(!inst.output().finished);
+        assert_eq!(inst.discarded, Some(2));
+    }
+}
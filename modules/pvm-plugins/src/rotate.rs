@@ -0,0 +1,389 @@
+//! Splitting file outputs into numbered segments
+//!
+//! With rotation enabled a view writing to `out/prov.trace` writes `out/prov.0000.trace`,
+//! `out/prov.0001.trace` and so on, and lists each segment once it is closed in
+//! `out/prov.trace.index`, one JSON object per line. Without any limits set the output is
+//! written to the given path as before and no index is kept. Compressed segments have the
+//! compression's extension added, as in `out/prov.0000.trace.gz`.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::{
+    compress::{Compression, Encoder},
+    views::{ParamDecl, ViewError, ViewParams},
+};
+
+/// The params read by `Rotation::from_params`, for inclusion in a view's `params`
+pub const ROTATE_PARAMS: [ParamDecl; 3] = [
+    ParamDecl::int(
+        "rotate_size",
+        "Start a new output file once the current one reaches this many bytes",
+    ),
+    ParamDecl::int(
+        "rotate_records",
+        "Start a new output file after this many records",
+    ),
+    ParamDecl::int(
+        "rotate_secs",
+        "Start a new output file once the current one has been open this many seconds",
+    ),
+];
+
+/// When to start a new segment, any limit reached triggers a rotation
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_records: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+fn limit(params: &ViewParams, key: &str) -> Result<Option<u64>, ViewError> {
+    match params.get_int(key) {
+        None => Ok(None),
+        Some(n) if n > 0 => Ok(Some(n as u64)),
+        Some(n) => Err(ViewError::InvalidParam(
+            key.to_string(),
+            format!("must be a positive integer, not {}", n),
+        )),
+    }
+}
+
+impl Rotation {
+    pub fn from_params(params: &ViewParams) -> Result<Self, ViewError> {
+        Ok(Rotation {
+            max_bytes: limit(params, "rotate_size")?,
+            max_records: limit(params, "rotate_records")?,
+            max_age: limit(params, "rotate_secs")?.map(Duration::from_secs),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_records.is_some() || self.max_age.is_some()
+    }
+
+    fn due(&self, bytes: u64, records: u64, age: Duration) -> bool {
+        reached(self.max_bytes, bytes)
+            || reached(self.max_records, records)
+            || reached(self.max_age, age)
+    }
+}
+
+fn reached<T: PartialOrd>(limit: Option<T>, val: T) -> bool {
+    match limit {
+        Some(limit) => val >= limit,
+        None => false,
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The segments of a rotated output, and the index listing them
+///
+/// Views that cannot write through a `RotatingWriter`, such as those building an archive per
+/// segment, use this directly: write each segment to `path`, then `close` it.
+#[derive(Debug)]
+pub struct Segments {
+    path: PathBuf,
+    suffix: &'static str,
+    rotation: Rotation,
+    index: Option<BufWriter<File>>,
+    seq: usize,
+    first_record: u64,
+    opened: Instant,
+    opened_at: SystemTime,
+}
+
+impl Segments {
+    pub fn new<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let index = if rotation.is_enabled() {
+            let mut name = path.clone().into_os_string();
+            name.push(".index");
+            Some(BufWriter::new(File::create(name)?))
+        } else {
+            None
+        };
+        Ok(Segments {
+            path,
+            suffix: "",
+            rotation,
+            index,
+            seq: 0,
+            first_record: 0,
+            opened: Instant::now(),
+            opened_at: SystemTime::now(),
+        })
+    }
+
+    /// Add `suffix` to the name of every segment
+    pub fn with_suffix(mut self, suffix: &'static str) -> Self {
+        self.suffix = suffix;
+        self
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// The file the current segment is written to
+    pub fn path(&self) -> PathBuf {
+        let name = if self.rotation.is_enabled() {
+            let stem = self
+                .path
+                .file_stem()
+                .map_or_else(Default::default, |s| s.to_string_lossy());
+            match self.path.extension() {
+                Some(ext) => format!("{}.{:04}.{}", stem, self.seq, ext.to_string_lossy()),
+                None => format!("{}.{:04}", stem, self.seq),
+            }
+        } else {
+            self.path
+                .file_name()
+                .map_or_else(Default::default, |s| s.to_string_lossy())
+                .into_owned()
+        };
+        self.path.with_file_name(name + self.suffix)
+    }
+
+    /// Whether the current segment has reached a limit, once it holds `bytes` and `records`
+    pub fn due(&self, bytes: u64, records: u64) -> bool {
+        self.rotation.due(bytes, records, self.opened.elapsed())
+    }
+
+    /// Record the current segment in the index and move on to the next
+    pub fn close(&mut self, bytes: u64, records: u64) -> io::Result<()> {
+        let file = self.path();
+        if let Some(ref mut index) = self.index {
+            let file = file.file_name().unwrap().to_string_lossy();
+            let entry = json!({
+                "file": file,
+                "first_record": self.first_record,
+                "records": records,
+                "bytes": bytes,
+                "opened": unix_secs(self.opened_at),
+                "closed": unix_secs(SystemTime::now()),
+            });
+            writeln!(index, "{}", entry)?;
+            index.flush()?;
+        }
+        self.seq += 1;
+        self.first_record += records;
+        self.opened = Instant::now();
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+/// A file output that moves on to a new segment when a rotation limit is reached
+///
+/// Segments only change between records, so a view calls `end_record` after writing each one.
+/// The age limit is checked at the same point, an idle view keeps its segment open. The next
+/// segment is created by the first write to it, so no empty segment is left at the end.
+/// Dropping the writer closes the last segment, call `finish` to see any error doing so.
+///
+/// With compression the size limit applies to the data written, before it is compressed.
+#[derive(Debug)]
+pub struct RotatingWriter {
+    segs: Segments,
+    compression: Compression,
+    out: Option<Encoder<BufWriter<File>>>,
+    bytes: u64,
+    records: u64,
+    finished: bool,
+}
+
+impl RotatingWriter {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        rotation: Rotation,
+        compression: Compression,
+    ) -> io::Result<Self> {
+        let segs = Segments::new(path, rotation)?.with_suffix(compression.extension());
+        let mut w = RotatingWriter {
+            segs,
+            compression,
+            out: None,
+            bytes: 0,
+            records: 0,
+            finished: false,
+        };
+        w.out = Some(w.open()?);
+        Ok(w)
+    }
+
+    fn open(&self) -> io::Result<Encoder<BufWriter<File>>> {
+        let out = BufWriter::new(File::create(self.segs.path())?);
+        self.compression.wrap(out)
+    }
+
+    pub fn end_record(&mut self) -> io::Result<()> {
+        self.records += 1;
+        if self.segs.due(self.bytes, self.records) {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(out) = self.out.take() {
+            out.finish()?.flush()?;
+            self.segs.close(self.bytes, self.records)?;
+            self.bytes = 0;
+            self.records = 0;
+        }
+        Ok(())
+    }
+
+    /// Close the last segment, after which nothing more can be written
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        self.close()
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write to a finished RotatingWriter"));
+        }
+        if self.out.is_none() {
+            self.out = Some(self.open()?);
+        }
+        let n = self.out.as_mut().unwrap().write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.out {
+            Some(ref mut out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        self.close().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testing::TestDir;
+
+    fn dir(name: &str) -> TestDir {
+        TestDir::new(&format!("rotate-{}", name))
+    }
+
+    #[test]
+    fn limits_must_be_positive() {
+        let mut params = ViewParams::new();
+        params.insert_param("rotate_records", 0i64);
+        match Rotation::from_params(&params) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "rotate_records"),
+            r => panic!("expected an invalid param, got {:?}", r),
+        }
+        params.insert_param("rotate_records", 10i64);
+        assert_eq!(
+            Rotation::from_params(&params).unwrap().max_records,
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn disabled_writes_single_file() {
+        let dir = dir("disabled");
+        let path = dir.join("out.trace");
+        let mut w = RotatingWriter::create(&path, Rotation::default(), Compression::None).unwrap();
+        for i in 0..10 {
+            writeln!(w, "{}", i).unwrap();
+            w.end_record().unwrap();
+        }
+        w.finish().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 10);
+    }
+
+    #[test]
+    fn rotates_on_records() {
+        let dir = dir("records");
+        let rotation = Rotation {
+            max_records: Some(4),
+            ..Rotation::default()
+        };
+        let mut w =
+            RotatingWriter::create(dir.join("out.trace"), rotation, Compression::None).unwrap();
+        for i in 0..12 {
+            writeln!(w, "{}", i).unwrap();
+            w.end_record().unwrap();
+        }
+        drop(w);
+        let seg = |n| fs::read_to_string(dir.join(n)).unwrap();
+        assert_eq!(seg("out.0000.trace"), "0\n1\n2\n3\n");
+        assert_eq!(seg("out.0002.trace"), "8\n9\n10\n11\n");
+        assert!(!dir.join("out.0003.trace").exists());
+        let index: Vec<serde_json::Value> = seg("out.trace.index")
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index[1]["file"], "out.0001.trace");
+        assert_eq!(index[1]["first_record"], 4);
+        assert_eq!(index[2]["records"], 4);
+    }
+
+    #[test]
+    fn rotates_on_size_between_records() {
+        let dir = dir("size");
+        let rotation = Rotation {
+            max_bytes: Some(5),
+            ..Rotation::default()
+        };
+        let mut w = RotatingWriter::create(dir.join("out"), rotation, Compression::None).unwrap();
+        for rec in &["abc\n", "def\n", "g\n"] {
+            w.write_all(rec.as_bytes()).unwrap();
+            w.end_record().unwrap();
+        }
+        w.finish().unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("out.0000")).unwrap(),
+            "abc\ndef\n"
+        );
+        assert_eq!(fs::read_to_string(dir.join("out.0001")).unwrap(), "g\n");
+    }
+
+    #[test]
+    fn compresses_each_segment() {
+        let dir = dir("compress");
+        let rotation = Rotation {
+            max_records: Some(2),
+            ..Rotation::default()
+        };
+        let mut w =
+            RotatingWriter::create(dir.join("out.trace"), rotation, Compression::Zstd).unwrap();
+        for i in 0..3 {
+            writeln!(w, "{}", i).unwrap();
+            w.end_record().unwrap();
+        }
+        w.finish().unwrap();
+        let seg = |n| zstd::decode_all(File::open(dir.join(n)).unwrap()).unwrap();
+        assert_eq!(seg("out.0000.trace.zst"), b"0\n1\n");
+        assert_eq!(seg("out.0001.trace.zst"), b"2\n");
+        let index = fs::read_to_string(dir.join("out.trace.index")).unwrap();
+        assert!(index.contains("\"out.0001.trace.zst\""));
+    }
+}
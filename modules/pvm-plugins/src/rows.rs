@@ -0,0 +1,191 @@
+//! Flattening nodes and relations into table rows
+//!
+//! Views writing columnar formats share one layout, a nodes table of `NODE_COLUMNS` and a
+//! relations table of `REL_COLUMNS`, so their outputs can be loaded alike. Every column is
+//! nullable, times are milliseconds since the epoch and metadata is a JSON object of the latest
+//! values.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::DateTime;
+
+use crate::views::data::{
+    node_types::{CtxNode, NameNode, Node},
+    rel_types::Rel,
+    HasDst, HasID, HasSrc, ID,
+};
+
+use self::Kind::*;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    Int,
+    Time,
+    Bool,
+    Str,
+}
+
+pub const NODE_COLUMNS: &[(&str, Kind)] = &[
+    ("db_id", Int),
+    ("update", Bool),
+    ("kind", Str),
+    ("pvm_type", Str),
+    ("type", Str),
+    ("uuid", Str),
+    ("name", Str),
+    ("ctx", Int),
+    ("time", Time),
+    ("meta", Str),
+];
+
+pub const REL_COLUMNS: &[(&str, Kind)] = &[
+    ("db_id", Int),
+    ("update", Bool),
+    ("kind", Str),
+    ("src", Int),
+    ("dst", Int),
+    ("pvm_op", Str),
+    ("ctx", Int),
+    ("time", Time),
+    ("end", Int),
+    ("end_time", Time),
+    ("actor", Int),
+    ("bytes_read", Int),
+    ("bytes_written", Int),
+    ("event", Str),
+];
+
+/// A value of an `Int` or `Time`, `Bool` or `Str` column
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+/// A row of one of the tables, `None` for a null
+pub type Row = Vec<Option<Value>>;
+
+fn int(id: ID) -> Option<Value> {
+    Some(Value::Int(id.inner() as i64))
+}
+
+fn string<S: ToString>(s: S) -> Option<Value> {
+    Some(Value::Str(s.to_string()))
+}
+
+/// Milliseconds since the epoch at which a context happened, from its `time`
+pub fn ctx_millis(ctx: &CtxNode) -> Option<i64> {
+    let time = ctx.cont.get("time")?;
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.timestamp_millis())
+}
+
+/// The row of the nodes table for `node`, if it has one
+///
+/// The times of contexts are kept in `times`, for placing the nodes and relations that follow.
+pub fn node_row(times: &mut HashMap<ID, i64>, node: &Node, update: bool) -> Option<Row> {
+    let id = node.get_db_id();
+    Some(match node {
+        Node::Data(d) => {
+            let meta: BTreeMap<&str, &str> =
+                d.meta.iter_latest().map(|(k, v, _, _)| (k, v)).collect();
+            vec![
+                int(id),
+                Some(Value::Bool(update)),
+                string("Data"),
+                string(d.pvm_ty()),
+                string(d.ty().name),
+                string(d.uuid().to_hyphenated_ref()),
+                None,
+                int(d.ctx()),
+                times.get(&d.ctx()).cloned().map(Value::Int),
+                string(serde_json::to_string(&meta).unwrap()),
+            ]
+        }
+        Node::Ctx(c) => {
+            let time = ctx_millis(c);
+            if let Some(ms) = time {
+                times.insert(id, ms);
+            }
+            let cont: BTreeMap<&str, &str> = c.cont.iter().map(|(k, v)| (*k, &v[..])).collect();
+            vec![
+                int(id),
+                Some(Value::Bool(update)),
+                string("Ctx"),
+                None,
+                string(c.ty().name),
+                None,
+                None,
+                None,
+                time.map(Value::Int),
+                string(serde_json::to_string(&cont).unwrap()),
+            ]
+        }
+        Node::Name(n) => {
+            let (ty, name) = match n {
+                NameNode::Path(_, path) => ("Path", path.clone()),
+                NameNode::Net(_, addr, port) => ("Net", format!("{}:{}", addr, port)),
+            };
+            vec![
+                int(id),
+                Some(Value::Bool(update)),
+                string("Name"),
+                None,
+                string(ty),
+                None,
+                string(name),
+                None,
+                None,
+                None,
+            ]
+        }
+        _ => return None,
+    })
+}
+
+/// The row of the relations table for `rel`
+pub fn rel_row(times: &HashMap<ID, i64>, rel: &Rel, update: bool) -> Row {
+    let time = |ctx: ID| times.get(&ctx).cloned().map(Value::Int);
+    match rel {
+        Rel::Inf(r) => vec![
+            int(r.get_db_id()),
+            Some(Value::Bool(update)),
+            string("Inf"),
+            int(r.get_src()),
+            int(r.get_dst()),
+            string(format!("{:?}", r.pvm_op)),
+            int(r.ctx),
+            time(r.ctx),
+            None,
+            None,
+            None,
+            Some(Value::Int(r.bytes_read as i64)),
+            Some(Value::Int(r.bytes_written as i64)),
+            r.event.as_ref().and_then(string),
+        ],
+        Rel::Named(r) => {
+            let end = if r.end == ID::new(0) {
+                None
+            } else {
+                Some(r.end)
+            };
+            vec![
+                int(r.get_db_id()),
+                Some(Value::Bool(update)),
+                string("Named"),
+                int(r.get_src()),
+                int(r.get_dst()),
+                None,
+                int(r.start),
+                time(r.start),
+                end.and_then(int),
+                end.and_then(time),
+                r.actor.and_then(int),
+                None,
+                None,
+                None,
+            ]
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Fixtures for testing views
+//!
+//! Plugins take this crate as a dev-dependency with the `testing` feature to use these.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{mpsc::channel, Arc},
+};
+
+use uuid::Uuid;
+
+use crate::views::{
+    data::{
+        node_types::{ConcreteType, ContextType, CtxNode, DataNode, Name, NameNode, PVMDataType},
+        rel_types::{Inf, InfInit, Named, NamedInit, PVMOps},
+        MetaStore, RelGenerable, ID,
+    },
+    DBTr, Node, Rel, View, ViewError, ViewMsg, ViewParams, ViewStream,
+};
+
+/// A scratch directory, removed with everything in it when dropped
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// An empty directory, unique to `name` and this process
+    pub fn new(name: &str) -> TestDir {
+        let dir = env::temp_dir().join(format!("pvm-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// `params` resolved against the params of `view`
+pub fn params(view: &dyn View, params: &[(&str, &str)]) -> ViewParams {
+    let mut p = ViewParams::new();
+    for (k, v) in params {
+        p.insert_param(k, v.to_string());
+    }
+    p.resolve(&view.params()).unwrap();
+    p
+}
+
+/// Create an instance of `view` and pass it `evts` before shutting it down
+///
+/// Returns once the instance has stopped, a panic on its thread is resumed on the caller's.
+pub fn run<I>(view: &dyn View, params: ViewParams, evts: I) -> Result<(), ViewError>
+where
+    I: IntoIterator<Item = DBTr>,
+{
+    let (send, recv) = channel();
+    let inst = view.create(0, params, ViewStream::new(recv))?;
+    for evt in evts {
+        send.send(ViewMsg::Event(Arc::new(evt))).unwrap();
+    }
+    send.send(ViewMsg::Shutdown).unwrap();
+    inst.handle.join().unwrap();
+    Ok(())
+}
+
+/// A context at `time`, on the host `test_host`
+pub fn ctx(id: u64, time: &str) -> DBTr {
+    let ty = Box::leak(Box::new(ContextType {
+        name: "test_context",
+        props: vec!["time", "host"],
+    }));
+    let cont: HashMap<_, _> = vec![
+        ("time", time.to_string()),
+        ("host", "test_host".to_string()),
+    ]
+    .into_iter()
+    .collect();
+    DBTr::CreateNode(Node::Ctx(CtxNode::new(ID::new(id), ty, cont).unwrap()))
+}
+
+/// A uuid made of `id`
+pub fn uuid(id: u64) -> Uuid {
+    let mut bytes = [0; 16];
+    bytes[8..].copy_from_slice(&id.to_be_bytes());
+    Uuid::from_bytes(bytes)
+}
+
+fn data(
+    id: u64,
+    pvm_ty: PVMDataType,
+    ty: &'static str,
+    ctx: u64,
+    meta: &[(&'static str, &str)],
+) -> DBTr {
+    let ty = Box::leak(Box::new(ConcreteType {
+        pvm_ty,
+        name: ty,
+        props: meta.iter().map(|(k, _)| (*k, true)).collect(),
+    }));
+    let mut store = MetaStore::new();
+    for (k, v) in meta {
+        store.update(*k, *v, ID::new(ctx), true);
+    }
+    DBTr::CreateNode(Node::Data(DataNode::new(
+        pvm_ty,
+        ty,
+        ID::new(id),
+        uuid(id),
+        ID::new(ctx),
+        Some(store),
+    )))
+}
+
+/// A process running `cmd`, as of the context `ctx`
+pub fn process(id: u64, ctx: u64, cmd: &str) -> DBTr {
+    data(id, PVMDataType::Actor, "process", ctx, &[("cmdline", cmd)])
+}
+
+/// A file created in the context `ctx`
+pub fn file(id: u64, ctx: u64) -> DBTr {
+    data(id, PVMDataType::Store, "file", ctx, &[])
+}
+
+/// The name `path`
+pub fn path(id: u64, path: &str) -> DBTr {
+    DBTr::CreateNode(Node::Name(NameNode::generate(
+        ID::new(id),
+        Name::Path(path.to_string()),
+    )))
+}
+
+/// An information flow of `bytes` from `src` to `dst`
+pub fn inf(id: u64, src: u64, dst: u64, ctx: u64, bytes: u64) -> DBTr {
+    DBTr::CreateRel(Rel::Inf(Inf::new(
+        ID::new(id),
+        ID::new(src),
+        ID::new(dst),
+        InfInit {
+            pvm_op: PVMOps::Sink,
+            ctx: ID::new(ctx),
+            bytes_read: 0,
+            bytes_written: bytes,
+            event: None,
+        },
+    )))
+}
+
+/// `src` named `dst` from the context `start`
+pub fn named(id: u64, src: u64, dst: u64, start: u64) -> DBTr {
+    DBTr::CreateRel(Rel::Named(Named::new(
+        ID::new(id),
+        ID::new(src),
+        ID::new(dst),
+        NamedInit {
+            start: ID::new(start),
+            end: ID::new(0),
+            actor: None,
+        },
+    )))
+}
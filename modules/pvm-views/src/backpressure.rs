@@ -0,0 +1,96 @@
+//! Handling view instances that fall behind the event stream
+//!
+//! Threaded view instances read events from a ring buffer shared by the coordinator, see
+//! `broadcast`. By default the coordinator blocks before overwriting an event an instance has not
+//! read yet, so a slow instance holds up every other instance. The `backpressure` param lets an
+//! instance give up completeness instead, so that a laggy view cannot stall the rest of the
+//! pipeline.
+
+use crate::{ParamDecl, ViewError, ViewParams};
+
+/// The params read by `Backpressure::from_params`, accepted by every view
+pub const BACKPRESSURE_PARAMS: [ParamDecl; 2] = [
+    ParamDecl::str(
+        "backpressure",
+        "What to do when the view falls behind, either block, drop_oldest, drop_newest or buffer",
+    )
+    .default("block"),
+    ParamDecl::int(
+        "backpressure_cap",
+        "Maximum events held back from a view that has fallen behind by any policy but block",
+    )
+    .default("100000"),
+];
+
+/// Events held back from an instance by default, on top of those in the ring
+const DEFAULT_CAP: usize = 100_000;
+
+/// What happens to events a threaded view instance has not read before the ring wraps around
+///
+/// Held back events are delivered before those still in the ring.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the instance to catch up, holding up every other instance
+    Block,
+    /// Hold events back up to a cap, discarding the oldest held back beyond it
+    DropOldest,
+    /// Hold events back up to a cap, discarding any more until the instance catches up
+    DropNewest,
+    /// Hold events back up to a cap, then block
+    Buffer,
+}
+
+impl Backpressure {
+    /// The policy requested in `params` and the number of events it may hold back
+    pub fn from_params(params: &ViewParams) -> Result<(Backpressure, usize), ViewError> {
+        let policy = match params.get_str("backpressure").unwrap_or("block") {
+            "block" => Backpressure::Block,
+            "drop_oldest" => Backpressure::DropOldest,
+            "drop_newest" => Backpressure::DropNewest,
+            "buffer" => Backpressure::Buffer,
+            v => {
+                return Err(ViewError::InvalidParam(
+                    "backpressure".to_string(),
+                    format!("unknown policy {}", v),
+                ))
+            }
+        };
+        let cap = match params.get_int("backpressure_cap") {
+            None => DEFAULT_CAP,
+            Some(n) if n >= 0 => n as usize,
+            Some(n) => {
+                return Err(ViewError::InvalidParam(
+                    "backpressure_cap".to_string(),
+                    format!("must not be negative, not {}", n),
+                ))
+            }
+        };
+        Ok((policy, cap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_params() {
+        let mut p = ViewParams::new();
+        assert_eq!(
+            Backpressure::from_params(&p).unwrap(),
+            (Backpressure::Block, DEFAULT_CAP)
+        );
+        p.insert_param("backpressure", "drop_oldest".to_string());
+        p.insert_param("backpressure_cap", "10".to_string());
+        p.resolve(&BACKPRESSURE_PARAMS).unwrap();
+        assert_eq!(
+            Backpressure::from_params(&p).unwrap(),
+            (Backpressure::DropOldest, 10)
+        );
+        p.insert_param("backpressure", "drop_everything".to_string());
+        match Backpressure::from_params(&p) {
+            Err(ViewError::InvalidParam(name, _)) if name == "backpressure" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,796 @@
+//! Fanning events out to threaded view instances
+//!
+//! The coordinator publishes each event once into a ring buffer shared by every threaded
+//! instance, and each instance reads the ring at its own pace through a cursor of its own.
+//! Readers never wait on one another, and the coordinator only has to look at a reader when it
+//! is about to overwrite an event that reader has not read yet.
+//!
+//! Passing events through the ring takes no locks. Each slot is stamped with the sequence number
+//! of the event in it, which the publisher clears before replacing the event and sets again
+//! after, so a reader that finds the stamp it expects both before and after cloning the event out
+//! knows it has the right one. Locks are only taken off that path: to wake readers waiting for
+//! events, to spill events for a reader about to be overtaken, and to queue messages other than
+//! events. `benches/view_fanout.rs` compares the ring with giving each instance a channel of its
+//! own.
+//!
+//! What happens then depends on the reader's `Backpressure` policy. A blocking reader holds up
+//! the coordinator until it moves on. Any other has the event moved to a spill queue of its own,
+//! bounded by its `backpressure_cap`, which it reads before returning to the ring. Events that
+//! do not fit are lost to that reader only, and it skips over them when it catches up.
+//!
+//! Messages other than events are queued per reader, tagged with the number of events published
+//! before them, and delivered once the reader has got that far.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering::SeqCst},
+        Arc, Condvar, Mutex,
+    },
+};
+
+use arc_swap::ArcSwapOption;
+
+use crate::{Backpressure, DBTr, Filter, PausePolicy, ViewMsg};
+
+/// Events kept in the ring, and so how far a blocking instance can fall behind
+pub(crate) const CAPACITY: usize = 1024;
+
+/// How far a view instance has fallen behind the events passed to it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViewLag {
+    /// Events passed on that the instance has not read yet
+    pub behind: u64,
+    /// Events the instance has missed after falling too far behind, see `Backpressure`
+    pub dropped: u64,
+}
+
+/// Blocking until another thread changes some state
+///
+/// The state itself is not guarded by the signal, changes to it are followed by `notify`.
+#[derive(Debug, Default)]
+struct Signal {
+    lock: Mutex<()>,
+    cond: Condvar,
+    waiting: AtomicUsize,
+}
+
+impl Signal {
+    fn wait_until<F: FnMut() -> bool>(&self, mut ready: F) {
+        if ready() {
+            return;
+        }
+        self.waiting.fetch_add(1, SeqCst);
+        let mut guard = self.lock.lock().unwrap();
+        while !ready() {
+            guard = self.cond.wait(guard).unwrap();
+        }
+        drop(guard);
+        self.waiting.fetch_sub(1, SeqCst);
+    }
+
+    fn notify(&self) {
+        // orders the change being signalled before the check for waiters
+        fence(SeqCst);
+        if self.waiting.load(SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.cond.notify_all();
+        }
+    }
+}
+
+/// The stamp of a slot whose event is being replaced, or that has never held one
+const UNSTAMPED: u64 = u64::MAX;
+
+/// A place in the ring, stamped with the sequence number of the event it holds
+#[derive(Debug)]
+struct Slot {
+    seq: AtomicU64,
+    evt: ArcSwapOption<DBTr>,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Slot {
+            seq: AtomicU64::new(UNSTAMPED),
+            evt: ArcSwapOption::empty(),
+        }
+    }
+}
+
+impl Slot {
+    /// The event numbered `seq`, if the slot still holds it
+    fn get(&self, seq: u64) -> Option<Arc<DBTr>> {
+        if self.seq.load(SeqCst) != seq {
+            return None;
+        }
+        let evt = self.evt.load_full();
+        // a stamp changed in between means the event taken may be its replacement
+        if self.seq.load(SeqCst) != seq {
+            return None;
+        }
+        evt
+    }
+
+    /// Replace the event in the slot, only called by the publisher
+    fn set(&self, seq: u64, evt: Arc<DBTr>) {
+        self.seq.store(UNSTAMPED, SeqCst);
+        self.evt.store(Some(evt));
+        self.seq.store(seq, SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Control {
+    msgs: VecDeque<(u64, ViewMsg)>,
+    closed: bool,
+}
+
+fn pause_to_u8(policy: Option<PausePolicy>) -> u8 {
+    match policy {
+        None => 0,
+        Some(PausePolicy::Drop) => 1,
+        Some(PausePolicy::Buffer) => 2,
+    }
+}
+
+fn pause_from_u8(val: u8) -> Option<PausePolicy> {
+    match val {
+        0 => None,
+        1 => Some(PausePolicy::Drop),
+        _ => Some(PausePolicy::Buffer),
+    }
+}
+
+/// The state of one threaded instance shared between the coordinator and the instance
+#[derive(Debug)]
+pub(crate) struct Reader {
+    pub(crate) iid: usize,
+    policy: Backpressure,
+    cap: usize,
+    /// The sequence number of the next event the instance will read
+    cursor: AtomicU64,
+    dropped: AtomicU64,
+    /// Events moved out of the ring before the instance read them, oldest first
+    spill: Mutex<VecDeque<(u64, Arc<DBTr>)>>,
+    control: Mutex<Control>,
+    pending: AtomicUsize,
+    closed: AtomicBool,
+    paused: AtomicU8,
+}
+
+impl Reader {
+    pub(crate) fn new(iid: usize, policy: Backpressure, cap: usize, cursor: u64) -> Self {
+        Reader {
+            iid,
+            policy,
+            cap,
+            cursor: AtomicU64::new(cursor),
+            dropped: AtomicU64::new(0),
+            spill: Mutex::new(VecDeque::new()),
+            control: Mutex::default(),
+            pending: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            paused: AtomicU8::new(0),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(SeqCst)
+    }
+
+    /// Stop taking messages, dropping any not yet delivered
+    fn close(&self) {
+        let mut control = self.control.lock().unwrap();
+        control.closed = true;
+        control.msgs.clear();
+        self.closed.store(true, SeqCst);
+    }
+
+    fn paused(&self) -> Option<PausePolicy> {
+        pause_from_u8(self.paused.load(SeqCst))
+    }
+
+    fn push_control(&self, seq: u64, msg: ViewMsg) {
+        let mut control = self.control.lock().unwrap();
+        // an instance that has already stopped reading has nothing left to act on
+        if !control.closed {
+            control.msgs.push_back((seq, msg));
+            self.pending.fetch_add(1, SeqCst);
+        }
+    }
+
+    fn has_control(&self) -> bool {
+        self.pending.load(SeqCst) > 0
+    }
+
+    /// The next message other than an event, if every event before it has been read
+    fn take_control(&self, cursor: u64) -> Option<ViewMsg> {
+        if !self.has_control() {
+            return None;
+        }
+        let mut control = self.control.lock().unwrap();
+        match control.msgs.front() {
+            Some(&(seq, _)) if seq <= cursor => {
+                self.pending.fetch_sub(1, SeqCst);
+                control.msgs.pop_front().map(|(_, msg)| msg)
+            }
+            _ => None,
+        }
+    }
+
+    fn lag(&self, published: u64) -> ViewLag {
+        ViewLag {
+            behind: published.saturating_sub(self.cursor.load(SeqCst)),
+            dropped: self.dropped.load(SeqCst),
+        }
+    }
+}
+
+/// The ring buffer shared by the coordinator and every threaded instance
+#[derive(Debug)]
+pub(crate) struct Ring {
+    slots: Box<[Slot]>,
+    /// The number of events published so far, and so the sequence number of the next
+    published: AtomicU64,
+    readers: Mutex<Arc<Vec<Arc<Reader>>>>,
+    /// Bumped whenever `readers` changes, for the publisher to pick up the new list
+    generation: AtomicU64,
+    /// Signalled when readers have something new to read
+    data: Signal,
+    /// Signalled when a reader moves on
+    space: Signal,
+    handles: AtomicUsize,
+    hung_up: AtomicBool,
+}
+
+impl Ring {
+    fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    pub(crate) fn published(&self) -> u64 {
+        self.published.load(SeqCst)
+    }
+
+    fn is_hung_up(&self) -> bool {
+        self.hung_up.load(SeqCst)
+    }
+
+    fn update_readers<F: FnOnce(&mut Vec<Arc<Reader>>)>(&self, f: F) {
+        let mut readers = self.readers.lock().unwrap();
+        let mut list = Vec::clone(&readers);
+        f(&mut list);
+        *readers = Arc::new(list);
+        self.generation.fetch_add(1, SeqCst);
+    }
+
+    pub(crate) fn add_reader(&self, reader: Arc<Reader>) {
+        self.update_readers(|readers| readers.push(reader));
+    }
+
+    pub(crate) fn remove_reader(&self, iid: usize) {
+        self.update_readers(|readers| readers.retain(|r| r.iid != iid));
+    }
+
+    pub(crate) fn reader(&self, iid: usize) -> Option<Arc<Reader>> {
+        self.readers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.iid == iid)
+            .cloned()
+    }
+
+    pub(crate) fn lag(&self, reader: &Reader) -> ViewLag {
+        reader.lag(self.published())
+    }
+
+    pub(crate) fn set_paused(&self, reader: &Reader, policy: Option<PausePolicy>) {
+        reader.paused.store(pause_to_u8(policy), SeqCst);
+        self.data.notify();
+    }
+
+    /// Send `msg` to a reader after every event published so far
+    pub(crate) fn control(&self, reader: &Reader, msg: ViewMsg) {
+        reader.push_control(self.published(), msg);
+        self.data.notify();
+    }
+
+    /// Send `msg` to every reader after every event published so far
+    pub(crate) fn control_all(&self, msg: &ViewMsg) {
+        let readers = self.readers.lock().unwrap().clone();
+        let seq = self.published();
+        for reader in readers.iter() {
+            reader.push_control(seq, msg.clone());
+        }
+        self.data.notify();
+    }
+
+    /// Keep the event numbered `seq`, about to be overwritten, for a reader that still needs it
+    fn make_room(&self, reader: &Reader, seq: u64) {
+        if reader.policy == Backpressure::Block {
+            self.space
+                .wait_until(|| reader.cursor.load(SeqCst) > seq || reader.is_closed());
+            return;
+        }
+        let evt = match self.slots[(seq % self.capacity()) as usize].get(seq) {
+            Some(evt) => evt,
+            None => return,
+        };
+        loop {
+            let mut spill = reader.spill.lock().unwrap();
+            let cursor = reader.cursor.load(SeqCst);
+            if cursor > seq || reader.is_closed() {
+                return;
+            }
+            // anything older was read from the ring before it could be overwritten
+            while let Some(&(old, _)) = spill.front() {
+                if old >= cursor {
+                    break;
+                }
+                spill.pop_front();
+            }
+            if spill.len() < reader.cap {
+                spill.push_back((seq, evt));
+                return;
+            }
+            match reader.policy {
+                Backpressure::DropOldest if reader.cap > 0 => {
+                    spill.pop_front();
+                    spill.push_back((seq, evt));
+                    return;
+                }
+                Backpressure::DropOldest | Backpressure::DropNewest => return,
+                Backpressure::Buffer => {
+                    drop(spill);
+                    self.space
+                        .wait_until(|| reader.cursor.load(SeqCst) != cursor || reader.is_closed());
+                }
+                Backpressure::Block => unreachable!(),
+            }
+        }
+    }
+}
+
+/// A reference to the ring held by the coordinator
+///
+/// Readers see the ring hang up once every handle has been dropped, after which no more events
+/// or messages will arrive.
+#[derive(Debug)]
+pub(crate) struct RingHandle(Arc<Ring>);
+
+impl RingHandle {
+    pub(crate) fn new(capacity: usize) -> Self {
+        RingHandle(Arc::new(Ring {
+            slots: (0..capacity).map(|_| Slot::default()).collect(),
+            published: AtomicU64::new(0),
+            readers: Mutex::default(),
+            generation: AtomicU64::new(0),
+            data: Signal::default(),
+            space: Signal::default(),
+            handles: AtomicUsize::new(1),
+            hung_up: AtomicBool::new(false),
+        }))
+    }
+
+    pub(crate) fn ring(&self) -> &Arc<Ring> {
+        &self.0
+    }
+
+    /// A publisher for the thread feeding the ring, only one may be in use at a time
+    pub(crate) fn publisher(&self) -> Publisher {
+        Publisher {
+            ring: self.clone(),
+            readers: Arc::default(),
+            generation: u64::MAX,
+        }
+    }
+}
+
+impl Clone for RingHandle {
+    fn clone(&self) -> Self {
+        self.0.handles.fetch_add(1, SeqCst);
+        RingHandle(self.0.clone())
+    }
+}
+
+impl Drop for RingHandle {
+    fn drop(&mut self) {
+        if self.0.handles.fetch_sub(1, SeqCst) == 1 {
+            self.0.hung_up.store(true, SeqCst);
+            self.0.data.notify();
+        }
+    }
+}
+
+/// The writing end of the ring
+#[derive(Debug)]
+pub(crate) struct Publisher {
+    ring: RingHandle,
+    readers: Arc<Vec<Arc<Reader>>>,
+    generation: u64,
+}
+
+impl Publisher {
+    pub(crate) fn publish(&mut self, evt: Arc<DBTr>) {
+        let ring = self.ring.ring();
+        let generation = ring.generation.load(SeqCst);
+        if generation != self.generation {
+            self.readers = ring.readers.lock().unwrap().clone();
+            self.generation = generation;
+        }
+        let seq = ring.published();
+        if let Some(old) = seq.checked_sub(ring.capacity()) {
+            for reader in self.readers.iter() {
+                if reader.cursor.load(SeqCst) <= old && !reader.is_closed() {
+                    ring.make_room(reader, old);
+                }
+            }
+        }
+        ring.slots[(seq % ring.capacity()) as usize].set(seq, evt);
+        ring.published.store(seq + 1, SeqCst);
+        ring.data.notify();
+    }
+}
+
+/// What a reader found when polled
+#[derive(Debug)]
+pub(crate) enum Poll {
+    Msg(ViewMsg),
+    /// Nothing to read yet
+    Empty,
+    /// The ring has hung up and everything has been read
+    Ended,
+}
+
+/// The reading end of the ring for one instance
+///
+/// Filtering and pausing happen here, on the instance's thread, rather than holding up the
+/// coordinator.
+#[derive(Debug)]
+pub(crate) struct RingReader {
+    ring: Arc<Ring>,
+    reader: Arc<Reader>,
+    cursor: u64,
+    filter: Option<Filter>,
+    /// Journaled events to deliver before any from the ring
+    replay: VecDeque<Arc<DBTr>>,
+    /// Events held back while paused with `PausePolicy::Buffer`
+    held: VecDeque<Arc<DBTr>>,
+    paused_drops: usize,
+    reported_drops: u64,
+    out: VecDeque<ViewMsg>,
+}
+
+impl RingReader {
+    pub(crate) fn new(
+        ring: Arc<Ring>,
+        reader: Arc<Reader>,
+        filter: Option<Filter>,
+        replay: VecDeque<Arc<DBTr>>,
+    ) -> Self {
+        RingReader {
+            cursor: reader.cursor.load(SeqCst),
+            ring,
+            reader,
+            filter,
+            replay,
+            held: VecDeque::new(),
+            paused_drops: 0,
+            reported_drops: 0,
+            out: VecDeque::new(),
+        }
+    }
+
+    /// Move the cursor on, counting the events before `skip_to` that were never read as dropped
+    fn advance(&mut self, skip_to: u64, cursor: u64) {
+        if skip_to > self.cursor {
+            self.reader.dropped.fetch_add(skip_to - self.cursor, SeqCst);
+        }
+        self.cursor = cursor;
+        self.reader.cursor.store(cursor, SeqCst);
+        self.ring.space.notify();
+    }
+
+    /// The next event in the ring, skipping any lost after falling behind
+    fn read(&mut self) -> Option<Arc<DBTr>> {
+        let ring = self.ring.clone();
+        loop {
+            if self.cursor >= ring.published() {
+                return None;
+            }
+            let slot = &ring.slots[(self.cursor % ring.capacity()) as usize];
+            if let Some(evt) = slot.get(self.cursor) {
+                self.advance(self.cursor, self.cursor + 1);
+                return Some(evt);
+            }
+            // overwritten before being read, so either spilled or lost
+            let reader = self.reader.clone();
+            let mut spill = reader.spill.lock().unwrap();
+            while let Some((seq, evt)) = spill.pop_front() {
+                if seq >= self.cursor {
+                    drop(spill);
+                    self.advance(seq, seq + 1);
+                    return Some(evt);
+                }
+            }
+            drop(spill);
+            let oldest = ring.published().saturating_sub(ring.capacity());
+            if oldest > self.cursor {
+                self.advance(oldest, oldest);
+            }
+        }
+    }
+
+    fn report_drops(&mut self) {
+        let dropped = self.reader.dropped.load(SeqCst);
+        if dropped > self.reported_drops {
+            eprintln!(
+                "View instance {} dropped {} events after falling behind",
+                self.reader.iid,
+                dropped - self.reported_drops
+            );
+            self.reported_drops = dropped;
+        }
+    }
+
+    fn resume(&mut self) {
+        self.out.extend(self.held.drain(..).map(ViewMsg::Event));
+        if self.paused_drops > 0 {
+            eprintln!(
+                "View instance {} dropped {} events while paused",
+                self.reader.iid, self.paused_drops
+            );
+            self.paused_drops = 0;
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        self.ring.published() > self.cursor
+            || self.reader.has_control()
+            || (self.reader.paused().is_none() && !self.held.is_empty())
+    }
+
+    pub(crate) fn poll(&mut self, block: bool) -> Poll {
+        loop {
+            if let Some(msg) = self.out.pop_front() {
+                return Poll::Msg(msg);
+            }
+            let paused = self.reader.paused();
+            if paused.is_none() && (!self.held.is_empty() || self.paused_drops > 0) {
+                self.resume();
+                continue;
+            }
+            if let Some(msg) = self.reader.take_control(self.cursor) {
+                self.report_drops();
+                if let ViewMsg::Shutdown = msg {
+                    self.resume();
+                }
+                self.out.push_back(msg);
+                continue;
+            }
+            let evt = match self.replay.pop_front() {
+                Some(evt) => evt,
+                None => match self.read() {
+                    Some(evt) => evt,
+                    None => {
+                        // checked first, so nothing sent before hanging up is missed
+                        let hung_up = self.ring.is_hung_up();
+                        if self.has_work() {
+                            continue;
+                        }
+                        if hung_up {
+                            return Poll::Ended;
+                        }
+                        if !block {
+                            return Poll::Empty;
+                        }
+                        let ring = self.ring.clone();
+                        ring.data
+                            .wait_until(|| ring.is_hung_up() || self.has_work());
+                        continue;
+                    }
+                },
+            };
+            if let Some(ref mut filter) = self.filter {
+                if !filter.matches(&evt) {
+                    continue;
+                }
+            }
+            match paused {
+                None => return Poll::Msg(ViewMsg::Event(evt)),
+                Some(PausePolicy::Drop) => self.paused_drops += 1,
+                Some(PausePolicy::Buffer) => self.held.push_back(evt),
+            }
+        }
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        self.reader.close();
+        self.ring.space.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::data::{
+        node_types::{Name, NameNode, Node},
+        HasID, ID,
+    };
+
+    fn evt(id: u64) -> Arc<DBTr> {
+        Arc::new(DBTr::CreateNode(Node::Name(NameNode::generate(
+            ID::new(id),
+            Name::Path(format!("/tmp/{}", id)),
+        ))))
+    }
+
+    /// The id of the node created by an event, `None` for other messages
+    fn id(msg: ViewMsg) -> Option<u64> {
+        match msg {
+            ViewMsg::Event(evt) => match *evt {
+                DBTr::CreateNode(ref n) => Some(n.get_db_id().inner()),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    fn reader(ring: &RingHandle, policy: Backpressure, cap: usize) -> RingReader {
+        let ring = ring.ring();
+        let reader = Arc::new(Reader::new(0, policy, cap, ring.published()));
+        ring.add_reader(reader.clone());
+        RingReader::new(ring.clone(), reader, None, VecDeque::new())
+    }
+
+    /// Everything waiting to be read
+    fn drain(r: &mut RingReader) -> Vec<Option<u64>> {
+        let mut msgs = Vec::new();
+        while let Poll::Msg(msg) = r.poll(false) {
+            msgs.push(id(msg));
+        }
+        msgs
+    }
+
+    fn run(policy: Backpressure) -> (RingHandle, RingReader) {
+        let ring = RingHandle::new(2);
+        let r = reader(&ring, policy, 2);
+        let mut publisher = ring.publisher();
+        for id in 1..=6 {
+            publisher.publish(evt(id));
+        }
+        (ring, r)
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (ring, mut r) = run(Backpressure::DropNewest);
+        assert_eq!(drain(&mut r), vec![Some(1), Some(2), Some(5), Some(6)]);
+        assert_eq!(
+            ring.ring().lag(&r.reader),
+            ViewLag {
+                behind: 0,
+                dropped: 2
+            }
+        );
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (_ring, mut r) = run(Backpressure::DropOldest);
+        assert_eq!(drain(&mut r), vec![Some(3), Some(4), Some(5), Some(6)]);
+    }
+
+    #[test]
+    fn readers_are_independent() {
+        let ring = RingHandle::new(2);
+        let mut slow = reader(&ring, Backpressure::DropOldest, 0);
+        let mut fast = reader(&ring, Backpressure::Block, 0);
+        let mut publisher = ring.publisher();
+        for id in 1..=4 {
+            publisher.publish(evt(id));
+            assert_eq!(drain(&mut fast), vec![Some(id)]);
+        }
+        assert_eq!(drain(&mut slow), vec![Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn block_and_buffer_keep_everything() {
+        for &policy in &[Backpressure::Block, Backpressure::Buffer] {
+            let ring = RingHandle::new(2);
+            let mut r = reader(&ring, policy, 2);
+            let mut publisher = ring.publisher();
+            let feed = thread::spawn(move || {
+                for id in 1..=100 {
+                    publisher.publish(evt(id));
+                }
+            });
+            drop(ring);
+            let mut msgs = Vec::new();
+            while let Poll::Msg(msg) = r.poll(true) {
+                msgs.push(id(msg).unwrap());
+            }
+            feed.join().unwrap();
+            assert_eq!(msgs, (1..=100).collect::<Vec<_>>());
+            assert_eq!(r.ring.lag(&r.reader).dropped, 0);
+        }
+    }
+
+    #[test]
+    fn overtaken_reads_stay_in_order() {
+        let ring = RingHandle::new(2);
+        let mut r = reader(&ring, Backpressure::DropOldest, 0);
+        let mut publisher = ring.publisher();
+        let feed = thread::spawn(move || {
+            for id in 1..=10_000 {
+                publisher.publish(evt(id));
+            }
+        });
+        drop(ring);
+        let mut msgs = Vec::new();
+        while let Poll::Msg(msg) = r.poll(true) {
+            msgs.push(id(msg).unwrap());
+        }
+        feed.join().unwrap();
+        // events are lost to the reader when overtaken, but never repeated or reordered
+        assert!(msgs.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(msgs.last(), Some(&10_000));
+        let lag = r.ring.lag(&r.reader);
+        assert_eq!(lag.dropped + msgs.len() as u64, 10_000);
+    }
+
+    #[test]
+    fn control_follows_events() {
+        let ring = RingHandle::new(2);
+        let mut r = reader(&ring, Backpressure::DropNewest, 2);
+        let mut publisher = ring.publisher();
+        publisher.publish(evt(1));
+        ring.ring().control_all(&ViewMsg::Shutdown);
+        publisher.publish(evt(2));
+        assert_eq!(ring.ring().lag(&r.reader).behind, 2);
+        assert_eq!(drain(&mut r), vec![Some(1), None, Some(2)]);
+        assert_eq!(ring.ring().lag(&r.reader).behind, 0);
+    }
+
+    #[test]
+    fn pausing() {
+        let ring = RingHandle::new(2);
+        let mut r = reader(&ring, Backpressure::Block, 0);
+        let mut publisher = ring.publisher();
+        ring.ring().set_paused(&r.reader, Some(PausePolicy::Buffer));
+        publisher.publish(evt(1));
+        assert!(drain(&mut r).is_empty());
+        ring.ring().set_paused(&r.reader, Some(PausePolicy::Drop));
+        publisher.publish(evt(2));
+        assert!(drain(&mut r).is_empty());
+        ring.ring().set_paused(&r.reader, None);
+        publisher.publish(evt(3));
+        assert_eq!(drain(&mut r), vec![Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn ends_once_hung_up() {
+        let ring = RingHandle::new(2);
+        let mut r = reader(&ring, Backpressure::Block, 0);
+        let mut publisher = ring.publisher();
+        publisher.publish(evt(1));
+        assert!(!r.ring.is_hung_up());
+        drop(publisher);
+        drop(ring);
+        match r.poll(true) {
+            Poll::Msg(msg) => assert_eq!(id(msg), Some(1)),
+            p => panic!("unexpected poll {:?}", p),
+        }
+        match r.poll(true) {
+            Poll::Ended => {}
+            p => panic!("unexpected poll {:?}", p),
+        }
+    }
+}
@@ -0,0 +1,465 @@
+//! Restricting the events delivered to a view instance
+//!
+//! Any view can be wrapped in a `FilterView`, given the name of the view in its `view` param
+//! along with the `filter_*` params. The coordinator then creates an instance of the wrapped
+//! view with the remaining params, and only delivers it the events matching every filter given,
+//! so views interested in a slice of the graph need not filter the stream themselves. Schema
+//! nodes are always delivered.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+
+use crate::{
+    data::{
+        node_types::{NameNode, Node},
+        rel_types::{PVMOps, Rel},
+        HasDst, HasID, HasSrc, ID,
+    },
+    DBTr, InlineView, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+};
+
+/// The params read by `Filter::from_params`, accepted by `FilterView`
+pub const FILTER_PARAMS: [ParamDecl; 5] = [
+    ParamDecl::list(
+        "filter_types",
+        "Comma separated node types to deliver, either concrete types such as process or \
+         context, path or net",
+    ),
+    ParamDecl::list(
+        "filter_ops",
+        "Comma separated PVM operations of the INF relations to deliver",
+    ),
+    ParamDecl::str(
+        "filter_meta",
+        "Only deliver data nodes with a property matching key=regex",
+    ),
+    ParamDecl::str(
+        "filter_after",
+        "Only deliver events from contexts at or after this RFC 3339 time",
+    ),
+    ParamDecl::str(
+        "filter_before",
+        "Only deliver events from contexts before this RFC 3339 time",
+    ),
+];
+
+const VIEW_PARAM: ParamDecl = ParamDecl::str("view", "Name of the view to deliver events to");
+
+/// A view type delivering the events matching a `Filter` to an instance of another view
+///
+/// Its instances are created by the coordinator as instances of the wrapped view, so it has
+/// no instances of its own.
+#[derive(Debug)]
+pub struct FilterView(usize);
+
+impl FilterView {
+    pub const NAME: &'static str = "FilterView";
+
+    /// Split the params of a `FilterView` instance into its own, resolved, and the rest, which
+    /// are those of the wrapped view
+    pub(crate) fn split_params(params: &mut ViewParams) -> Result<ViewParams, ViewError> {
+        let mut own = ViewParams::new();
+        for decl in FILTER_PARAMS.iter().chain(Some(&VIEW_PARAM)) {
+            if let Some(val) = params.remove(decl.name) {
+                own.insert_param(decl.name, val);
+            }
+        }
+        own.resolve(&FilterView(0).params())?;
+        match own.get_str(VIEW_PARAM.name) {
+            None => Err(ViewError::InvalidParam(
+                VIEW_PARAM.name.to_string(),
+                "the view to filter must be given".to_string(),
+            )),
+            Some(FilterView::NAME) => Err(ViewError::InvalidParam(
+                VIEW_PARAM.name.to_string(),
+                "filters cannot be nested, give every filter param to one".to_string(),
+            )),
+            Some(_) => Ok(own),
+        }
+    }
+}
+
+impl View for FilterView {
+    fn new(id: usize) -> Self {
+        FilterView(id)
+    }
+    fn id(&self) -> usize {
+        self.0
+    }
+    fn name(&self) -> &'static str {
+        FilterView::NAME
+    }
+    fn desc(&self) -> &'static str {
+        "Deliver only the matching events to an instance of another view, which is given the \
+         params not read by the filter."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        let mut params = vec![VIEW_PARAM];
+        params.extend_from_slice(&FILTER_PARAMS);
+        params
+    }
+    fn create(
+        &self,
+        _id: usize,
+        _params: ViewParams,
+        _stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        unreachable!("Instances of the wrapped view are created instead")
+    }
+    fn create_inline(
+        &self,
+        _id: usize,
+        _params: &ViewParams,
+    ) -> Result<Box<dyn InlineView>, ViewError> {
+        unreachable!("Instances of the wrapped view are created instead")
+    }
+}
+
+/// A predicate over the event stream
+///
+/// When nodes are filtered by type or metadata, relations are only delivered between nodes
+/// that have been delivered, and a node stays delivered once it has matched. Events are placed
+/// in time by their context, those whose context time is not known are always delivered.
+///
+/// Contexts are expected to arrive in time order, so that only the contexts before the window
+/// need remembering until it opens. Contexts created before the window opens are then all
+/// treated as before it, and once a context at or after its end arrives nothing but schema
+/// nodes is delivered again.
+#[derive(Debug, Default)]
+pub struct Filter {
+    types: Option<HashSet<String>>,
+    ops: Option<HashSet<PVMOps>>,
+    meta: Option<(String, Regex)>,
+    after: Option<DateTime<FixedOffset>>,
+    before: Option<DateTime<FixedOffset>>,
+    delivered: HashSet<ID>,
+    /// Contexts known to be before the window, those created before it opened are dropped
+    /// once it does
+    early: HashSet<ID>,
+    /// The first context in the window
+    opened: Option<ID>,
+    closed: bool,
+}
+
+fn time(
+    params: &ViewParams,
+    key: &'static str,
+) -> Result<Option<DateTime<FixedOffset>>, ViewError> {
+    match params.get_str(key) {
+        None => Ok(None),
+        Some(v) => DateTime::parse_from_rfc3339(v)
+            .map(Some)
+            .map_err(|e| ViewError::InvalidParam(key.to_string(), e.to_string())),
+    }
+}
+
+fn node_type(node: &Node) -> &'static str {
+    match node {
+        Node::Data(d) => d.ty().name,
+        Node::Ctx(_) => "context",
+        Node::Name(NameNode::Path(..)) => "path",
+        Node::Name(NameNode::Net(..)) => "net",
+        Node::Schema(_) => "schema",
+    }
+}
+
+impl Filter {
+    /// Build the filter described by `params`, or `None` if they do not restrict the stream
+    pub fn from_params(params: &ViewParams) -> Result<Option<Filter>, ViewError> {
+        let ops = match params.get_list("filter_ops") {
+            Some(ops) => Some(
+                ops.iter()
+                    .map(|op| op.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| {
+                        ViewError::InvalidParam("filter_ops".to_string(), format!("{}", e))
+                    })?,
+            ),
+            None => None,
+        };
+        let meta = match params.get_str("filter_meta") {
+            None => None,
+            Some(v) => {
+                let mut kv = v.splitn(2, '=');
+                let key = kv.next().unwrap().to_string();
+                let re = kv.next().ok_or_else(|| {
+                    ViewError::InvalidParam(
+                        "filter_meta".to_string(),
+                        "expected key=regex".to_string(),
+                    )
+                })?;
+                let re = Regex::new(re).map_err(|e| {
+                    ViewError::InvalidParam("filter_meta".to_string(), e.to_string())
+                })?;
+                Some((key, re))
+            }
+        };
+        let filter = Filter {
+            types: params
+                .get_list("filter_types")
+                .map(|t| t.iter().cloned().collect()),
+            ops,
+            meta,
+            after: time(params, "filter_after")?,
+            before: time(params, "filter_before")?,
+            ..Filter::default()
+        };
+        if filter.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(filter))
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.types.is_none()
+            && self.ops.is_none()
+            && self.meta.is_none()
+            && self.after.is_none()
+            && self.before.is_none()
+    }
+
+    fn filters_nodes(&self) -> bool {
+        self.types.is_some() || self.meta.is_some()
+    }
+
+    fn in_window(&self, ctx: ID) -> bool {
+        if self.closed || self.early.contains(&ctx) {
+            return false;
+        }
+        match self.opened {
+            Some(opened) => ctx.inner() >= opened.inner(),
+            None => true,
+        }
+    }
+
+    /// Place a context in time, forgetting what is no longer needed as the window passes
+    fn see_ctx(&mut self, ctx: ID, t: DateTime<FixedOffset>) {
+        if self.before.is_some_and(|before| t >= before) {
+            self.closed = true;
+            self.early.clear();
+            self.delivered.clear();
+        } else if self.after.is_some_and(|after| t < after) {
+            self.early.insert(ctx);
+        } else if self.opened.is_none() {
+            self.opened = Some(ctx);
+            self.early.clear();
+        }
+    }
+
+    fn node_matches(&self, node: &Node) -> bool {
+        if let Some(ref types) = self.types {
+            if !types.contains(node_type(node)) {
+                return false;
+            }
+        }
+        match node {
+            Node::Data(d) => {
+                if let Some((ref key, ref re)) = self.meta {
+                    match d.meta.cur(key) {
+                        Some(v) if re.is_match(v) => {}
+                        _ => return false,
+                    }
+                }
+                self.in_window(d.ctx())
+            }
+            Node::Ctx(c) => self.in_window(c.get_db_id()),
+            _ => true,
+        }
+    }
+
+    fn rel_matches(&self, rel: &Rel) -> bool {
+        if self.filters_nodes()
+            && !(self.delivered.contains(&rel.get_src()) && self.delivered.contains(&rel.get_dst()))
+        {
+            return false;
+        }
+        match rel {
+            Rel::Inf(i) => {
+                if let Some(ref ops) = self.ops {
+                    if !ops.contains(&i.pvm_op) {
+                        return false;
+                    }
+                }
+                self.in_window(i.ctx)
+            }
+            Rel::Named(n) => self.in_window(n.start),
+        }
+    }
+
+    /// Whether `evt` should be delivered, given the events seen before it
+    pub fn matches(&mut self, evt: &DBTr) -> bool {
+        match evt {
+            DBTr::CreateNode(node) | DBTr::UpdateNode(node) => {
+                if let Node::Schema(_) = node {
+                    return true;
+                }
+                if let Node::Ctx(c) = node {
+                    if self.after.is_some() || self.before.is_some() {
+                        if let Some(t) = c.cont.get("time") {
+                            if let Ok(t) = DateTime::parse_from_rfc3339(t) {
+                                self.see_ctx(c.get_db_id(), t);
+                            }
+                        }
+                    }
+                }
+                if self.closed {
+                    return false;
+                }
+                let id = node.get_db_id();
+                if self.filters_nodes() && self.delivered.contains(&id) {
+                    return true;
+                }
+                let matched = self.node_matches(node);
+                if matched && self.filters_nodes() {
+                    self.delivered.insert(id);
+                }
+                matched
+            }
+            DBTr::CreateRel(rel) | DBTr::UpdateRel(rel) => self.rel_matches(rel),
+            DBTr::Flush(_) | DBTr::Tick(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::hashmap;
+    use uuid::Uuid;
+
+    use crate::data::{
+        node_types::{ConcreteType, ContextType, CtxNode, DataNode, PVMDataType},
+        rel_types::{Inf, InfInit},
+        RelGenerable,
+    };
+
+    fn filter(params: &[(&str, &str)]) -> Filter {
+        let mut p = ViewParams::new();
+        for (k, v) in params {
+            p.insert_param(k, v.to_string());
+        }
+        p.resolve(&FILTER_PARAMS).unwrap();
+        Filter::from_params(&p).unwrap().unwrap()
+    }
+
+    fn ctx(id: u64, time: &str) -> DBTr {
+        let ty = Box::leak(Box::new(ContextType {
+            name: "test_context",
+            props: vec!["time"],
+        }));
+        let cont = hashmap!("time" => time.to_string());
+        DBTr::CreateNode(Node::Ctx(CtxNode::new(ID::new(id), ty, cont).unwrap()))
+    }
+
+    fn data(id: u64, ty: &'static str, ctx: u64, cmd: &str) -> DBTr {
+        let ty = Box::leak(Box::new(ConcreteType {
+            pvm_ty: PVMDataType::Actor,
+            name: ty,
+            props: hashmap!("cmdline" => true),
+        }));
+        let mut node = DataNode::new(
+            PVMDataType::Actor,
+            ty,
+            ID::new(id),
+            Uuid::nil(),
+            ID::new(ctx),
+            None,
+        );
+        node.meta.update("cmdline", cmd, ID::new(ctx), true);
+        DBTr::CreateNode(Node::Data(node))
+    }
+
+    fn inf(id: u64, src: u64, dst: u64, op: PVMOps, ctx: u64) -> DBTr {
+        let init = InfInit {
+            pvm_op: op,
+            ctx: ID::new(ctx),
+            bytes_read: 0,
+            bytes_written: 0,
+            event: None,
+        };
+        DBTr::CreateRel(Rel::Inf(Inf::new(
+            ID::new(id),
+            ID::new(src),
+            ID::new(dst),
+            init,
+        )))
+    }
+
+    #[test]
+    fn unrestricted_params_build_no_filter() {
+        assert!(Filter::from_params(&ViewParams::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_params_are_rejected() {
+        let mut p = ViewParams::new();
+        p.insert_param("filter_ops", "Source,Teleport".to_string());
+        p.resolve(&FILTER_PARAMS).unwrap();
+        match Filter::from_params(&p) {
+            Err(ViewError::InvalidParam(name, _)) if name == "filter_ops" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rels_follow_delivered_nodes() {
+        let mut f = filter(&[("filter_types", "process")]);
+        assert!(!f.matches(&ctx(1, "2018-07-31T14:10:00Z")));
+        assert!(f.matches(&data(2, "process", 1, "sh")));
+        assert!(f.matches(&data(3, "process", 1, "ls")));
+        assert!(!f.matches(&data(4, "file", 1, "")));
+        assert!(f.matches(&inf(5, 2, 3, PVMOps::Version, 1)));
+        assert!(!f.matches(&inf(6, 2, 4, PVMOps::Sink, 1)));
+    }
+
+    #[test]
+    fn ops_and_meta() {
+        let mut f = filter(&[("filter_ops", "Sink"), ("filter_meta", "cmdline=^s")]);
+        assert!(f.matches(&data(2, "process", 1, "sh")));
+        assert!(f.matches(&data(3, "process", 1, "ssh")));
+        assert!(!f.matches(&data(4, "process", 1, "ls")));
+        assert!(f.matches(&inf(5, 2, 3, PVMOps::Sink, 1)));
+        assert!(!f.matches(&inf(6, 2, 3, PVMOps::Source, 1)));
+    }
+
+    #[test]
+    fn time_window() {
+        let mut f = filter(&[
+            ("filter_after", "2018-07-31T14:00:00Z"),
+            ("filter_before", "2018-07-31T15:00:00Z"),
+        ]);
+        assert!(!f.matches(&ctx(1, "2018-07-31T13:59:59Z")));
+        assert!(f.matches(&ctx(2, "2018-07-31T14:00:00+00:00")));
+        assert!(!f.matches(&data(3, "process", 1, "sh")));
+        assert!(f.matches(&data(4, "process", 2, "sh")));
+        assert!(f.matches(&inf(5, 3, 4, PVMOps::Version, 2)));
+        assert!(!f.matches(&inf(6, 3, 4, PVMOps::Version, 1)));
+    }
+
+    #[test]
+    fn window_state_is_pruned() {
+        let mut f = filter(&[
+            ("filter_types", "process"),
+            ("filter_after", "2018-07-31T14:00:00Z"),
+            ("filter_before", "2018-07-31T15:00:00Z"),
+        ]);
+        assert!(!f.matches(&ctx(1, "2018-07-31T13:58:00Z")));
+        assert!(!f.matches(&ctx(2, "2018-07-31T13:59:00Z")));
+        assert_eq!(f.early.len(), 2);
+        assert!(!f.matches(&ctx(3, "2018-07-31T14:00:00Z")));
+        assert!(f.early.is_empty());
+        // still before the window, although no longer remembered individually
+        assert!(!f.matches(&data(4, "process", 2, "sh")));
+        assert!(f.matches(&data(5, "process", 3, "sh")));
+        assert!(f.matches(&data(6, "process", 3, "ls")));
+        assert_eq!(f.delivered.len(), 2);
+        assert!(!f.matches(&ctx(7, "2018-07-31T15:00:00Z")));
+        assert!(f.delivered.is_empty());
+        assert!(!f.matches(&inf(8, 5, 6, PVMOps::Version, 3)));
+        assert!(!f.matches(&data(9, "process", 12, "sh")));
+    }
+}
@@ -1,17 +1,44 @@
 pub extern crate pvm_data as data;
 
 use std::{
-    any::Any,
-    collections::HashMap,
+    borrow::Borrow,
+    collections::{HashMap, VecDeque},
     fmt::Debug,
     io,
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
     thread::{Builder as ThreadBuilder, JoinHandle},
+    time::{Duration, Instant},
 };
 
-pub use crate::data::{node_types::Node, rel_types::Rel};
+use chrono::{DateTime, Utc};
+
+pub use crate::{
+    backpressure::{Backpressure, BACKPRESSURE_PARAMS},
+    broadcast::ViewLag,
+    data::{node_types::Node, rel_types::Rel},
+    filter::{Filter, FilterView, FILTER_PARAMS},
+    params::{ParamDecl, ParamType, ParamValue, ViewParams},
+    replay::REPLAY_PARAMS,
+    stream::{FlushMarker, FlushWait, ViewMsg, ViewStream},
+};
+
+use crate::{
+    broadcast::{Publisher, Reader, Ring, RingHandle, RingReader, CAPACITY},
+    replay::{replay_from_params, SharedJournal},
+};
+
+mod backpressure;
+mod broadcast;
+mod filter;
+mod params;
+mod replay;
+mod stream;
 
 use quick_error::quick_error;
+use serde_derive::Serialize;
 
 mod built_info {
     #![allow(dead_code)]
@@ -34,6 +61,15 @@ quick_error! {
         MissingViewID(id: usize){
             display("No View type registered with id {}.", id)
         }
+        InlineUnsupported(name: &'static str) {
+            display("View {} does not support inline execution.", name)
+        }
+        MissingViewInst(id: usize) {
+            display("No View instance running with id {}.", id)
+        }
+        InvalidParam(name: String, msg: String) {
+            display("Invalid param {}: {}", name, msg)
+        }
         ThreadingErr(err: io::Error) {
             cause(err)
             from()
@@ -42,31 +78,46 @@ quick_error! {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum DBTr {
     CreateNode(Node),
     CreateRel(Rel),
     UpdateNode(Node),
     UpdateRel(Rel),
+    /// A barrier rather than an update, the coordinator has every view instance flush on
+    /// reaching it instead of passing it on, see `FlushMarker`
+    #[serde(skip)]
+    Flush(FlushMarker),
+    /// The wall-clock time, passed to every view instance periodically rather than being an
+    /// update, see `ViewCoordinator::with_tick_interval`
+    #[serde(skip)]
+    Tick(DateTime<Utc>),
 }
 
-pub type ViewParams = HashMap<String, Box<dyn Any>>;
-
-pub trait ViewParamsExt {
-    fn insert_param<K: ToString, V: Any>(&mut self, key: K, val: V);
-    fn get_or_def<'a>(&'a self, key: &str, def: &'a str) -> &'a str;
+/// A failure a view instance could not recover from, after which its output is incomplete
+#[derive(Clone, Debug)]
+pub struct ViewFailure {
+    pub iid: usize,
+    pub msg: String,
 }
 
-impl ViewParamsExt for ViewParams {
-    fn insert_param<K: ToString, V: Any>(&mut self, key: K, val: V) {
-        self.insert(key.to_string(), Box::new(val));
-    }
+/// Where a view instance reports failures it cannot recover from
+///
+/// Failures are always printed, and are also collected by the coordinator when the instance was
+/// created through one, see `ViewCoordinator::failures`.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorSink {
+    iid: usize,
+    send: Option<mpsc::Sender<ViewFailure>>,
+}
 
-    fn get_or_def<'a>(&'a self, key: &str, def: &'a str) -> &'a str {
-        self.get(key)
-            .and_then(|val| val.downcast_ref::<String>())
-            .map(|val| val as &str)
-            .unwrap_or(def)
+impl ErrorSink {
+    pub fn report<S: ToString>(&self, msg: S) {
+        let msg = msg.to_string();
+        eprintln!("View instance {} failed: {}", self.iid, msg);
+        if let Some(ref send) = self.send {
+            send.send(ViewFailure { iid: self.iid, msg }).ok();
+        }
     }
 }
 
@@ -93,6 +144,29 @@ impl ViewInst {
     }
 }
 
+/// A view instance that processes events synchronously on the thread driving it
+///
+/// Used by inline coordinators, see `ViewCoordinator::new_inline`, and by threaded instances
+/// driven through `ViewStream::run`.
+pub trait InlineView: Debug + Send {
+    /// Called once before any events, for setup such as opening connections
+    fn on_start(&mut self) {}
+    fn process(&mut self, evt: &DBTr);
+    /// Write out anything buffered, see `ViewCoordinator::flush_views`
+    ///
+    /// A view whose output can only be written once it has seen the whole stream, such as a
+    /// document listing every node before any edge, writes nothing here but all of it in
+    /// `on_shutdown`.
+    fn on_flush(&mut self) {}
+    /// Called by `ViewStream::run` whenever the instance has caught up with the stream, before
+    /// waiting for more, so output can be handed on promptly without flushing every event
+    fn on_idle(&mut self) {}
+    /// Called once when the instance is stopped cleanly, for writing footers and the like
+    ///
+    /// Not called if the pipeline is torn down without being shut down.
+    fn on_shutdown(&mut self) {}
+}
+
 pub trait View: Debug {
     fn new(id: usize) -> Self
     where
@@ -100,50 +174,363 @@ pub trait View: Debug {
     fn id(&self) -> usize;
     fn name(&self) -> &'static str;
     fn desc(&self) -> &'static str;
-    fn params(&self) -> HashMap<&'static str, &'static str>;
-    fn create(&self, id: usize, params: ViewParams, stream: mpsc::Receiver<Arc<DBTr>>) -> ViewInst;
+    fn params(&self) -> Vec<ParamDecl>;
+    /// Create an instance reading `stream` on its own thread
+    ///
+    /// The params have been resolved against `params`, a value the view cannot use is reported
+    /// as `ViewError::InvalidParam` rather than panicking.
+    fn create(&self, id: usize, params: ViewParams, stream: ViewStream) -> Result<ViewInst>;
+    /// Create an instance that runs on the caller's thread, if the view supports it
+    fn create_inline(&self, _id: usize, _params: &ViewParams) -> Result<Box<dyn InlineView>> {
+        Err(ViewError::InlineUnsupported(self.name()))
+    }
 }
 
 type Result<T> = std::result::Result<T, ViewError>;
 
+/// What happens to events destined for a paused view instance
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PausePolicy {
+    /// Discard events while paused, the view misses them entirely
+    Drop,
+    /// Hold events in memory and deliver them in order on resume
+    ///
+    /// The buffer is unbounded, so pausing a view for a long time will grow memory usage.
+    Buffer,
+}
+
+#[derive(Debug)]
+struct Stream<S, T> {
+    iid: usize,
+    dst: S,
+    paused: Option<PausePolicy>,
+    buffer: VecDeque<T>,
+    dropped: usize,
+    filter: Option<Filter>,
+}
+
+impl<S, T: Borrow<DBTr>> Stream<S, T> {
+    fn new(iid: usize, dst: S, filter: Option<Filter>) -> Self {
+        Stream {
+            iid,
+            dst,
+            paused: None,
+            buffer: VecDeque::new(),
+            dropped: 0,
+            filter,
+        }
+    }
+
+    /// Whether the instance should see `evt` at all, filtered events are neither buffered nor
+    /// counted as dropped
+    fn admits(&mut self, evt: &DBTr) -> bool {
+        match self.filter {
+            Some(ref mut filter) => filter.matches(evt),
+            None => true,
+        }
+    }
+
+    fn emit<F: FnMut(&mut S, T)>(&mut self, evt: T, mut f: F) {
+        if !self.admits(evt.borrow()) {
+            return;
+        }
+        match self.paused {
+            None => f(&mut self.dst, evt),
+            Some(PausePolicy::Drop) => self.dropped += 1,
+            Some(PausePolicy::Buffer) => self.buffer.push_back(evt),
+        }
+    }
+
+    fn resume<F: FnMut(&mut S, T)>(&mut self, mut f: F) {
+        self.paused = None;
+        for evt in self.buffer.drain(..) {
+            f(&mut self.dst, evt);
+        }
+        if self.dropped > 0 {
+            eprintln!(
+                "View instance {} dropped {} events while paused",
+                self.iid, self.dropped
+            );
+            self.dropped = 0;
+        }
+    }
+}
+
+type InlineStream = Stream<Box<dyn InlineView>, DBTr>;
+
+fn process(dst: &mut Box<dyn InlineView>, evt: DBTr) {
+    dst.process(&evt);
+}
+
+/// Pass events from `recv` on to threaded instances until it hangs up, with a `DBTr::Tick`
+/// every `tick` if given
+fn forward(
+    recv: &mpsc::Receiver<DBTr>,
+    ring: &Ring,
+    mut publisher: Publisher,
+    journal: &SharedJournal,
+    tick: Option<Duration>,
+) {
+    let mut next_tick = tick.map(|t| Instant::now() + t);
+    loop {
+        let evt = match (tick, next_tick) {
+            (Some(interval), Some(at)) => {
+                let now = Instant::now();
+                if now >= at {
+                    publisher.publish(Arc::new(DBTr::Tick(Utc::now())));
+                    // a stalled coordinator ticks once on recovering, not once per tick missed
+                    let mut at = at + interval;
+                    while at <= now {
+                        at += interval;
+                    }
+                    next_tick = Some(at);
+                    continue;
+                }
+                match recv.recv_timeout(at - now) {
+                    Ok(evt) => evt,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            _ => match recv.recv() {
+                Ok(evt) => evt,
+                Err(_) => return,
+            },
+        };
+        if let DBTr::Flush(marker) = evt {
+            ring.control_all(&ViewMsg::Flush(marker));
+            continue;
+        }
+        let v = Arc::new(evt);
+        if !journal.is_enabled() {
+            publisher.publish(v);
+            continue;
+        }
+        // holding the journal lock keeps new instances from missing `v`
+        let mut journal = journal.lock();
+        publisher.publish(v.clone());
+        journal.push(v);
+    }
+}
+
 #[derive(Debug)]
 pub struct ViewCoordinator {
     views: HashMap<usize, Box<dyn View>>,
     view_name_map: HashMap<&'static str, usize>,
     insts: Vec<ViewInst>,
-    streams: Arc<Mutex<Vec<mpsc::SyncSender<Arc<DBTr>>>>>,
-    thread: JoinHandle<()>,
+    inline_insts: Vec<InlineStream>,
+    ring: RingHandle,
+    journal: Arc<SharedJournal>,
+    thread: Option<JoinHandle<()>>,
+    fail_send: mpsc::Sender<ViewFailure>,
+    fail_recv: mpsc::Receiver<ViewFailure>,
     vid_gen: usize,
     viid_gen: usize,
 }
 
 impl ViewCoordinator {
     pub fn new(recv: mpsc::Receiver<DBTr>) -> Result<Self> {
-        let streams: Arc<Mutex<Vec<mpsc::SyncSender<Arc<DBTr>>>>> =
-            Arc::new(Mutex::new(Vec::new()));
-        let thread_streams = streams.clone();
-        Ok(ViewCoordinator {
-            thread: ThreadBuilder::new()
-                .name("ViewCoordinator".to_string())
-                .spawn(move || {
-                    for evt in recv {
-                        {
-                            let v = Arc::new(evt);
-                            let mut strs = thread_streams.lock().unwrap();
-                            for stream in strs.iter_mut() {
-                                stream.send(v.clone()).unwrap();
-                            }
-                            drop(v);
-                        }
-                    }
-                })?,
+        Self::with_tick_interval(recv, None)
+    }
+
+    /// Create a coordinator that also passes a `DBTr::Tick` to every threaded view instance each
+    /// `tick` if given
+    ///
+    /// Ticks are sent whether or not events are arriving, so that views aggregating events can
+    /// act on wall-clock time even when the input stalls. They are not journaled. Ticks are
+    /// subject to instance filters and pausing like any other event, and may be dropped by an
+    /// instance that has fallen behind.
+    pub fn with_tick_interval(recv: mpsc::Receiver<DBTr>, tick: Option<Duration>) -> Result<Self> {
+        let ring = RingHandle::new(CAPACITY);
+        let publisher = ring.publisher();
+        let thread_ring = ring.ring().clone();
+        let journal: Arc<SharedJournal> = Arc::default();
+        let thread_journal = journal.clone();
+        let (fail_send, fail_recv) = mpsc::channel();
+        let vc = ViewCoordinator {
+            thread: Some(
+                ThreadBuilder::new()
+                    .name("ViewCoordinator".to_string())
+                    .spawn(move || {
+                        forward(&recv, &thread_ring, publisher, &thread_journal, tick)
+                    })?,
+            ),
             views: HashMap::new(),
             view_name_map: HashMap::new(),
             insts: Vec::new(),
-            streams,
+            inline_insts: Vec::new(),
+            ring,
+            journal,
+            fail_send,
+            fail_recv,
             vid_gen: 0,
             viid_gen: 0,
-        })
+        };
+        Ok(vc.with_builtin_views())
+    }
+
+    /// Create a coordinator that runs views on the calling thread
+    ///
+    /// Events are passed to views via `process` and are fully handled by every view before it
+    /// returns, giving deterministic output for testing. Only views that implement
+    /// `View::create_inline` can be created on an inline coordinator. An inline coordinator has
+    /// no timer, ticks only reach its views when passed to `process`.
+    pub fn new_inline() -> Self {
+        let (fail_send, fail_recv) = mpsc::channel();
+        ViewCoordinator {
+            thread: None,
+            views: HashMap::new(),
+            view_name_map: HashMap::new(),
+            insts: Vec::new(),
+            inline_insts: Vec::new(),
+            ring: RingHandle::new(CAPACITY),
+            journal: Arc::default(),
+            fail_send,
+            fail_recv,
+            vid_gen: 0,
+            viid_gen: 0,
+        }
+        .with_builtin_views()
+    }
+
+    fn with_builtin_views(mut self) -> Self {
+        self.register_view_type::<FilterView>()
+            .expect("No views are registered yet");
+        self
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.thread.is_none()
+    }
+
+    /// Pass an event to each view instance of an inline coordinator
+    ///
+    /// A `DBTr::Flush` has every instance flush instead.
+    pub fn process(&mut self, evt: &DBTr) {
+        if let DBTr::Flush(_) = evt {
+            for inst in self.inline_insts.iter_mut() {
+                inst.dst.on_flush();
+            }
+            return;
+        }
+        for inst in self.inline_insts.iter_mut() {
+            if inst.paused.is_none() {
+                if inst.admits(evt) {
+                    inst.dst.process(evt);
+                }
+            } else {
+                inst.emit(evt.clone(), process);
+            }
+        }
+        if let DBTr::Tick(_) = evt {
+            return;
+        }
+        if self.journal.is_enabled() {
+            self.journal.lock().push(Arc::new(evt.clone()));
+        }
+    }
+
+    /// Keep the last `cap` events passed on, for instances created later to replay
+    ///
+    /// No journal is kept by default, a `cap` of 0 disables it again. See `REPLAY_PARAMS`.
+    pub fn set_journal_capacity(&mut self, cap: usize) {
+        self.journal.set_capacity(cap);
+    }
+
+    /// Stop passing events to a single view instance
+    ///
+    /// Other instances are unaffected. Pausing an already paused instance changes its policy,
+    /// events buffered so far are kept.
+    pub fn pause_view(&mut self, iid: usize, policy: PausePolicy) -> Result<()> {
+        if self.is_inline() {
+            let inst = self
+                .inline_insts
+                .iter_mut()
+                .find(|s| s.iid == iid)
+                .ok_or(ViewError::MissingViewInst(iid))?;
+            inst.paused = Some(policy);
+        } else {
+            let ring = self.ring.ring();
+            let reader = ring.reader(iid).ok_or(ViewError::MissingViewInst(iid))?;
+            ring.set_paused(&reader, Some(policy));
+        }
+        Ok(())
+    }
+
+    /// Resume passing events to a paused view instance, delivering any buffered events first
+    pub fn resume_view(&mut self, iid: usize) -> Result<()> {
+        if self.is_inline() {
+            let inst = self
+                .inline_insts
+                .iter_mut()
+                .find(|s| s.iid == iid)
+                .ok_or(ViewError::MissingViewInst(iid))?;
+            inst.resume(process);
+        } else {
+            let ring = self.ring.ring();
+            let reader = ring.reader(iid).ok_or(ViewError::MissingViewInst(iid))?;
+            ring.set_paused(&reader, None);
+        }
+        Ok(())
+    }
+
+    /// Ask every view instance to write out anything it has buffered
+    ///
+    /// Inline instances have flushed when this returns. Threaded instances are sent a
+    /// `ViewMsg::Flush` behind the events already passed to them, and flush once they reach it.
+    /// Paused instances flush without the events held back from them.
+    ///
+    /// Events still queued for the coordinator are not waited for, to flush behind those send it
+    /// a `DBTr::Flush` instead.
+    pub fn flush_views(&mut self) {
+        for inst in self.inline_insts.iter_mut() {
+            inst.dst.on_flush();
+        }
+        let (marker, _) = FlushMarker::new();
+        self.ring.ring().control_all(&ViewMsg::Flush(marker));
+    }
+
+    /// How far a view instance has fallen behind the events passed to it
+    ///
+    /// Inline instances handle every event as it is passed on, so never fall behind.
+    pub fn view_lag(&self, iid: usize) -> Result<ViewLag> {
+        if self.is_inline() {
+            if self.inline_insts.iter().any(|s| s.iid == iid) {
+                return Ok(ViewLag::default());
+            }
+            return Err(ViewError::MissingViewInst(iid));
+        }
+        let ring = self.ring.ring();
+        let reader = ring.reader(iid).ok_or(ViewError::MissingViewInst(iid))?;
+        Ok(ring.lag(&reader))
+    }
+
+    /// Stop a single view instance and release it
+    ///
+    /// Events buffered by a paused instance are delivered first, then the instance is shut down
+    /// as it would be with the coordinator, joining its thread if it has one. Other instances are
+    /// unaffected.
+    pub fn remove_view_inst(&mut self, iid: usize) -> Result<()> {
+        if self.is_inline() {
+            let pos = self
+                .inline_insts
+                .iter()
+                .position(|s| s.iid == iid)
+                .ok_or(ViewError::MissingViewInst(iid))?;
+            let mut inst = self.inline_insts.remove(pos);
+            inst.resume(process);
+            inst.dst.on_shutdown();
+        } else {
+            let ring = self.ring.ring();
+            let reader = ring.reader(iid).ok_or(ViewError::MissingViewInst(iid))?;
+            ring.control(&reader, ViewMsg::Shutdown);
+            if let Some(pos) = self.insts.iter().position(|v| v.id == iid) {
+                self.insts.remove(pos).join();
+            }
+            ring.remove_reader(iid);
+        }
+        Ok(())
     }
 
     pub fn register_view_type<T: View + 'static>(&mut self) -> Result<usize> {
@@ -167,18 +554,89 @@ impl ViewCoordinator {
         self.insts.iter().collect()
     }
 
-    pub fn create_view_with_id(&mut self, id: usize, params: ViewParams) -> Result<usize> {
-        if self.views.contains_key(&id) {
-            let iid = self.viid_gen;
-            self.viid_gen += 1;
-            let (w, r) = mpsc::sync_channel(1000);
-            let view = self.views[&id].create(iid, params, r);
-            self.insts.push(view);
-            self.streams.lock().unwrap().push(w);
-            Ok(iid)
+    /// Create an instance of a view type
+    ///
+    /// The params are resolved against those the view declares, see `ViewParams::resolve`.
+    /// `BACKPRESSURE_PARAMS` decide what happens when a threaded instance falls behind. With
+    /// `REPLAY_PARAMS` the instance is first delivered journaled events, before any live ones.
+    ///
+    /// Creating a `FilterView` creates an instance of the view it wraps instead, given the
+    /// params the filter does not read, which only receives the events the filter matches.
+    pub fn create_view_with_id(&mut self, id: usize, mut params: ViewParams) -> Result<usize> {
+        let view = self.views.get(&id).ok_or(ViewError::MissingViewID(id))?;
+        if view.name() != FilterView::NAME {
+            return self.create_inst(id, params, None);
+        }
+        let own = FilterView::split_params(&mut params)?;
+        let inner = own.get_str("view").unwrap();
+        let inner = *self
+            .view_name_map
+            .get(inner)
+            .ok_or_else(|| ViewError::MissingViewName(inner.to_string()))?;
+        let filter = Filter::from_params(&own)?;
+        self.create_inst(inner, params, filter)
+    }
+
+    fn create_inst(
+        &mut self,
+        id: usize,
+        mut params: ViewParams,
+        filter: Option<Filter>,
+    ) -> Result<usize> {
+        let view = self.views.get(&id).ok_or(ViewError::MissingViewID(id))?;
+        let mut decls = view.params();
+        decls.extend_from_slice(&BACKPRESSURE_PARAMS);
+        decls.extend_from_slice(&REPLAY_PARAMS);
+        params.resolve(&decls)?;
+        let (policy, cap) = Backpressure::from_params(&params)?;
+        let replay = replay_from_params(&params)?;
+        if replay.is_some() && !self.journal.is_enabled() {
+            return Err(ViewError::InvalidParam(
+                "catch_up".to_string(),
+                "no journal is being kept".to_string(),
+            ));
+        }
+        let iid = self.viid_gen;
+        params.set_error_sink(ErrorSink {
+            iid,
+            send: Some(self.fail_send.clone()),
+        });
+        if self.is_inline() {
+            let mut inst = view.create_inline(iid, &params)?;
+            inst.on_start();
+            let mut stream = Stream::new(iid, inst, filter);
+            if let Some(n) = replay {
+                for evt in self.journal.lock().recent(n) {
+                    stream.emit((**evt).clone(), process);
+                }
+            }
+            self.inline_insts.push(stream);
         } else {
-            Err(ViewError::MissingViewID(id))
+            let ring = self.ring.ring();
+            let (reader, replay) = {
+                // holding the journal lock keeps live events out until the reader is in place
+                let journal = self.journal.lock();
+                let reader = Arc::new(Reader::new(iid, policy, cap, ring.published()));
+                ring.add_reader(reader.clone());
+                let replay = match replay {
+                    Some(n) => journal.recent(n).cloned().collect(),
+                    None => VecDeque::new(),
+                };
+                (reader, replay)
+            };
+            let stream = RingReader::new(ring.clone(), reader, filter, replay);
+            let mut inst = match view.create(iid, params, ViewStream::from_ring(stream)) {
+                Ok(inst) => inst,
+                Err(e) => {
+                    ring.remove_reader(iid);
+                    return Err(e);
+                }
+            };
+            inst.params.set_error_sink(ErrorSink::default());
+            self.insts.push(inst);
         }
+        self.viid_gen += 1;
+        Ok(iid)
     }
 
     pub fn create_view_with_name(&mut self, name: &str, params: ViewParams) -> Result<usize> {
@@ -189,11 +647,648 @@ impl ViewCoordinator {
         }
     }
 
-    pub fn shutdown(self) {
-        self.thread.join().unwrap();
-        self.streams.lock().unwrap().clear();
+    /// Failures reported by view instances since the last call
+    pub fn failures(&self) -> Vec<ViewFailure> {
+        self.fail_recv.try_iter().collect()
+    }
+
+    pub fn shutdown(mut self) {
+        if let Some(thread) = self.thread {
+            thread.join().unwrap();
+        }
+        self.ring.ring().control_all(&ViewMsg::Shutdown);
         for view in self.insts {
             view.join();
         }
+        for inst in self.inline_insts.iter_mut() {
+            inst.resume(process);
+            inst.dst.on_shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{
+        node_types::{Name, NameNode},
+        HasID, ID,
+    };
+
+    use std::{cell::RefCell, sync::Mutex};
+
+    type Collected = Arc<Mutex<Vec<ID>>>;
+
+    thread_local! {
+        static COLLECTORS: RefCell<Vec<Collected>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Params for a view adding to `out`, which is passed as its index in `COLLECTORS`
+    fn out_params(out: &Collected) -> ViewParams {
+        let mut params = ViewParams::new();
+        COLLECTORS.with(|c| {
+            let mut c = c.borrow_mut();
+            params.insert_param("out", c.len() as i64);
+            c.push(out.clone());
+        });
+        params
+    }
+
+    fn collected(params: &ViewParams) -> Option<Collected> {
+        let idx = params.get_int("out")?;
+        COLLECTORS.with(|c| c.borrow().get(idx as usize).cloned())
+    }
+
+    const OUT_PARAM: ParamDecl = ParamDecl::int("out", "Index of the collector to add to");
+
+    /// Run `inst` over `stream` on its own thread, as a threaded view would
+    fn spawn_inst<V: InlineView + 'static>(
+        id: usize,
+        vtype: usize,
+        params: ViewParams,
+        stream: ViewStream,
+        mut inst: V,
+    ) -> Result<ViewInst> {
+        Ok(ViewInst {
+            id,
+            vtype,
+            params,
+            handle: ThreadBuilder::new().spawn(move || stream.run(&mut inst))?,
+        })
+    }
+
+    #[derive(Debug)]
+    struct DiscardInst;
+
+    impl InlineView for DiscardInst {
+        fn process(&mut self, _: &DBTr) {}
+    }
+
+    #[derive(Debug)]
+    struct CollectInst(Collected);
+
+    impl InlineView for CollectInst {
+        fn process(&mut self, evt: &DBTr) {
+            if let DBTr::CreateNode(n) = evt {
+                self.0.lock().unwrap().push(n.get_db_id());
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct CollectView(usize);
+
+    impl View for CollectView {
+        fn new(id: usize) -> Self {
+            CollectView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "CollectView"
+        }
+        fn desc(&self) -> &'static str {
+            "Collects created node ids."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![OUT_PARAM]
+        }
+        fn create(&self, id: usize, params: ViewParams, r: ViewStream) -> Result<ViewInst> {
+            let inst = CollectInst(collected(&params).unwrap());
+            spawn_inst(id, self.0, params, r, inst)
+        }
+        fn create_inline(&self, _: usize, params: &ViewParams) -> Result<Box<dyn InlineView>> {
+            Ok(Box::new(CollectInst(collected(params).unwrap())))
+        }
+    }
+
+    #[derive(Debug)]
+    struct ThreadedView(usize);
+
+    impl View for ThreadedView {
+        fn new(id: usize) -> Self {
+            ThreadedView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "ThreadedView"
+        }
+        fn desc(&self) -> &'static str {
+            "Only runs threaded."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![]
+        }
+        fn create(&self, id: usize, params: ViewParams, r: ViewStream) -> Result<ViewInst> {
+            spawn_inst(id, self.0, params, r, DiscardInst)
+        }
+    }
+
+    #[derive(Debug)]
+    struct RejectingView(usize);
+
+    impl View for RejectingView {
+        fn new(id: usize) -> Self {
+            RejectingView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "RejectingView"
+        }
+        fn desc(&self) -> &'static str {
+            "Rejects every set of params."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![]
+        }
+        fn create(&self, _: usize, _: ViewParams, _: ViewStream) -> Result<ViewInst> {
+            Err(ViewError::InvalidParam(
+                "output".to_string(),
+                "unavailable".to_string(),
+            ))
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingInst(ErrorSink);
+
+    impl InlineView for FailingInst {
+        fn process(&mut self, _: &DBTr) {
+            self.0.report("output unavailable");
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingView(usize);
+
+    impl View for FailingView {
+        fn new(id: usize) -> Self {
+            FailingView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "FailingView"
+        }
+        fn desc(&self) -> &'static str {
+            "Reports a failure for every event."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![]
+        }
+        fn create(&self, id: usize, params: ViewParams, r: ViewStream) -> Result<ViewInst> {
+            let inst = FailingInst(params.error_sink());
+            spawn_inst(id, self.0, params, r, inst)
+        }
+        fn create_inline(&self, _: usize, params: &ViewParams) -> Result<Box<dyn InlineView>> {
+            Ok(Box::new(FailingInst(params.error_sink())))
+        }
+    }
+
+    fn name_node(id: u64) -> DBTr {
+        DBTr::CreateNode(Node::Name(NameNode::generate(
+            ID::new(id),
+            Name::Path(format!("/tmp/{}", id)),
+        )))
+    }
+
+    #[test]
+    fn inline_processing_is_immediate() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<CollectView>().unwrap();
+        for _ in 0..2 {
+            let params = out_params(&out);
+            vc.create_view_with_name("CollectView", params).unwrap();
+        }
+        vc.process(&name_node(1));
+        assert_eq!(*out.lock().unwrap(), vec![ID::new(1), ID::new(1)]);
+        vc.process(&name_node(2));
+        assert_eq!(out.lock().unwrap().len(), 4);
+        vc.shutdown();
+    }
+
+    fn collect_view(out: &Collected) -> (ViewCoordinator, usize) {
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<CollectView>().unwrap();
+        let params = out_params(out);
+        let iid = vc.create_view_with_name("CollectView", params).unwrap();
+        (vc, iid)
+    }
+
+    #[test]
+    fn pause_drop() {
+        let out = Collected::default();
+        let (mut vc, iid) = collect_view(&out);
+        vc.pause_view(iid, PausePolicy::Drop).unwrap();
+        vc.process(&name_node(1));
+        vc.resume_view(iid).unwrap();
+        vc.process(&name_node(2));
+        assert_eq!(*out.lock().unwrap(), vec![ID::new(2)]);
+    }
+
+    #[test]
+    fn pause_buffer() {
+        let out = Collected::default();
+        let (mut vc, iid) = collect_view(&out);
+        vc.pause_view(iid, PausePolicy::Buffer).unwrap();
+        vc.process(&name_node(1));
+        vc.process(&name_node(2));
+        assert!(out.lock().unwrap().is_empty());
+        vc.resume_view(iid).unwrap();
+        vc.process(&name_node(3));
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![ID::new(1), ID::new(2), ID::new(3)]
+        );
+    }
+
+    #[test]
+    fn pause_missing_inst() {
+        let mut vc = ViewCoordinator::new_inline();
+        match vc.pause_view(0, PausePolicy::Drop) {
+            Err(ViewError::MissingViewInst(0)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    /// Ids recorded by `HookInst` for its lifecycle callbacks
+    const STARTED: u64 = 1000;
+    const FLUSHED: u64 = 1001;
+    const SHUT_DOWN: u64 = 1002;
+    const TICKED: u64 = 1003;
+
+    #[derive(Debug)]
+    struct HookInst(Collected);
+
+    impl HookInst {
+        fn record(&self, id: u64) {
+            self.0.lock().unwrap().push(ID::new(id));
+        }
+    }
+
+    impl InlineView for HookInst {
+        fn on_start(&mut self) {
+            self.record(STARTED);
+        }
+
+        fn process(&mut self, evt: &DBTr) {
+            match evt {
+                DBTr::CreateNode(n) => self.record(n.get_db_id().inner()),
+                DBTr::Tick(_) => self.record(TICKED),
+                _ => {}
+            }
+        }
+
+        fn on_flush(&mut self) {
+            self.record(FLUSHED);
+        }
+
+        fn on_shutdown(&mut self) {
+            self.record(SHUT_DOWN);
+        }
+    }
+
+    #[derive(Debug)]
+    struct HookView(usize);
+
+    impl View for HookView {
+        fn new(id: usize) -> Self {
+            HookView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "HookView"
+        }
+        fn desc(&self) -> &'static str {
+            "Records its lifecycle callbacks."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![OUT_PARAM]
+        }
+        fn create(&self, id: usize, params: ViewParams, stream: ViewStream) -> Result<ViewInst> {
+            let mut inst = HookInst(collected(&params).unwrap());
+            Ok(ViewInst {
+                id,
+                vtype: self.0,
+                params,
+                handle: std::thread::spawn(move || stream.run(&mut inst)),
+            })
+        }
+        fn create_inline(&self, _: usize, params: &ViewParams) -> Result<Box<dyn InlineView>> {
+            Ok(Box::new(HookInst(collected(params).unwrap())))
+        }
+    }
+
+    fn ids(out: &Collected) -> Vec<u64> {
+        out.lock().unwrap().iter().map(|id| id.inner()).collect()
+    }
+
+    #[test]
+    fn inline_lifecycle() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<HookView>().unwrap();
+        vc.create_view_with_name("HookView", out_params(&out))
+            .unwrap();
+        assert_eq!(ids(&out), vec![STARTED]);
+        vc.process(&name_node(1));
+        vc.flush_views();
+        assert_eq!(ids(&out), vec![STARTED, 1, FLUSHED]);
+        vc.shutdown();
+        assert_eq!(ids(&out), vec![STARTED, 1, FLUSHED, SHUT_DOWN]);
+    }
+
+    #[test]
+    fn threaded_lifecycle() {
+        let (send, recv) = mpsc::channel();
+        let mut vc = ViewCoordinator::new(recv).unwrap();
+        vc.register_view_type::<HookView>().unwrap();
+        let out = Collected::default();
+        vc.create_view_with_name("HookView", out_params(&out))
+            .unwrap();
+        vc.flush_views();
+        send.send(name_node(1)).unwrap();
+        drop(send);
+        vc.shutdown();
+        assert_eq!(ids(&out), vec![STARTED, FLUSHED, 1, SHUT_DOWN]);
+    }
+
+    #[test]
+    fn flush_barrier() {
+        let (send, recv) = mpsc::channel();
+        let mut vc = ViewCoordinator::new(recv).unwrap();
+        vc.register_view_type::<HookView>().unwrap();
+        let out = Collected::default();
+        for _ in 0..2 {
+            vc.create_view_with_name("HookView", out_params(&out))
+                .unwrap();
+        }
+        let (marker, wait) = FlushMarker::new();
+        send.send(name_node(1)).unwrap();
+        send.send(DBTr::Flush(marker)).unwrap();
+        wait.wait();
+        let mut seen = ids(&out);
+        seen.sort();
+        assert_eq!(seen, vec![1, 1, STARTED, STARTED, FLUSHED, FLUSHED]);
+        drop(send);
+        vc.shutdown();
+    }
+
+    #[test]
+    fn ticks_while_idle() {
+        let (send, recv) = mpsc::channel();
+        let tick = Some(Duration::from_millis(5));
+        let mut vc = ViewCoordinator::with_tick_interval(recv, tick).unwrap();
+        vc.register_view_type::<HookView>().unwrap();
+        let out = Collected::default();
+        vc.create_view_with_name("HookView", out_params(&out))
+            .unwrap();
+        while ids(&out).iter().filter(|&&id| id == TICKED).count() < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(send);
+        vc.shutdown();
+    }
+
+    #[test]
+    fn inline_flush_barrier() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<HookView>().unwrap();
+        vc.create_view_with_name("HookView", out_params(&out))
+            .unwrap();
+        vc.process(&DBTr::Flush(FlushMarker::new().0));
+        assert_eq!(ids(&out), vec![STARTED, FLUSHED]);
+    }
+
+    #[test]
+    fn remove_inline_inst() {
+        let out = Collected::default();
+        let (mut vc, iid) = collect_view(&out);
+        vc.register_view_type::<HookView>().unwrap();
+        let finished = Collected::default();
+        let params = out_params(&finished);
+        let fin = vc.create_view_with_name("HookView", params).unwrap();
+
+        vc.pause_view(iid, PausePolicy::Buffer).unwrap();
+        vc.process(&name_node(1));
+        vc.remove_view_inst(iid).unwrap();
+        vc.process(&name_node(2));
+        assert_eq!(*out.lock().unwrap(), vec![ID::new(1)]);
+
+        vc.remove_view_inst(fin).unwrap();
+        assert_eq!(ids(&finished), vec![STARTED, 1, 2, SHUT_DOWN]);
+        match vc.remove_view_inst(fin) {
+            Err(ViewError::MissingViewInst(i)) if i == fin => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        vc.shutdown();
+        assert_eq!(finished.lock().unwrap().len(), 4);
+    }
+
+    #[derive(Debug)]
+    struct ThreadedCollectView(usize);
+
+    impl View for ThreadedCollectView {
+        fn new(id: usize) -> Self {
+            ThreadedCollectView(id)
+        }
+        fn id(&self) -> usize {
+            self.0
+        }
+        fn name(&self) -> &'static str {
+            "ThreadedCollectView"
+        }
+        fn desc(&self) -> &'static str {
+            "Collects created node ids on its own thread."
+        }
+        fn params(&self) -> Vec<ParamDecl> {
+            vec![OUT_PARAM]
+        }
+        fn create(&self, id: usize, params: ViewParams, r: ViewStream) -> Result<ViewInst> {
+            let mut inst = CollectInst(collected(&params).unwrap());
+            Ok(ViewInst {
+                id,
+                vtype: self.0,
+                params,
+                handle: std::thread::spawn(move || {
+                    for evt in r {
+                        inst.process(&evt);
+                    }
+                }),
+            })
+        }
+    }
+
+    #[test]
+    fn remove_threaded_inst() {
+        let (send, recv) = mpsc::channel();
+        let mut vc = ViewCoordinator::new(recv).unwrap();
+        vc.register_view_type::<ThreadedCollectView>().unwrap();
+        let out = Collected::default();
+        let params = out_params(&out);
+        let iid = vc
+            .create_view_with_name("ThreadedCollectView", params)
+            .unwrap();
+        vc.pause_view(iid, PausePolicy::Buffer).unwrap();
+        send.send(name_node(1)).unwrap();
+        // read, but held back while paused
+        while vc.ring.ring().published() == 0 || vc.view_lag(iid).unwrap().behind > 0 {
+            std::thread::yield_now();
+        }
+        assert!(out.lock().unwrap().is_empty());
+        vc.remove_view_inst(iid).unwrap();
+        // the thread has been joined, so everything it was sent has been seen
+        assert_eq!(*out.lock().unwrap(), vec![ID::new(1)]);
+        assert!(vc.list_view_insts().is_empty());
+        send.send(name_node(2)).unwrap();
+        drop(send);
+        vc.shutdown();
+        assert_eq!(out.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejected_params_leave_no_reader() {
+        let (send, recv) = mpsc::channel();
+        let mut vc = ViewCoordinator::new(recv).unwrap();
+        vc.register_view_type::<RejectingView>().unwrap();
+        match vc.create_view_with_name("RejectingView", ViewParams::new()) {
+            Err(ViewError::InvalidParam(name, _)) if name == "output" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(vc.ring.ring().reader(0).is_none());
+        assert!(vc.list_view_insts().is_empty());
+        drop(send);
+        vc.shutdown();
+    }
+
+    #[test]
+    fn replay_journaled_events() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<CollectView>().unwrap();
+        let mut params = ViewParams::new();
+        params.insert_param("catch_up", "all".to_string());
+        match vc.create_view_with_name("CollectView", params) {
+            Err(ViewError::InvalidParam(name, _)) if name == "catch_up" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        vc.set_journal_capacity(2);
+        for id in 1..=3 {
+            vc.process(&name_node(id));
+        }
+        // not journaled, so does not push out the events before it
+        vc.process(&DBTr::Tick(Utc::now()));
+        for replay in &["all", "1"] {
+            let mut params = out_params(&out);
+            params.insert_param("catch_up", replay.to_string());
+            vc.create_view_with_name("CollectView", params).unwrap();
+        }
+        vc.process(&name_node(4));
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![2, 3, 3, 4, 4]
+                .into_iter()
+                .map(ID::new)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn inline_unsupported() {
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<ThreadedView>().unwrap();
+        match vc.create_view_with_name("ThreadedView", ViewParams::new()) {
+            Err(ViewError::InlineUnsupported("ThreadedView")) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn filter_view_restricts_delivery() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<CollectView>().unwrap();
+        let mut params = out_params(&out);
+        params.insert_param("view", "CollectView");
+        params.insert_param("filter_types", "net".to_string());
+        vc.create_view_with_name("FilterView", params).unwrap();
+        vc.process(&name_node(1));
+        vc.process(&DBTr::CreateNode(Node::Name(NameNode::generate(
+            ID::new(2),
+            Name::Net("10.0.0.1".to_string(), 80),
+        ))));
+        assert_eq!(*out.lock().unwrap(), vec![ID::new(2)]);
+
+        let mut params = ViewParams::new();
+        params.insert_param("view", "CollectView");
+        params.insert_param("filter_after", "yesterday".to_string());
+        match vc.create_view_with_name("FilterView", params) {
+            Err(ViewError::InvalidParam(name, _)) if name == "filter_after" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // filter params are only read by the filter
+        let mut params = ViewParams::new();
+        params.insert_param("filter_types", "net".to_string());
+        match vc.create_view_with_name("CollectView", params) {
+            Err(ViewError::InvalidParam(name, _)) if name == "filter_types" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        for inner in &[None, Some("FilterView"), Some("NoSuchView")] {
+            let mut params = ViewParams::new();
+            params.insert_param("filter_types", "net".to_string());
+            if let Some(inner) = inner {
+                params.insert_param("view", *inner);
+            }
+            assert!(vc.create_view_with_name("FilterView", params).is_err());
+        }
+    }
+
+    #[test]
+    fn undeclared_params_rejected() {
+        let out = Collected::default();
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<CollectView>().unwrap();
+        let mut params = out_params(&out);
+        params.insert_param("filter_type", "net".to_string());
+        match vc.create_view_with_name("CollectView", params) {
+            Err(ViewError::InvalidParam(name, _)) if name == "filter_type" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        vc.process(&name_node(1));
+        assert!(out.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failures_are_collected() {
+        let mut vc = ViewCoordinator::new_inline();
+        vc.register_view_type::<FailingView>().unwrap();
+        let paused = vc
+            .create_view_with_name("FailingView", ViewParams::new())
+            .unwrap();
+        let iid = vc
+            .create_view_with_name("FailingView", ViewParams::new())
+            .unwrap();
+        assert!(vc.failures().is_empty());
+        vc.pause_view(paused, PausePolicy::Drop).unwrap();
+        vc.process(&name_node(1));
+        let failures = vc.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].iid, iid);
+        assert_eq!(failures[0].msg, "output unavailable");
+        assert!(vc.failures().is_empty());
     }
 }
@@ -0,0 +1,379 @@
+//! Typed view parameters
+//!
+//! Each view declares the params it reads as `ParamDecl`s, giving the type of value each takes
+//! and the default used when it is not given. Params usually arrive as strings, from the command
+//! line or the C API, and are parsed into a `ParamValue` of the declared type when an instance
+//! is created, so a malformed value is rejected up front rather than read as the default.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{ErrorSink, ViewError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    Str,
+    Int,
+    Bool,
+    Path,
+    /// Comma separated values
+    List,
+}
+
+impl ParamType {
+    pub fn parse(self, val: &str) -> Result<ParamValue, String> {
+        Ok(match self {
+            ParamType::Str => ParamValue::Str(val.to_string()),
+            ParamType::Int => ParamValue::Int(
+                val.parse()
+                    .map_err(|_| format!("expected an integer, not {}", val))?,
+            ),
+            ParamType::Bool => match val {
+                "true" => ParamValue::Bool(true),
+                "false" => ParamValue::Bool(false),
+                _ => return Err(format!("expected true or false, not {}", val)),
+            },
+            ParamType::Path => ParamValue::Path(PathBuf::from(val)),
+            ParamType::List => ParamValue::List(
+                val.split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(String::from)
+                    .collect(),
+            ),
+        })
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ParamType::Str => "string",
+                ParamType::Int => "integer",
+                ParamType::Bool => "boolean",
+                ParamType::Path => "path",
+                ParamType::List => "list",
+            }
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Path(PathBuf),
+    List(Vec<String>),
+}
+
+impl ParamValue {
+    pub fn ty(&self) -> ParamType {
+        match self {
+            ParamValue::Str(_) => ParamType::Str,
+            ParamValue::Int(_) => ParamType::Int,
+            ParamValue::Bool(_) => ParamType::Bool,
+            ParamValue::Path(_) => ParamType::Path,
+            ParamValue::List(_) => ParamType::List,
+        }
+    }
+}
+
+/// Formats the value as it would be given as a string
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamValue::Str(v) => write!(f, "{}", v),
+            ParamValue::Int(v) => write!(f, "{}", v),
+            ParamValue::Bool(v) => write!(f, "{}", v),
+            ParamValue::Path(v) => write!(f, "{}", v.display()),
+            ParamValue::List(v) => write!(f, "{}", v.join(",")),
+        }
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(val: String) -> Self {
+        ParamValue::Str(val)
+    }
+}
+
+impl From<&str> for ParamValue {
+    fn from(val: &str) -> Self {
+        ParamValue::Str(val.to_string())
+    }
+}
+
+impl From<i64> for ParamValue {
+    fn from(val: i64) -> Self {
+        ParamValue::Int(val)
+    }
+}
+
+impl From<bool> for ParamValue {
+    fn from(val: bool) -> Self {
+        ParamValue::Bool(val)
+    }
+}
+
+impl From<PathBuf> for ParamValue {
+    fn from(val: PathBuf) -> Self {
+        ParamValue::Path(val)
+    }
+}
+
+impl From<Vec<String>> for ParamValue {
+    fn from(val: Vec<String>) -> Self {
+        ParamValue::List(val)
+    }
+}
+
+/// A param read by a view, as listed by `View::params`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamDecl {
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub ty: ParamType,
+    /// The value used when the param is not given, in its string form
+    pub default: Option<&'static str>,
+}
+
+impl ParamDecl {
+    pub const fn new(name: &'static str, ty: ParamType, desc: &'static str) -> Self {
+        ParamDecl {
+            name,
+            desc,
+            ty,
+            default: None,
+        }
+    }
+
+    pub const fn str(name: &'static str, desc: &'static str) -> Self {
+        ParamDecl::new(name, ParamType::Str, desc)
+    }
+
+    pub const fn int(name: &'static str, desc: &'static str) -> Self {
+        ParamDecl::new(name, ParamType::Int, desc)
+    }
+
+    pub const fn bool(name: &'static str, desc: &'static str) -> Self {
+        ParamDecl::new(name, ParamType::Bool, desc)
+    }
+
+    pub const fn path(name: &'static str, desc: &'static str) -> Self {
+        ParamDecl::new(name, ParamType::Path, desc)
+    }
+
+    pub const fn list(name: &'static str, desc: &'static str) -> Self {
+        ParamDecl::new(name, ParamType::List, desc)
+    }
+
+    pub const fn default(self, default: &'static str) -> Self {
+        ParamDecl {
+            default: Some(default),
+            ..self
+        }
+    }
+}
+
+/// The params a view instance is created with
+///
+/// Once resolved against the view's declarations, see `resolve`, every declared param given or
+/// defaulted holds a value of its declared type. Reading a param as another type is a bug in
+/// the view and panics.
+#[derive(Clone, Debug, Default)]
+pub struct ViewParams {
+    values: HashMap<String, ParamValue>,
+    errors: ErrorSink,
+}
+
+impl ViewParams {
+    pub fn new() -> Self {
+        ViewParams::default()
+    }
+
+    pub fn insert_param<K: ToString, V: Into<ParamValue>>(&mut self, key: K, val: V) {
+        self.values.insert(key.to_string(), val.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ParamValue> {
+        self.values.get(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<ParamValue> {
+        self.values.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ParamValue)> {
+        self.values.iter().map(|(k, v)| (&k[..], v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn mismatch(key: &str, ty: ParamType, val: &ParamValue) -> ! {
+        panic!("Param {} is of type {}, not {}", key, val.ty(), ty)
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            ParamValue::Str(v) => Some(v),
+            v => ViewParams::mismatch(key, ParamType::Str, v),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            ParamValue::Int(v) => Some(*v),
+            v => ViewParams::mismatch(key, ParamType::Int, v),
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            ParamValue::Bool(v) => Some(*v),
+            v => ViewParams::mismatch(key, ParamType::Bool, v),
+        }
+    }
+
+    pub fn get_path(&self, key: &str) -> Option<&Path> {
+        match self.get(key)? {
+            ParamValue::Path(v) => Some(v),
+            v => ViewParams::mismatch(key, ParamType::Path, v),
+        }
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<&[String]> {
+        match self.get(key)? {
+            ParamValue::List(v) => Some(v),
+            v => ViewParams::mismatch(key, ParamType::List, v),
+        }
+    }
+
+    /// The sink the instance should report failures to, see `ErrorSink`
+    pub fn error_sink(&self) -> ErrorSink {
+        self.errors.clone()
+    }
+
+    pub(crate) fn set_error_sink(&mut self, errors: ErrorSink) {
+        self.errors = errors;
+    }
+
+    /// Parse string values into the types they are declared with and fill in defaults
+    ///
+    /// An empty string is taken as the param not being given. A param without a declaration is
+    /// rejected, so that a misspelt one is not quietly ignored.
+    pub fn resolve(&mut self, decls: &[ParamDecl]) -> Result<(), ViewError> {
+        let unknown = self
+            .values
+            .keys()
+            .filter(|key| decls.iter().all(|decl| decl.name != *key))
+            .min();
+        if let Some(key) = unknown {
+            let names: Vec<_> = decls.iter().map(|decl| decl.name).collect();
+            return Err(ViewError::InvalidParam(
+                key.clone(),
+                format!(
+                    "not a param of this view, expected one of {}",
+                    names.join(", ")
+                ),
+            ));
+        }
+        for decl in decls {
+            if let Some(ParamValue::Str(v)) = self.values.get(decl.name) {
+                if v.is_empty() {
+                    self.values.remove(decl.name);
+                }
+            }
+            let val = match self.values.remove(decl.name) {
+                Some(ParamValue::Str(v)) => decl.ty.parse(&v),
+                Some(v) if v.ty() == decl.ty => Ok(v),
+                Some(v) => Err(format!("expected type {}, not {}", decl.ty, v.ty())),
+                None => match decl.default {
+                    Some(def) => decl.ty.parse(def),
+                    None => continue,
+                },
+            };
+            let val = val.map_err(|e| ViewError::InvalidParam(decl.name.to_string(), e))?;
+            self.values.insert(decl.name.to_string(), val);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECLS: [ParamDecl; 4] = [
+        ParamDecl::int("batch", "Batch size").default("100"),
+        ParamDecl::bool("tls", "Use TLS"),
+        ParamDecl::list("ops", "Operations"),
+        ParamDecl::path("output", "Output file").default("./out"),
+    ];
+
+    #[test]
+    fn resolve_parses_and_defaults() {
+        let mut p = ViewParams::new();
+        p.insert_param("tls", "true");
+        p.insert_param("ops", "Source, Sink");
+        p.insert_param("output", "");
+        p.resolve(&DECLS).unwrap();
+        assert_eq!(p.get_int("batch"), Some(100));
+        assert_eq!(p.get_bool("tls"), Some(true));
+        assert_eq!(
+            p.get_list("ops"),
+            Some(&["Source".to_string(), "Sink".to_string()][..])
+        );
+        assert_eq!(p.get_path("output"), Some(Path::new("./out")));
+        assert_eq!(p.get_str("missing"), None);
+    }
+
+    #[test]
+    fn resolve_rejects_malformed() {
+        let mut p = ViewParams::new();
+        p.insert_param("batch", "lots");
+        match p.resolve(&DECLS) {
+            Err(ViewError::InvalidParam(name, _)) if name == "batch" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut p = ViewParams::new();
+        p.insert_param("tls", 1i64);
+        match p.resolve(&DECLS) {
+            Err(ViewError::InvalidParam(name, _)) if name == "tls" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn resolve_rejects_undeclared() {
+        let mut p = ViewParams::new();
+        p.insert_param("batch", "10");
+        p.insert_param("outptu", "./elsewhere");
+        match p.resolve(&DECLS) {
+            Err(ViewError::InvalidParam(name, msg)) if name == "outptu" => {
+                assert!(msg.ends_with("batch, tls, ops, output"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Param batch is of type integer, not string")]
+    fn mistyped_read_panics() {
+        let mut p = ViewParams::new();
+        p.resolve(&DECLS).unwrap();
+        p.get_str("batch");
+    }
+}
@@ -0,0 +1,97 @@
+//! Catching up view instances created after ingestion has started
+//!
+//! A coordinator can journal the most recent events it has passed on, see
+//! `ViewCoordinator::set_journal_capacity`. An instance created with the `catch_up` param is then
+//! delivered the journaled events before any live ones.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use crate::{DBTr, ParamDecl, ViewError, ViewParams};
+
+/// The params read by `replay_from_params`, accepted by every view
+pub const REPLAY_PARAMS: [ParamDecl; 1] = [ParamDecl::str(
+    "catch_up",
+    "Deliver events from before the view was created first, either all those journaled or the \
+     last N",
+)];
+
+/// How many journaled events an instance asked to be replayed, if any
+pub(crate) fn replay_from_params(params: &ViewParams) -> Result<Option<usize>, ViewError> {
+    match params.get_str("catch_up") {
+        None => Ok(None),
+        Some("all") => Ok(Some(usize::MAX)),
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|e| ViewError::InvalidParam("catch_up".to_string(), format!("{}", e))),
+    }
+}
+
+/// A ring buffer of the most recent events
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    cap: usize,
+    events: VecDeque<Arc<DBTr>>,
+}
+
+impl Journal {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.cap > 0
+    }
+
+    /// Change the number of events kept, discarding the oldest if there are now too many
+    pub(crate) fn set_capacity(&mut self, cap: usize) {
+        self.cap = cap;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.events.len() > self.cap {
+            self.events.pop_front();
+        }
+    }
+
+    pub(crate) fn push(&mut self, evt: Arc<DBTr>) {
+        if self.is_enabled() {
+            self.events.push_back(evt);
+            self.trim();
+        }
+    }
+
+    /// The last `n` events, oldest first
+    pub(crate) fn recent(&self, n: usize) -> impl Iterator<Item = &Arc<DBTr>> {
+        self.events.iter().skip(self.events.len().saturating_sub(n))
+    }
+}
+
+/// A `Journal` shared between the coordinator and its forwarding thread
+///
+/// Whether it is enabled can be read without taking the lock, so that events need not lock it
+/// while no journal is kept.
+#[derive(Debug, Default)]
+pub(crate) struct SharedJournal {
+    enabled: AtomicBool,
+    journal: Mutex<Journal>,
+}
+
+impl SharedJournal {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Journal> {
+        self.journal.lock().unwrap()
+    }
+
+    pub(crate) fn set_capacity(&self, cap: usize) {
+        let mut journal = self.lock();
+        journal.set_capacity(cap);
+        self.enabled.store(journal.is_enabled(), Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,281 @@
+//! The stream of events a threaded view instance reads
+//!
+//! Besides events, the coordinator sends an instance a `ViewMsg::Flush` when asked to flush
+//! every view, or on reaching a `DBTr::Flush` in the event stream, and a `ViewMsg::Shutdown`
+//! when the instance is stopped cleanly, either by `ViewCoordinator::shutdown` or
+//! `ViewCoordinator::remove_view_inst`. If the stream ends without a `Shutdown`, the pipeline was
+//! torn down without finishing.
+//!
+//! A coordinator created with a tick interval also sends `DBTr::Tick` events, which are read
+//! like any other event.
+//!
+//! Iterating a `ViewStream` yields only the events, ending at shutdown, so a view can read it
+//! like a plain channel. A flush read past that way is acknowledged once every event before it
+//! has been handed out, without the view writing anything out, so views that buffer output hand
+//! an `InlineView` to `ViewStream::run` instead, which calls its lifecycle callbacks.
+
+use std::sync::{
+    mpsc::{channel, Receiver, RecvError, Sender, TryRecvError},
+    Arc,
+};
+
+use crate::{
+    broadcast::{Poll, RingReader},
+    DBTr, InlineView,
+};
+
+/// A point in the event stream that view instances acknowledge reaching
+///
+/// Each instance is handed its own clone, which acknowledges the marker when dropped, after the
+/// instance has flushed.
+#[derive(Clone, Debug)]
+pub struct FlushMarker {
+    _ack: Sender<()>,
+}
+
+impl FlushMarker {
+    /// A marker, and the means to wait for every clone of it to be acknowledged
+    pub fn new() -> (FlushMarker, FlushWait) {
+        let (send, recv) = channel();
+        (FlushMarker { _ack: send }, FlushWait(recv))
+    }
+}
+
+#[derive(Debug)]
+pub struct FlushWait(Receiver<()>);
+
+impl FlushWait {
+    /// Block until the marker and all its clones have been dropped
+    pub fn wait(self) {
+        while self.0.recv().is_ok() {}
+    }
+}
+
+/// A message sent to a threaded view instance
+#[derive(Clone, Debug)]
+pub enum ViewMsg {
+    Event(Arc<DBTr>),
+    /// Write out anything buffered, see `InlineView::on_flush`
+    Flush(FlushMarker),
+    /// The instance is being stopped cleanly, nothing follows
+    Shutdown,
+}
+
+#[derive(Debug)]
+enum Source {
+    Chan(Receiver<ViewMsg>),
+    Ring(Box<RingReader>),
+}
+
+#[derive(Debug)]
+pub struct ViewStream {
+    src: Source,
+    shut_down: bool,
+}
+
+impl ViewStream {
+    pub fn new(recv: Receiver<ViewMsg>) -> Self {
+        ViewStream {
+            src: Source::Chan(recv),
+            shut_down: false,
+        }
+    }
+
+    /// A stream reading from the coordinator's ring, see `broadcast`
+    pub(crate) fn from_ring(reader: RingReader) -> Self {
+        ViewStream {
+            src: Source::Ring(Box::new(reader)),
+            shut_down: false,
+        }
+    }
+
+    fn next_msg(&mut self, block: bool) -> Result<ViewMsg, TryRecvError> {
+        match self.src {
+            Source::Chan(ref recv) if block => recv.recv().map_err(|_| TryRecvError::Disconnected),
+            Source::Chan(ref recv) => recv.try_recv(),
+            Source::Ring(ref mut reader) => match reader.poll(block) {
+                Poll::Msg(msg) => Ok(msg),
+                Poll::Empty => Err(TryRecvError::Empty),
+                Poll::Ended => Err(TryRecvError::Disconnected),
+            },
+        }
+    }
+
+    /// Whether the stream ended with a `ViewMsg::Shutdown`
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+
+    fn read(&mut self, block: bool) -> Result<ViewMsg, TryRecvError> {
+        if self.shut_down {
+            return Err(TryRecvError::Disconnected);
+        }
+        let msg = self.next_msg(block)?;
+        if let ViewMsg::Shutdown = msg {
+            self.shut_down = true;
+        }
+        Ok(msg)
+    }
+
+    /// The next message, blocking until one arrives, or `None` once the stream has ended
+    pub fn recv_msg(&mut self) -> Option<ViewMsg> {
+        self.read(true).ok()
+    }
+
+    /// The next event, skipping flushes, see the module docs
+    pub fn recv(&mut self) -> Result<Arc<DBTr>, RecvError> {
+        loop {
+            match self.recv_msg() {
+                Some(ViewMsg::Event(evt)) => return Ok(evt),
+                Some(ViewMsg::Flush(_)) => {}
+                Some(ViewMsg::Shutdown) | None => return Err(RecvError),
+            }
+        }
+    }
+
+    /// The next event if one is waiting, skipping flushes
+    pub fn try_recv(&mut self) -> Result<Arc<DBTr>, TryRecvError> {
+        if self.shut_down {
+            return Err(TryRecvError::Disconnected);
+        }
+        loop {
+            match self.next_msg(false)? {
+                ViewMsg::Event(evt) => return Ok(evt),
+                ViewMsg::Flush(_) => {}
+                ViewMsg::Shutdown => {
+                    self.shut_down = true;
+                    return Err(TryRecvError::Disconnected);
+                }
+            }
+        }
+    }
+
+    /// Drive `inst` with the messages of the stream until it ends
+    ///
+    /// `on_start` is called before anything else, and `on_shutdown` only if the stream ends with
+    /// a `ViewMsg::Shutdown`. Flushes are acknowledged once `on_flush` returns. `on_idle` is
+    /// called each time no message is waiting.
+    pub fn run<V: InlineView + ?Sized>(mut self, inst: &mut V) {
+        inst.on_start();
+        loop {
+            let msg = match self.read(false) {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => {
+                    inst.on_idle();
+                    match self.read(true) {
+                        Ok(msg) => msg,
+                        Err(_) => break,
+                    }
+                }
+                Err(TryRecvError::Disconnected) => break,
+            };
+            match msg {
+                ViewMsg::Event(evt) => inst.process(&evt),
+                ViewMsg::Flush(_marker) => inst.on_flush(),
+                ViewMsg::Shutdown => inst.on_shutdown(),
+            }
+        }
+    }
+}
+
+impl Iterator for ViewStream {
+    type Item = Arc<DBTr>;
+
+    fn next(&mut self) -> Option<Arc<DBTr>> {
+        self.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{sync::mpsc::channel, thread, time::Duration};
+
+    use crate::data::{
+        node_types::{Name, NameNode, Node},
+        ID,
+    };
+
+    fn evt() -> ViewMsg {
+        ViewMsg::Event(Arc::new(DBTr::CreateNode(Node::Name(NameNode::generate(
+            ID::new(1),
+            Name::Path("/tmp/1".to_string()),
+        )))))
+    }
+
+    #[test]
+    fn iterating_skips_flushes_and_stops_at_shutdown() {
+        let (w, r) = channel();
+        let (marker, wait) = FlushMarker::new();
+        for msg in [
+            evt(),
+            ViewMsg::Flush(marker),
+            evt(),
+            ViewMsg::Shutdown,
+            evt(),
+        ] {
+            w.send(msg).unwrap();
+        }
+        let mut stream = ViewStream::new(r);
+        assert!(stream.next().is_some());
+        assert!(stream.next().is_some());
+        // passing the flush acknowledged it
+        wait.wait();
+        assert!(stream.next().is_none());
+        assert!(stream.is_shut_down());
+        assert!(stream.try_recv().is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct Calls(Vec<&'static str>);
+
+    impl InlineView for Calls {
+        fn on_start(&mut self) {
+            self.0.push("start");
+        }
+        fn process(&mut self, _: &DBTr) {
+            self.0.push("event");
+        }
+        fn on_flush(&mut self) {
+            self.0.push("flush");
+        }
+        fn on_idle(&mut self) {
+            self.0.push("idle");
+        }
+        fn on_shutdown(&mut self) {
+            self.0.push("shutdown");
+        }
+    }
+
+    #[test]
+    fn run_without_shutdown() {
+        let (w, r) = channel();
+        w.send(evt()).unwrap();
+        w.send(ViewMsg::Flush(FlushMarker::new().0)).unwrap();
+        drop(w);
+        let mut calls = Calls::default();
+        ViewStream::new(r).run(&mut calls);
+        assert_eq!(calls.0, vec!["start", "event", "flush"]);
+    }
+
+    #[test]
+    fn run_idles_between_bursts() {
+        let (w, r) = channel();
+        w.send(evt()).unwrap();
+        w.send(evt()).unwrap();
+        let reader = thread::spawn(move || {
+            let mut calls = Calls::default();
+            ViewStream::new(r).run(&mut calls);
+            calls.0
+        });
+        // the reader idles before its first read or after the first burst, or both
+        thread::sleep(Duration::from_millis(50));
+        w.send(evt()).unwrap();
+        w.send(ViewMsg::Shutdown).unwrap();
+        let calls = reader.join().unwrap();
+        assert_eq!(calls.iter().filter(|c| **c == "event").count(), 3);
+        assert_eq!(calls.last(), Some(&"shutdown"));
+        assert!(calls.contains(&"idle"));
+    }
+}
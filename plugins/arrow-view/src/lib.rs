@@ -0,0 +1,472 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::TcpListener,
+    sync::Arc,
+    thread,
+};
+
+use pvm_plugins::{
+    define_plugin,
+    rows::{node_row, rel_row, Kind, Kind::*, Row, Value, NODE_COLUMNS, REL_COLUMNS},
+    views::{
+        data::ID, DBTr, ErrorSink, InlineView, ParamDecl, View, ViewError, ViewInst, ViewParams,
+        ViewStream,
+    },
+};
+
+use arrow::{
+    array::{
+        ArrayBuilder, BooleanBuilder, Int64Builder, StringBuilder, TimestampMillisecondBuilder,
+    },
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    error::ArrowError,
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+
+define_plugin!(views => [ ArrowView ]);
+
+#[derive(Debug)]
+pub struct ArrowView {
+    id: usize,
+}
+
+impl View for ArrowView {
+    fn new(id: usize) -> ArrowView {
+        ArrowView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "ArrowView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for streaming nodes and relations as Arrow IPC record batches."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str(
+                "nodes",
+                "Output file for nodes, or tcp://<addr> to serve them to one consumer",
+            )
+            .default("./prov_nodes.arrows"),
+            ParamDecl::str(
+                "rels",
+                "Output file for relations, or tcp://<addr> to serve them to one consumer",
+            )
+            .default("./prov_rels.arrows"),
+            ParamDecl::int(
+                "batch",
+                "Number of rows per record batch, fewer are only written out on a flush",
+            )
+            .default("1024"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let batch = match params.get_int("batch").unwrap() {
+            n if n > 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "batch".to_string(),
+                    format!("must be a positive integer, not {}", n),
+                ))
+            }
+        };
+        let mut inst = ArrowWriter {
+            dests: Some((
+                Dest::open("nodes", params.get_str("nodes").unwrap())?,
+                Dest::open("rels", params.get_str("rels").unwrap())?,
+            )),
+            batch,
+            tables: None,
+            times: HashMap::new(),
+            errors: params.error_sink(),
+            discarded: 0,
+        };
+        let thr = thread::Builder::new()
+            .name("ArrowView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// Where a table is written
+enum Dest {
+    Open(Box<dyn Write + Send>),
+    /// Waiting for a consumer, the first to connect receives the stream
+    Listening(TcpListener),
+}
+
+impl Dest {
+    /// Create the file or bind the `tcp://` address given by the param `name`
+    fn open(name: &str, dest: &str) -> Result<Dest, ViewError> {
+        let invalid = |e: io::Error| ViewError::InvalidParam(name.to_string(), e.to_string());
+        match dest.strip_prefix("tcp://") {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr).map_err(invalid)?;
+                listener.set_nonblocking(true).map_err(invalid)?;
+                Ok(Dest::Listening(listener))
+            }
+            None => File::create(dest)
+                .map(|f| Dest::Open(Box::new(BufWriter::new(f))))
+                .map_err(invalid),
+        }
+    }
+
+    /// Accept a consumer if one is waiting, returning whether the output is open
+    fn poll(&mut self) -> io::Result<bool> {
+        if let Dest::Listening(listener) = self {
+            let con = match listener.accept() {
+                Ok((con, _)) => con,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            con.set_nonblocking(false)?;
+            *self = Dest::Open(Box::new(BufWriter::new(con)));
+        }
+        Ok(true)
+    }
+
+    fn into_output(self) -> Box<dyn Write + Send> {
+        match self {
+            Dest::Open(out) => out,
+            Dest::Listening(_) => panic!("No consumer has connected"),
+        }
+    }
+}
+
+/// Writes rows to both tables until either fails, discarding any after that
+///
+/// The tables are opened once both outputs are, until then updates are discarded so that a tcp
+/// consumer that never connects does not hold up the stream.
+struct ArrowWriter {
+    dests: Option<(Dest, Dest)>,
+    batch: usize,
+    tables: Option<(Table, Table)>,
+    times: HashMap<ID, i64>,
+    errors: ErrorSink,
+    /// The number of updates discarded before the tables were opened or after a failure
+    discarded: usize,
+}
+
+impl fmt::Debug for ArrowWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArrowWriter")
+            .field("tables", &self.tables)
+            .field("discarded", &self.discarded)
+            .finish()
+    }
+}
+
+impl ArrowWriter {
+    /// Open the tables once both outputs are, returning whether they are
+    fn connect(&mut self) -> bool {
+        if self.tables.is_some() {
+            return true;
+        }
+        let res = match self.dests {
+            Some((ref mut nodes, ref mut rels)) => nodes
+                .poll()
+                .and_then(|nodes| Ok(rels.poll()? && nodes))
+                .map_err(ArrowError::from),
+            // failed already
+            None => return false,
+        };
+        let res = match res {
+            Ok(false) => return false,
+            Ok(true) => {
+                let (nodes, rels) = self.dests.take().unwrap();
+                Table::new(nodes.into_output(), NODE_COLUMNS, self.batch).and_then(|nodes| {
+                    Ok((
+                        nodes,
+                        Table::new(rels.into_output(), REL_COLUMNS, self.batch)?,
+                    ))
+                })
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(tables) => self.tables = Some(tables),
+            Err(e) => {
+                self.dests = None;
+                self.errors.report(format!(
+                    "ArrowView failed to open its outputs, discarding all updates: {}",
+                    e
+                ));
+            }
+        }
+        self.tables.is_some()
+    }
+
+    fn check(&mut self, res: Result<(), ArrowError>) {
+        if let Err(e) = res {
+            self.errors.report(format!(
+                "ArrowView failed to write, discarding further updates: {}",
+                e
+            ));
+            self.tables = None;
+        }
+    }
+}
+
+impl InlineView for ArrowWriter {
+    fn on_start(&mut self) {
+        self.connect();
+    }
+
+    fn process(&mut self, evt: &DBTr) {
+        if !self.connect() {
+            self.discarded += 1;
+            return;
+        }
+        let (nodes, rels) = self.tables.as_mut().unwrap();
+        let res = match *evt {
+            DBTr::CreateNode(ref n) => match node_row(&mut self.times, n, false) {
+                Some(row) => nodes.push(row),
+                None => Ok(()),
+            },
+            DBTr::UpdateNode(ref n) => match node_row(&mut self.times, n, true) {
+                Some(row) => nodes.push(row),
+                None => Ok(()),
+            },
+            DBTr::CreateRel(ref r) => rels.push(rel_row(&self.times, r, false)),
+            DBTr::UpdateRel(ref r) => rels.push(rel_row(&self.times, r, true)),
+            DBTr::Flush(_) | DBTr::Tick(_) => Ok(()),
+        };
+        self.check(res);
+    }
+
+    fn on_flush(&mut self) {
+        if let Some((ref mut nodes, ref mut rels)) = self.tables {
+            let res = nodes.flush().and_then(|_| rels.flush());
+            self.check(res);
+        }
+    }
+
+    fn on_idle(&mut self) {
+        self.connect();
+    }
+
+    fn on_shutdown(&mut self) {
+        if let Some((mut nodes, mut rels)) = self.tables.take() {
+            let res = nodes.close().and_then(|_| rels.close());
+            self.check(res);
+        }
+        if self.discarded > 0 {
+            self.errors
+                .report(format!("ArrowView discarded {} updates", self.discarded));
+        }
+    }
+}
+
+enum Column {
+    Int(Int64Builder),
+    Time(TimestampMillisecondBuilder),
+    Bool(BooleanBuilder),
+    Str(StringBuilder),
+}
+
+/// An Arrow IPC stream being written, with the rows of its next record batch held in builders
+struct Table {
+    writer: StreamWriter<Box<dyn Write + Send>>,
+    schema: Arc<Schema>,
+    cols: Vec<Column>,
+    rows: usize,
+    batch: usize,
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("rows", &self.rows)
+            .field("batch", &self.batch)
+            .finish()
+    }
+}
+
+impl Table {
+    fn new(
+        out: Box<dyn Write + Send>,
+        columns: &[(&str, Kind)],
+        batch: usize,
+    ) -> Result<Table, ArrowError> {
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(col, kind)| {
+                    let ty = match kind {
+                        Int => DataType::Int64,
+                        Time => DataType::Timestamp(TimeUnit::Millisecond, None),
+                        Bool => DataType::Boolean,
+                        Str => DataType::Utf8,
+                    };
+                    Field::new(*col, ty, true)
+                })
+                .collect::<Vec<_>>(),
+        ));
+        let writer = StreamWriter::try_new(out, &schema)?;
+        let cols = columns
+            .iter()
+            .map(|(_, kind)| match kind {
+                Int => Column::Int(Int64Builder::new()),
+                Time => Column::Time(TimestampMillisecondBuilder::new()),
+                Bool => Column::Bool(BooleanBuilder::new()),
+                Str => Column::Str(StringBuilder::new()),
+            })
+            .collect();
+        Ok(Table {
+            writer,
+            schema,
+            cols,
+            rows: 0,
+            batch: batch.max(1),
+        })
+    }
+
+    fn push(&mut self, row: Row) -> Result<(), ArrowError> {
+        for (col, val) in self.cols.iter_mut().zip(row) {
+            match (col, val) {
+                (Column::Int(b), Some(Value::Int(v))) => b.append_value(v),
+                (Column::Time(b), Some(Value::Int(v))) => b.append_value(v),
+                (Column::Bool(b), Some(Value::Bool(v))) => b.append_value(v),
+                (Column::Str(b), Some(Value::Str(v))) => b.append_value(v),
+                (Column::Int(b), None) => b.append_null(),
+                (Column::Time(b), None) => b.append_null(),
+                (Column::Bool(b), None) => b.append_null(),
+                (Column::Str(b), None) => b.append_null(),
+                _ => panic!("Value does not match its column type"),
+            }
+        }
+        self.rows += 1;
+        if self.rows >= self.batch {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write out any buffered rows as a record batch and push them through to the consumer
+    fn flush(&mut self) -> Result<(), ArrowError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let arrays = self
+            .cols
+            .iter_mut()
+            .map(|col| match col {
+                Column::Int(b) => ArrayBuilder::finish(b),
+                Column::Time(b) => ArrayBuilder::finish(b),
+                Column::Bool(b) => ArrayBuilder::finish(b),
+                Column::Str(b) => ArrayBuilder::finish(b),
+            })
+            .collect();
+        self.rows = 0;
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), ArrowError> {
+        self.flush()?;
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{Int64Type, TimestampMillisecondType},
+        ipc::reader::StreamReader,
+    };
+
+    use pvm_plugins::testing::{self, ctx, inf, named, path, process, TestDir};
+
+    fn read(path: &std::path::Path) -> Vec<RecordBatch> {
+        StreamReader::try_new(File::open(path).unwrap(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trips_rows() {
+        let dir = TestDir::new("arrow-view");
+        let (nodes, rels) = (dir.join("nodes.arrows"), dir.join("rels.arrows"));
+        let view = ArrowView::new(0);
+        let params = testing::params(
+            &view,
+            &[
+                ("nodes", nodes.to_str().unwrap()),
+                ("rels", rels.to_str().unwrap()),
+                ("batch", "2"),
+            ],
+        );
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            path(3, "/etc/passwd"),
+            inf(4, 2, 3, 1, 10),
+            named(5, 2, 3, 1),
+        ];
+        testing::run(&view, params, evts).unwrap();
+
+        let batches = read(&nodes);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 3);
+        let kinds: Vec<_> = batches
+            .iter()
+            .flat_map(|b| b.column(2).as_string::<i32>().iter().collect::<Vec<_>>())
+            .map(Option::unwrap)
+            .collect();
+        assert_eq!(kinds, ["Ctx", "Data", "Name"]);
+        let data = &batches[0];
+        assert_eq!(data.column(7).as_primitive::<Int64Type>().value(1), 1);
+        let time = data.column(8).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(time.value(1), 1_577_836_801_000);
+
+        let batches = read(&rels);
+        assert_eq!(batches.len(), 1);
+        let rels = &batches[0];
+        assert_eq!(rels.num_rows(), 2);
+        assert_eq!(rels.column(2).as_string::<i32>().value(0), "Inf");
+        assert_eq!(rels.column(12).as_primitive::<Int64Type>().value(0), 10);
+        assert_eq!(
+            rels.column(7)
+                .as_primitive::<TimestampMillisecondType>()
+                .value(0),
+            1_577_836_801_000
+        );
+        assert!(rels.column(8).is_null(1));
+    }
+
+    #[test]
+    fn shuts_down_without_a_consumer() {
+        let view = ArrowView::new(0);
+        let params = testing::params(
+            &view,
+            &[
+                ("nodes", "tcp://127.0.0.1:0"),
+                ("rels", "tcp://127.0.0.1:0"),
+            ],
+        );
+        let evts = vec![path(1, "/etc/passwd"), path(2, "/etc/group")];
+        testing::run(&view, params, evts).unwrap();
+    }
+}
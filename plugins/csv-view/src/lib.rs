@@ -1,28 +1,31 @@
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::File,
-    io::Write,
+    io::{self, Write},
     mem,
-    sync::{mpsc::Receiver, Arc},
+    path::Path,
     thread,
 };
 
 use pvm_plugins::{
+    compress::Compression,
     define_plugin,
+    output::{Output, Sender},
+    rotate::{Rotation, Segments, ROTATE_PARAMS},
     views::{
         data::{
-            node_types::{NameNode, Node, PVMDataType::*, SchemaNode},
+            node_types::{ConcreteType, ContextType, NameNode, Node, PVMDataType::*, SchemaNode},
             rel_types::Rel,
             HasDst, HasID, HasSrc, ID,
         },
-        DBTr, View, ViewInst, ViewParams, ViewParamsExt,
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
     },
 };
 
-use maplit::hashmap;
+use maplit::btreemap;
 use serde_json;
-use zip::{write::FileOptions, ZipWriter};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 define_plugin!(views => [ CSVView ]);
 
@@ -64,8 +67,8 @@ pub struct CSVView {
     id: usize,
 }
 
-fn write_str<W: Write>(f: &mut W, s: &str) {
-    write!(f, ",\"{}\"", s.replace("\"", "\"\"")).unwrap();
+fn write_str<W: Write>(f: &mut W, s: &str) -> io::Result<()> {
+    write!(f, ",\"{}\"", s.replace("\"", "\"\""))
 }
 
 impl View for CSVView {
@@ -81,180 +84,340 @@ impl View for CSVView {
     fn desc(&self) -> &'static str {
         "View for writing a static csv files for later consumption."
     }
-    fn params(&self) -> HashMap<&'static str, &'static str> {
-        hashmap!("path" => "The file to write the csv data to.")
+    fn params(&self) -> Vec<ParamDecl> {
+        let mut params = vec![
+            ParamDecl::path("path", "The file to write the csv data to.").default("./prov_csv.zip"),
+            ParamDecl::str(
+                "compress",
+                "Compression of the files in the archive, either none, gzip or zstd",
+            )
+            .default("gzip"),
+        ];
+        // the size of an archive is only known once it is written, so it cannot limit a segment
+        params.extend(ROTATE_PARAMS.iter().filter(|p| p.name != "rotate_size"));
+        params
     }
-    fn create(&self, id: usize, params: ViewParams, stream: Receiver<Arc<DBTr>>) -> ViewInst {
-        let path = params.get_or_def("path", "./prov_csv.zip");
-        let mut out = ZipWriter::new(File::create(path).unwrap());
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let path = params.get_path("path").unwrap();
+        let rotation = Rotation::from_params(&params)?;
+        let method = match Compression::from_params(&params)? {
+            Compression::None => CompressionMethod::Stored,
+            Compression::Gzip => CompressionMethod::Deflated,
+            Compression::Zstd => CompressionMethod::Zstd,
+        };
+        let out = CSVWriter {
+            segs: Segments::new(path, rotation)
+                .map_err(|e| ViewError::InvalidParam("path".to_string(), e.to_string()))?,
+            method,
+            layout: Layout::new(),
+            nodes: HashMap::new(),
+            rels: HashMap::new(),
+            records: 0,
+        };
+        // the archive of the empty segment, so an unwritable path is found before any update
+        out.write_segment()
+            .map_err(|e| ViewError::InvalidParam("path".to_string(), e.to_string()))?;
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
         let thr = thread::Builder::new()
             .name("CSVView".to_string())
-            .spawn(move || {
-                out.start_file("db/n_dbinfo.csv", FileOptions::default())
-                    .unwrap();
-                writeln!(out, ":LABEL,pvm_version:int,source").unwrap();
-                writeln!(out, "DBInfo,2,libPVM-{}", /*crate::VERSION*/ "").unwrap();
-
-                let mut nodes: HashMap<Cow<'static, str>, HashMap<ID, Node>> = HashMap::new();
-                let mut rels: HashMap<Cow<'static, str>, HashMap<ID, Rel>> = HashMap::new();
-
-                for evt in stream {
-                    match *evt {
-                        DBTr::CreateNode(ref node) | DBTr::UpdateNode(ref node) => {
-                            nodes
-                                .entry(node.fname())
-                                .or_insert_with(HashMap::new)
-                                .insert(node.get_db_id(), node.clone());
-                        }
-                        DBTr::CreateRel(ref rel) | DBTr::UpdateRel(ref rel) => {
-                            rels.entry(rel.fname())
-                                .or_insert_with(HashMap::new)
-                                .insert(rel.get_db_id(), rel.clone());
-                        }
-                    }
-                }
-
-                out.start_file(
-                    "db/hydrate.sh",
-                    FileOptions::default().unix_permissions(0o755),
-                )
-                .unwrap();
-                {
-                    write!(out, "{}", HYDRATE_SH_PRE).unwrap();
-                    let mut options = vec![
-                        "--id-type=INTEGER".to_string(),
-                        "--multiline-fields=true".to_string(),
-                        "--nodes n_dbinfo.csv".to_string(),
-                    ];
-                    options.extend(nodes.keys().map(|k| format!("--nodes {}", k)));
-                    options.extend(rels.keys().map(|k| format!("--relationships {}", k)));
-                    writeln!(out, "neo4j-admin import {}", options.join(" "),).unwrap();
-                    write!(out, "{}", HYDRATE_SH_POST).unwrap();
-                }
-
-                for (fname, rlist) in rels {
-                    out.start_file(format!("db/{}", fname), FileOptions::default())
-                        .unwrap();
-                    for (i, r) in rlist.values().enumerate() {
-                        if i == 0 {
-                            write!(out, "db_id,:START_ID,:END_ID,:TYPE").unwrap();
-                            match r {
-                                Rel::Inf(_) => {
-                                    writeln!(out, ",pvm_op,ctx:long,byte_count:long").unwrap()
-                                }
-                                Rel::Named(_) => writeln!(out, ",start:long,end:long").unwrap(),
-                            }
-                        }
-                        write!(
-                            out,
-                            "{},{},{},{}",
-                            format_id(r.get_db_id()),
-                            format_id(r.get_src()),
-                            format_id(r.get_dst()),
-                            r._lab(),
-                        )
-                        .unwrap();
-                        match r {
-                            Rel::Inf(i) => writeln!(
-                                out,
-                                ",{:?},\"{}\",{}",
-                                i.pvm_op,
-                                format_id(i.ctx),
-                                i.byte_count
-                            )
-                            .unwrap(),
-                            Rel::Named(n) => {
-                                writeln!(out, ",{},\"{}\"", format_id(n.start), format_id(n.end),)
-                                    .unwrap()
-                            }
-                        }
-                    }
-                }
-                for (fname, nlist) in nodes {
-                    out.start_file(format!("db/{}", fname), FileOptions::default())
-                        .unwrap();
-                    for (i, n) in nlist.values().enumerate() {
-                        if i == 0 {
-                            write!(out, "db_id:ID,:LABEL").unwrap();
-                            match n {
-                                Node::Data(d) => {
-                                    write!(out, ",uuid,ty,ctx:long,meta_hist").unwrap();
-                                    for k in d.ty().props.keys() {
-                                        write!(out, ",{}", k).unwrap();
-                                    }
-                                    writeln!(out).unwrap();
-                                }
-                                Node::Ctx(c) => {
-                                    write!(out, ",ty").unwrap();
-                                    for f in &c.ty().props {
-                                        write!(out, ",{}", f).unwrap();
-                                    }
-                                    writeln!(out).unwrap();
-                                }
-                                Node::Name(n) => match n {
-                                    NameNode::Path(..) => writeln!(out, ",path").unwrap(),
-                                    NameNode::Net(..) => writeln!(out, ",addr,port:int").unwrap(),
-                                },
-                                Node::Schema(_) => {
-                                    writeln!(out, ",name,base,props:string[]").unwrap()
-                                }
-                            }
-                        }
-                        write!(out, "{},{}", format_id(n.get_db_id()), n._lab()).unwrap();
-                        match n {
-                            Node::Data(d) => {
-                                write!(out, ",{},{},{}", d.uuid(), d.ty().name, format_id(d.ctx()))
-                                    .unwrap();
-                                write_str(&mut out, &serde_json::to_string(&d.meta).unwrap());
-                                for k in d.ty().props.keys() {
-                                    let val = d.meta.cur(k);
-                                    match val {
-                                        Some(v) => write_str(&mut out, v),
-                                        None => write!(out, ",").unwrap(),
-                                    }
-                                }
-                            }
-                            Node::Ctx(c) => {
-                                write!(out, ",{}", c.ty().name).unwrap();
-                                for f in &c.ty().props {
-                                    write!(out, ",{}", c.cont[f]).unwrap();
-                                }
-                                writeln!(out).unwrap();
-                            }
-                            Node::Name(n) => match n {
-                                NameNode::Path(_, path) => {
-                                    write_str(&mut out, path);
-                                }
-                                NameNode::Net(_, addr, port) => {
-                                    write_str(&mut out, addr);
-                                    write!(out, ",{}", port).unwrap();
-                                }
-                            },
-                            Node::Schema(s) => match s {
-                                SchemaNode::Data(_, ty) => {
-                                    write_str(&mut out, ty.name);
-                                    let v: Vec<&str> = ty.props.keys().cloned().collect();
-                                    write!(out, ",{},{}", ty.pvm_ty, v.join(";")).unwrap();
-                                }
-                                SchemaNode::Context(_, ty) => {
-                                    write_str(&mut out, ty.name);
-                                    write!(out, ",Context,{}", ty.props.join(";")).unwrap();
-                                }
-                            },
-                        }
-                        writeln!(out).unwrap();
-                    }
-                }
-                out.finish().unwrap();
-            })
-            .unwrap();
-        ViewInst {
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
             id,
             vtype: self.id,
             params,
             handle: thr,
+        })
+    }
+}
+
+/// Collects the elements of the current segment, writing its archive when it is closed
+///
+/// A flush writes the archive of the segment so far, which is rewritten as the segment goes on.
+#[derive(Debug)]
+struct CSVWriter {
+    segs: Segments,
+    method: CompressionMethod,
+    layout: Layout,
+    nodes: Nodes,
+    rels: Rels,
+    records: u64,
+}
+
+impl CSVWriter {
+    fn write_segment(&self) -> io::Result<u64> {
+        write_archive(
+            &self.segs.path(),
+            FileOptions::default().compression_method(self.method),
+            &self.layout,
+            &self.nodes,
+            &self.rels,
+        )
+    }
+}
+
+impl Output for CSVWriter {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        match *evt {
+            DBTr::CreateNode(ref node) | DBTr::UpdateNode(ref node) => {
+                self.layout.add_node(node);
+                self.nodes
+                    .entry(node.fname())
+                    .or_insert_with(HashMap::new)
+                    .insert(node.get_db_id(), node.clone());
+            }
+            DBTr::CreateRel(ref rel) | DBTr::UpdateRel(ref rel) => {
+                self.rels
+                    .entry(rel.fname())
+                    .or_insert_with(HashMap::new)
+                    .insert(rel.get_db_id(), rel.clone());
+            }
+            DBTr::Flush(_) | DBTr::Tick(_) => return Ok(()),
+        }
+        self.records += 1;
+        if self.segs.due(0, self.records) {
+            let bytes = self.write_segment()?;
+            self.segs.close(bytes, self.records)?;
+            self.nodes.clear();
+            self.rels.clear();
+            self.records = 0;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.records > 0 || !self.segs.rotation().is_enabled() {
+            self.write_segment()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.records > 0 || !self.segs.rotation().is_enabled() {
+            let bytes = self.write_segment()?;
+            self.segs.close(bytes, self.records)?;
+        }
+        Ok(())
+    }
+}
+
+type Nodes = HashMap<Cow<'static, str>, HashMap<ID, Node>>;
+type Rels = HashMap<Cow<'static, str>, HashMap<ID, Rel>>;
+
+/// The files written to each archive and their header rows
+///
+/// A file is added for each type as its schema node arrives, so every registered type gets a
+/// file with the columns of that type even if no node of it is written. The header row of a
+/// file for a type without a schema node is taken from the first node written to it.
+#[derive(Debug)]
+struct Layout {
+    nodes: BTreeMap<Cow<'static, str>, String>,
+    rels: BTreeMap<Cow<'static, str>, String>,
+}
+
+impl Layout {
+    fn new() -> Self {
+        Layout {
+            nodes: btreemap!(
+                "n_paths.csv".into() => PATH_HEADER.to_string(),
+                "n_net.csv".into() => NET_HEADER.to_string(),
+                "n_schema.csv".into() => SCHEMA_HEADER.to_string()
+            ),
+            rels: btreemap!(
+                "r_inf.csv".into() => INF_HEADER.to_string(),
+                "r_named.csv".into() => NAMED_HEADER.to_string()
+            ),
+        }
+    }
+
+    fn add_node(&mut self, node: &Node) {
+        if let Node::Schema(s) = node {
+            let (fname, header) = match s {
+                SchemaNode::Data(_, ty) => (data_fname(ty), data_header(ty)),
+                SchemaNode::Context(_, ty) => (ctx_fname(ty), ctx_header(ty)),
+            };
+            self.nodes.insert(fname.into(), header);
+        }
+        self.nodes
+            .entry(node.fname())
+            .or_insert_with(|| node.header());
+    }
+}
+
+const PATH_HEADER: &str = "db_id:ID,:LABEL,path";
+const NET_HEADER: &str = "db_id:ID,:LABEL,addr,port:int";
+const SCHEMA_HEADER: &str = "db_id:ID,:LABEL,name,base,props:string[]";
+const INF_HEADER: &str =
+    "db_id:long,:START_ID,:END_ID,:TYPE,pvm_op,ctx:long,bytes_read:long,bytes_written:long,event";
+const NAMED_HEADER: &str = "db_id:long,:START_ID,:END_ID,:TYPE,start:long,end:long,actor:long";
+
+/// The properties of a type in column order
+fn data_props(ty: &ConcreteType) -> Vec<&'static str> {
+    let mut props: Vec<&'static str> = ty.props.keys().cloned().collect();
+    props.sort();
+    props
+}
+
+fn data_header(ty: &ConcreteType) -> String {
+    let mut header = "db_id:ID,:LABEL,uuid,ty,ctx:long,meta_hist".to_string();
+    for k in data_props(ty) {
+        header += &format!(",{}", k);
+    }
+    header
+}
+
+fn ctx_header(ty: &ContextType) -> String {
+    let mut header = "db_id:ID,:LABEL,ty".to_string();
+    for f in &ty.props {
+        header += &format!(",{}", f);
+    }
+    header
+}
+
+fn data_fname(ty: &ConcreteType) -> String {
+    match ty.pvm_ty {
+        Actor => format!("n_actor_{}.csv", ty.name),
+        Store => format!("n_store_{}.csv", ty.name),
+        Conduit => format!("n_conduit_{}.csv", ty.name),
+        EditSession => format!("n_es_{}.csv", ty.name),
+    }
+}
+
+fn ctx_fname(ty: &ContextType) -> String {
+    format!("n_ctx_{}.csv", ty.name)
+}
+
+/// Write an archive importable with the contained hydrate.sh, returning its size
+///
+/// With rotation each segment is its own archive holding the latest state of the elements
+/// created or updated during it.
+fn write_archive(
+    path: &Path,
+    opts: FileOptions,
+    layout: &Layout,
+    nodes: &Nodes,
+    rels: &Rels,
+) -> io::Result<u64> {
+    let mut out = ZipWriter::new(File::create(path)?);
+    out.start_file("db/n_dbinfo.csv", opts)?;
+    writeln!(out, ":LABEL,pvm_version:int,source")?;
+    writeln!(out, "DBInfo,2,libPVM-{}", /*crate::VERSION*/ "")?;
+
+    out.start_file("db/hydrate.sh", opts.unix_permissions(0o755))?;
+    {
+        write!(out, "{}", HYDRATE_SH_PRE)?;
+        let mut options = vec![
+            "--id-type=INTEGER".to_string(),
+            "--multiline-fields=true".to_string(),
+            "--nodes n_dbinfo.csv".to_string(),
+        ];
+        options.extend(layout.nodes.keys().map(|k| format!("--nodes {}", k)));
+        options.extend(layout.rels.keys().map(|k| format!("--relationships {}", k)));
+        writeln!(out, "neo4j-admin import {}", options.join(" "),)?;
+        write!(out, "{}", HYDRATE_SH_POST)?;
+    }
+
+    for (fname, header) in &layout.rels {
+        out.start_file(format!("db/{}", fname), opts)?;
+        writeln!(out, "{}", header)?;
+        for r in rels.get(fname).into_iter().flat_map(HashMap::values) {
+            write_rel(&mut out, r)?;
+        }
+    }
+    for (fname, header) in &layout.nodes {
+        out.start_file(format!("db/{}", fname), opts)?;
+        writeln!(out, "{}", header)?;
+        for n in nodes.get(fname).into_iter().flat_map(HashMap::values) {
+            write_node(&mut out, n)?;
+        }
+    }
+    Ok(out.finish()?.metadata()?.len())
+}
+
+fn write_rel<W: Write>(out: &mut W, r: &Rel) -> io::Result<()> {
+    write!(
+        out,
+        "{},{},{},{}",
+        format_id(r.get_db_id()),
+        format_id(r.get_src()),
+        format_id(r.get_dst()),
+        r._lab(),
+    )?;
+    match r {
+        Rel::Inf(i) => {
+            write!(
+                out,
+                ",{},{},{},{}",
+                i.pvm_op,
+                format_id(i.ctx),
+                i.bytes_read,
+                i.bytes_written
+            )?;
+            match i.event {
+                Some(ref e) => write_str(out, e)?,
+                None => write!(out, ",")?,
+            }
         }
+        Rel::Named(n) => write!(
+            out,
+            ",{},{},{}",
+            format_id(n.start),
+            format_id(n.end),
+            n.actor
+                .map(|a| format_id(a).to_string())
+                .unwrap_or_default(),
+        )?,
     }
+    writeln!(out)
+}
+
+fn write_node<W: Write>(out: &mut W, n: &Node) -> io::Result<()> {
+    write!(out, "{},{}", format_id(n.get_db_id()), n._lab())?;
+    match n {
+        Node::Data(d) => {
+            write!(out, ",{},{},{}", d.uuid(), d.ty().name, format_id(d.ctx()))?;
+            write_str(out, &serde_json::to_string(&d.meta)?)?;
+            for k in data_props(d.ty()) {
+                match d.meta.cur(k) {
+                    Some(v) => write_str(out, v)?,
+                    None => write!(out, ",")?,
+                }
+            }
+        }
+        Node::Ctx(c) => {
+            write!(out, ",{}", c.ty().name)?;
+            for f in &c.ty().props {
+                match c.cont.get(*f) {
+                    Some(v) => write_str(out, v)?,
+                    None => write!(out, ",")?,
+                }
+            }
+        }
+        Node::Name(n) => match n {
+            NameNode::Path(_, path) => write_str(out, path)?,
+            NameNode::Net(_, addr, port) => {
+                write_str(out, addr)?;
+                write!(out, ",{}", port)?;
+            }
+        },
+        Node::Schema(s) => match s {
+            SchemaNode::Data(_, ty) => {
+                write_str(out, ty.name)?;
+                write!(out, ",{},{}", ty.pvm_ty, data_props(ty).join(";"))?;
+            }
+            SchemaNode::Context(_, ty) => {
+                write_str(out, ty.name)?;
+                write!(out, ",Context,{}", ty.props.join(";"))?;
+            }
+        },
+    }
+    writeln!(out)
 }
 
 fn format_id(v: ID) -> i64 {
@@ -268,19 +431,14 @@ fn format_u64(v: u64) -> i64 {
 trait ToCSV {
     fn fname(&self) -> Cow<'static, str>;
     fn _lab(&self) -> &str;
+    fn header(&self) -> String;
 }
 
 impl ToCSV for Node {
     fn fname(&self) -> Cow<'static, str> {
         match self {
-            Node::Data(d) => match d.pvm_ty() {
-                Actor => format!("n_actor_{}.csv", d.ty().name),
-                Store => format!("n_store_{}.csv", d.ty().name),
-                Conduit => format!("n_conduit_{}.csv", d.ty().name),
-                EditSession => format!("n_es_{}.csv", d.ty().name),
-            }
-            .into(),
-            Node::Ctx(n) => format!("n_ctx_{}.csv", n.ty().name).into(),
+            Node::Data(d) => data_fname(d.ty()).into(),
+            Node::Ctx(n) => ctx_fname(n.ty()).into(),
             Node::Name(n) => match n {
                 NameNode::Path(..) => "n_paths.csv",
                 NameNode::Net(..) => "n_net.csv",
@@ -306,6 +464,19 @@ impl ToCSV for Node {
             Node::Schema(_) => "Node;Schema",
         }
     }
+
+    fn header(&self) -> String {
+        match self {
+            Node::Data(d) => data_header(d.ty()),
+            Node::Ctx(c) => ctx_header(c.ty()),
+            Node::Name(n) => match n {
+                NameNode::Path(..) => PATH_HEADER,
+                NameNode::Net(..) => NET_HEADER,
+            }
+            .to_string(),
+            Node::Schema(_) => SCHEMA_HEADER.to_string(),
+        }
+    }
 }
 
 impl ToCSV for Rel {
@@ -323,4 +494,67 @@ impl ToCSV for Rel {
             Rel::Named(_) => "NAMED",
         }
     }
+
+    fn header(&self) -> String {
+        match self {
+            Rel::Inf(_) => INF_HEADER,
+            Rel::Named(_) => NAMED_HEADER,
+        }
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Read, sync::mpsc::channel};
+
+    use pvm_plugins::testing::{self, ctx, inf, process, TestDir};
+    use zip::ZipArchive;
+
+    #[test]
+    fn writes_archive() {
+        let dir = TestDir::new("csv-view");
+        let path = dir.join("prov.zip");
+        let view = CSVView::new(0);
+        let params = testing::params(&view, &[("path", path.to_str().unwrap())]);
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            inf(3, 2, 2, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut s = String::new();
+            archive
+                .by_name(&format!("db/{}", name))
+                .unwrap()
+                .read_to_string(&mut s)
+                .unwrap();
+            s
+        };
+        assert_eq!(
+            read("r_inf.csv"),
+            format!("{}\n3,2,2,INF,Sink,1,0,10,\n", INF_HEADER)
+        );
+        let procs = read("n_actor_process.csv");
+        assert!(procs.starts_with("db_id:ID,:LABEL,uuid,ty,ctx:long,meta_hist,cmdline\n2,"));
+        assert!(procs.ends_with(",\"/bin/sh\"\n"));
+    }
+
+    #[test]
+    fn unwritable_path_is_an_error() {
+        let dir = TestDir::new("csv-view-missing");
+        let view = CSVView::new(0);
+        let path = dir.join("missing").join("prov.zip");
+        let params = testing::params(&view, &[("path", path.to_str().unwrap())]);
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "path"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created with a path in a missing directory"),
+        }
+    }
 }
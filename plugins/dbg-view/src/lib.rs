@@ -1,18 +1,16 @@
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufWriter, Write},
-    sync::{mpsc::Receiver, Arc},
+    io::{self, Write},
     thread,
 };
 
 use pvm_plugins::{
+    compress::{Compression, COMPRESS_PARAM},
     define_plugin,
-    views::{DBTr, View, ViewInst, ViewParams, ViewParamsExt},
+    output::{Output, Sender},
+    rotate::{RotatingWriter, Rotation, ROTATE_PARAMS},
+    views::{DBTr, InlineView, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream},
 };
 
-use maplit::hashmap;
-
 define_plugin!(views => [ DBGView ]);
 
 #[derive(Debug)]
@@ -33,25 +31,141 @@ impl View for DBGView {
     fn desc(&self) -> &'static str {
         "View presenting debug output."
     }
-    fn params(&self) -> HashMap<&'static str, &'static str> {
-        hashmap!("output" => "Output file location")
+    fn params(&self) -> Vec<ParamDecl> {
+        let mut params = vec![
+            ParamDecl::path("output", "Output file location").default("./dbg.trace"),
+            ParamDecl::str(
+                "format",
+                "Either debug, for Rust debug output, or json for JSON lines",
+            )
+            .default("debug"),
+        ];
+        params.extend_from_slice(&ROTATE_PARAMS);
+        params.push(COMPRESS_PARAM);
+        params
     }
-    fn create(&self, id: usize, params: ViewParams, stream: Receiver<Arc<DBTr>>) -> ViewInst {
-        let path = params.get_or_def("output", "./dbg.trace");
-        let mut out = BufWriter::new(File::create(path).unwrap());
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let out = DBGInline::from_params(&params)?;
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
         let thr = thread::Builder::new()
             .name("DBGView".to_string())
-            .spawn(move || {
-                for tr in stream {
-                    writeln!(out, "{:?}", tr).unwrap();
-                }
-            })
-            .unwrap();
-        ViewInst {
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
             id,
             vtype: self.id,
             params,
             handle: thr,
+        })
+    }
+    fn create_inline(
+        &self,
+        _id: usize,
+        params: &ViewParams,
+    ) -> Result<Box<dyn InlineView>, ViewError> {
+        let out = DBGInline::from_params(params)?;
+        Ok(Box::new(Sender::new(self.name(), out, params.error_sink())))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Debug,
+    /// One JSON object per line, as serialized by `DBTr`'s `Serialize` impl
+    Json,
+}
+
+impl Format {
+    fn from_params(params: &ViewParams) -> Result<Self, ViewError> {
+        match params.get_str("format").unwrap() {
+            "debug" => Ok(Format::Debug),
+            "json" | "jsonl" => Ok(Format::Json),
+            f => Err(ViewError::InvalidParam(
+                "format".to_string(),
+                format!("must be debug or json, not {}", f),
+            )),
+        }
+    }
+
+    fn write<W: Write>(self, out: &mut W, tr: &DBTr) -> io::Result<()> {
+        match self {
+            Format::Debug => write!(out, "{:?}", tr)?,
+            Format::Json => serde_json::to_writer(&mut *out, tr)?,
+        }
+        writeln!(out)
+    }
+}
+
+#[derive(Debug)]
+struct DBGInline(Format, RotatingWriter);
+
+impl DBGInline {
+    fn from_params(params: &ViewParams) -> Result<Self, ViewError> {
+        let out = RotatingWriter::create(
+            params.get_path("output").unwrap(),
+            Rotation::from_params(params)?,
+            Compression::from_params(params)?,
+        )
+        .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?;
+        Ok(DBGInline(Format::from_params(params)?, out))
+    }
+}
+
+impl Output for DBGInline {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        if let DBTr::Tick(_) = evt {
+            return Ok(());
         }
+        self.0.write(&mut self.1, evt)?;
+        self.1.end_record()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.1.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.1.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use pvm_plugins::testing::{self, ctx, inf, process, TestDir};
+    use serde_json::Value;
+
+    #[test]
+    fn writes_json_lines() {
+        let dir = TestDir::new("dbg-view");
+        let out = dir.join("dbg.trace");
+        let view = DBGView::new(0);
+        let params = testing::params(
+            &view,
+            &[("output", out.to_str().unwrap()), ("format", "json")],
+        );
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            inf(3, 2, 2, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+        let lines: Vec<Value> = fs::read_to_string(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].get("CreateNode").is_some());
+        assert!(lines[2].get("CreateRel").is_some());
     }
 }
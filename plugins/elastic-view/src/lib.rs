@@ -0,0 +1,323 @@
+use std::{collections::HashMap, thread};
+
+use pvm_plugins::{
+    define_plugin,
+    output::{Output, Sender},
+    views::{
+        data::{
+            node_types::{NameNode, Node},
+            rel_types::Rel,
+            HasDst, HasID, HasSrc, ID,
+        },
+        DBTr, ErrorSink, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use serde_json::{json, Map, Value};
+
+define_plugin!(views => [ ElasticView ]);
+
+#[derive(Debug)]
+pub struct ElasticView {
+    id: usize,
+}
+
+impl View for ElasticView {
+    fn new(id: usize) -> ElasticView {
+        ElasticView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "ElasticView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for indexing nodes and relations into Elasticsearch or OpenSearch."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str("url", "Base URL of the cluster.").default("http://localhost:9200"),
+            ParamDecl::str(
+                "index",
+                "Prefix of the indices written to, nodes go to <index>-nodes and relations to \
+                 <index>-rels.",
+            )
+            .default("pvm"),
+            ParamDecl::str(
+                "user",
+                "The username to auth with, if the cluster requires it.",
+            ),
+            ParamDecl::str("pass", "The password to auth with."),
+            ParamDecl::int("batch", "Number of documents sent in each bulk request.")
+                .default("1000"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let url = params.get_str("url").unwrap().trim_end_matches('/');
+        let index = params.get_str("index").unwrap();
+        let auth = params.get_str("user").map(|user| {
+            format!(
+                "Basic {}",
+                base64::encode(&format!(
+                    "{}:{}",
+                    user,
+                    params.get_str("pass").unwrap_or("")
+                ))
+            )
+        });
+        let batch = match params.get_int("batch").unwrap() {
+            n if n > 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "batch".to_string(),
+                    format!("must be a positive integer, not {}", n),
+                ))
+            }
+        };
+        let out = Indexer {
+            bulk: format!("{}/_bulk", url),
+            auth,
+            nodes: format!("{}-nodes", index),
+            rels: format!("{}-rels", index),
+            body: String::new(),
+            docs: 0,
+            batch,
+            times: HashMap::new(),
+            errors: params.error_sink(),
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("ElasticView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// Accumulates documents into bulk requests
+///
+/// Every document is indexed under its element's db_id, so an update replaces the document
+/// written for the creation.
+#[derive(Debug)]
+struct Indexer {
+    bulk: String,
+    auth: Option<String>,
+    nodes: String,
+    rels: String,
+    body: String,
+    docs: usize,
+    batch: usize,
+    /// The time of each context, stamped onto the elements created in it
+    times: HashMap<ID, String>,
+    errors: ErrorSink,
+}
+
+impl Output for Indexer {
+    type Error = Box<ureq::Error>;
+
+    fn write(&mut self, tr: &DBTr) -> Result<(), Box<ureq::Error>> {
+        let (index, id, doc) = match tr {
+            DBTr::CreateNode(n) | DBTr::UpdateNode(n) => match self.node(n) {
+                Some(doc) => (&self.nodes, n.get_db_id(), doc),
+                None => return Ok(()),
+            },
+            DBTr::CreateRel(r) | DBTr::UpdateRel(r) => (&self.rels, r.get_db_id(), self.rel(r)),
+            DBTr::Flush(_) | DBTr::Tick(_) => return Ok(()),
+        };
+        let action = json!({ "index": { "_index": index, "_id": id.inner().to_string() } });
+        self.body += &format!("{}\n{}\n", action, doc);
+        self.docs += 1;
+        if self.docs >= self.batch {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send the pending documents, reporting any the cluster rejects
+    fn flush(&mut self) -> Result<(), Box<ureq::Error>> {
+        if self.docs == 0 {
+            return Ok(());
+        }
+        let mut req = ureq::post(&self.bulk).set("Content-Type", "application/x-ndjson");
+        if let Some(ref auth) = self.auth {
+            req = req.set("Authorization", auth);
+        }
+        let resp: Value = req
+            .send_string(&self.body)?
+            .into_json()
+            .map_err(ureq::Error::from)?;
+        self.body.clear();
+        self.docs = 0;
+        if resp["errors"] == json!(true) {
+            let failed: Vec<&Value> = resp["items"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item["index"].get("error"))
+                .collect();
+            self.errors.report(format!(
+                "ElasticView failed to index {} documents, first error: {}",
+                failed.len(),
+                failed.first().map_or(&Value::Null, |e| *e)
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Indexer {
+    fn time(&self, ctx: ID) -> Value {
+        self.times.get(&ctx).map_or(Value::Null, |t| json!(t))
+    }
+
+    fn node(&mut self, node: &Node) -> Option<Value> {
+        let doc = match node {
+            Node::Data(d) => {
+                let meta: Map<String, Value> = d
+                    .meta
+                    .iter_latest()
+                    .map(|(k, v, _, _)| (k.to_string(), json!(v)))
+                    .collect();
+                json!({
+                    "kind": "data",
+                    "pvm_type": d.pvm_ty().to_string(),
+                    "type": d.ty().name,
+                    "uuid": d.uuid().to_hyphenated_ref().to_string(),
+                    "ctx": d.ctx().inner(),
+                    "@timestamp": self.time(d.ctx()),
+                    "meta": meta,
+                })
+            }
+            Node::Ctx(c) => {
+                if let Some(time) = c.cont.get("time") {
+                    self.times.insert(c.get_db_id(), time.clone());
+                }
+                json!({
+                    "kind": "context",
+                    "type": c.ty().name,
+                    "@timestamp": self.time(c.get_db_id()),
+                    "context": c.cont,
+                })
+            }
+            Node::Name(NameNode::Path(_, path)) => json!({ "kind": "path", "path": path }),
+            Node::Name(NameNode::Net(_, addr, port)) => {
+                json!({ "kind": "net", "addr": addr, "port": port })
+            }
+            Node::Schema(_) => return None,
+        };
+        let mut doc = doc;
+        doc["db_id"] = json!(node.get_db_id().inner());
+        Some(doc)
+    }
+
+    fn rel(&self, rel: &Rel) -> Value {
+        let mut doc = match rel {
+            Rel::Inf(i) => json!({
+                "kind": "inf",
+                "pvm_op": i.pvm_op.to_string(),
+                "ctx": i.ctx.inner(),
+                "@timestamp": self.time(i.ctx),
+                "bytes_read": i.bytes_read,
+                "bytes_written": i.bytes_written,
+                "event": i.event,
+            }),
+            Rel::Named(n) => {
+                let bound = n.end == ID::new(0);
+                json!({
+                    "kind": "named",
+                    "start": n.start.inner(),
+                    "@timestamp": self.time(n.start),
+                    "end": if bound { Value::Null } else { json!(n.end.inner()) },
+                    "end_time": if bound { Value::Null } else { self.time(n.end) },
+                    "actor": n.actor.map(|a| a.inner()),
+                })
+            }
+        };
+        doc["db_id"] = json!(rel.get_db_id().inner());
+        doc["src"] = json!(rel.get_src().inner());
+        doc["dst"] = json!(rel.get_dst().inner());
+        doc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pvm_plugins::testing::{ctx, inf, process};
+
+    #[test]
+    fn builds_bulk_body() {
+        let mut out = Indexer {
+            bulk: "http://localhost:9200/_bulk".to_string(),
+            auth: None,
+            nodes: "pvm-nodes".to_string(),
+            rels: "pvm-rels".to_string(),
+            body: String::new(),
+            docs: 0,
+            batch: 10,
+            times: HashMap::new(),
+            errors: ErrorSink::default(),
+        };
+        for evt in &[
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            inf(3, 2, 2, 1, 10),
+        ] {
+            out.write(evt).unwrap();
+        }
+        assert_eq!(out.docs, 3);
+        let lines: Vec<Value> = out
+            .body
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[2],
+            json!({ "index": { "_index": "pvm-nodes", "_id": "2" } })
+        );
+        assert_eq!(
+            lines[3],
+            json!({
+                "kind": "data",
+                "pvm_type": "Actor",
+                "type": "process",
+                "uuid": "00000000-0000-0000-0000-000000000002",
+                "ctx": 1,
+                "@timestamp": "2020-01-01T00:00:01Z",
+                "meta": { "cmdline": "/bin/sh" },
+                "db_id": 2,
+            })
+        );
+        assert_eq!(
+            lines[4],
+            json!({ "index": { "_index": "pvm-rels", "_id": "3" } })
+        );
+        assert_eq!(
+            lines[5],
+            json!({
+                "kind": "inf",
+                "pvm_op": "Sink",
+                "ctx": 1,
+                "@timestamp": "2020-01-01T00:00:01Z",
+                "bytes_read": 0,
+                "bytes_written": 10,
+                "event": null,
+                "db_id": 3,
+                "src": 2,
+                "dst": 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    thread,
+};
+
+use pvm_plugins::{
+    compress::{Compression, Encoder, COMPRESS_PARAM},
+    define_plugin,
+    output::{Output, Sender},
+    rows::ctx_millis,
+    views::{
+        data::{
+            node_types::{NameNode, Node},
+            rel_types::Rel,
+            HasDst, HasID, HasSrc, ID,
+        },
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+define_plugin!(views => [ GexfView ]);
+
+#[derive(Debug)]
+pub struct GexfView {
+    id: usize,
+}
+
+impl View for GexfView {
+    fn new(id: usize) -> GexfView {
+        GexfView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "GexfView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for writing a dynamic GEXF graph for temporal playback in Gephi."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::path("output", "Output file location").default("./prov.gexf"),
+            COMPRESS_PARAM,
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let path = params.get_path("output").unwrap();
+        let out = GexfWriter {
+            graph: Graph::default(),
+            out: Some(
+                Compression::from_params(&params)?
+                    .create(path)
+                    .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?,
+            ),
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("GexfView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The ` start` and ` end` attributes of an element alive between two contexts
+fn span(start: Option<i64>, end: Option<i64>) -> String {
+    let mut ret = String::new();
+    if let Some(start) = start {
+        ret += &format!(" start=\"{}\"", start);
+    }
+    if let Some(end) = end {
+        ret += &format!(" end=\"{}\"", end);
+    }
+    ret
+}
+
+#[derive(Debug)]
+struct GexfWriter {
+    graph: Graph,
+    out: Option<Encoder<BufWriter<File>>>,
+}
+
+impl Output for GexfWriter {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        self.graph.add(evt);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut out = self.out.take().unwrap();
+        self.graph.write(&mut out)?;
+        out.finish()?.flush()
+    }
+}
+
+/// The latest state of every node and relation, held until the stream ends as GEXF lists all
+/// nodes before any edge
+#[derive(Debug, Default)]
+struct Graph {
+    times: HashMap<ID, i64>,
+    nodes: BTreeMap<u64, Node>,
+    rels: BTreeMap<u64, Rel>,
+}
+
+impl Graph {
+    fn add(&mut self, tr: &DBTr) {
+        match tr {
+            DBTr::CreateNode(Node::Ctx(ctx)) => {
+                if let Some(ms) = ctx_millis(ctx) {
+                    self.times.insert(ctx.get_db_id(), ms);
+                }
+            }
+            DBTr::CreateNode(n @ Node::Data(_))
+            | DBTr::UpdateNode(n @ Node::Data(_))
+            | DBTr::CreateNode(n @ Node::Name(_)) => {
+                self.nodes.insert(n.get_db_id().inner(), n.clone());
+            }
+            DBTr::CreateRel(r) | DBTr::UpdateRel(r) => {
+                self.rels.insert(r.get_db_id().inner(), r.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn time(&self, ctx: ID) -> Option<i64> {
+        self.times.get(&ctx).cloned()
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let keys: BTreeSet<&str> = self
+            .nodes
+            .values()
+            .filter_map(|n| match n {
+                Node::Data(d) => Some(d),
+                _ => None,
+            })
+            .flat_map(|d| d.meta.iter().map(|(k, _, _, _)| k))
+            .collect();
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<gexf xmlns="http://www.gexf.net/1.2draft" version="1.2">"#
+        )?;
+        writeln!(
+            out,
+            r#"  <graph mode="dynamic" defaultedgetype="directed" timeformat="double">"#
+        )?;
+        writeln!(out, r#"    <attributes class="node" mode="static">"#)?;
+        for key in &["pvm_type", "type", "uuid"] {
+            writeln!(
+                out,
+                r#"      <attribute id="{0}" title="{0}" type="string"/>"#,
+                key
+            )?;
+        }
+        writeln!(out, r#"    </attributes>"#)?;
+        writeln!(out, r#"    <attributes class="node" mode="dynamic">"#)?;
+        for key in &keys {
+            writeln!(
+                out,
+                r#"      <attribute id="meta:{0}" title="{0}" type="string"/>"#,
+                escape(key)
+            )?;
+        }
+        writeln!(out, r#"    </attributes>"#)?;
+        writeln!(out, r#"    <attributes class="edge" mode="static">"#)?;
+        for key in &["bytes_read", "bytes_written"] {
+            writeln!(
+                out,
+                r#"      <attribute id="{0}" title="{0}" type="long"/>"#,
+                key
+            )?;
+        }
+        writeln!(out, r#"    </attributes>"#)?;
+
+        writeln!(out, "    <nodes>")?;
+        for (id, node) in &self.nodes {
+            self.write_node(out, *id, node)?;
+        }
+        writeln!(out, "    </nodes>")?;
+
+        writeln!(out, "    <edges>")?;
+        for (id, rel) in &self.rels {
+            self.write_edge(out, *id, rel)?;
+        }
+        writeln!(out, "    </edges>")?;
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</gexf>")?;
+        Ok(())
+    }
+
+    fn write_node<W: Write>(&self, out: &mut W, id: u64, node: &Node) -> io::Result<()> {
+        match node {
+            Node::Data(d) => {
+                writeln!(
+                    out,
+                    r#"      <node id="{}" label="{}"{}>"#,
+                    id,
+                    escape(d.ty().name),
+                    span(self.time(d.ctx()), None)
+                )?;
+                writeln!(out, "        <attvalues>")?;
+                let statics = [
+                    ("pvm_type", d.pvm_ty().to_string()),
+                    ("type", d.ty().name.to_string()),
+                    ("uuid", d.uuid().to_hyphenated_ref().to_string()),
+                ];
+                for (key, val) in &statics {
+                    writeln!(
+                        out,
+                        r#"          <attvalue for="{}" value="{}"/>"#,
+                        key,
+                        escape(val)
+                    )?;
+                }
+                let mut history: BTreeMap<&str, Vec<(&str, ID)>> = BTreeMap::new();
+                for (key, val, ctx, _) in d.meta.iter() {
+                    history.entry(key).or_default().push((val, ctx));
+                }
+                for (key, vals) in &history {
+                    for (i, (val, ctx)) in vals.iter().enumerate() {
+                        let end = vals.get(i + 1).and_then(|(_, next)| self.time(*next));
+                        writeln!(
+                            out,
+                            r#"          <attvalue for="meta:{}" value="{}"{}/>"#,
+                            escape(key),
+                            escape(val),
+                            span(self.time(*ctx), end)
+                        )?;
+                    }
+                }
+                writeln!(out, "        </attvalues>")?;
+                writeln!(out, "      </node>")?;
+            }
+            Node::Name(n) => {
+                let label = match n {
+                    NameNode::Path(_, path) => path.clone(),
+                    NameNode::Net(_, addr, port) => format!("{}:{}", addr, port),
+                };
+                writeln!(
+                    out,
+                    r#"      <node id="{}" label="{}"/>"#,
+                    id,
+                    escape(&label)
+                )?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn write_edge<W: Write>(&self, out: &mut W, id: u64, rel: &Rel) -> io::Result<()> {
+        let (src, dst) = match rel {
+            Rel::Inf(r) => (r.get_src(), r.get_dst()),
+            Rel::Named(r) => (r.get_src(), r.get_dst()),
+        };
+        if !self.nodes.contains_key(&src.inner()) || !self.nodes.contains_key(&dst.inner()) {
+            return Ok(());
+        }
+        match rel {
+            Rel::Inf(r) => {
+                writeln!(
+                    out,
+                    r#"      <edge id="{}" source="{}" target="{}" label="{}"{}>"#,
+                    id,
+                    src.inner(),
+                    dst.inner(),
+                    r.pvm_op,
+                    span(self.time(r.ctx), None)
+                )?;
+                writeln!(out, "        <attvalues>")?;
+                writeln!(
+                    out,
+                    r#"          <attvalue for="bytes_read" value="{}"/>"#,
+                    r.bytes_read
+                )?;
+                writeln!(
+                    out,
+                    r#"          <attvalue for="bytes_written" value="{}"/>"#,
+                    r.bytes_written
+                )?;
+                writeln!(out, "        </attvalues>")?;
+                writeln!(out, "      </edge>")?;
+            }
+            Rel::Named(r) => {
+                let end = if r.end == ID::new(0) {
+                    None
+                } else {
+                    self.time(r.end)
+                };
+                writeln!(
+                    out,
+                    r#"      <edge id="{}" source="{}" target="{}" label="Named"{}/>"#,
+                    id,
+                    src.inner(),
+                    dst.inner(),
+                    span(self.time(r.start), end)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use pvm_plugins::testing::{self, ctx, inf, named, path, process, TestDir};
+
+    #[test]
+    fn writes_nodes_and_edges() {
+        let dir = TestDir::new("gexf-view");
+        let out = dir.join("prov.gexf");
+        let view = GexfView::new(0);
+        let params = testing::params(&view, &[("output", out.to_str().unwrap())]);
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            path(3, "/etc/passwd"),
+            inf(4, 2, 3, 1, 10),
+            named(5, 2, 3, 1),
+            // an edge to a node never seen is left out
+            inf(6, 2, 7, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+
+        let gexf = fs::read_to_string(out).unwrap();
+        let lines: Vec<&str> = gexf.lines().map(str::trim).collect();
+        let section = |tag: &str| {
+            let start = lines
+                .iter()
+                .position(|l| *l == format!("<{}>", tag))
+                .unwrap();
+            let end = lines
+                .iter()
+                .position(|l| *l == format!("</{}>", tag))
+                .unwrap();
+            lines[start + 1..end].to_vec()
+        };
+        let t = 1_577_836_801_000i64;
+        assert_eq!(
+            section("nodes"),
+            [
+                format!(r#"<node id="2" label="process" start="{}">"#, t),
+                "<attvalues>".to_string(),
+                r#"<attvalue for="pvm_type" value="Actor"/>"#.to_string(),
+                r#"<attvalue for="type" value="process"/>"#.to_string(),
+                format!(
+                    r#"<attvalue for="uuid" value="{}"/>"#,
+                    testing::uuid(2).to_hyphenated_ref()
+                ),
+                format!(
+                    r#"<attvalue for="meta:cmdline" value="/bin/sh" start="{}"/>"#,
+                    t
+                ),
+                "</attvalues>".to_string(),
+                "</node>".to_string(),
+                r#"<node id="3" label="/etc/passwd"/>"#.to_string(),
+            ]
+        );
+        assert_eq!(
+            section("edges"),
+            [
+                format!(
+                    r#"<edge id="4" source="2" target="3" label="Sink" start="{}">"#,
+                    t
+                ),
+                "<attvalues>".to_string(),
+                r#"<attvalue for="bytes_read" value="0"/>"#.to_string(),
+                r#"<attvalue for="bytes_written" value="10"/>"#.to_string(),
+                "</attvalues>".to_string(),
+                "</edge>".to_string(),
+                format!(
+                    r#"<edge id="5" source="2" target="3" label="Named" start="{}"/>"#,
+                    t
+                ),
+            ]
+        );
+    }
+}
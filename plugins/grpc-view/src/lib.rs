@@ -0,0 +1,383 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use pvm_plugins::{
+    define_plugin,
+    views::{
+        data::{
+            node_types::{NameNode, Node, SchemaNode},
+            rel_types::{PVMOps, Rel},
+            HasDst, HasID, HasSrc,
+        },
+        DBTr, InlineView, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("pvm");
+}
+
+use proto::pvm_stream_server::{PvmStream, PvmStreamServer};
+
+define_plugin!(views => [ GrpcView ]);
+
+#[derive(Debug)]
+pub struct GrpcView {
+    id: usize,
+}
+
+impl View for GrpcView {
+    fn new(id: usize) -> GrpcView {
+        GrpcView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "GrpcView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for streaming graph deltas to gRPC subscribers."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str(
+                "bind_addr",
+                "The address to serve the PVMStream service on.",
+            )
+            .default("127.0.0.1:50051"),
+            ParamDecl::int(
+                "max_client_buffer",
+                "The number of messages queued for a subscriber before it starts missing them.",
+            )
+            .default("1024"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let addr: SocketAddr = params
+            .get_str("bind_addr")
+            .unwrap()
+            .parse()
+            .map_err(|e| ViewError::InvalidParam("bind_addr".to_string(), format!("{}", e)))?;
+        let max_buffer = match params.get_int("max_client_buffer").unwrap() {
+            n if n >= 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "max_client_buffer".to_string(),
+                    format!("must not be negative, not {}", n),
+                ))
+            }
+        };
+        let thr = thread::Builder::new()
+            .name("GrpcView".to_string())
+            .spawn(move || {
+                let rt = tokio::runtime::Runtime::new().unwrap();
+                let hub = Arc::new(Mutex::new(Hub::default()));
+                let (stop, stopped) = oneshot::channel::<()>();
+                let service = Streamer {
+                    hub: hub.clone(),
+                    max_buffer,
+                };
+                let server = rt.spawn(
+                    Server::builder()
+                        .add_service(PvmStreamServer::new(service))
+                        .serve_with_shutdown(addr, async {
+                            stopped.await.ok();
+                        }),
+                );
+                stream.run(&mut Publisher(hub.clone()));
+                // subscribers are let go however the stream ends, or the server would wait on them
+                hub.lock().unwrap().close();
+                stop.send(()).ok();
+                if let Err(e) = rt.block_on(server).unwrap() {
+                    eprintln!("GrpcView server error: {}", e);
+                }
+            })?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// Passes every update on to the subscribers as it arrives, so a flush has nothing to write out
+#[derive(Debug)]
+struct Publisher(Arc<Mutex<Hub>>);
+
+impl InlineView for Publisher {
+    fn process(&mut self, evt: &DBTr) {
+        if let DBTr::Tick(_) = evt {
+            return;
+        }
+        self.0.lock().unwrap().publish(convert(evt));
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    name: String,
+    queue: mpsc::Sender<Result<proto::DbTr, Status>>,
+    dropped: usize,
+}
+
+/// The current subscribers, each with a bounded queue so a slow one misses messages rather than
+/// holding up ingestion
+#[derive(Debug, Default)]
+struct Hub {
+    subs: Vec<Subscriber>,
+}
+
+impl Hub {
+    fn publish(&mut self, msg: proto::DbTr) {
+        let mut i = 0;
+        while i < self.subs.len() {
+            match self.subs[i].queue.try_send(Ok(msg.clone())) {
+                Ok(()) => i += 1,
+                Err(TrySendError::Full(_)) => {
+                    self.subs[i].dropped += 1;
+                    i += 1;
+                }
+                Err(TrySendError::Closed(_)) => report(&self.subs.swap_remove(i)),
+            }
+        }
+    }
+
+    /// End every subscriber's stream once their queues drain
+    fn close(&mut self) {
+        for sub in self.subs.drain(..) {
+            report(&sub);
+        }
+    }
+}
+
+fn report(sub: &Subscriber) {
+    if sub.dropped > 0 {
+        eprintln!(
+            "GrpcView subscriber {} missed {} messages",
+            sub.name, sub.dropped
+        );
+    }
+}
+
+/// The PVMStream service, registering each subscriber with the view thread
+struct Streamer {
+    hub: Arc<Mutex<Hub>>,
+    max_buffer: usize,
+}
+
+#[tonic::async_trait]
+impl PvmStream for Streamer {
+    type SubscribeStream = ReceiverStream<Result<proto::DbTr, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let name = request
+            .remote_addr()
+            .map_or_else(|| "unknown".to_string(), |a| a.to_string());
+        let (send, recv) = mpsc::channel(self.max_buffer);
+        self.hub.lock().unwrap().subs.push(Subscriber {
+            name,
+            queue: send,
+            dropped: 0,
+        });
+        Ok(Response::new(ReceiverStream::new(recv)))
+    }
+}
+
+fn convert_op(op: PVMOps) -> proto::PvmOp {
+    match op {
+        PVMOps::Source => proto::PvmOp::Source,
+        PVMOps::Sink => proto::PvmOp::Sink,
+        PVMOps::Connect => proto::PvmOp::Connect,
+        PVMOps::Version => proto::PvmOp::Version,
+        PVMOps::Unknown => proto::PvmOp::Unknown,
+    }
+}
+
+fn convert_node(node: &Node) -> proto::Node {
+    use proto::node::Node as N;
+    let inner = match node {
+        Node::Data(d) => N::Data(proto::DataNode {
+            id: d.get_db_id().inner(),
+            pvm_type: d.pvm_ty().to_string(),
+            r#type: d.ty().name.to_string(),
+            uuid: d.uuid().to_hyphenated_ref().to_string(),
+            ctx: d.ctx().inner(),
+            meta: d
+                .meta
+                .iter_latest()
+                .map(|(k, v, _, _)| (k.to_string(), v.to_string()))
+                .collect(),
+        }),
+        Node::Ctx(c) => N::Ctx(proto::CtxNode {
+            id: c.get_db_id().inner(),
+            r#type: c.ty().name.to_string(),
+            cont: c
+                .cont
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        }),
+        Node::Name(n) => N::Name(proto::NameNode {
+            id: n.get_db_id().inner(),
+            name: Some(match n {
+                NameNode::Path(_, path) => proto::name_node::Name::Path(path.clone()),
+                NameNode::Net(_, addr, port) => proto::name_node::Name::Net(proto::NetName {
+                    addr: addr.clone(),
+                    port: u32::from(*port),
+                }),
+            }),
+        }),
+        Node::Schema(s) => N::Schema(match s {
+            SchemaNode::Data(id, ty) => proto::SchemaNode {
+                id: id.inner(),
+                pvm_type: ty.pvm_ty.to_string(),
+                r#type: ty.name.to_string(),
+                props: ty.props.keys().map(|p| p.to_string()).collect(),
+            },
+            SchemaNode::Context(id, ty) => proto::SchemaNode {
+                id: id.inner(),
+                pvm_type: String::new(),
+                r#type: ty.name.to_string(),
+                props: ty.props.iter().map(|p| p.to_string()).collect(),
+            },
+        }),
+    };
+    proto::Node { node: Some(inner) }
+}
+
+fn convert_rel(rel: &Rel) -> proto::Rel {
+    use proto::rel::Rel as R;
+    let inner = match rel {
+        Rel::Inf(r) => R::Inf(proto::InfRel {
+            id: r.get_db_id().inner(),
+            src: r.get_src().inner(),
+            dst: r.get_dst().inner(),
+            op: convert_op(r.pvm_op) as i32,
+            ctx: r.ctx.inner(),
+            bytes_read: r.bytes_read,
+            bytes_written: r.bytes_written,
+            event: r.event.clone().unwrap_or_default(),
+        }),
+        Rel::Named(r) => R::Named(proto::NamedRel {
+            id: r.get_db_id().inner(),
+            src: r.get_src().inner(),
+            dst: r.get_dst().inner(),
+            start: r.start.inner(),
+            end: r.end.inner(),
+            actor: r.actor.map_or(0, |a| a.inner()),
+        }),
+    };
+    proto::Rel { rel: Some(inner) }
+}
+
+fn convert(evt: &DBTr) -> proto::DbTr {
+    use proto::db_tr::Tr;
+    let tr = match evt {
+        DBTr::CreateNode(n) => Tr::CreateNode(convert_node(n)),
+        DBTr::UpdateNode(n) => Tr::UpdateNode(convert_node(n)),
+        DBTr::CreateRel(r) => Tr::CreateRel(convert_rel(r)),
+        DBTr::UpdateRel(r) => Tr::UpdateRel(convert_rel(r)),
+        DBTr::Flush(_) | DBTr::Tick(_) => unreachable!("Only updates are streamed"),
+    };
+    proto::DbTr { tr: Some(tr) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{net::TcpListener, sync::mpsc::channel, time::Duration};
+
+    use pvm_plugins::{
+        testing::{ctx, inf, process},
+        views::ViewMsg,
+    };
+    use tokio::runtime::Runtime;
+
+    use proto::{db_tr::Tr, node::Node as N, pvm_stream_client::PvmStreamClient};
+
+    #[test]
+    fn streams_to_subscribers() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let view = GrpcView::new(0);
+        let mut params = ViewParams::new();
+        params.insert_param("bind_addr", format!("127.0.0.1:{}", port));
+        params.resolve(&view.params()).unwrap();
+        let (send, recv) = channel();
+        let inst = view.create(0, params, ViewStream::new(recv)).unwrap();
+
+        let rt = Runtime::new().unwrap();
+        let mut updates = rt.block_on(async {
+            let mut client = loop {
+                match PvmStreamClient::connect(format!("http://127.0.0.1:{}", port)).await {
+                    Ok(client) => break client,
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            client
+                .subscribe(proto::SubscribeRequest {})
+                .await
+                .unwrap()
+                .into_inner()
+        });
+        for evt in &[
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            inf(3, 2, 2, 1, 10),
+        ] {
+            send.send(ViewMsg::Event(Arc::new(evt.clone()))).unwrap();
+        }
+        send.send(ViewMsg::Shutdown).unwrap();
+        inst.handle.join().unwrap();
+
+        let mut msgs = Vec::new();
+        while let Some(msg) = rt.block_on(updates.message()).unwrap() {
+            msgs.push(msg.tr.unwrap());
+        }
+        assert_eq!(msgs.len(), 3);
+        match &msgs[1] {
+            Tr::CreateNode(proto::Node {
+                node: Some(N::Data(d)),
+            }) => {
+                assert_eq!((d.id, d.ctx), (2, 1));
+                assert_eq!(d.r#type, "process");
+                assert_eq!(d.meta["cmdline"], "/bin/sh");
+            }
+            tr => panic!("expected a data node, not {:?}", tr),
+        }
+        match &msgs[2] {
+            Tr::CreateRel(proto::Rel {
+                rel: Some(proto::rel::Rel::Inf(r)),
+            }) => {
+                assert_eq!((r.id, r.src, r.dst), (3, 2, 2));
+                assert_eq!(r.op, proto::PvmOp::Sink as i32);
+                assert_eq!(r.bytes_written, 10);
+            }
+            tr => panic!("expected an information flow, not {:?}", tr),
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    thread,
+};
+
+use pvm_plugins::{
+    compress::{Compression, Encoder, COMPRESS_PARAM},
+    define_plugin,
+    output::{Output, Sender},
+    views::{
+        data::{
+            node_types::{NameNode, Node},
+            rel_types::Rel,
+            HasDst, HasID, HasSrc, ID,
+        },
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use chrono::Utc;
+
+define_plugin!(views => [ NQuadsView ]);
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+const XSD_DATETIME: &str = "<http://www.w3.org/2001/XMLSchema#dateTime>";
+const XSD_LONG: &str = "<http://www.w3.org/2001/XMLSchema#long>";
+
+#[derive(Debug)]
+pub struct NQuadsView {
+    id: usize,
+}
+
+impl View for NQuadsView {
+    fn new(id: usize) -> NQuadsView {
+        NQuadsView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "NQuadsView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for writing the graph as RDF N-Quads for loading into a triple store."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::path("output", "Output file location").default("./prov.nq"),
+            ParamDecl::str(
+                "base_iri",
+                "IRI that node, relation and property IRIs are minted under",
+            )
+            .default("urn:pvm:"),
+            ParamDecl::str(
+                "graph",
+                "IRI of the named graph to write into, defaults to one per session",
+            ),
+            COMPRESS_PARAM,
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let path = params.get_path("output").unwrap();
+        let base = params.get_str("base_iri").unwrap().to_string();
+        let graph = match params.get_str("graph") {
+            None => format!(
+                "{}session/{}",
+                base,
+                Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+            ),
+            Some(g) => g.to_string(),
+        };
+        let out = Latest {
+            nodes: BTreeMap::new(),
+            rels: BTreeMap::new(),
+            quads: Some(Quads {
+                out: Compression::from_params(&params)?
+                    .create(path)
+                    .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?,
+                base,
+                graph: format!("<{}>", graph),
+            }),
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("NQuadsView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+fn literal(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn typed(s: &str, ty: &str) -> String {
+    format!("{}^^{}", literal(s), ty)
+}
+
+/// Percent-encode anything that may not appear unescaped in an IRI
+fn iri_part(s: &str) -> String {
+    let mut ret = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                ret.push(b as char)
+            }
+            b => ret += &format!("%{:02X}", b),
+        }
+    }
+    ret
+}
+
+/// The latest state of every node and relation, written once the stream is shut down
+#[derive(Debug)]
+struct Latest {
+    nodes: BTreeMap<u64, Node>,
+    rels: BTreeMap<u64, Rel>,
+    quads: Option<Quads<Encoder<BufWriter<File>>>>,
+}
+
+impl Output for Latest {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        match *evt {
+            DBTr::CreateNode(ref n) | DBTr::UpdateNode(ref n) => {
+                self.nodes.insert(n.get_db_id().inner(), n.clone());
+            }
+            DBTr::CreateRel(ref r) | DBTr::UpdateRel(ref r) => {
+                self.rels.insert(r.get_db_id().inner(), r.clone());
+            }
+            DBTr::Flush(_) | DBTr::Tick(_) => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut quads = self.quads.take().unwrap();
+        for node in self.nodes.values() {
+            quads.node(node)?;
+        }
+        for rel in self.rels.values() {
+            quads.rel(rel)?;
+        }
+        quads.out.finish()?.flush()
+    }
+}
+
+/// Writer of quads into a single named graph, with IRIs minted under a base
+#[derive(Debug)]
+struct Quads<W: Write> {
+    out: W,
+    base: String,
+    graph: String,
+}
+
+impl<W: Write> Quads<W> {
+    fn iri(&self, path: &str) -> String {
+        format!("<{}{}>", self.base, path)
+    }
+
+    fn node_iri(&self, id: ID) -> String {
+        self.iri(&format!("node/{}", id.inner()))
+    }
+
+    fn ctx_iri(&self, id: ID) -> String {
+        self.iri(&format!("context/{}", id.inner()))
+    }
+
+    fn quad(&mut self, s: &str, p: &str, o: &str) -> io::Result<()> {
+        writeln!(self.out, "{} {} {} {} .", s, p, o, self.graph)
+    }
+
+    fn node(&mut self, node: &Node) -> io::Result<()> {
+        match node {
+            Node::Data(d) => {
+                let s = self.node_iri(d.get_db_id());
+                self.quad(&s, RDF_TYPE, &self.iri(&d.pvm_ty().to_string()))?;
+                self.quad(
+                    &s,
+                    RDF_TYPE,
+                    &self.iri(&format!("type/{}", iri_part(d.ty().name))),
+                )?;
+                self.quad(
+                    &s,
+                    &self.iri("uuid"),
+                    &literal(&d.uuid().to_hyphenated_ref().to_string()),
+                )?;
+                self.quad(&s, &self.iri("ctx"), &self.ctx_iri(d.ctx()))?;
+                for (key, val, _, _) in d.meta.iter_latest() {
+                    self.quad(
+                        &s,
+                        &self.iri(&format!("meta/{}", iri_part(key))),
+                        &literal(val),
+                    )?;
+                }
+            }
+            Node::Ctx(c) => {
+                let s = self.ctx_iri(c.get_db_id());
+                self.quad(&s, RDF_TYPE, &self.iri("Context"))?;
+                self.quad(
+                    &s,
+                    RDF_TYPE,
+                    &self.iri(&format!("type/{}", iri_part(c.ty().name))),
+                )?;
+                for (key, val) in &c.cont {
+                    let o = if *key == "time" {
+                        typed(val, XSD_DATETIME)
+                    } else {
+                        literal(val)
+                    };
+                    self.quad(&s, &self.iri(&format!("ctx/{}", iri_part(key))), &o)?;
+                }
+            }
+            Node::Name(n) => {
+                let s = self.node_iri(n.get_db_id());
+                match n {
+                    NameNode::Path(_, path) => {
+                        self.quad(&s, RDF_TYPE, &self.iri("Path"))?;
+                        self.quad(&s, &self.iri("path"), &literal(path))?;
+                    }
+                    NameNode::Net(_, addr, port) => {
+                        self.quad(&s, RDF_TYPE, &self.iri("Net"))?;
+                        self.quad(&s, &self.iri("addr"), &literal(addr))?;
+                        self.quad(&s, &self.iri("port"), &typed(&port.to_string(), XSD_LONG))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Write a relation both as a plain edge between its ends, for simple queries, and as a
+    /// resource of its own carrying its properties
+    fn rel(&mut self, rel: &Rel) -> io::Result<()> {
+        let s = self.iri(&format!("rel/{}", rel.get_db_id().inner()));
+        let src = self.node_iri(rel.get_src());
+        let dst = self.node_iri(rel.get_dst());
+        match rel {
+            Rel::Inf(r) => {
+                let op = self.iri(&format!("op/{}", r.pvm_op));
+                self.quad(&src, &op, &dst)?;
+                self.quad(&s, RDF_TYPE, &self.iri("Inf"))?;
+                self.quad(&s, &self.iri("op"), &literal(&r.pvm_op.to_string()))?;
+                self.quad(&s, &self.iri("ctx"), &self.ctx_iri(r.ctx))?;
+                self.quad(
+                    &s,
+                    &self.iri("bytes_read"),
+                    &typed(&r.bytes_read.to_string(), XSD_LONG),
+                )?;
+                self.quad(
+                    &s,
+                    &self.iri("bytes_written"),
+                    &typed(&r.bytes_written.to_string(), XSD_LONG),
+                )?;
+                if let Some(event) = &r.event {
+                    self.quad(&s, &self.iri("event"), &literal(event))?;
+                }
+            }
+            Rel::Named(r) => {
+                self.quad(&src, &self.iri("named"), &dst)?;
+                self.quad(&s, RDF_TYPE, &self.iri("Named"))?;
+                self.quad(&s, &self.iri("start"), &self.ctx_iri(r.start))?;
+                if r.end != ID::new(0) {
+                    self.quad(&s, &self.iri("end"), &self.ctx_iri(r.end))?;
+                }
+                if let Some(actor) = r.actor {
+                    self.quad(&s, &self.iri("actor"), &self.node_iri(actor))?;
+                }
+            }
+        }
+        self.quad(&s, &self.iri("src"), &src)?;
+        self.quad(&s, &self.iri("dst"), &dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use pvm_plugins::testing::{self, ctx, inf, path, process, TestDir};
+
+    #[test]
+    fn writes_latest_state() {
+        let dir = TestDir::new("nquads-view");
+        let out = dir.join("prov.nq");
+        let view = NQuadsView::new(0);
+        let params = testing::params(
+            &view,
+            &[("output", out.to_str().unwrap()), ("graph", "urn:g")],
+        );
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            path(3, "/etc/passwd"),
+            inf(4, 2, 3, 1, 10),
+            inf(4, 2, 3, 1, 20),
+        ];
+        testing::run(&view, params, evts).unwrap();
+
+        let nq = fs::read_to_string(out).unwrap();
+        let quads: Vec<&str> = nq.lines().collect();
+        let long = |n: u64| format!("\"{}\"^^{}", n, XSD_LONG);
+        for quad in &[
+            format!(
+                r#"<urn:pvm:context/1> <urn:pvm:ctx/time> "2020-01-01T00:00:01Z"^^{} <urn:g> ."#,
+                XSD_DATETIME
+            ),
+            format!("<urn:pvm:node/2> {} <urn:pvm:Actor> <urn:g> .", RDF_TYPE),
+            r#"<urn:pvm:node/2> <urn:pvm:meta/cmdline> "/bin/sh" <urn:g> ."#.to_string(),
+            r#"<urn:pvm:node/3> <urn:pvm:path> "/etc/passwd" <urn:g> ."#.to_string(),
+            "<urn:pvm:node/2> <urn:pvm:op/Sink> <urn:pvm:node/3> <urn:g> .".to_string(),
+            format!(
+                "<urn:pvm:rel/4> <urn:pvm:bytes_written> {} <urn:g> .",
+                long(20)
+            ),
+        ] {
+            assert!(quads.contains(&&quad[..]), "missing {}", quad);
+        }
+        assert!(!quads.contains(
+            &&format!(
+                "<urn:pvm:rel/4> <urn:pvm:bytes_written> {} <urn:g> .",
+                long(10)
+            )[..]
+        ));
+        assert!(quads.iter().all(|q| q.ends_with(" <urn:g> .")));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fs::File, io, thread, time::Instant};
+
+use pvm_plugins::{
+    define_plugin,
+    output::{Output, Sender},
+    views::{DBTr, InlineView, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream},
+};
+
+use serde_derive::Serialize;
+
+define_plugin!(views => [ NullView ]);
+
+#[derive(Debug)]
+pub struct NullView {
+    id: usize,
+}
+
+impl View for NullView {
+    fn new(id: usize) -> NullView {
+        NullView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "NullView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for discarding the stream while measuring its throughput, for benchmarking."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![ParamDecl::path(
+            "output",
+            "File to also write the throughput to as JSON",
+        )]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let counter = Counter::from_params(&params)?;
+        let mut counter = Sender::new(self.name(), counter, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("NullView".to_string())
+            .spawn(move || stream.run(&mut counter))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+    fn create_inline(
+        &self,
+        _id: usize,
+        params: &ViewParams,
+    ) -> Result<Box<dyn InlineView>, ViewError> {
+        let counter = Counter::from_params(params)?;
+        Ok(Box::new(Sender::new(
+            self.name(),
+            counter,
+            params.error_sink(),
+        )))
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Throughput {
+    events: u64,
+    nodes: u64,
+    node_updates: u64,
+    rels: u64,
+    rel_updates: u64,
+    /// Seconds from the first event to the last
+    secs: f64,
+    events_per_sec: f64,
+}
+
+#[derive(Debug)]
+struct Counter {
+    output: Option<File>,
+    counts: Throughput,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Counter {
+    fn from_params(params: &ViewParams) -> Result<Self, ViewError> {
+        let output = params
+            .get_path("output")
+            .map(File::create)
+            .transpose()
+            .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?;
+        Ok(Counter {
+            output,
+            counts: Throughput::default(),
+            first: None,
+            last: None,
+        })
+    }
+
+    fn add(&mut self, tr: &DBTr) {
+        if let DBTr::Tick(_) = tr {
+            return;
+        }
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.counts.events += 1;
+        match tr {
+            DBTr::CreateNode(_) => self.counts.nodes += 1,
+            DBTr::UpdateNode(_) => self.counts.node_updates += 1,
+            DBTr::CreateRel(_) => self.counts.rels += 1,
+            DBTr::UpdateRel(_) => self.counts.rel_updates += 1,
+            DBTr::Flush(_) | DBTr::Tick(_) => {}
+        }
+    }
+
+    fn report(&mut self) -> io::Result<()> {
+        if let (Some(first), Some(last)) = (self.first, self.last) {
+            self.counts.secs = last.duration_since(first).as_secs_f64();
+        }
+        if self.counts.secs > 0.0 {
+            self.counts.events_per_sec = self.counts.events as f64 / self.counts.secs;
+        }
+        eprintln!(
+            "NullView: {} events in {:.3}s, {:.0} events/s",
+            self.counts.events, self.counts.secs, self.counts.events_per_sec
+        );
+        if let Some(ref mut out) = self.output {
+            serde_json::to_writer_pretty(out, &self.counts)?;
+        }
+        Ok(())
+    }
+}
+
+impl Output for Counter {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        self.add(evt);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, sync::mpsc::channel};
+
+    use pvm_plugins::testing::{self, ctx, inf, process, TestDir};
+    use serde_json::Value;
+
+    #[test]
+    fn writes_throughput() {
+        let dir = TestDir::new("null-view");
+        let out = dir.join("throughput.json");
+        let view = NullView::new(0);
+        let params = testing::params(&view, &[("output", out.to_str().unwrap())]);
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            inf(3, 2, 2, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+        let counts: Value = serde_json::from_str(&fs::read_to_string(out).unwrap()).unwrap();
+        assert_eq!(counts["events"], 3);
+        assert_eq!(counts["nodes"], 2);
+        assert_eq!(counts["rels"], 1);
+        assert_eq!(counts["node_updates"], 0);
+    }
+
+    #[test]
+    fn unopenable_output_is_an_error() {
+        let dir = TestDir::new("null-view-missing");
+        let view = NullView::new(0);
+        let out = dir.join("missing").join("throughput.json");
+        let params = testing::params(&view, &[("output", out.to_str().unwrap())]);
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "output"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created with an output in a missing directory"),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::{collections::HashMap, fmt, fs::File, io::BufWriter, path::Path, sync::Arc, thread};
+
+use pvm_plugins::{
+    compress::Compression,
+    define_plugin,
+    output::{Output, Sender},
+    rows::{node_row, rel_row, Kind, Kind::*, Row, Value, NODE_COLUMNS, REL_COLUMNS},
+    views::{data::ID, DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream},
+};
+
+use parquet::{
+    basic::Compression as Codec,
+    data_type::{BoolType, ByteArray, ByteArrayType, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+define_plugin!(views => [ ParquetView ]);
+
+#[derive(Debug)]
+pub struct ParquetView {
+    id: usize,
+}
+
+impl View for ParquetView {
+    fn new(id: usize) -> ParquetView {
+        ParquetView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "ParquetView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for writing nodes and relations to columnar Parquet files."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::path("nodes", "Output file for nodes").default("./prov_nodes.parquet"),
+            ParamDecl::path("rels", "Output file for relations").default("./prov_rels.parquet"),
+            ParamDecl::int("row_group", "Number of rows buffered per row group").default("65536"),
+            ParamDecl::str(
+                "compress",
+                "Compression codec of the column chunks, either none, gzip or zstd",
+            )
+            .default("none"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let group = match params.get_int("row_group").unwrap() {
+            n if n > 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "row_group".to_string(),
+                    format!("must be a positive integer, not {}", n),
+                ))
+            }
+        };
+        let codec = match Compression::from_params(&params)? {
+            Compression::None => Codec::UNCOMPRESSED,
+            Compression::Gzip => Codec::GZIP(Default::default()),
+            Compression::Zstd => Codec::ZSTD(Default::default()),
+        };
+        let out = ParquetWriter {
+            nodes: Table::new(
+                params.get_path("nodes").unwrap(),
+                "nodes",
+                NODE_COLUMNS,
+                group,
+                codec,
+            )
+            .map_err(|e| ViewError::InvalidParam("nodes".to_string(), e.to_string()))?,
+            rels: Table::new(
+                params.get_path("rels").unwrap(),
+                "rels",
+                REL_COLUMNS,
+                group,
+                codec,
+            )
+            .map_err(|e| ViewError::InvalidParam("rels".to_string(), e.to_string()))?,
+            times: HashMap::new(),
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("ParquetView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// Writes the buffered rows out as a row group on a flush, though the files are only readable
+/// once closed as the footer holding their metadata is written last
+#[derive(Debug)]
+struct ParquetWriter {
+    nodes: Table,
+    rels: Table,
+    times: HashMap<ID, i64>,
+}
+
+impl Output for ParquetWriter {
+    type Error = ParquetError;
+
+    fn write(&mut self, evt: &DBTr) -> Result<(), ParquetError> {
+        match *evt {
+            DBTr::CreateNode(ref n) => {
+                if let Some(row) = node_row(&mut self.times, n, false) {
+                    self.nodes.push(row)?;
+                }
+            }
+            DBTr::UpdateNode(ref n) => {
+                if let Some(row) = node_row(&mut self.times, n, true) {
+                    self.nodes.push(row)?;
+                }
+            }
+            DBTr::CreateRel(ref r) => self.rels.push(rel_row(&self.times, r, false))?,
+            DBTr::UpdateRel(ref r) => self.rels.push(rel_row(&self.times, r, true))?,
+            DBTr::Flush(_) | DBTr::Tick(_) => {}
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ParquetError> {
+        self.nodes.flush()?;
+        self.rels.flush()
+    }
+
+    fn finish(&mut self) -> Result<(), ParquetError> {
+        self.nodes.close()?;
+        self.rels.close()
+    }
+}
+
+enum Column {
+    Int(Vec<i64>),
+    Bool(Vec<bool>),
+    Str(Vec<ByteArray>),
+}
+
+/// A Parquet file being written, with the rows of its current row group buffered by column
+///
+/// Every column is optional, with a definition level of 1 for rows holding a value.
+struct Table {
+    writer: SerializedFileWriter<BufWriter<File>>,
+    cols: Vec<(Column, Vec<i16>)>,
+    rows: usize,
+    group: usize,
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("rows", &self.rows)
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+impl Table {
+    fn new(
+        path: &Path,
+        name: &str,
+        columns: &[(&str, Kind)],
+        group: usize,
+        codec: Codec,
+    ) -> Result<Table, ParquetError> {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|(col, kind)| match kind {
+                Int => format!("OPTIONAL INT64 {};", col),
+                Time => format!("OPTIONAL INT64 {} (TIMESTAMP_MILLIS);", col),
+                Bool => format!("OPTIONAL BOOLEAN {};", col),
+                Str => format!("OPTIONAL BINARY {} (UTF8);", col),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message {} {{ {} }}", name, fields.join(" ")))?;
+        let writer = SerializedFileWriter::new(
+            BufWriter::new(File::create(path)?),
+            Arc::new(schema),
+            Arc::new(WriterProperties::builder().set_compression(codec).build()),
+        )?;
+        let cols = columns
+            .iter()
+            .map(|(_, kind)| {
+                let col = match kind {
+                    Int | Time => Column::Int(Vec::new()),
+                    Bool => Column::Bool(Vec::new()),
+                    Str => Column::Str(Vec::new()),
+                };
+                (col, Vec::new())
+            })
+            .collect();
+        Ok(Table {
+            writer,
+            cols,
+            rows: 0,
+            group: group.max(1),
+        })
+    }
+
+    fn push(&mut self, row: Row) -> Result<(), ParquetError> {
+        for ((col, defs), val) in self.cols.iter_mut().zip(row) {
+            match (col, val) {
+                (Column::Int(vals), Some(Value::Int(v))) => vals.push(v),
+                (Column::Bool(vals), Some(Value::Bool(v))) => vals.push(v),
+                (Column::Str(vals), Some(Value::Str(v))) => {
+                    vals.push(ByteArray::from(v.into_bytes()))
+                }
+                (_, None) => {
+                    defs.push(0);
+                    continue;
+                }
+                _ => panic!("Value does not match its column type"),
+            }
+            defs.push(1);
+        }
+        self.rows += 1;
+        if self.rows >= self.group {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ParquetError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let mut group = self.writer.next_row_group()?;
+        for (col, defs) in &mut self.cols {
+            let mut writer = group
+                .next_column()?
+                .expect("Table has a column writer for each of its columns");
+            match col {
+                Column::Int(vals) => {
+                    writer
+                        .typed::<Int64Type>()
+                        .write_batch(vals, Some(defs), None)?;
+                    vals.clear();
+                }
+                Column::Bool(vals) => {
+                    writer
+                        .typed::<BoolType>()
+                        .write_batch(vals, Some(defs), None)?;
+                    vals.clear();
+                }
+                Column::Str(vals) => {
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(vals, Some(defs), None)?;
+                    vals.clear();
+                }
+            }
+            writer.close()?;
+            defs.clear();
+        }
+        group.close()?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), ParquetError> {
+        self.flush()?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::{Row as Record, RowAccessor},
+    };
+    use pvm_plugins::testing::{self, ctx, file, inf, named, path, process, TestDir};
+
+    fn read(path: &Path) -> (usize, Vec<Record>) {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        let groups = reader.num_row_groups();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        (groups, rows)
+    }
+
+    #[test]
+    fn round_trips_rows() {
+        let dir = TestDir::new("parquet-view");
+        let (nodes, rels) = (dir.join("nodes.parquet"), dir.join("rels.parquet"));
+        let view = ParquetView::new(0);
+        let params = testing::params(
+            &view,
+            &[
+                ("nodes", nodes.to_str().unwrap()),
+                ("rels", rels.to_str().unwrap()),
+                ("row_group", "2"),
+                ("compress", "zstd"),
+            ],
+        );
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            file(3, 1),
+            path(4, "/tmp/out"),
+            named(5, 3, 4, 1),
+            inf(6, 2, 3, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+
+        let (groups, rows) = read(&nodes);
+        assert_eq!(groups, 2);
+        assert_eq!(rows.len(), 4);
+        let proc = &rows[1];
+        assert_eq!(proc.get_long(0).unwrap(), 2);
+        assert!(!proc.get_bool(1).unwrap());
+        assert_eq!(proc.get_string(2).unwrap(), "Data");
+        assert_eq!(proc.get_string(4).unwrap(), "process");
+        assert_eq!(proc.get_long(7).unwrap(), 1);
+        assert_eq!(proc.get_timestamp_millis(8).unwrap(), 1_577_836_801_000);
+        assert_eq!(proc.get_string(9).unwrap(), r#"{"cmdline":"/bin/sh"}"#);
+        assert_eq!(rows[3].get_string(6).unwrap(), "/tmp/out");
+
+        let (groups, rows) = read(&rels);
+        assert_eq!(groups, 1);
+        assert_eq!(rows.len(), 2);
+        let named = &rows[0];
+        assert_eq!(named.get_string(2).unwrap(), "Named");
+        assert!(named.get_long(8).is_err());
+        let inf = &rows[1];
+        assert_eq!((inf.get_long(3).unwrap(), inf.get_long(4).unwrap()), (2, 3));
+        assert_eq!(inf.get_string(5).unwrap(), "Sink");
+        assert_eq!(inf.get_long(12).unwrap(), 10);
+    }
+}
@@ -1,43 +1,67 @@
 #![feature(custom_attribute)]
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::Write,
-    sync::{mpsc::Receiver, Arc},
+    collections::{HashMap, HashSet},
+    io::{self, Write},
     thread,
 };
 
 use pvm_plugins::{
+    compress::{Compression, COMPRESS_PARAM},
     define_plugin,
+    output::{Output, Sender},
+    rotate::{RotatingWriter, Rotation, ROTATE_PARAMS},
     views::{
         data::{
             node_types::{CtxNode, Node, PVMDataType},
-            rel_types::Rel,
+            rel_types::{PVMOps, Rel},
             HasDst, HasID, HasSrc, ID,
         },
-        DBTr, View, ViewInst, ViewParams, ViewParamsExt,
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
     },
 };
 
-use maplit::hashmap;
 use serde_derive::Serialize;
 use serde_json::to_writer;
 
 define_plugin!(views => [ ProcTreeView ]);
 
+/// Metadata keys of the process properties recorded besides its name
+const PID_KEY: &str = "pid";
+const EXIT_KEY: &str = "exit_status";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Start {
+    /// The first time a process is seen
+    Spawn,
+    /// A process seen before running a new command
+    Exec,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 enum Record<'a> {
     Node {
         id: ID,
+        start: Start,
+        pid: Option<&'a str>,
         cmd: Option<&'a str>,
         host: Option<i32>,
         trace_idx: Option<&'a str>,
         ts: Option<&'a str>,
     },
+    Exit {
+        id: ID,
+        status: &'a str,
+        trace_idx: Option<&'a str>,
+        ts: Option<&'a str>,
+    },
     Edge {
         src: ID,
         dst: ID,
+        op: PVMOps,
+        trace_idx: Option<&'a str>,
+        ts: Option<&'a str>,
     },
     HostVal {
         uuid: &'a str,
@@ -45,6 +69,31 @@ enum Record<'a> {
     },
 }
 
+fn emit(out: &mut RotatingWriter, rec: &Record) -> io::Result<()> {
+    to_writer(&mut *out, rec)?;
+    writeln!(out)?;
+    out.end_record()?;
+    out.flush()
+}
+
+/// The context `id`, moving the current context into the store if it is the one asked for
+fn ctx_of<'a>(
+    cur_ctx: &mut Option<CtxNode>,
+    ctx_store: &'a mut HashMap<ID, CtxNode>,
+    id: ID,
+) -> Option<&'a CtxNode> {
+    if let Some(c) = cur_ctx {
+        if c.get_db_id() == id {
+            ctx_store.insert(id, cur_ctx.take().unwrap());
+        }
+    }
+    ctx_store.get(&id)
+}
+
+fn ctx_val<'a>(ctx: Option<&'a CtxNode>, key: &str) -> Option<&'a str> {
+    ctx.and_then(|c| c.cont.get(key)).map(|v| &v[..])
+}
+
 #[derive(Debug)]
 pub struct ProcTreeView {
     id: usize,
@@ -71,107 +120,182 @@ impl View for ProcTreeView {
         "ProcTreeView"
     }
     fn desc(&self) -> &'static str {
-        "View for storing a process tree."
+        "View for storing a process tree, with the lifespan of each process."
     }
-    fn params(&self) -> HashMap<&'static str, &'static str> {
-        hashmap!("output" => "Output file location",
-                 "meta_key" => "Metadata key for process name")
+    fn params(&self) -> Vec<ParamDecl> {
+        let mut params = vec![
+            ParamDecl::path("output", "Output file location").default("./proc_tree.json"),
+            ParamDecl::str("meta_key", "Metadata key for process name").default("cmdline"),
+            ParamDecl::list("edge_ops", "PVM operations treated as tree edges").default("Version"),
+        ];
+        params.extend_from_slice(&ROTATE_PARAMS);
+        params.push(COMPRESS_PARAM);
+        params
     }
-    fn create(&self, id: usize, params: ViewParams, stream: Receiver<Arc<DBTr>>) -> ViewInst {
-        let path = params.get_or_def("output", "./proc_tree.json");
-        let meta_key = params.get_or_def("meta_key", "cmdline").to_string();
-        let mut out = File::create(path).unwrap();
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let path = params.get_path("output").unwrap();
+        let meta_key = params.get_str("meta_key").unwrap().to_string();
+        let edge_ops: HashSet<PVMOps> = params
+            .get_list("edge_ops")
+            .unwrap()
+            .iter()
+            .map(|op| op.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| ViewError::InvalidParam("edge_ops".to_string(), format!("{}", e)))?;
+        let out = RotatingWriter::create(
+            path,
+            Rotation::from_params(&params)?,
+            Compression::from_params(&params)?,
+        )
+        .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?;
+        let out = ProcTree {
+            out,
+            meta_key,
+            edge_ops,
+            nodes: HashMap::new(),
+            exited: HashSet::new(),
+            ctx_store: HashMap::new(),
+            cur_ctx: None,
+            host_map: HashMap::new(),
+            host_count: 0,
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
         let thr = thread::Builder::new()
             .name("ProcTreeView".to_string())
-            .spawn(move || {
-                let mut nodes = HashMap::new();
-                let mut ctx_store: HashMap<ID, CtxNode> = HashMap::new();
-                let mut cur_ctx: Option<CtxNode> = None;
-                let mut host_map = HashMap::new();
-                let mut host_count = 0;
-                for tr in stream {
-                    match *tr {
-                        DBTr::CreateNode(ref n) | DBTr::UpdateNode(ref n) => match n {
-                            Node::Data(n) if *n.pvm_ty() == PVMDataType::Actor => {
-                                let id = n.get_db_id();
-                                let cmd = n.meta.cur(&meta_key);
-                                if !nodes.contains_key(&id) || neq(&cmd, &nodes[&id]) {
-                                    if let Some(c) = &cur_ctx {
-                                        if c.get_db_id() == n.ctx() {
-                                            ctx_store
-                                                .insert(c.get_db_id(), cur_ctx.take().unwrap());
-                                        }
-                                    }
-                                    let ctx = ctx_store.get(&n.ctx());
-                                    let trace_idx = ctx
-                                        .and_then(|c| c.cont.get("trace_offset"))
-                                        .map(|v| &v[..]);
-                                    let ts = ctx.and_then(|c| c.cont.get("time")).map(|v| &v[..]);
-                                    let host = ctx.and_then(|c| c.cont.get("host"));
-
-                                    let host = if let Some(h) = host {
-                                        if host_map.contains_key(h) {
-                                            Some(host_map[h])
-                                        } else {
-                                            host_count += 1;
-                                            host_map.insert(h.clone(), host_count);
-                                            to_writer(
-                                                &mut out,
-                                                &Record::HostVal {
-                                                    uuid: h,
-                                                    idx: host_count,
-                                                },
-                                            )
-                                            .unwrap();
-                                            writeln!(out).unwrap();
-                                            Some(host_count)
-                                        }
-                                    } else {
-                                        None
-                                    };
-
-                                    to_writer(
-                                        &mut out,
-                                        &Record::Node {
-                                            id,
-                                            cmd,
-                                            host,
-                                            trace_idx,
-                                            ts,
-                                        },
-                                    )
-                                    .unwrap();
-                                    writeln!(out).unwrap();
-                                    out.flush().unwrap();
-                                    nodes.insert(id, cmd.map(|v| v.to_string()));
-                                }
-                            }
-                            Node::Ctx(n) => {
-                                cur_ctx = Some(n.clone());
-                            }
-                            _ => {}
-                        },
-                        DBTr::CreateRel(ref r) => {
-                            if let Rel::Inf(r) = r {
-                                let src = r.get_src();
-                                let dst = r.get_dst();
-                                if nodes.contains_key(&src) && nodes.contains_key(&dst) {
-                                    to_writer(&mut out, &Record::Edge { src, dst }).unwrap();
-                                    writeln!(out).unwrap();
-                                    out.flush().unwrap();
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            })
-            .unwrap();
-        ViewInst {
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
             id,
             vtype: self.id,
             params,
             handle: thr,
+        })
+    }
+}
+
+/// The processes seen so far, with their records written out as they start, exec and exit
+#[derive(Debug)]
+struct ProcTree {
+    out: RotatingWriter,
+    meta_key: String,
+    edge_ops: HashSet<PVMOps>,
+    /// The command of each process started
+    nodes: HashMap<ID, Option<String>>,
+    exited: HashSet<ID>,
+    ctx_store: HashMap<ID, CtxNode>,
+    cur_ctx: Option<CtxNode>,
+    host_map: HashMap<String, i32>,
+    host_count: i32,
+}
+
+impl Output for ProcTree {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        match *evt {
+            DBTr::CreateNode(ref n) | DBTr::UpdateNode(ref n) => match n {
+                Node::Data(n) if *n.pvm_ty() == PVMDataType::Actor => {
+                    let id = n.get_db_id();
+                    let cmd = n.meta.cur(&self.meta_key);
+                    let status = n.meta.cur(EXIT_KEY);
+                    let started = self.nodes.contains_key(&id);
+                    let exec = started && neq(&cmd, &self.nodes[&id]);
+                    let exit = status.is_some() && !self.exited.contains(&id);
+                    if !started || exec {
+                        let ctx = match n.meta.cur_ctx(&self.meta_key) {
+                            Some(ctx) if exec => ctx,
+                            _ => n.ctx(),
+                        };
+                        let ctx = ctx_of(&mut self.cur_ctx, &mut self.ctx_store, ctx);
+                        let host = if let Some(h) = ctx_val(ctx, "host") {
+                            if self.host_map.contains_key(h) {
+                                Some(self.host_map[h])
+                            } else {
+                                self.host_count += 1;
+                                self.host_map.insert(h.to_string(), self.host_count);
+                                emit(
+                                    &mut self.out,
+                                    &Record::HostVal {
+                                        uuid: h,
+                                        idx: self.host_count,
+                                    },
+                                )?;
+                                Some(self.host_count)
+                            }
+                        } else {
+                            None
+                        };
+
+                        emit(
+                            &mut self.out,
+                            &Record::Node {
+                                id,
+                                start: if exec { Start::Exec } else { Start::Spawn },
+                                pid: n.meta.cur(PID_KEY),
+                                cmd,
+                                host,
+                                trace_idx: ctx_val(ctx, "trace_offset"),
+                                ts: ctx_val(ctx, "time"),
+                            },
+                        )?;
+                        self.nodes.insert(id, cmd.map(|v| v.to_string()));
+                    }
+                    if let (true, Some(status)) = (exit, status) {
+                        let ctx = n.meta.cur_ctx(EXIT_KEY).unwrap_or_else(|| n.ctx());
+                        let ctx = ctx_of(&mut self.cur_ctx, &mut self.ctx_store, ctx);
+                        emit(
+                            &mut self.out,
+                            &Record::Exit {
+                                id,
+                                status,
+                                trace_idx: ctx_val(ctx, "trace_offset"),
+                                ts: ctx_val(ctx, "time"),
+                            },
+                        )?;
+                        self.exited.insert(id);
+                    }
+                }
+                Node::Ctx(n) => {
+                    self.cur_ctx = Some(n.clone());
+                }
+                _ => {}
+            },
+            DBTr::CreateRel(ref r) => {
+                if let Rel::Inf(r) = r {
+                    if !self.edge_ops.contains(&r.pvm_op) {
+                        return Ok(());
+                    }
+                    let src = r.get_src();
+                    let dst = r.get_dst();
+                    if self.nodes.contains_key(&src) && self.nodes.contains_key(&dst) {
+                        let ctx = ctx_of(&mut self.cur_ctx, &mut self.ctx_store, r.ctx);
+                        emit(
+                            &mut self.out,
+                            &Record::Edge {
+                                src,
+                                dst,
+                                op: r.pvm_op,
+                                trace_idx: ctx_val(ctx, "trace_offset"),
+                                ts: ctx_val(ctx, "time"),
+                            },
+                        )?;
+                    }
+                }
+            }
+            _ => {}
         }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.finish()
     }
 }
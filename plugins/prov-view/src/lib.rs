@@ -0,0 +1,496 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    thread,
+};
+
+use pvm_plugins::{
+    compress::{Compression, Encoder, COMPRESS_PARAM},
+    define_plugin,
+    output::{Output, Sender},
+    views::{
+        data::{
+            node_types::{DataNode, NameNode, Node, PVMDataType},
+            rel_types::{Inf, Rel},
+            HasDst, HasID, HasSrc, ID,
+        },
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use serde_json::{json, Map, Value};
+
+define_plugin!(views => [ ProvView ]);
+
+#[derive(Debug)]
+pub struct ProvView {
+    id: usize,
+}
+
+impl View for ProvView {
+    fn new(id: usize) -> ProvView {
+        ProvView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "ProvView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for exporting the graph as W3C PROV, in PROV-JSON or PROV-O Turtle."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::path(
+                "output",
+                "Output file location, defaults to ./prov.json or ./prov.ttl",
+            ),
+            ParamDecl::str("format", "Either json or turtle").default("json"),
+            ParamDecl::str(
+                "namespace",
+                "IRI of the pvm: namespace used for identifiers",
+            )
+            .default("urn:pvm:"),
+            COMPRESS_PARAM,
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let turtle = match params.get_str("format").unwrap() {
+            "json" => false,
+            "turtle" => true,
+            f => {
+                return Err(ViewError::InvalidParam(
+                    "format".to_string(),
+                    format!("must be json or turtle, not {}", f),
+                ))
+            }
+        };
+        let path = params
+            .get_path("output")
+            .unwrap_or_else(|| Path::new(if turtle { "./prov.ttl" } else { "./prov.json" }));
+        let ns = params.get_str("namespace").unwrap().to_string();
+        let out = ProvWriter {
+            graph: Graph::default(),
+            out: Some(
+                Compression::from_params(&params)?
+                    .create(path)
+                    .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?,
+            ),
+            turtle,
+            ns,
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("ProvView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// A PROV relation, with the PROV-JSON keys of its subject and object
+struct RelKind {
+    name: &'static str,
+    subj: &'static str,
+    obj: &'static str,
+}
+
+const USED: RelKind = RelKind {
+    name: "used",
+    subj: "prov:activity",
+    obj: "prov:entity",
+};
+const GENERATED_BY: RelKind = RelKind {
+    name: "wasGeneratedBy",
+    subj: "prov:entity",
+    obj: "prov:activity",
+};
+const INFORMED_BY: RelKind = RelKind {
+    name: "wasInformedBy",
+    subj: "prov:informed",
+    obj: "prov:informant",
+};
+const DERIVED_FROM: RelKind = RelKind {
+    name: "wasDerivedFrom",
+    subj: "prov:generatedEntity",
+    obj: "prov:usedEntity",
+};
+
+/// An information flow relation mapped onto PROV, from its destination back to its source
+struct Relation<'a> {
+    id: u64,
+    kind: &'static RelKind,
+    subj: u64,
+    obj: u64,
+    inf: &'a Inf,
+}
+
+fn node_id(id: u64) -> String {
+    format!("pvm:n{}", id)
+}
+
+fn agent_id(euid: &str) -> String {
+    format!("pvm:uid{}", euid)
+}
+
+fn is_activity(d: &DataNode) -> bool {
+    *d.pvm_ty() == PVMDataType::Actor
+}
+
+fn turtle_str(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '\t' => ret += "\\t",
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[derive(Debug)]
+struct ProvWriter {
+    graph: Graph,
+    out: Option<Encoder<BufWriter<File>>>,
+    turtle: bool,
+    ns: String,
+}
+
+impl Output for ProvWriter {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        self.graph.add(evt);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut out = self.out.take().unwrap();
+        if self.turtle {
+            self.graph.write_turtle(&mut out, &self.ns)?;
+        } else {
+            self.graph.write_json(&mut out, &self.ns)?;
+        }
+        out.finish()?.flush()
+    }
+}
+
+/// The latest state of every data node and relation, held until the stream ends as both PROV
+/// serialisations group records by kind
+#[derive(Debug, Default)]
+struct Graph {
+    times: HashMap<ID, String>,
+    nodes: BTreeMap<u64, DataNode>,
+    names: BTreeMap<u64, String>,
+    rels: BTreeMap<u64, Rel>,
+}
+
+impl Graph {
+    fn add(&mut self, tr: &DBTr) {
+        match tr {
+            DBTr::CreateNode(Node::Ctx(ctx)) => {
+                if let Some(time) = ctx.cont.get("time") {
+                    self.times.insert(ctx.get_db_id(), time.clone());
+                }
+            }
+            DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d)) => {
+                self.nodes.insert(d.get_db_id().inner(), d.clone());
+            }
+            DBTr::CreateNode(Node::Name(n)) => {
+                let name = match n {
+                    NameNode::Path(_, path) => path.clone(),
+                    NameNode::Net(_, addr, port) => format!("{}:{}", addr, port),
+                };
+                self.names.insert(n.get_db_id().inner(), name);
+            }
+            DBTr::CreateRel(r) | DBTr::UpdateRel(r) => {
+                self.rels.insert(r.get_db_id().inner(), r.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn time(&self, ctx: ID) -> Option<&str> {
+        self.times.get(&ctx).map(String::as_str)
+    }
+
+    fn relations(&self) -> Vec<Relation<'_>> {
+        self.rels
+            .iter()
+            .filter_map(|(id, r)| match r {
+                Rel::Inf(inf) => {
+                    let src = self.nodes.get(&inf.get_src().inner())?;
+                    let dst = self.nodes.get(&inf.get_dst().inner())?;
+                    let kind = match (is_activity(src), is_activity(dst)) {
+                        (false, true) => &USED,
+                        (true, false) => &GENERATED_BY,
+                        (true, true) => &INFORMED_BY,
+                        (false, false) => &DERIVED_FROM,
+                    };
+                    Some(Relation {
+                        id: *id,
+                        kind,
+                        subj: inf.get_dst().inner(),
+                        obj: inf.get_src().inner(),
+                        inf,
+                    })
+                }
+                Rel::Named(_) => None,
+            })
+            .collect()
+    }
+
+    /// Every name a data node has been bound to, used as its labels
+    fn labels(&self) -> HashMap<u64, Vec<&str>> {
+        let mut ret: HashMap<u64, Vec<&str>> = HashMap::new();
+        for r in self.rels.values() {
+            if let Rel::Named(n) = r {
+                if let Some(name) = self.names.get(&n.get_dst().inner()) {
+                    ret.entry(n.get_src().inner()).or_default().push(name);
+                }
+            }
+        }
+        ret
+    }
+
+    /// The agent each activity acted on behalf of, from its effective user
+    fn agents(&self) -> BTreeMap<u64, &str> {
+        self.nodes
+            .iter()
+            .filter(|(_, d)| is_activity(d))
+            .filter_map(|(id, d)| Some((*id, d.meta.cur("euid")?)))
+            .collect()
+    }
+
+    fn write_json<W: Write>(&self, out: &mut W, ns: &str) -> io::Result<()> {
+        let labels = self.labels();
+        let mut doc = Map::new();
+        doc.insert(
+            "prefix".to_string(),
+            json!({ "pvm": ns, "xsd": "http://www.w3.org/2001/XMLSchema#" }),
+        );
+        let mut entities = Map::new();
+        let mut activities = Map::new();
+        for (id, d) in &self.nodes {
+            let mut rec = Map::new();
+            rec.insert(
+                "prov:type".to_string(),
+                json!(format!("pvm:{}", d.ty().name)),
+            );
+            rec.insert(
+                "pvm:uuid".to_string(),
+                json!(d.uuid().to_hyphenated_ref().to_string()),
+            );
+            for (key, val, _, _) in d.meta.iter_latest() {
+                rec.insert(format!("pvm:{}", key), json!(val));
+            }
+            if let Some(names) = labels.get(id) {
+                rec.insert("prov:label".to_string(), json!(names));
+            }
+            if is_activity(d) {
+                if let Some(time) = self.time(d.ctx()) {
+                    rec.insert("prov:startTime".to_string(), json!(time));
+                }
+                activities.insert(node_id(*id), Value::Object(rec));
+            } else {
+                entities.insert(node_id(*id), Value::Object(rec));
+            }
+        }
+        doc.insert("entity".to_string(), Value::Object(entities));
+        doc.insert("activity".to_string(), Value::Object(activities));
+
+        let agents = self.agents();
+        let mut agent = Map::new();
+        let mut assoc = Map::new();
+        for (id, euid) in &agents {
+            agent.insert(
+                agent_id(euid),
+                json!({ "prov:type": "prov:Person", "pvm:euid": euid }),
+            );
+            assoc.insert(
+                format!("_:a{}", id),
+                json!({ "prov:activity": node_id(*id), "prov:agent": agent_id(euid) }),
+            );
+        }
+        doc.insert("agent".to_string(), Value::Object(agent));
+        doc.insert("wasAssociatedWith".to_string(), Value::Object(assoc));
+
+        for kind in &[&USED, &GENERATED_BY, &INFORMED_BY, &DERIVED_FROM] {
+            doc.insert(kind.name.to_string(), Value::Object(Map::new()));
+        }
+        for r in self.relations() {
+            let mut rec = Map::new();
+            rec.insert(r.kind.subj.to_string(), json!(node_id(r.subj)));
+            rec.insert(r.kind.obj.to_string(), json!(node_id(r.obj)));
+            if let Some(time) = self.time(r.inf.ctx) {
+                rec.insert("prov:time".to_string(), json!(time));
+            }
+            rec.insert("pvm:op".to_string(), json!(r.inf.pvm_op.to_string()));
+            if r.inf.bytes_read > 0 {
+                rec.insert("pvm:bytes_read".to_string(), json!(r.inf.bytes_read));
+            }
+            if r.inf.bytes_written > 0 {
+                rec.insert("pvm:bytes_written".to_string(), json!(r.inf.bytes_written));
+            }
+            if let Some(Value::Object(recs)) = doc.get_mut(r.kind.name) {
+                recs.insert(format!("_:r{}", r.id), Value::Object(rec));
+            }
+        }
+        serde_json::to_writer_pretty(&mut *out, &doc)?;
+        writeln!(out)
+    }
+
+    /// Write PROV-O, which keeps the unqualified relations only so per-relation times are lost
+    fn write_turtle<W: Write>(&self, out: &mut W, ns: &str) -> io::Result<()> {
+        let labels = self.labels();
+        writeln!(out, "@prefix prov: <http://www.w3.org/ns/prov#> .")?;
+        writeln!(
+            out,
+            "@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> ."
+        )?;
+        writeln!(out, "@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .")?;
+        writeln!(out, "@prefix pvm: <{}> .", ns)?;
+        writeln!(out)?;
+        for (id, d) in &self.nodes {
+            let class = if is_activity(d) {
+                "prov:Activity"
+            } else {
+                "prov:Entity"
+            };
+            writeln!(out, "{} a {} ;", node_id(*id), class)?;
+            writeln!(out, "    pvm:type {} ;", turtle_str(d.ty().name))?;
+            for (key, val, _, _) in d.meta.iter_latest() {
+                writeln!(out, "    pvm:{} {} ;", key, turtle_str(val))?;
+            }
+            for name in labels.get(id).into_iter().flatten() {
+                writeln!(out, "    rdfs:label {} ;", turtle_str(name))?;
+            }
+            if is_activity(d) {
+                if let Some(time) = self.time(d.ctx()) {
+                    writeln!(
+                        out,
+                        "    prov:startedAtTime {}^^xsd:dateTime ;",
+                        turtle_str(time)
+                    )?;
+                }
+            }
+            writeln!(
+                out,
+                "    pvm:uuid {} .",
+                turtle_str(&d.uuid().to_hyphenated_ref().to_string())
+            )?;
+        }
+        writeln!(out)?;
+        let agents = self.agents();
+        for euid in agents.values().collect::<BTreeSet<_>>() {
+            writeln!(
+                out,
+                "{} a prov:Agent, prov:Person ; pvm:euid {} .",
+                agent_id(euid),
+                turtle_str(euid)
+            )?;
+        }
+        for (id, euid) in &agents {
+            writeln!(
+                out,
+                "{} prov:wasAssociatedWith {} .",
+                node_id(*id),
+                agent_id(euid)
+            )?;
+        }
+        writeln!(out)?;
+        for r in self.relations() {
+            writeln!(
+                out,
+                "{} prov:{} {} .",
+                node_id(r.subj),
+                r.kind.name,
+                node_id(r.obj)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use pvm_plugins::testing::{self, ctx, file, inf, named, path, process, TestDir};
+
+    fn write(format: &str) -> String {
+        let dir = TestDir::new(&format!("prov-view-{}", format));
+        let out = dir.join("prov");
+        let view = ProvView::new(0);
+        let params = testing::params(
+            &view,
+            &[("output", out.to_str().unwrap()), ("format", format)],
+        );
+        let evts = vec![
+            ctx(1, "2020-01-01T00:00:01Z"),
+            process(2, 1, "/bin/sh"),
+            file(3, 1),
+            path(4, "/tmp/out"),
+            named(5, 3, 4, 1),
+            inf(6, 2, 3, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+        fs::read_to_string(out).unwrap()
+    }
+
+    #[test]
+    fn writes_json() {
+        let doc: Value = serde_json::from_str(&write("json")).unwrap();
+        assert_eq!(doc["activity"]["pvm:n2"]["pvm:cmdline"], "/bin/sh");
+        assert_eq!(
+            doc["activity"]["pvm:n2"]["prov:startTime"],
+            "2020-01-01T00:00:01Z"
+        );
+        assert_eq!(doc["entity"]["pvm:n3"]["prov:label"], json!(["/tmp/out"]));
+        assert_eq!(
+            doc["wasGeneratedBy"]["_:r6"],
+            json!({
+                "prov:entity": "pvm:n3",
+                "prov:activity": "pvm:n2",
+                "prov:time": "2020-01-01T00:00:01Z",
+                "pvm:op": "Sink",
+                "pvm:bytes_written": 10,
+            })
+        );
+        assert_eq!(doc["used"], json!({}));
+    }
+
+    #[test]
+    fn writes_turtle() {
+        let ttl = write("turtle");
+        let lines: Vec<&str> = ttl.lines().collect();
+        for line in &[
+            "pvm:n2 a prov:Activity ;",
+            "    pvm:cmdline \"/bin/sh\" ;",
+            "pvm:n3 a prov:Entity ;",
+            "    rdfs:label \"/tmp/out\" ;",
+            "pvm:n3 prov:wasGeneratedBy pvm:n2 .",
+        ] {
+            assert!(lines.contains(line), "missing {}", line);
+        }
+    }
+}
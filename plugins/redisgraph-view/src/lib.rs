@@ -0,0 +1,318 @@
+use std::{fmt, thread};
+
+use pvm_plugins::{
+    define_plugin,
+    output::{Output, Sender},
+    views::{
+        data::{
+            node_types::{NameNode, Node, PVMDataType::*},
+            rel_types::Rel,
+            HasDst, HasID, HasSrc, ID,
+        },
+        DBTr, ErrorSink, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use redis::{Client, Connection, Pipeline, RedisError};
+
+define_plugin!(views => [ RedisGraphView ]);
+
+const INDEXES: &[(&str, &str)] = &[
+    ("Node", "db_id"),
+    ("Actor", "uuid"),
+    ("Object", "uuid"),
+    ("Store", "uuid"),
+    ("EditSession", "uuid"),
+    ("Conduit", "uuid"),
+    ("Path", "path"),
+    ("Net", "addr"),
+];
+
+#[derive(Debug)]
+pub struct RedisGraphView {
+    id: usize,
+}
+
+impl View for RedisGraphView {
+    fn new(id: usize) -> RedisGraphView {
+        RedisGraphView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "RedisGraphView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for streaming data to a RedisGraph or FalkorDB graph."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str("addr", "The redis:// URL of the server to connect to.")
+                .default("redis://127.0.0.1:6379/"),
+            ParamDecl::str("graph", "The key of the graph to write into.").default("pvm"),
+            ParamDecl::int(
+                "batch",
+                "Number of queries sent to the server in each round trip.",
+            )
+            .default("1000"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let addr = params.get_str("addr").unwrap();
+        let graph = params.get_str("graph").unwrap().to_string();
+        let batch = match params.get_int("batch").unwrap() {
+            n if n > 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "batch".to_string(),
+                    format!("must be a positive integer, not {}", n),
+                ))
+            }
+        };
+        let errors = params.error_sink();
+        let mut con = Client::open(addr)
+            .and_then(|c| c.get_connection())
+            .map_err(|e| ViewError::InvalidParam("addr".to_string(), e.to_string()))?;
+        for (label, prop) in INDEXES {
+            let q = format!("CREATE INDEX ON :{}({})", label, prop);
+            let res = redis::cmd("GRAPH.QUERY")
+                .arg(&graph)
+                .arg(q)
+                .query::<()>(&mut con);
+            if let Err(e) = res {
+                errors.report(format!("RedisGraphView failed to create index: {}", e));
+            }
+        }
+        let out = Writer {
+            con,
+            graph,
+            pipe: redis::pipe(),
+            queued: 0,
+            batch,
+            errors: errors.clone(),
+        };
+        let mut inst = Sender::new(self.name(), out, errors);
+        let thr = thread::Builder::new()
+            .name("RedisGraphView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+/// Sends queries to the server in pipelined batches
+///
+/// A query the server rejects is reported and skipped, only losing the connection stops the
+/// view.
+struct Writer {
+    con: Connection,
+    graph: String,
+    pipe: Pipeline,
+    queued: usize,
+    batch: usize,
+    errors: ErrorSink,
+}
+
+impl fmt::Debug for Writer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("graph", &self.graph)
+            .field("queued", &self.queued)
+            .finish()
+    }
+}
+
+impl Output for Writer {
+    type Error = RedisError;
+
+    fn write(&mut self, tr: &DBTr) -> Result<(), RedisError> {
+        if let Some(q) = statement(tr) {
+            self.pipe
+                .cmd("GRAPH.QUERY")
+                .arg(&self.graph)
+                .arg(q)
+                .ignore();
+            self.queued += 1;
+        }
+        if self.queued >= self.batch {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), RedisError> {
+        let res = self.pipe.query::<()>(&mut self.con);
+        self.pipe.clear();
+        self.queued = 0;
+        match res {
+            Err(ref e) if e.is_io_error() || e.is_connection_dropped() => res,
+            Err(e) => {
+                self.errors
+                    .report(format!("RedisGraphView query failed: {}", e));
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+type Props = Vec<(String, String)>;
+
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn id(id: ID) -> String {
+    id.inner().to_string()
+}
+
+fn key(k: &str) -> String {
+    format!("`{}`", k.replace('`', "``"))
+}
+
+fn map(props: &[(String, String)]) -> String {
+    let entries: Vec<String> = props
+        .iter()
+        .map(|(k, v)| format!("{}: {}", key(k), v))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+fn assign(var: &str, props: &[(String, String)]) -> String {
+    let entries: Vec<String> = props
+        .iter()
+        .map(|(k, v)| format!("{}.{} = {}", var, key(k), v))
+        .collect();
+    entries.join(", ")
+}
+
+/// The labels and properties a node is stored with, matching the Neo4j view
+fn node(node: &Node) -> Option<(Vec<&'static str>, Props)> {
+    let mut props = vec![("db_id".to_string(), id(node.get_db_id()))];
+    let labels = match node {
+        Node::Data(d) => {
+            for (k, v, _, _) in d.meta.iter_latest() {
+                props.push((k.to_string(), string(v)));
+            }
+            props.push((
+                "uuid".to_string(),
+                string(&d.uuid().to_hyphenated_ref().to_string()),
+            ));
+            props.push(("type".to_string(), string(d.ty().name)));
+            props.push(("ctx".to_string(), id(d.ctx())));
+            let base = match d.pvm_ty() {
+                EditSession => "EditSession",
+                Store => "Store",
+                Actor => "Actor",
+                Conduit => "Conduit",
+            };
+            vec!["Node", base]
+        }
+        Node::Ctx(c) => {
+            props.push(("type".to_string(), string(c.ty().name)));
+            for (k, v) in &c.cont {
+                props.push((k.to_string(), string(v)));
+            }
+            vec!["Node", "Context"]
+        }
+        Node::Name(NameNode::Path(_, path)) => {
+            props.push(("path".to_string(), string(path)));
+            vec!["Node", "Name", "Path"]
+        }
+        Node::Name(NameNode::Net(_, addr, port)) => {
+            props.push(("addr".to_string(), string(addr)));
+            props.push(("port".to_string(), port.to_string()));
+            vec!["Node", "Name", "Net"]
+        }
+        Node::Schema(_) => return None,
+    };
+    Some((labels, props))
+}
+
+/// The type and properties a relationship is stored with, matching the Neo4j view
+fn rel(rel: &Rel) -> (&'static str, Props) {
+    let mut props = vec![("db_id".to_string(), id(rel.get_db_id()))];
+    match rel {
+        Rel::Inf(i) => {
+            props.push(("pvm_op".to_string(), string(&i.pvm_op.to_string())));
+            props.push(("ctx".to_string(), id(i.ctx)));
+            props.push(("bytes_read".to_string(), i.bytes_read.to_string()));
+            props.push(("bytes_written".to_string(), i.bytes_written.to_string()));
+            if let Some(ref event) = i.event {
+                props.push(("event".to_string(), string(event)));
+            }
+            ("INF", props)
+        }
+        Rel::Named(n) => {
+            props.push(("start".to_string(), id(n.start)));
+            props.push(("end".to_string(), id(n.end)));
+            if let Some(actor) = n.actor {
+                props.push(("actor".to_string(), id(actor)));
+            }
+            ("NAMED", props)
+        }
+    }
+}
+
+/// The query applying an update to the graph
+///
+/// Relationships are merged on their db_id as an update may arrive before the relationship's
+/// creation.
+fn statement(tr: &DBTr) -> Option<String> {
+    match tr {
+        DBTr::CreateNode(n) => {
+            node(n).map(|(labels, props)| format!("CREATE (:{} {})", labels.join(":"), map(&props)))
+        }
+        DBTr::UpdateNode(n) => node(n).map(|(_, props)| {
+            format!(
+                "MATCH (n:Node {{db_id: {}}}) SET {}",
+                id(n.get_db_id()),
+                assign("n", &props[1..])
+            )
+        }),
+        DBTr::CreateRel(r) | DBTr::UpdateRel(r) => {
+            let (ty, props) = rel(r);
+            Some(format!(
+                "MATCH (s:Node {{db_id: {}}}), (d:Node {{db_id: {}}}) \
+                 MERGE (s)-[r:{} {{db_id: {}}}]->(d) SET {}",
+                id(r.get_src()),
+                id(r.get_dst()),
+                ty,
+                id(r.get_db_id()),
+                assign("r", &props[1..])
+            ))
+        }
+        DBTr::Flush(_) | DBTr::Tick(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn refused_connection_is_an_error() {
+        let view = RedisGraphView::new(0);
+        let mut params = ViewParams::new();
+        params.insert_param("addr", "redis://127.0.0.1:1/");
+        params.resolve(&view.params()).unwrap();
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "addr"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created without a server to connect to"),
+        }
+    }
+}
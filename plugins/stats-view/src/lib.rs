@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    thread,
+};
+
+use pvm_plugins::{
+    compress::{Compression, Encoder, COMPRESS_PARAM},
+    define_plugin,
+    output::{Output, Sender},
+    views::{
+        data::{
+            node_types::{CtxNode, NameNode, Node},
+            rel_types::Rel,
+            HasID, ID,
+        },
+        DBTr, ParamDecl, View, ViewError, ViewInst, ViewParams, ViewStream,
+    },
+};
+
+use chrono::{DateTime, Timelike, Utc};
+use serde_derive::Serialize;
+
+define_plugin!(views => [ StatsView ]);
+
+#[derive(Debug)]
+pub struct StatsView {
+    id: usize,
+}
+
+impl View for StatsView {
+    fn new(id: usize) -> StatsView {
+        StatsView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "StatsView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for counting nodes and relations by type, host and hour into a JSON summary."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::path("output", "Output file location").default("./prov_stats.json"),
+            COMPRESS_PARAM,
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let path = params.get_path("output").unwrap();
+        let out = StatsWriter {
+            stats: Stats::default(),
+            out: Some(
+                Compression::from_params(&params)?
+                    .create(path)
+                    .map_err(|e| ViewError::InvalidParam("output".to_string(), e.to_string()))?,
+            ),
+        };
+        let mut inst = Sender::new(self.name(), out, params.error_sink());
+        let thr = thread::Builder::new()
+            .name("StatsView".to_string())
+            .spawn(move || stream.run(&mut inst))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Counts {
+    contexts: u64,
+    nodes: u64,
+    rels: u64,
+}
+
+/// Where and when a context happened, kept so the nodes and relations created in it can be
+/// attributed to its host and hour
+#[derive(Clone, Debug, Default)]
+struct Origin {
+    host: Option<String>,
+    hour: Option<String>,
+}
+
+impl Origin {
+    fn of(ctx: &CtxNode) -> Self {
+        let hour = ctx
+            .cont
+            .get("time")
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .and_then(|t| {
+                t.with_timezone(&Utc)
+                    .with_minute(0)?
+                    .with_second(0)?
+                    .with_nanosecond(0)
+            })
+            .map(|t| t.to_rfc3339());
+        Origin {
+            host: ctx.cont.get("host").cloned(),
+            hour,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StatsWriter {
+    stats: Stats,
+    out: Option<Encoder<BufWriter<File>>>,
+}
+
+impl Output for StatsWriter {
+    type Error = io::Error;
+
+    fn write(&mut self, evt: &DBTr) -> io::Result<()> {
+        self.stats.add(evt);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut out = self.out.take().unwrap();
+        serde_json::to_writer_pretty(&mut out, &self.stats)?;
+        writeln!(out)?;
+        out.finish()?.flush()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    nodes: u64,
+    node_updates: u64,
+    rels: u64,
+    rel_updates: u64,
+    /// Data nodes by concrete type
+    data_types: BTreeMap<&'static str, u64>,
+    /// Data nodes by PVM type
+    pvm_types: BTreeMap<String, u64>,
+    contexts: BTreeMap<&'static str, u64>,
+    names: BTreeMap<&'static str, u64>,
+    /// Relations by PVM operation, or `Named`
+    rel_types: BTreeMap<String, u64>,
+    hosts: BTreeMap<String, Counts>,
+    hours: BTreeMap<String, Counts>,
+    #[serde(skip)]
+    origins: HashMap<ID, Origin>,
+}
+
+impl Stats {
+    fn add(&mut self, tr: &DBTr) {
+        match tr {
+            DBTr::CreateNode(n) => {
+                self.nodes += 1;
+                let ctx = match n {
+                    Node::Data(d) => {
+                        *self.data_types.entry(d.ty().name).or_default() += 1;
+                        *self.pvm_types.entry(d.pvm_ty().to_string()).or_default() += 1;
+                        Some(d.ctx())
+                    }
+                    Node::Ctx(c) => {
+                        *self.contexts.entry(c.ty().name).or_default() += 1;
+                        let origin = Origin::of(c);
+                        self.count(&origin, |c| c.contexts += 1);
+                        self.origins.insert(c.get_db_id(), origin);
+                        None
+                    }
+                    Node::Name(NameNode::Path(..)) => {
+                        *self.names.entry("Path").or_default() += 1;
+                        None
+                    }
+                    Node::Name(NameNode::Net(..)) => {
+                        *self.names.entry("Net").or_default() += 1;
+                        None
+                    }
+                    Node::Schema(_) => None,
+                };
+                if let Some(origin) = ctx.and_then(|c| self.origins.get(&c)).cloned() {
+                    self.count(&origin, |c| c.nodes += 1);
+                }
+            }
+            DBTr::UpdateNode(_) => self.node_updates += 1,
+            DBTr::CreateRel(r) => {
+                self.rels += 1;
+                let (ty, ctx) = match r {
+                    Rel::Inf(i) => (i.pvm_op.to_string(), i.ctx),
+                    Rel::Named(n) => ("Named".to_string(), n.start),
+                };
+                *self.rel_types.entry(ty).or_default() += 1;
+                if let Some(origin) = self.origins.get(&ctx).cloned() {
+                    self.count(&origin, |c| c.rels += 1);
+                }
+            }
+            DBTr::UpdateRel(_) => self.rel_updates += 1,
+            DBTr::Flush(_) | DBTr::Tick(_) => {}
+        }
+    }
+
+    fn count<F: Fn(&mut Counts)>(&mut self, origin: &Origin, f: F) {
+        if let Some(host) = &origin.host {
+            f(self.hosts.entry(host.clone()).or_default());
+        }
+        if let Some(hour) = &origin.hour {
+            f(self.hours.entry(hour.clone()).or_default());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs, sync::mpsc::channel};
+
+    use pvm_plugins::testing::{self, ctx, file, inf, path, process, TestDir};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn counts_by_type_host_and_hour() {
+        let dir = TestDir::new("stats-view");
+        let out = dir.join("stats.json");
+        let view = StatsView::new(0);
+        let params = testing::params(&view, &[("output", out.to_str().unwrap())]);
+        let evts = vec![
+            ctx(1, "2020-01-01T10:30:00Z"),
+            process(2, 1, "/bin/sh"),
+            file(3, 1),
+            path(4, "/tmp/out"),
+            inf(5, 2, 3, 1, 10),
+        ];
+        testing::run(&view, params, evts).unwrap();
+        let stats: Value = serde_json::from_str(&fs::read_to_string(out).unwrap()).unwrap();
+        assert_eq!(stats["nodes"], 4);
+        assert_eq!(stats["rels"], 1);
+        assert_eq!(stats["data_types"], json!({"file": 1, "process": 1}));
+        assert_eq!(stats["names"], json!({"Path": 1}));
+        assert_eq!(stats["rel_types"], json!({"Sink": 1}));
+        let counts = json!({"contexts": 1, "nodes": 2, "rels": 1});
+        assert_eq!(stats["hosts"], json!({ "test_host": counts }));
+        assert_eq!(
+            stats["hours"],
+            json!({ "2020-01-01T10:00:00+00:00": counts })
+        );
+    }
+
+    #[test]
+    fn unopenable_output_is_an_error() {
+        let view = StatsView::new(0);
+        let mut params = ViewParams::new();
+        let path = env::temp_dir()
+            .join("pvm-stats-view-missing")
+            .join("stats.json");
+        params.insert_param("output", path.to_str().unwrap());
+        params.resolve(&view.params()).unwrap();
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "output"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created with an output in a missing directory"),
+        }
+    }
+}
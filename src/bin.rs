@@ -6,19 +6,24 @@ use std::{
 };
 
 use pvm::{
-    cfg::Config,
+    cfg::{Config, ConfigBuilder},
     engine::Engine,
-    view::{View, ViewParams, ViewParamsExt},
+    trace::{cadets::CadetsVersion, TsFormat},
+    view::{
+        FilterView, ParamDecl, ParamType, View, ViewParams, BACKPRESSURE_PARAMS, FILTER_PARAMS,
+    },
 };
 
 use clap::{
     app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches,
 };
 
+/// The command line arguments creating a view, owning everything taken from the view type as
+/// the engine it was listed by is dropped before parsing
 struct ViewArgDetails {
-    id: usize,
+    vtype: String,
     name: String,
-    help: &'static str,
+    help: String,
     params: Vec<ViewParamArgDetails>,
 }
 
@@ -29,11 +34,13 @@ impl ViewArgDetails {
             vname.truncate(vname.len() - 4);
         }
         ViewArgDetails {
-            id: view.id(),
-            help: view.desc(),
+            vtype: view.name().to_string(),
+            help: view.desc().to_string(),
             params: view
                 .params()
                 .into_iter()
+                .chain(FILTER_PARAMS.iter().cloned())
+                .chain(BACKPRESSURE_PARAMS.iter().cloned())
                 .map(|p| ViewParamArgDetails::from_param(&vname, p))
                 .collect(),
             name: vname,
@@ -51,31 +58,48 @@ impl ViewArgDetails {
         m.is_present(&self.name)
     }
 
-    fn get_id_and_params(&self, m: &ArgMatches) -> (usize, ViewParams) {
+    /// The type of view to create and its params, a `FilterView` wrapping the view when any
+    /// filter params are given
+    fn get_name_and_params(&self, m: &ArgMatches) -> (&str, ViewParams) {
         let mut params = ViewParams::new();
         for param in &self.params {
-            if let Some(val) = m.value_of(&param.name) {
+            if param.ty == ParamType::Bool {
+                if m.is_present(&param.name) {
+                    params.insert_param(&param.act_name, true);
+                }
+            } else if let Some(val) = m.value_of(&param.name) {
                 params.insert_param(&param.act_name, val.to_string());
             }
         }
-        (self.id, params)
+        if FILTER_PARAMS.iter().any(|p| params.get(p.name).is_some()) {
+            params.insert_param("view", self.vtype.as_str());
+            return (FilterView::NAME, params);
+        }
+        (&self.vtype, params)
     }
 }
 
 struct ViewParamArgDetails {
-    act_name: &'static str,
+    act_name: String,
     name: String,
-    help: &'static str,
+    help: String,
+    ty: ParamType,
 }
 
 impl ViewParamArgDetails {
-    fn from_param(vname: &str, param: (&'static str, &'static str)) -> Self {
-        let (name, desc) = param;
-        let p_name = format!("{}-{}", &vname, name.to_lowercase());
+    fn from_param(vname: &str, param: ParamDecl) -> Self {
+        let p_name = format!("{}-{}", &vname, param.name.to_lowercase());
+        let help = match param.default {
+            Some(def) if param.ty != ParamType::Bool => {
+                format!("{} [default: {}]", param.desc, def)
+            }
+            _ => param.desc.to_string(),
+        };
         ViewParamArgDetails {
-            act_name: name,
+            act_name: param.name.to_string(),
             name: p_name,
-            help: desc,
+            help,
+            ty: param.ty,
         }
     }
 
@@ -84,34 +108,80 @@ impl ViewParamArgDetails {
             .long(&self.name)
             .help(&self.help)
             .requires(aname)
-            .takes_value(true)
+            .takes_value(self.ty != ParamType::Bool)
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let plugin_dir = var("PVM_PLUGIN_DIR").ok();
+/// The config the command line builds on, loading plugins from `PVM_PLUGIN_DIR` if set
+fn base_config() -> ConfigBuilder {
+    match var("PVM_PLUGIN_DIR") {
+        Ok(plugin_dir) => Config::build().plugin_dir(plugin_dir),
+        Err(_) => Config::build(),
+    }
+}
 
-    let cfg = if let Some(plugin_dir) = plugin_dir {
-        Config::build().plugin_dir(plugin_dir).finish()
-    } else {
-        Config::default()
+fn main() -> Result<(), Box<dyn Error>> {
+    // The arguments depend on the views and formats the plugins provide, while the engine
+    // ingesting is configured by the arguments, so they are listed by an engine of their own.
+    let (args, formats) = {
+        let mut e = Engine::new(base_config().disable_perf().finish())?;
+        e.init_pipeline()?;
+        let args = e
+            .list_view_types()?
+            .into_iter()
+            .filter(|v| v.name() != "StdoutJSONView" && v.name() != FilterView::NAME)
+            .map(ViewArgDetails::from_view)
+            .collect::<Vec<_>>();
+        let formats = e
+            .list_formats()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        (args, formats)
     };
 
-    let mut e = Engine::new(cfg)?;
-    e.init_pipeline()?;
-
-    let args = e
-        .list_view_types()?
-        .into_iter()
-        .map(ViewArgDetails::from_view)
-        .collect::<Vec<_>>();
-
     let m = app_from_crate!()
         .arg(
             Arg::with_name("path")
                 .required(true)
                 .help("Path to begin ingesting data from."),
         )
+        .arg(
+            Arg::with_name("ts-format")
+                .long("ts-format")
+                .takes_value(true)
+                .possible_values(&["nanos", "millis", "seconds", "rfc3339"])
+                .help("Encoding of timestamps in the trace, defaults to nanos."),
+        )
+        .arg(
+            Arg::with_name("cadets-version")
+                .long("cadets-version")
+                .takes_value(true)
+                .possible_values(&["auto", "v1", "v2"])
+                .help("CADETS schema version of the trace, recognised per record by default."),
+        )
+        .arg(
+            Arg::with_name("cadets-threads")
+                .long("cadets-threads")
+                .help("Model the threads of CADETS processes as separate actors."),
+        )
+        .arg(
+            Arg::with_name("cadets-env")
+                .long("cadets-env")
+                .help("Record selected environment variables given to exec in CADETS traces."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&formats.iter().map(String::as_str).collect::<Vec<_>>())
+                .help("Trace format to ingest, defaults to cadets."),
+        )
+        .arg(
+            Arg::with_name("stdout-json")
+                .long("stdout-json")
+                .help("Stream the output as newline delimited JSON to stdout."),
+        )
         .args(
             &args
                 .iter()
@@ -120,10 +190,31 @@ fn main() -> Result<(), Box<dyn Error>> {
         )
         .get_matches();
 
+    let mut cfg = base_config();
+    if let Some(fmt) = m.value_of("ts-format") {
+        cfg = cfg.ts_format(fmt.parse::<TsFormat>()?);
+    }
+    if let Some(ver) = m.value_of("cadets-version") {
+        cfg = cfg.cadets_version(ver.parse::<CadetsVersion>()?);
+    }
+    if m.is_present("cadets-threads") {
+        cfg = cfg.cadets_threads(true);
+    }
+    if m.is_present("cadets-env") {
+        cfg = cfg.cadets_env(true);
+    }
+
+    let mut e = Engine::new(cfg.finish())?;
+    e.init_pipeline()?;
+
+    if m.is_present("stdout-json") {
+        e.create_view_by_name("StdoutJSONView", ViewParams::new())?;
+    }
+
     for arg in &args {
         if arg.is_present(&m) {
-            let (id, params) = arg.get_id_and_params(&m);
-            e.create_view_by_id(id, params)?;
+            let (name, params) = arg.get_name_and_params(&m);
+            e.create_view_by_name(name, params)?;
         }
     }
 
@@ -136,7 +227,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
-    pvm::timeit!(e.ingest_reader(src)?);
+    match m.value_of("format") {
+        Some(fmt) => pvm::timeit!(e.ingest_reader_fmt(src, fmt)?),
+        None => pvm::timeit!(e.ingest_reader(src)?),
+    };
 
     e.shutdown_pipeline()?;
 
@@ -0,0 +1,173 @@
+//! The structs and enums passed over the C API
+//!
+//! This module depends on nothing else in the crate, so that the build script can compile it as
+//! well and write out `abi_description` next to the generated headers.
+
+#![cfg_attr(not(feature = "capi"), allow(dead_code))]
+
+use std::os::raw::c_char;
+
+#[cfg(feature = "capi")]
+use std::mem::{align_of, size_of};
+
+#[cfg(feature = "capi")]
+use memoffset::{offset_of, span_of};
+#[cfg(feature = "capi")]
+use serde_json::{json, Value};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum PVMErr {
+    EUNKNOWN = 1,
+    EAMBIGUOUSVIEWNAME = 2,
+    ENOVIEWWITHNAME = 3,
+    ENOVIEWWITHID = 5,
+    EINVALIDARG = 4,
+    EPIPELINENOTRUNNING = 6,
+    EPIPELINERUNNING = 7,
+    EPLUGINLOAD = 8,
+    ETHREADSTARTUP = 9,
+    EIO = 10,
+    ENOVIEWINST = 11,
+    ENOFORMAT = 12,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub enum CfgMode {
+    Auto,
+    Advanced,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct AdvancedConfig {
+    pub(crate) consumer_threads: usize,
+    pub(crate) persistence_threads: usize,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct KeyVal {
+    pub(crate) key: *mut c_char,
+    pub(crate) val: *mut c_char,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct View {
+    pub(crate) id: usize,
+    pub(crate) name: *mut c_char,
+    pub(crate) desc: *mut c_char,
+    pub(crate) num_parameters: usize,
+    pub(crate) parameters: *mut KeyVal,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ViewInst {
+    pub(crate) id: usize,
+    pub(crate) vtype: usize,
+    pub(crate) num_parameters: usize,
+    pub(crate) parameters: *mut KeyVal,
+}
+
+#[repr(C)]
+pub struct Config {
+    pub(crate) cfg_mode: CfgMode,
+    pub(crate) plugin_dir: *mut c_char,
+    pub(crate) cfg_detail: *const AdvancedConfig,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum ViewPausePolicy {
+    Drop,
+    Buffer,
+}
+
+#[cfg(feature = "capi")]
+macro_rules! abi_struct {
+    ($t:ty { $($f:ident: $cty:expr),* $(,)* }) => {
+        json!({
+            "size": size_of::<$t>(),
+            "align": align_of::<$t>(),
+            "fields": [$(
+                {
+                    "name": stringify!($f),
+                    "type": $cty,
+                    "offset": offset_of!($t, $f),
+                    "size": span_of!($t, $f).len(),
+                }
+            ),*],
+        })
+    };
+}
+
+#[cfg(feature = "capi")]
+macro_rules! abi_enum {
+    ($t:ident { $($v:ident),* $(,)* }) => {
+        json!({
+            "size": size_of::<$t>(),
+            "variants": {$(stringify!($v): $t::$v as isize),*},
+        })
+    };
+}
+
+/// Describe the layout of the structs and enums exposed over the C API
+///
+/// Offsets and sizes are taken from the compiled code, so they are exact for the target it was
+/// built for. The copy written by the build script is laid out for the build host, which only
+/// differs when cross-compiling.
+#[cfg(feature = "capi")]
+pub fn abi_description() -> Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "structs": {
+            "KeyVal": abi_struct!(KeyVal {
+                key: "char *",
+                val: "char *",
+            }),
+            "View": abi_struct!(View {
+                id: "size_t",
+                name: "char *",
+                desc: "char *",
+                num_parameters: "size_t",
+                parameters: "KeyVal *",
+            }),
+            "ViewInst": abi_struct!(ViewInst {
+                id: "size_t",
+                vtype: "size_t",
+                num_parameters: "size_t",
+                parameters: "KeyVal *",
+            }),
+            "AdvancedConfig": abi_struct!(AdvancedConfig {
+                consumer_threads: "size_t",
+                persistence_threads: "size_t",
+            }),
+            "Config": abi_struct!(Config {
+                cfg_mode: "CfgMode",
+                plugin_dir: "char *",
+                cfg_detail: "const AdvancedConfig *",
+            }),
+        },
+        "enums": {
+            "CfgMode": abi_enum!(CfgMode { Auto, Advanced }),
+            "ViewPausePolicy": abi_enum!(ViewPausePolicy { Drop, Buffer }),
+            "PVMErr": abi_enum!(PVMErr {
+                EUNKNOWN,
+                EAMBIGUOUSVIEWNAME,
+                ENOVIEWWITHNAME,
+                ENOVIEWWITHID,
+                EINVALIDARG,
+                EPIPELINENOTRUNNING,
+                EPIPELINERUNNING,
+                EPLUGINLOAD,
+                ETHREADSTARTUP,
+                EIO,
+                ENOVIEWINST,
+                ENOFORMAT,
+            }),
+        },
+    })
+}
@@ -1,39 +1,25 @@
 #![allow(unused_attributes)]
 
 use std::{
-    collections::HashMap,
     ffi::CStr,
     mem::size_of,
-    os::{
-        raw::c_char,
-        unix::io::{FromRawFd, RawFd},
-    },
+    os::{raw::c_char, unix::io::RawFd},
+    panic::{self, AssertUnwindSafe},
     ptr, slice,
 };
 
+pub use crate::c_abi::{abi_description, Config, KeyVal, PVMErr, View, ViewInst, ViewPausePolicy};
+
 use crate::{
-    cfg::{self, AdvancedConfig, CfgMode},
+    cfg,
     engine::{Engine, EngineError},
+    ingest::FormatError,
     iostream::IOStream,
-    view::{ViewError, ViewParams, ViewParamsExt},
+    view::{self, ParamDecl, ViewError, ViewParams},
 };
 
 use libc::malloc;
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug)]
-pub enum PVMErr {
-    EUNKNOWN = 1,
-    EAMBIGUOUSVIEWNAME = 2,
-    ENOVIEWWITHNAME = 3,
-    ENOVIEWWITHID = 5,
-    EINVALIDARG = 4,
-    EPIPELINENOTRUNNING = 6,
-    EPIPELINERUNNING = 7,
-    EPLUGINLOAD = 8,
-    ETHREADSTARTUP = 9,
-}
-
 impl From<EngineError> for PVMErr {
     fn from(val: EngineError) -> Self {
         match val {
@@ -42,11 +28,21 @@ impl From<EngineError> for PVMErr {
             EngineError::PluginError(_) => PVMErr::EPLUGINLOAD,
             EngineError::PluginVersionMismatch(_) => PVMErr::EPLUGINLOAD,
             EngineError::ProcessingError(_) => PVMErr::EUNKNOWN,
+            EngineError::PerfMonError(_) => PVMErr::EIO,
+            EngineError::FormatError(e) => match e {
+                FormatError::DuplicateFormat(_) | FormatError::TranslatedBase(_) => {
+                    PVMErr::EINVALIDARG
+                }
+                FormatError::MissingFormat(_) => PVMErr::ENOFORMAT,
+            },
             EngineError::ViewError(e) => match e {
                 ViewError::ThreadingErr(_) => PVMErr::ETHREADSTARTUP,
                 ViewError::DuplicateViewName(_) => PVMErr::EAMBIGUOUSVIEWNAME,
                 ViewError::MissingViewID(_) => PVMErr::ENOVIEWWITHID,
                 ViewError::MissingViewName(_) => PVMErr::ENOVIEWWITHNAME,
+                ViewError::InlineUnsupported(_) => PVMErr::EUNKNOWN,
+                ViewError::MissingViewInst(_) => PVMErr::ENOVIEWINST,
+                ViewError::InvalidParam(..) => PVMErr::EINVALIDARG,
             },
         }
     }
@@ -56,67 +52,49 @@ fn ret<T: Into<PVMErr>>(err: T) -> isize {
     -(err.into() as isize)
 }
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct KeyVal {
-    key: *mut c_char,
-    val: *mut c_char,
-}
-
-#[repr(C)]
-#[derive(Debug)]
-pub struct View {
-    id: usize,
-    name: *mut c_char,
-    desc: *mut c_char,
-    num_parameters: usize,
-    parameters: *mut KeyVal,
-}
-
-#[repr(C)]
-#[derive(Debug)]
-pub struct ViewInst {
-    id: usize,
-    vtype: usize,
-    num_parameters: usize,
-    parameters: *mut KeyVal,
+/// Run the body of an exported function, converting any panic into `default`
+///
+/// Unwinding across the FFI boundary is undefined behaviour, so every entry point should have
+/// its body wrapped in this.
+fn guard<T, F: FnOnce() -> T>(default: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("Error: libpvm panicked, see above for details");
+            default
+        }
+    }
 }
 
-#[repr(C)]
-pub struct Config {
-    cfg_mode: CfgMode,
-    plugin_dir: *mut c_char,
-    cfg_detail: *const AdvancedConfig,
+impl From<ViewPausePolicy> for view::PausePolicy {
+    fn from(val: ViewPausePolicy) -> Self {
+        match val {
+            ViewPausePolicy::Drop => view::PausePolicy::Drop,
+            ViewPausePolicy::Buffer => view::PausePolicy::Buffer,
+        }
+    }
 }
 
 pub struct PVMHdl(Engine);
 
-fn keyval_arr_to_hashmap(ptr: *const KeyVal, n: usize) -> ViewParams {
-    let mut ret = ViewParams::with_capacity(n);
+fn keyval_arr_to_hashmap(ptr: *const KeyVal, n: usize) -> Option<ViewParams> {
+    let mut ret = ViewParams::new();
     if !ptr.is_null() {
         let s = unsafe { slice::from_raw_parts(ptr, n) };
         for kv in s {
-            ret.insert_param(
-                string_from_c_char(kv.key).unwrap(),
-                string_from_c_char(kv.val).unwrap(),
-            );
+            ret.insert_param(string_from_c_char(kv.key)?, string_from_c_char(kv.val)?);
         }
     }
-    ret
+    Some(ret)
 }
 
-fn view_params_to_keyval_arr(h: &HashMap<&'static str, &'static str>) -> (*mut KeyVal, usize) {
-    iter_to_keyval_arr(h.iter().map(|(k, v)| (*k, *v)), h.len())
+fn view_params_to_keyval_arr(h: &[ParamDecl]) -> (*mut KeyVal, usize) {
+    iter_to_keyval_arr(h.iter().map(|p| (p.name, p.desc)), h.len())
 }
 
 fn view_inst_params_to_keyval_arr(h: &ViewParams) -> (*mut KeyVal, usize) {
-    iter_to_keyval_arr(
-        h.iter().map(|(k, v)| match v.downcast_ref::<String>() {
-            Some(r) => (k as &str, r as &str),
-            None => (k as &str, "<non-string>"),
-        }),
-        h.len(),
-    )
+    let vals: Vec<_> = h.iter().map(|(k, v)| (k, v.to_string())).collect();
+    iter_to_keyval_arr(vals.iter().map(|(k, v)| (*k, &v[..])), vals.len())
 }
 
 fn iter_to_keyval_arr<'a, 'b, T: IntoIterator<Item = (&'a str, &'b str)>>(
@@ -124,6 +102,9 @@ fn iter_to_keyval_arr<'a, 'b, T: IntoIterator<Item = (&'a str, &'b str)>>(
     len: usize,
 ) -> (*mut KeyVal, usize) {
     let data = unsafe { malloc(len * size_of::<KeyVal>()) as *mut KeyVal };
+    if data.is_null() {
+        return (data, 0);
+    }
     let s = unsafe { slice::from_raw_parts_mut(data, len) };
     for ((k, v), kv) in h.into_iter().zip(s) {
         kv.key = string_to_c_char(k);
@@ -132,12 +113,19 @@ fn iter_to_keyval_arr<'a, 'b, T: IntoIterator<Item = (&'a str, &'b str)>>(
     (data, len)
 }
 
+/// Copy a string into a malloc'd C string
+///
+/// Strings containing nulls are truncated at the first null. Returns null if allocation fails.
 fn string_to_c_char(val: &str) -> *mut c_char {
-    if val.contains('\0') {
-        panic!("Trying to convert a string containing nulls to a C-string");
-    }
+    let val = match val.find('\0') {
+        Some(end) => &val[..end],
+        None => val,
+    };
     unsafe {
         let data = malloc((val.len() + 1) * size_of::<c_char>()) as *mut c_char;
+        if data.is_null() {
+            return data;
+        }
         ptr::copy(val.as_ptr() as *const c_char, data, val.len());
         *data.offset(val.len() as isize) = 0x00 as c_char;
         data
@@ -155,50 +143,74 @@ fn string_from_c_char(str_p: *const c_char) -> Option<String> {
     )
 }
 
+/// Returns a malloc'd JSON description of the C API structs, see `abi_description`
+///
+/// The caller is responsible for freeing the returned string.
+#[no_mangle]
+pub unsafe extern "C" fn pvm_abi_describe() -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        string_to_c_char(&abi_description().to_string())
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pvm_init(cfg: Config) -> *mut PVMHdl {
-    let r_cfg = cfg::Config {
-        cfg_mode: cfg.cfg_mode,
-        plugin_dir: string_from_c_char(cfg.plugin_dir),
-        cfg_detail: if cfg.cfg_detail.is_null() {
-            Option::None
-        } else {
-            Option::Some(ptr::read(cfg.cfg_detail))
-        },
-    };
-    let e = match Engine::new(r_cfg) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            return ptr::null_mut();
-        }
-    };
-    let hdl = Box::new(PVMHdl(e));
-    Box::into_raw(hdl)
+    guard(ptr::null_mut(), || {
+        let r_cfg = cfg::Config {
+            cfg_mode: cfg.cfg_mode,
+            plugin_dir: string_from_c_char(cfg.plugin_dir),
+            cfg_detail: if cfg.cfg_detail.is_null() {
+                Option::None
+            } else {
+                Option::Some(ptr::read(cfg.cfg_detail))
+            },
+            perf_file: None,
+            ..cfg::Config::default()
+        };
+        let e = match Engine::new(r_cfg) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return ptr::null_mut();
+            }
+        };
+        let hdl = Box::new(PVMHdl(e));
+        Box::into_raw(hdl)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_start_pipeline(hdl: *mut PVMHdl) -> isize {
-    let engine = &mut (*hdl).0;
-    match engine.init_pipeline() {
-        Ok(_) => 0,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
-        }
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.init_pipeline() {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_shutdown_pipeline(hdl: *mut PVMHdl) -> isize {
-    let engine = &mut (*hdl).0;
-    match engine.shutdown_pipeline() {
-        Ok(_) => 0,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
-        }
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.shutdown_pipeline() {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -208,48 +220,66 @@ pub unsafe extern "C" fn pvm_init_persistance(
     user: *const c_char,
     pass: *const c_char,
 ) -> isize {
-    let engine = &mut (*hdl).0;
-    match engine.init_persistance(
-        string_from_c_char(addr),
-        string_from_c_char(user),
-        string_from_c_char(pass),
-    ) {
-        Ok(_) => 0,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
-        }
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.init_persistance(
+            string_from_c_char(addr),
+            string_from_c_char(user),
+            string_from_c_char(pass),
+        ) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_print_cfg(hdl: *const PVMHdl) {
-    let engine = &(*hdl).0;
-    engine.print_cfg();
+    if hdl.is_null() {
+        return;
+    }
+    guard((), || {
+        let engine = &(*hdl).0;
+        engine.print_cfg();
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_list_view_types(hdl: *const PVMHdl, out: *mut *mut View) -> isize {
-    let engine = &(*hdl).0;
-    let views = match engine.list_view_types() {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            return ret(e);
-        }
-    };
-    let len = views.len();
-    *out = malloc(len * size_of::<View>()) as *mut View;
-    let s = slice::from_raw_parts_mut(*out, len);
-    for (view, c_view) in views.into_iter().zip(s) {
-        c_view.id = view.id();
-        c_view.name = string_to_c_char(view.name());
-        c_view.desc = string_to_c_char(view.desc());
-        let (params, num) = view_params_to_keyval_arr(&view.params());
-        c_view.num_parameters = num;
-        c_view.parameters = params;
+    if hdl.is_null() || out.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
-    len as isize
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &(*hdl).0;
+        let views = match engine.list_view_types() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return ret(e);
+            }
+        };
+        let len = views.len();
+        *out = malloc(len * size_of::<View>()) as *mut View;
+        if (*out).is_null() {
+            return ret(PVMErr::EUNKNOWN);
+        }
+        let s = slice::from_raw_parts_mut(*out, len);
+        for (view, c_view) in views.into_iter().zip(s) {
+            c_view.id = view.id();
+            c_view.name = string_to_c_char(view.name());
+            c_view.desc = string_to_c_char(view.desc());
+            let (params, num) = view_params_to_keyval_arr(&view.params());
+            c_view.num_parameters = num;
+            c_view.parameters = params;
+        }
+        len as isize
+    })
 }
 
 #[no_mangle]
@@ -259,15 +289,25 @@ pub unsafe extern "C" fn pvm_create_view_by_id(
     params: *const KeyVal,
     n_params: usize,
 ) -> isize {
-    let engine = &mut (*hdl).0;
-    let rparams = keyval_arr_to_hashmap(params, n_params);
-    match engine.create_view_by_id(view_id, rparams) {
-        Ok(vid) => vid as isize,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
-        }
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        let rparams = match keyval_arr_to_hashmap(params, n_params) {
+            Some(p) => p,
+            None => {
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        match engine.create_view_by_id(view_id, rparams) {
+            Ok(vid) => vid as isize,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -277,67 +317,244 @@ pub unsafe extern "C" fn pvm_create_view_by_name(
     params: *const KeyVal,
     n_params: usize,
 ) -> isize {
-    let engine = &mut (*hdl).0;
-    let rparams = keyval_arr_to_hashmap(params, n_params);
-    let name = match string_from_c_char(name) {
-        Some(s) => s,
-        None => {
-            return ret(PVMErr::EINVALIDARG);
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        let rparams = match keyval_arr_to_hashmap(params, n_params) {
+            Some(p) => p,
+            None => {
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        let name = match string_from_c_char(name) {
+            Some(s) => s,
+            None => {
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        match engine.create_view_by_name(&name, rparams) {
+            Ok(vid) => vid as isize,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pvm_list_view_inst(hdl: *const PVMHdl, out: *mut *mut ViewInst) -> isize {
+    if hdl.is_null() || out.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &(*hdl).0;
+        let views = match engine.list_running_views() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return ret(e);
+            }
+        };
+        let len = views.len();
+        *out = malloc(len * size_of::<ViewInst>()) as *mut ViewInst;
+        if (*out).is_null() {
+            return ret(PVMErr::EUNKNOWN);
+        }
+        let s = slice::from_raw_parts_mut(*out, len);
+        for (view, c_view) in views.into_iter().zip(s) {
+            c_view.id = view.id();
+            c_view.vtype = view.vtype();
+            let (params, num) = view_inst_params_to_keyval_arr(view.params());
+            c_view.num_parameters = num;
+            c_view.parameters = params;
+        }
+        len as isize
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pvm_pause_view(
+    hdl: *mut PVMHdl,
+    view_inst_id: usize,
+    policy: ViewPausePolicy,
+) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.pause_view(view_inst_id, policy.into()) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
         }
-    };
-    match engine.create_view_by_name(&name, rparams) {
-        Ok(vid) => vid as isize,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pvm_resume_view(hdl: *mut PVMHdl, view_inst_id: usize) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.resume_view(view_inst_id) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
         }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pvm_destroy_view(hdl: *mut PVMHdl, view_inst_id: usize) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.destroy_view(view_inst_id) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
+/// The number of events passed to a running view that it has not read yet
 #[no_mangle]
-pub unsafe extern "C" fn pvm_list_view_inst(hdl: *const PVMHdl, out: *mut *mut ViewInst) -> isize {
-    let engine = &(*hdl).0;
-    let views = match engine.list_running_views() {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            return ret(e);
+pub unsafe extern "C" fn pvm_view_lag(hdl: *mut PVMHdl, view_inst_id: usize) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &(*hdl).0;
+        match engine.view_lag(view_inst_id) {
+            Ok(lag) => lag.behind as isize,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
         }
-    };
-    let len = views.len();
-    *out = malloc(len * size_of::<ViewInst>()) as *mut ViewInst;
-    let s = slice::from_raw_parts_mut(*out, len);
-    for (view, c_view) in views.into_iter().zip(s) {
-        c_view.id = view.id();
-        c_view.vtype = view.vtype();
-        let (params, num) = view_inst_params_to_keyval_arr(view.params());
-        c_view.num_parameters = num;
-        c_view.parameters = params;
+    })
+}
+
+/// Block until every running view has flushed everything ingested so far
+#[no_mangle]
+pub unsafe extern "C" fn pvm_flush(hdl: *mut PVMHdl) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
-    len as isize
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        match engine.flush() {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_ingest_fd(hdl: *mut PVMHdl, fd: i32) -> isize {
-    let engine = &mut (*hdl).0;
-    let stream = IOStream::from_raw_fd(fd as RawFd);
-    match timeit!(engine.ingest_stream(stream)) {
-        Ok(_) => 0,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            ret(e)
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
+    }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        let stream = match IOStream::try_from_raw_fd(fd as RawFd) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        match timeit!(engine.ingest_stream(stream)) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
         }
+    })
+}
+
+/// Ingest a stream in the trace format registered under `fmt`, see `pvm_ingest_fd`
+#[no_mangle]
+pub unsafe extern "C" fn pvm_ingest_fd_fmt(hdl: *mut PVMHdl, fd: i32, fmt: *const c_char) -> isize {
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG);
     }
+    guard(ret(PVMErr::EUNKNOWN), || {
+        let engine = &mut (*hdl).0;
+        let fmt = match string_from_c_char(fmt) {
+            Some(s) => s,
+            None => {
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        let stream = match IOStream::try_from_raw_fd(fd as RawFd) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return ret(PVMErr::EINVALIDARG);
+            }
+        };
+        match timeit!(engine.ingest_stream_fmt(stream, &fmt)) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ret(e)
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_cleanup(hdl: *mut PVMHdl) {
-    drop(Box::from_raw(hdl));
-    println!("Cleaning up..");
+    if hdl.is_null() {
+        return;
+    }
+    guard((), || {
+        drop(Box::from_raw(hdl));
+        println!("Cleaning up..");
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn pvm_count_processes(hdl: *const PVMHdl) -> i64 {
-    let engine = &(*hdl).0;
-    engine.count_processes()
+    if hdl.is_null() {
+        return ret(PVMErr::EINVALIDARG) as i64;
+    }
+    guard(ret(PVMErr::EUNKNOWN) as i64, || {
+        let engine = &(*hdl).0;
+        engine.count_processes()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_description_layout() {
+        let desc = abi_description();
+        let keyval = &desc["structs"]["KeyVal"];
+        assert_eq!(keyval["size"], size_of::<KeyVal>());
+        assert_eq!(keyval["fields"][0]["name"], "key");
+        assert_eq!(keyval["fields"][1]["offset"], size_of::<*mut c_char>());
+        assert_eq!(desc["enums"]["PVMErr"]["variants"]["EINVALIDARG"], 4);
+        assert_eq!(desc["enums"]["PVMErr"]["variants"]["ENOFORMAT"], 12);
+    }
 }
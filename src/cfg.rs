@@ -1,16 +1,12 @@
-#[repr(C)]
-#[derive(Debug, PartialEq)]
-pub enum CfgMode {
-    Auto,
-    Advanced,
-}
+use std::{collections::HashMap, time::Duration};
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct AdvancedConfig {
-    consumer_threads: usize,
-    persistence_threads: usize,
-}
+pub use crate::{
+    c_abi::{AdvancedConfig, CfgMode},
+    ingest::pvm::MetaPolicy,
+    trace::{cadets::CadetsVersion, TsFormat},
+};
+
+use crate::{ingest::IngestOptions, view::ViewParams};
 
 impl Default for AdvancedConfig {
     fn default() -> Self {
@@ -26,12 +22,36 @@ pub struct Config {
     pub(crate) cfg_mode: CfgMode,
     pub(crate) plugin_dir: Option<String>,
     pub(crate) cfg_detail: Option<AdvancedConfig>,
+    pub(crate) ts_format: TsFormat,
+    pub(crate) cadets_version: CadetsVersion,
+    pub(crate) cadets_threads: bool,
+    pub(crate) cadets_env: bool,
+    pub(crate) perf_file: Option<String>,
+    pub(crate) edge_events: bool,
+    pub(crate) skip_fbt: bool,
+    pub(crate) heritability: HashMap<String, bool>,
+    pub(crate) meta_policy: MetaPolicy,
+    pub(crate) replay_journal: usize,
+    /// How often views are sent a `DBTr::Tick`, if at all
+    pub(crate) tick_interval: Option<Duration>,
+    /// Views to create when the pipeline starts, by type name
+    pub(crate) views: Vec<(String, ViewParams)>,
 }
 
 impl Config {
     pub fn build() -> ConfigBuilder {
         ConfigBuilder::default()
     }
+
+    /// The options the pipeline's PVM maps records by
+    pub(crate) fn ingest_options(&self) -> IngestOptions {
+        IngestOptions {
+            ts_format: self.ts_format,
+            cadets_version: self.cadets_version,
+            cadets_threads: self.cadets_threads,
+            cadets_env: self.cadets_env,
+        }
+    }
 }
 
 impl Default for Config {
@@ -40,10 +60,108 @@ impl Default for Config {
             cfg_mode: CfgMode::Auto,
             plugin_dir: None,
             cfg_detail: None,
+            ts_format: TsFormat::default(),
+            cadets_version: CadetsVersion::default(),
+            cadets_threads: false,
+            cadets_env: false,
+            perf_file: Some("./perfinfo".to_string()),
+            edge_events: false,
+            skip_fbt: false,
+            heritability: HashMap::new(),
+            meta_policy: MetaPolicy::default(),
+            replay_journal: 0,
+            tick_interval: None,
+            views: Vec::new(),
         }
     }
 }
 
+/// The setters shared by `ConfigBuilder` and `AdvancedConfigBuilder`, both wrapping a `Config`
+macro_rules! config_setters {
+    () => {
+        pub fn plugin_dir<S: ToString>(mut self, dir: S) -> Self {
+            self.0.plugin_dir = Some(dir.to_string());
+            self
+        }
+
+        pub fn ts_format(mut self, fmt: TsFormat) -> Self {
+            self.0.ts_format = fmt;
+            self
+        }
+
+        /// The CADETS schema version traces were recorded with, recognised per record by default
+        pub fn cadets_version(mut self, ver: CadetsVersion) -> Self {
+            self.0.cadets_version = ver;
+            self
+        }
+
+        /// Model the threads of CADETS processes as actors of their own
+        pub fn cadets_threads(mut self, on: bool) -> Self {
+            self.0.cadets_threads = on;
+            self
+        }
+
+        /// Record selected environment variables given to exec in CADETS traces, off by default
+        pub fn cadets_env(mut self, on: bool) -> Self {
+            self.0.cadets_env = on;
+            self
+        }
+
+        pub fn perf_file<S: ToString>(mut self, path: S) -> Self {
+            self.0.perf_file = Some(path.to_string());
+            self
+        }
+
+        pub fn disable_perf(mut self) -> Self {
+            self.0.perf_file = None;
+            self
+        }
+
+        /// Annotate Inf edges with the trace event that produced them
+        pub fn edge_events(mut self, on: bool) -> Self {
+            self.0.edge_events = on;
+            self
+        }
+
+        /// Only ingest audit events from CADETS streams, skipping FBT records without parsing them
+        pub fn skip_fbt(mut self, on: bool) -> Self {
+            self.0.skip_fbt = on;
+            self
+        }
+
+        /// Override the heritability of a metadata key given by the trace format's types
+        pub fn heritable<S: ToString>(mut self, key: S, heritable: bool) -> Self {
+            self.0.heritability.insert(key.to_string(), heritable);
+            self
+        }
+
+        /// How metadata keys unknown to a node's type are handled, strict by default
+        pub fn meta_policy(mut self, policy: MetaPolicy) -> Self {
+            self.0.meta_policy = policy;
+            self
+        }
+
+        /// Keep the last `cap` events for views created during ingestion to replay, off by default
+        pub fn replay_journal(mut self, cap: usize) -> Self {
+            self.0.replay_journal = cap;
+            self
+        }
+
+        /// Send every view a `DBTr::Tick` each `interval`, even while no events arrive, off by
+        /// default
+        pub fn tick_interval(mut self, interval: Duration) -> Self {
+            self.0.tick_interval = Some(interval);
+            self
+        }
+
+        /// Create an instance of the view type `name` as the pipeline starts, before any events
+        pub fn view<S: ToString>(mut self, name: S, params: ViewParams) -> Self {
+            self.0.views.push((name.to_string(), params));
+            self
+        }
+    };
+}
+
 #[derive(Debug, Default)]
 pub struct ConfigBuilder(Config);
 
@@ -52,10 +170,7 @@ impl ConfigBuilder {
         self.0
     }
 
-    pub fn plugin_dir<S: ToString>(mut self, dir: S) -> Self {
-        self.0.plugin_dir = Some(dir.to_string());
-        self
-    }
+    config_setters!();
 
     pub fn advanced(self) -> AdvancedConfigBuilder {
         AdvancedConfigBuilder::new(self)
@@ -77,10 +192,7 @@ impl AdvancedConfigBuilder {
         self.0
     }
 
-    pub fn plugin_dir<S: ToString>(mut self, dir: S) -> Self {
-        self.0.plugin_dir = Some(dir.to_string());
-        self
-    }
+    config_setters!();
 
     pub fn consumer_threads(mut self, threads: usize) -> Self {
         self.0.cfg_detail.as_mut().unwrap().consumer_threads = threads;
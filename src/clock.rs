@@ -0,0 +1,69 @@
+//! Wall clock access for time-dependent behaviour
+//!
+//! Components that measure elapsed real time take a `Clock` rather than calling
+//! `Instant::now()` directly, so tests can substitute a `MockClock` and advance time manually
+//! instead of sleeping.
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when advanced
+///
+/// Clones share the same time, so a test can keep a handle to a clock it has passed into the
+/// component under test.
+#[derive(Clone, Debug)]
+pub struct MockClock(Arc<Mutex<Instant>>);
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_advances_shared_time() {
+        let clock = MockClock::new();
+        let handle = clock.clone();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        handle.advance(Duration::from_millis(5));
+        assert_eq!(clock.now() - start, Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,310 @@
+//! Comparison of the graphs produced by two ingests
+//!
+//! Snapshots are the newline delimited JSON stream written by the `StdoutJSONView`. As IDs are
+//! assigned per run, elements are matched between snapshots on stable keys instead: data nodes
+//! by their fingerprint and version, names by their value and edges by the keys of their
+//! endpoints and their operation. Context nodes are specific to a run and are ignored.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use crate::data::node_types::data_fingerprint;
+
+use serde_derive::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// The final state of the graph described by a snapshot
+#[derive(Debug, Default)]
+pub struct Graph {
+    nodes: BTreeMap<String, BTreeMap<String, String>>,
+    edges: BTreeMap<String, BTreeMap<String, String>>,
+    node_keys: HashMap<u64, String>,
+    edge_keys: HashMap<u64, String>,
+    versions: HashMap<u64, usize>,
+}
+
+impl Graph {
+    pub fn from_reader<R: Read>(src: R) -> io::Result<Self> {
+        let mut graph = Graph::default();
+        for line in BufReader::new(src).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let tr: Value = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            graph.apply(&tr);
+        }
+        Ok(graph)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Graph::from_reader(File::open(path)?)
+    }
+
+    /// Apply a single serialised `DBTr`
+    pub fn apply(&mut self, tr: &Value) {
+        let (kind, body) = match tr.as_object().and_then(|o| o.iter().next()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        match &kind[..] {
+            "CreateNode" => self.node(body, true),
+            "UpdateNode" => self.node(body, false),
+            "CreateRel" | "UpdateRel" => self.edge(body),
+            _ => {}
+        }
+    }
+
+    fn node(&mut self, node: &Value, create: bool) {
+        let (ty, body) = match node.as_object().and_then(|o| o.iter().next()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let (id, key, props) = match &ty[..] {
+            "Data" => {
+                let id = body["id"].as_u64().unwrap_or_default();
+                let key = if create {
+                    let pvm_ty = body["pvm_ty"].as_str().unwrap_or_default();
+                    let name = body["ty"].as_str().unwrap_or_default();
+                    let uuid = body["uuid"].as_str().unwrap_or_default();
+                    let fp =
+                        data_fingerprint(pvm_ty, name, &Uuid::parse_str(uuid).unwrap_or_default());
+                    let version = self.versions.entry(fp).or_insert(0);
+                    *version += 1;
+                    format!("{} {} v{} [{:016x}]", name, uuid, version, fp)
+                } else {
+                    match self.node_keys.get(&id) {
+                        Some(key) => key.clone(),
+                        None => return,
+                    }
+                };
+                (id, key, latest_meta(&body["meta"]))
+            }
+            "Name" => match body.as_object().and_then(|o| o.iter().next()) {
+                Some((kind, val)) => {
+                    let id = val[0].as_u64().unwrap_or_default();
+                    let name = val
+                        .as_array()
+                        .map(|v| v[1..].iter().map(Value::to_string).collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    (id, format!("{} {}", kind, name.join(":")), BTreeMap::new())
+                }
+                None => return,
+            },
+            "Schema" => match body.as_object().and_then(|o| o.iter().next()) {
+                Some((kind, val)) => {
+                    let id = val[0].as_u64().unwrap_or_default();
+                    let name = val[1]["name"].as_str().unwrap_or_default();
+                    (id, format!("schema {} {}", kind, name), BTreeMap::new())
+                }
+                None => return,
+            },
+            _ => return,
+        };
+        self.node_keys.insert(id, key.clone());
+        self.nodes.insert(key, props);
+    }
+
+    fn edge(&mut self, rel: &Value) {
+        let (ty, body) = match rel.as_object().and_then(|o| o.iter().next()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let id = body["id"].as_u64().unwrap_or_default();
+        let key = match self.edge_keys.get(&id) {
+            Some(key) => key.clone(),
+            None => {
+                let end = |field: &str| {
+                    body[field]
+                        .as_u64()
+                        .and_then(|id| self.node_keys.get(&id))
+                        .cloned()
+                        .unwrap_or_else(|| "?".to_string())
+                };
+                let op = match &ty[..] {
+                    "Inf" => body["pvm_op"].as_str().unwrap_or_default(),
+                    other => other,
+                };
+                let key = format!("({}) -[{}]-> ({})", end("src"), op, end("dst"));
+                self.edge_keys.insert(id, key.clone());
+                key
+            }
+        };
+        let mut props = BTreeMap::new();
+        for field in &["bytes_read", "bytes_written"] {
+            if let Some(count) = body.get(*field) {
+                props.insert(field.to_string(), count.to_string());
+            }
+        }
+        self.edges.insert(key, props);
+    }
+}
+
+/// The latest value of each key in a serialised `MetaStore`
+fn latest_meta(meta: &Value) -> BTreeMap<String, String> {
+    let mut ret = BTreeMap::new();
+    if let Some(entries) = meta["entries"].as_object() {
+        for (key, entry) in entries {
+            if let Some(val) = entry[1].as_array().and_then(|v| v.last()) {
+                ret.insert(key.clone(), val[0].as_str().unwrap_or_default().to_string());
+            }
+        }
+    }
+    ret
+}
+
+/// Differences between two graphs
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct GraphDiff {
+    pub nodes_added: Vec<String>,
+    pub nodes_removed: Vec<String>,
+    /// Nodes present in both graphs whose final metadata differs
+    pub nodes_changed: Vec<String>,
+    pub edges_added: Vec<String>,
+    pub edges_removed: Vec<String>,
+    /// Edges present in both graphs whose properties differ
+    pub edges_changed: Vec<String>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == GraphDiff::default()
+    }
+}
+
+fn diff_maps<V: PartialEq>(
+    a: &BTreeMap<String, V>,
+    b: &BTreeMap<String, V>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let added = b.keys().filter(|k| !a.contains_key(*k)).cloned().collect();
+    let removed = a.keys().filter(|k| !b.contains_key(*k)).cloned().collect();
+    let changed = a
+        .iter()
+        .filter(|(k, v)| b.get(*k).into_iter().any(|bv| bv != *v))
+        .map(|(k, _)| k.clone())
+        .collect();
+    (added, removed, changed)
+}
+
+/// Compare two graphs, reporting the changes needed to get from `a` to `b`
+pub fn diff(a: &Graph, b: &Graph) -> GraphDiff {
+    let (nodes_added, nodes_removed, nodes_changed) = diff_maps(&a.nodes, &b.nodes);
+    let (edges_added, edges_removed, edges_changed) = diff_maps(&a.edges, &b.edges);
+    GraphDiff {
+        nodes_added,
+        nodes_removed,
+        nodes_changed,
+        edges_added,
+        edges_removed,
+        edges_changed,
+    }
+}
+
+/// Compare the snapshots at two paths
+pub fn graph_diff<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> io::Result<GraphDiff> {
+    Ok(diff(&Graph::from_path(a)?, &Graph::from_path(b)?))
+}
+
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Nodes: +{} -{} ~{}",
+            self.nodes_added.len(),
+            self.nodes_removed.len(),
+            self.nodes_changed.len()
+        )?;
+        writeln!(
+            f,
+            "Edges: +{} -{} ~{}",
+            self.edges_added.len(),
+            self.edges_removed.len(),
+            self.edges_changed.len()
+        )?;
+        for (mark, list) in &[
+            ("+", &self.nodes_added),
+            ("-", &self.nodes_removed),
+            ("~", &self.nodes_changed),
+            ("+", &self.edges_added),
+            ("-", &self.edges_removed),
+            ("~", &self.edges_changed),
+        ] {
+            for item in list.iter() {
+                writeln!(f, "{} {}", mark, item)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::sync_channel;
+
+    use crate::{
+        ingest::{ingest_values, pvm::PVM},
+        trace::cadets::TraceEvent,
+    };
+
+    use serde_json::json;
+
+    fn snapshot(records: Vec<Value>) -> Graph {
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        ingest_values::<_, TraceEvent>(records, &mut pvm);
+        pvm.shutdown();
+        let mut graph = Graph::default();
+        for tr in recv.iter() {
+            graph.apply(&serde_json::to_value(&tr).unwrap());
+        }
+        graph
+    }
+
+    fn open(path: &str, file: &str) -> Value {
+        json!({
+            "event": "audit:event:aue_open_rwtc:",
+            "time": 1_533_046_200_000_000_000i64,
+            "pid": 10, "ppid": 1, "tid": 100, "uid": 0, "exec": "test", "retval": 3,
+            "subjprocuuid": "3f7a9a54-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            "subjthruuid": "3f7a9a55-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            "ret_objuuid1": file,
+            "upath1": path,
+        })
+    }
+
+    const FILE_A: &str = "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6";
+    const FILE_B: &str = "6c0b5c33-94b8-11e8-8f3e-a1b2c3d4e5f6";
+
+    #[test]
+    fn identical_ingests_do_not_differ() {
+        let a = snapshot(vec![open("/tmp/a", FILE_A)]);
+        let b = snapshot(vec![open("/tmp/a", FILE_A)]);
+        assert!(diff(&a, &b).is_empty());
+    }
+
+    #[test]
+    fn reports_added_and_removed() {
+        let a = snapshot(vec![open("/tmp/a", FILE_A)]);
+        let b = snapshot(vec![open("/tmp/a", FILE_A), open("/tmp/b", FILE_B)]);
+        let d = diff(&a, &b);
+        assert_eq!(d.nodes_added.len(), 2);
+        assert!(d.nodes_added.iter().any(|n| n == "Path \"/tmp/b\""));
+        assert_eq!(d.edges_added.len(), 1);
+        assert!(d.nodes_removed.is_empty() && d.edges_removed.is_empty());
+        let rev = diff(&b, &a);
+        assert_eq!(rev.nodes_removed, d.nodes_added);
+        assert!(d
+            .to_string()
+            .starts_with("Nodes: +2 -0 ~0\nEdges: +1 -0 ~0\n"));
+    }
+}
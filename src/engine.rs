@@ -1,20 +1,30 @@
-use std::{ffi::OsStr, io::Read, path::Path, sync::mpsc};
+use std::{
+    ffi::OsStr,
+    io::{self, Read},
+    path::Path,
+    sync::mpsc,
+};
 
 use crate::{
     cfg::Config,
     ingest::{
-        ingest_stream,
-        pvm::{PVMError, PVM},
-        Mapped,
+        pvm::{CommitHook, PVMError, PVM},
+        FormatError, FormatRegistry, IngestStats, Mapped,
     },
     iostream::IOStream,
+    json_view::StdoutJSONView,
     neo4j_glue::Neo4JView,
     plugins::{plugin_version, Plugin, PluginInit},
     //    query::low::count_processes,
-    trace::cadets::TraceEvent,
-    view::{View, ViewCoordinator, ViewError, ViewInst, ViewParams, ViewParamsExt},
+    view::{
+        FlushMarker, PausePolicy, View, ViewCoordinator, ViewError, ViewFailure, ViewInst, ViewLag,
+        ViewParams,
+    },
 };
 
+#[cfg(feature = "websocket")]
+use crate::ws_view::WebSocketView;
+
 use libloading::{Library, Symbol};
 //use neo4j::Neo4jDB;
 use quick_error::quick_error;
@@ -46,6 +56,15 @@ quick_error! {
             from()
             display("View Orchestration error: {}", err)
         }
+        PerfMonError(err: io::Error) {
+            cause(err)
+            display("Failed to start performance monitor: {}", err)
+        }
+        FormatError(err: FormatError) {
+            cause(err)
+            from()
+            display("Trace format error: {}", err)
+        }
     }
 }
 
@@ -90,13 +109,40 @@ impl PluginManager {
         Ok(())
     }
 
-    fn init_view_coordinator(&self, vc: &mut ViewCoordinator) {
+    fn init_view_coordinator(&self, vc: &mut ViewCoordinator) -> Result<()> {
+        for (p, _) in &self.plugins {
+            p.view_ops(vc).map_err(|e| match e {
+                ViewError::DuplicateViewName(name) => EngineError::PluginError(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Plugin view {} conflicts with an existing view", name),
+                )),
+                e => e.into(),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn init_format_registry(&self, reg: &mut FormatRegistry) -> Result<()> {
         for (p, _) in &self.plugins {
-            p.view_ops(vc);
+            register_plugin_formats(p.as_ref(), reg)?;
         }
+        Ok(())
     }
 }
 
+fn register_plugin_formats(p: &dyn Plugin, reg: &mut FormatRegistry) -> Result<()> {
+    p.formats(reg).map_err(|e| {
+        let kind = match e.downcast_ref::<FormatError>() {
+            Some(FormatError::DuplicateFormat(_)) => io::ErrorKind::AlreadyExists,
+            _ => io::ErrorKind::Other,
+        };
+        EngineError::PluginError(io::Error::new(
+            kind,
+            format!("Plugin failed to register its trace formats: {}", e),
+        ))
+    })
+}
+
 pub struct Pipeline {
     pvm: PVM,
     view_ctrl: ViewCoordinator,
@@ -104,6 +150,8 @@ pub struct Pipeline {
 
 pub struct Engine {
     cfg: Config,
+    // dropped before the plugins, as it may hold formats from their libraries
+    formats: FormatRegistry,
     plugins: PluginManager,
     pipeline: Option<Pipeline>,
 }
@@ -120,8 +168,11 @@ impl Engine {
         if let Some(plugin_dir) = &cfg.plugin_dir {
             plugins.load_all(Path::new(plugin_dir))?;
         }
+        let mut formats = FormatRegistry::with_builtin();
+        plugins.init_format_registry(&mut formats)?;
         Ok(Engine {
             cfg,
+            formats,
             plugins,
             pipeline: None,
         })
@@ -132,13 +183,33 @@ impl Engine {
             return Err(EngineError::PipelineRunning);
         }
         let (send, recv) = mpsc::sync_channel(100_000);
-        let mut view_ctrl = ViewCoordinator::new(recv)?;
+        let mut view_ctrl = ViewCoordinator::with_tick_interval(recv, self.cfg.tick_interval)?;
+        view_ctrl.set_journal_capacity(self.cfg.replay_journal);
         view_ctrl.register_view_type::<Neo4JView>()?;
-        self.plugins.init_view_coordinator(&mut view_ctrl);
-        self.pipeline = Some(Pipeline {
-            pvm: PVM::new(send),
-            view_ctrl,
-        });
+        view_ctrl.register_view_type::<StdoutJSONView>()?;
+        #[cfg(feature = "websocket")]
+        view_ctrl.register_view_type::<WebSocketView>()?;
+        self.plugins.init_view_coordinator(&mut view_ctrl)?;
+        let mut pvm = PVM::new(send);
+        pvm.set_edge_events(self.cfg.edge_events);
+        pvm.set_meta_policy(self.cfg.meta_policy);
+        pvm.set_options(self.cfg.ingest_options());
+        for (key, &heritable) in &self.cfg.heritability {
+            pvm.set_heritable(key, heritable);
+        }
+        if let Some(path) = &self.cfg.perf_file {
+            pvm.enable_perf_mon(path)
+                .map_err(EngineError::PerfMonError)?;
+        }
+        // created before anything is ingested, so no view misses the first events
+        for (name, params) in &self.cfg.views {
+            if let Err(e) = view_ctrl.create_view_with_name(name, params.clone()) {
+                pvm.shutdown();
+                view_ctrl.shutdown();
+                return Err(e.into());
+            }
+        }
+        self.pipeline = Some(Pipeline { pvm, view_ctrl });
         Ok(())
     }
 
@@ -174,8 +245,16 @@ impl Engine {
         user: Option<String>,
         pass: Option<String>,
     ) -> Result<()> {
+        let writers = self
+            .cfg
+            .cfg_detail
+            .as_ref()
+            .map(|detail| detail.persistence_threads);
         let pipeline = self.get_pipeline_mut()?;
         let mut params = ViewParams::new();
+        if let Some(writers) = writers {
+            params.insert_param("writers", writers as i64);
+        }
         if let Some(addr) = addr {
             params.insert_param("addr", addr);
         }
@@ -213,20 +292,115 @@ impl Engine {
         Ok(pipeline.view_ctrl.create_view_with_id(view_id, params)?)
     }
 
-    pub fn list_running_views(&self) -> Result<Vec<&ViewInst>> {
+    /// Stop sending events to a single running view, see `ViewCoordinator::pause_view`
+    pub fn pause_view(&mut self, view_inst_id: usize, policy: PausePolicy) -> Result<()> {
+        let pipeline = self.get_pipeline_mut()?;
+        Ok(pipeline.view_ctrl.pause_view(view_inst_id, policy)?)
+    }
+
+    pub fn resume_view(&mut self, view_inst_id: usize) -> Result<()> {
+        let pipeline = self.get_pipeline_mut()?;
+        Ok(pipeline.view_ctrl.resume_view(view_inst_id)?)
+    }
+
+    /// Stop a single running view and release it, see `ViewCoordinator::remove_view_inst`
+    pub fn destroy_view(&mut self, view_inst_id: usize) -> Result<()> {
+        let pipeline = self.get_pipeline_mut()?;
+        Ok(pipeline.view_ctrl.remove_view_inst(view_inst_id)?)
+    }
+
+    /// Failures reported by running views since the last call, see `ViewCoordinator::failures`
+    pub fn view_failures(&self) -> Result<Vec<ViewFailure>> {
         let pipeline = self.get_pipeline()?;
-        Ok(pipeline.view_ctrl.list_view_insts())
+        Ok(pipeline.view_ctrl.failures())
+    }
+
+    /// How far a running view has fallen behind, see `ViewCoordinator::view_lag`
+    pub fn view_lag(&self, view_inst_id: usize) -> Result<ViewLag> {
+        let pipeline = self.get_pipeline()?;
+        Ok(pipeline.view_ctrl.view_lag(view_inst_id)?)
     }
 
-    pub fn ingest_stream(&mut self, stream: IOStream) -> Result<()> {
+    /// Block until every running view has flushed the output of everything ingested so far
+    pub fn flush(&mut self) -> Result<()> {
         let pipeline = self.get_pipeline_mut()?;
-        ingest_stream::<_, TraceEvent>(stream, &mut pipeline.pvm);
+        let (marker, wait) = FlushMarker::new();
+        pipeline.pvm.flush(marker);
+        wait.wait();
         Ok(())
     }
 
-    pub fn ingest_reader<R: Read>(&mut self, reader: R) -> Result<()> {
+    pub fn list_running_views(&self) -> Result<Vec<&ViewInst>> {
+        let pipeline = self.get_pipeline()?;
+        Ok(pipeline.view_ctrl.list_view_insts())
+    }
+
+    /// The names of the trace formats that can be ingested, see `register_format`
+    pub fn list_formats(&self) -> Vec<&str> {
+        self.formats.names()
+    }
+
+    /// Make a trace format available for ingestion under a name
+    pub fn register_format<T: Mapped + 'static>(&mut self, name: &str) -> Result<()> {
+        Ok(self.formats.register::<T>(name)?)
+    }
+
+    /// The format used by `ingest_stream` and `ingest_values`, CADETS traces with or without FBT
+    /// records
+    fn default_format(&self) -> &'static str {
+        if self.cfg.skip_fbt {
+            "cadets_audit"
+        } else {
+            "cadets"
+        }
+    }
+
+    pub fn ingest_stream(&mut self, stream: IOStream) -> Result<IngestStats> {
+        self.ingest_reader(stream)
+    }
+
+    pub fn ingest_reader<R: Read>(&mut self, reader: R) -> Result<IngestStats> {
+        let fmt = self.default_format();
+        self.ingest_reader_fmt(reader, fmt)
+    }
+
+    /// Ingest a stream of records in the trace format registered under `fmt`
+    pub fn ingest_stream_fmt(&mut self, stream: IOStream, fmt: &str) -> Result<IngestStats> {
+        self.ingest_reader_fmt(stream, fmt)
+    }
+
+    pub fn ingest_reader_fmt<R: Read>(&mut self, reader: R, fmt: &str) -> Result<IngestStats> {
+        let pipeline = self
+            .pipeline
+            .as_mut()
+            .ok_or(EngineError::PipelineNotRunning)?;
+        Ok(self.formats.ingest_stream(fmt, reader, &mut pipeline.pvm)?)
+    }
+
+    pub fn ingest_values<I: Iterator<Item = serde_json::Value>>(
+        &mut self,
+        values: I,
+    ) -> Result<IngestStats> {
+        let fmt = self.default_format();
+        self.ingest_values_fmt(values, fmt)
+    }
+
+    pub fn ingest_values_fmt<I: Iterator<Item = serde_json::Value>>(
+        &mut self,
+        values: I,
+        fmt: &str,
+    ) -> Result<IngestStats> {
+        let pipeline = self
+            .pipeline
+            .as_mut()
+            .ok_or(EngineError::PipelineNotRunning)?;
+        Ok(self.formats.ingest_values(fmt, values, &mut pipeline.pvm)?)
+    }
+
+    /// Run a hook synchronously after every committed transaction, see `CommitHook`
+    pub fn add_commit_hook(&mut self, hook: CommitHook) -> Result<()> {
         let pipeline = self.get_pipeline_mut()?;
-        ingest_stream::<_, TraceEvent>(reader, &mut pipeline.pvm);
+        pipeline.pvm.add_commit_hook(hook);
         Ok(())
     }
 
@@ -253,3 +427,121 @@ impl Engine {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error;
+
+    use crate::plugins::format::{FormatRegistrar, TraceFormat};
+
+    use serde_json::{json, Value};
+
+    struct Bro;
+
+    impl TraceFormat for Bro {
+        fn base(&self) -> &str {
+            "zeek"
+        }
+
+        fn translate(
+            &self,
+            line: &str,
+        ) -> std::result::Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+            Ok(vec![json!({ "ts": 0.0, "uid": line, "proto": "tcp" })])
+        }
+    }
+
+    struct FormatPlugin;
+
+    impl Plugin for FormatPlugin {
+        fn init() -> Self {
+            FormatPlugin
+        }
+
+        fn view_ops(&self, _vc: &mut ViewCoordinator) -> std::result::Result<(), ViewError> {
+            Ok(())
+        }
+
+        fn formats(
+            &self,
+            reg: &mut dyn FormatRegistrar,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            reg.register("bro", Box::new(Bro))
+        }
+    }
+
+    #[test]
+    fn plugin_registers_format() {
+        let mut reg = FormatRegistry::with_builtin();
+        register_plugin_formats(&FormatPlugin, &mut reg).unwrap();
+        assert!(reg.contains("bro"));
+        match register_plugin_formats(&FormatPlugin, &mut reg) {
+            Err(EngineError::PluginError(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::AlreadyExists)
+            }
+            _ => panic!("expected a plugin error"),
+        }
+    }
+
+    #[test]
+    fn flush_needs_pipeline() {
+        let mut engine = Engine::new(Config::build().disable_perf().finish()).unwrap();
+        match engine.flush() {
+            Err(EngineError::PipelineNotRunning) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        engine.init_pipeline().unwrap();
+        engine.flush().unwrap();
+    }
+
+    #[test]
+    fn ingest_values_uses_default_format() {
+        let fbt = json!({
+            "event": "fbt:kernel:cc_conn_init:",
+            "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            "time": 1533046200000000000u64,
+            "so_uuid": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            "lport": 22,
+            "fport": 5000,
+            "laddr": "10.0.0.1",
+            "faddr": "10.0.0.2",
+        });
+        for &skip_fbt in &[false, true] {
+            let cfg = Config::build().disable_perf().skip_fbt(skip_fbt).finish();
+            let mut engine = Engine::new(cfg).unwrap();
+            engine.init_pipeline().unwrap();
+            let stats = engine.ingest_values(vec![fbt.clone()].into_iter()).unwrap();
+            // audit only ingest cannot deserialise FBT records
+            assert_eq!(stats.records, if skip_fbt { 0 } else { 1 });
+            engine.shutdown_pipeline().unwrap();
+        }
+    }
+
+    #[test]
+    fn configured_views_start_with_pipeline() {
+        let cfg = Config::build()
+            .disable_perf()
+            .view("StdoutJSONView", ViewParams::new())
+            .finish();
+        let mut engine = Engine::new(cfg).unwrap();
+        engine.init_pipeline().unwrap();
+        assert_eq!(engine.list_running_views().unwrap().len(), 1);
+        engine.shutdown_pipeline().unwrap();
+
+        let cfg = Config::build()
+            .disable_perf()
+            .view("StdoutJSONView", ViewParams::new())
+            .view("NoSuchView", ViewParams::new())
+            .finish();
+        let mut engine = Engine::new(cfg).unwrap();
+        match engine.init_pipeline() {
+            Err(EngineError::ViewError(ViewError::MissingViewName(name))) => {
+                assert_eq!(name, "NoSuchView")
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(engine.list_running_views().is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Dense arena storage for nodes keyed by their database ID
+//!
+//! IDs handed out by the `IDCounter` are dense and monotonic, so rather than hashing them as
+//! `LendingLibrary` does the arena keeps a flat index from ID to a slot in a slab. Slots freed by
+//! removals are recycled via a free list so the slab stays compact as nodes are released. The
+//! API mirrors the subset of `LendingLibrary` that the PVM uses so that the two can be swapped
+//! via the `node-arena` feature.
+
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    thread,
+};
+
+use crate::data::ID;
+
+/// Marker in the index for an ID that has no slot
+const NO_SLOT: u32 = 0;
+
+#[derive(Debug)]
+enum Slot<T> {
+    /// Unused, the slot is on the free list
+    Vacant,
+    /// Holds a stored value
+    Occupied(T),
+    /// The value is currently out on loan
+    Lent,
+    /// The value was removed while out on loan and should be dropped when it returns
+    Orphaned,
+}
+
+/// Slab backed store of values indexed by `ID`
+#[derive(Debug)]
+pub struct NodeArena<T> {
+    index: Vec<u32>,
+    slab: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+    outstanding: usize,
+}
+
+/// A value borrowed from a `NodeArena`
+///
+/// The value is returned to the arena when the loan is dropped. As with `lending_library::Loan`
+/// the loan is not tied to the lifetime of the arena, the arena must outlive all of its loans
+/// and must not be moved while they are outstanding.
+#[derive(Debug)]
+pub struct ArenaLoan<T> {
+    arena: *mut NodeArena<T>,
+    slot: u32,
+    inner: Option<T>,
+}
+
+impl<T> NodeArena<T> {
+    pub fn new() -> Self {
+        NodeArena {
+            index: Vec::new(),
+            slab: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            outstanding: 0,
+        }
+    }
+
+    /// Number of values held, including those out on loan
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of values the slab can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.slab.capacity()
+    }
+
+    /// Number of IDs the index can map without reallocating
+    pub fn index_capacity(&self) -> usize {
+        self.index.capacity()
+    }
+
+    fn slot_of(&self, key: ID) -> Option<u32> {
+        match self.index.get(key.inner() as usize) {
+            Some(&NO_SLOT) | None => None,
+            Some(&s) => Some(s - 1),
+        }
+    }
+
+    fn release_slot(&mut self, slot: u32) {
+        self.slab[slot as usize] = Slot::Vacant;
+        self.free.push(slot);
+    }
+
+    pub fn contains_key(&self, key: &ID) -> bool {
+        self.slot_of(*key).is_some()
+    }
+
+    /// Insert a value, returning the previous value for the key if one was present
+    pub fn insert(&mut self, key: ID, val: T) -> Option<T> {
+        let prev = self.take(key);
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slab[slot as usize] = Slot::Occupied(val);
+                slot
+            }
+            None => {
+                self.slab.push(Slot::Occupied(val));
+                (self.slab.len() - 1) as u32
+            }
+        };
+        let idx = key.inner() as usize;
+        if idx >= self.index.len() {
+            self.index.resize(idx + 1, NO_SLOT);
+        }
+        self.index[idx] = slot + 1;
+        self.len += 1;
+        prev
+    }
+
+    /// Remove a value, returning whether the key was present
+    ///
+    /// Removing a value that is out on loan drops it once the loan is returned.
+    pub fn remove(&mut self, key: &ID) -> bool {
+        if self.contains_key(key) {
+            self.take(*key);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take(&mut self, key: ID) -> Option<T> {
+        let slot = self.slot_of(key)?;
+        self.index[key.inner() as usize] = NO_SLOT;
+        self.len -= 1;
+        match mem::replace(&mut self.slab[slot as usize], Slot::Orphaned) {
+            Slot::Occupied(val) => {
+                self.release_slot(slot);
+                Some(val)
+            }
+            Slot::Lent => None,
+            Slot::Vacant | Slot::Orphaned => unreachable!("Indexed arena slot was not in use"),
+        }
+    }
+
+    /// Borrow a value out of the arena
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is already out on loan.
+    pub fn lend(&mut self, key: &ID) -> Option<ArenaLoan<T>> {
+        let slot = self.slot_of(*key)?;
+        match mem::replace(&mut self.slab[slot as usize], Slot::Lent) {
+            Slot::Occupied(val) => {
+                self.outstanding += 1;
+                Some(ArenaLoan {
+                    arena: self as *mut _,
+                    slot,
+                    inner: Some(val),
+                })
+            }
+            Slot::Lent => panic!("Lending already lent node {:?}", key),
+            Slot::Vacant | Slot::Orphaned => unreachable!("Indexed arena slot was not in use"),
+        }
+    }
+
+    fn checkin(&mut self, slot: u32, val: T) {
+        self.outstanding -= 1;
+        match self.slab[slot as usize] {
+            Slot::Lent => self.slab[slot as usize] = Slot::Occupied(val),
+            Slot::Orphaned => self.release_slot(slot),
+            Slot::Vacant | Slot::Occupied(_) => unreachable!("Returned loan to unlent arena slot"),
+        }
+    }
+}
+
+impl<T> Default for NodeArena<T> {
+    fn default() -> Self {
+        NodeArena::new()
+    }
+}
+
+impl<T> Drop for NodeArena<T> {
+    fn drop(&mut self) {
+        if self.outstanding != 0 && !thread::panicking() {
+            panic!(
+                "NodeArena dropped with {} outstanding loans",
+                self.outstanding
+            );
+        }
+    }
+}
+
+impl<T> Deref for ArenaLoan<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for ArenaLoan<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for ArenaLoan<T> {
+    fn drop(&mut self) {
+        if let Some(val) = self.inner.take() {
+            unsafe { (*self.arena).checkin(self.slot, val) }
+        }
+    }
+}
+
+/// Transactional view of a `NodeArena`
+///
+/// Node cache changes are kept whether a transaction commits or rolls back, so unlike
+/// `LendingWrap` this applies operations directly to the underlying arena.
+pub struct ArenaWrap<'a, T> {
+    inner: &'a mut NodeArena<T>,
+}
+
+impl<'a, T> ArenaWrap<'a, T> {
+    pub fn new(inner: &'a mut NodeArena<T>) -> Self {
+        ArenaWrap { inner }
+    }
+
+    pub fn commit(self) {}
+
+    pub fn contains_key(&self, key: &ID) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: ID, val: T) -> Option<T> {
+        self.inner.insert(key, val)
+    }
+
+    pub fn remove(&mut self, key: &ID) -> bool {
+        self.inner.remove(key)
+    }
+
+    pub fn lend(&mut self, key: &ID) -> Option<ArenaLoan<T>> {
+        self.inner.lend(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_lend() {
+        let mut arena = NodeArena::new();
+        arena.insert(ID::new(3), 30);
+        arena.insert(ID::new(7), 70);
+        assert_eq!(arena.len(), 2);
+        {
+            let mut v = arena.lend(&ID::new(3)).unwrap();
+            *v += 1;
+        }
+        assert_eq!(*arena.lend(&ID::new(3)).unwrap(), 31);
+        assert!(arena.lend(&ID::new(4)).is_none());
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut arena = NodeArena::new();
+        assert_eq!(arena.insert(ID::new(1), 1), None);
+        assert_eq!(arena.insert(ID::new(1), 2), Some(1));
+        assert_eq!(arena.len(), 1);
+        assert_eq!(*arena.lend(&ID::new(1)).unwrap(), 2);
+    }
+
+    #[test]
+    fn remove_reuses_slot() {
+        let mut arena = NodeArena::new();
+        arena.insert(ID::new(1), 1);
+        assert!(arena.remove(&ID::new(1)));
+        assert!(!arena.remove(&ID::new(1)));
+        arena.insert(ID::new(2), 2);
+        assert_eq!(arena.slab.len(), 1);
+        assert_eq!(arena.len(), 1);
+    }
+
+    #[test]
+    fn remove_while_lent() {
+        let mut arena = NodeArena::new();
+        arena.insert(ID::new(1), 1);
+        let loan = arena.lend(&ID::new(1)).unwrap();
+        assert!(arena.remove(&ID::new(1)));
+        arena.insert(ID::new(2), 2);
+        assert_eq!(arena.slab.len(), 2);
+        drop(loan);
+        assert!(!arena.contains_key(&ID::new(1)));
+        assert_eq!(arena.free, vec![0]);
+        assert_eq!(*arena.lend(&ID::new(2)).unwrap(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn double_lend() {
+        let mut arena = NodeArena::new();
+        arena.insert(ID::new(1), 1);
+        let _a = arena.lend(&ID::new(1));
+        let _b = arena.lend(&ID::new(1));
+    }
+}
@@ -1,17 +1,69 @@
-use std::{mem::swap, sync::mpsc::SyncSender};
+use std::{
+    mem::swap,
+    sync::{mpsc::SyncSender, Arc, Mutex},
+};
 
 use crate::{
     data::{node_types::Node, rel_types::Rel, Enumerable, HasID},
-    view::DBTr,
+    view::{DBTr, FlushMarker},
 };
 
+/// A consumer of the operations produced by the PVM
+///
+/// Operations are delivered in order as each transaction commits. The only method that must be
+/// provided is `op`, the typed methods exist for consumers that would rather not match on
+/// `DBTr` themselves.
+pub trait DBSink: Send {
+    fn op(&mut self, op: DBTr);
+
+    fn create_node(&mut self, node: Node) {
+        self.op(DBTr::CreateNode(node))
+    }
+
+    fn create_rel(&mut self, rel: Rel) {
+        self.op(DBTr::CreateRel(rel))
+    }
+
+    fn update_node(&mut self, node: Node) {
+        self.op(DBTr::UpdateNode(node))
+    }
+
+    fn update_rel(&mut self, rel: Rel) {
+        self.op(DBTr::UpdateRel(rel))
+    }
+}
+
+/// Forwards operations to the view coordinator
+impl DBSink for SyncSender<DBTr> {
+    fn op(&mut self, op: DBTr) {
+        self.send(op)
+            .expect("Database worker closed queue unexpectadly")
+    }
+}
+
+/// Collects operations in memory
+impl DBSink for Vec<DBTr> {
+    fn op(&mut self, op: DBTr) {
+        self.push(op)
+    }
+}
+
+/// Shares collected operations with another handle, such as a test that keeps a clone
+impl<S: DBSink> DBSink for Arc<Mutex<S>> {
+    fn op(&mut self, op: DBTr) {
+        self.lock().unwrap().op(op)
+    }
+}
+
 pub struct DB {
-    persist_pipe: SyncSender<DBTr>,
+    sink: Box<dyn DBSink>,
 }
 
 impl DB {
-    pub fn create(pipe: SyncSender<DBTr>) -> DB {
-        DB { persist_pipe: pipe }
+    pub fn create<S: DBSink + 'static>(sink: S) -> DB {
+        DB {
+            sink: Box::new(sink),
+        }
     }
 
     pub fn store(&mut self) -> DBStore {
@@ -22,15 +74,12 @@ impl DB {
     }
 
     pub fn create_node<N: Enumerable<Target = Node>>(&mut self, node: N) {
-        self.persist_pipe
-            .send(DBTr::CreateNode(node.enumerate()))
-            .expect("Database worker closed queue unexpectadly")
+        self.sink.create_node(node.enumerate())
     }
 
-    fn op(&mut self, op: DBTr) {
-        self.persist_pipe
-            .send(op)
-            .expect("Database worker closed queue unexpectadly")
+    /// Place a flush barrier after every operation committed so far
+    pub fn flush(&mut self, marker: FlushMarker) {
+        self.sink.op(DBTr::Flush(marker))
     }
 }
 
@@ -109,6 +158,7 @@ impl<'a> DBStore<'a> {
                     }
                     _ => {}
                 },
+                DBTr::Flush(_) | DBTr::Tick(_) => unreachable!("Only updates are stored"),
             }
         }
         self.ops.push(op);
@@ -118,9 +168,13 @@ impl<'a> DBStore<'a> {
         self.ops.len()
     }
 
+    pub fn ops(&self) -> &[DBTr] {
+        &self.ops
+    }
+
     pub fn commit(self) {
         for op in self.ops {
-            self.inner.op(op)
+            self.inner.sink.op(op)
         }
     }
 }
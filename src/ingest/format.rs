@@ -0,0 +1,329 @@
+//! Trace formats selectable by name at runtime
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{BufRead, BufReader, Read},
+    sync::Arc,
+};
+
+use super::{ingest_stream, ingest_values, pvm::PVM, IngestStats, Mapped};
+use crate::{
+    plugins::format::{FormatRegistrar, TraceFormat},
+    trace::{
+        auditd::AuditdEvent,
+        cadets::{AuditEvent, TraceEvent},
+        camflow::CamFlowDoc,
+        cloudtrail::CloudTrailLog,
+        dtrace::DTraceEvent,
+        journald::JournalEntry,
+        k8s_audit::K8sAuditEvent,
+        openbsm::BsmEvent,
+        otel::OtelExport,
+        prov_json::ProvDoc,
+        spade::SpadeElement,
+        strace::StraceEvent,
+        sysdig::SysdigEvent,
+        zeek::ZeekEvent,
+    },
+};
+
+use quick_error::quick_error;
+use serde_json::Value;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum FormatError {
+        DuplicateFormat(name: String) {
+            display("Trace format with name {} already exists.", name)
+        }
+        MissingFormat(name: String) {
+            display("No trace format registered under name {}.", name)
+        }
+        TranslatedBase(name: String) {
+            display("Trace format {} is provided by a plugin and cannot be translated into.", name)
+        }
+    }
+}
+
+type StreamFn = fn(&mut dyn Read, &mut PVM) -> IngestStats;
+type ValuesFn = fn(&mut dyn Iterator<Item = Value>, &mut PVM) -> IngestStats;
+
+fn stream_fn<T: Mapped>(stream: &mut dyn Read, pvm: &mut PVM) -> IngestStats {
+    ingest_stream::<_, T>(stream, pvm)
+}
+
+fn values_fn<T: Mapped>(values: &mut dyn Iterator<Item = Value>, pvm: &mut PVM) -> IngestStats {
+    ingest_values::<_, T>(values, pvm)
+}
+
+/// The ingest functions for one `Mapped` type
+#[derive(Clone, Copy)]
+struct Native {
+    stream: StreamFn,
+    values: ValuesFn,
+}
+
+/// A trace format, either mapped by the engine or translated by a plugin into a native one
+enum Format {
+    Native(Native),
+    Translated(Native, Arc<dyn TraceFormat>),
+}
+
+impl Format {
+    fn ingest_stream(&self, stream: &mut dyn Read, pvm: &mut PVM) -> IngestStats {
+        match self {
+            Format::Native(n) => (n.stream)(stream, pvm),
+            Format::Translated(base, fmt) => {
+                let mut failed = 0;
+                let mut stats = {
+                    let mut values =
+                        BufReader::new(stream)
+                            .lines()
+                            .enumerate()
+                            .flat_map(|(n, line)| {
+                                let err = match line.map(|line| fmt.translate(&line)) {
+                                    Ok(Ok(vals)) => return vals,
+                                    Ok(Err(e)) => format!("Translation error: {}", e),
+                                    Err(e) => format!("File Reading error: {}", e),
+                                };
+                                eprintln!("Line: {}", n + 1);
+                                eprintln!("{}", err);
+                                failed += 1;
+                                Vec::new()
+                            });
+                    (base.values)(&mut values, pvm)
+                };
+                stats.parse_errors += failed;
+                stats
+            }
+        }
+    }
+
+    fn ingest_values(&self, values: &mut dyn Iterator<Item = Value>, pvm: &mut PVM) -> IngestStats {
+        match self {
+            Format::Native(n) | Format::Translated(n, _) => (n.values)(values, pvm),
+        }
+    }
+}
+
+/// The trace formats known to an engine, keyed by name
+///
+/// Each `Mapped` implementation is registered under a name that ingestion can then select it
+/// by, so adding a format does not require changes to the engine. Plugins add theirs through
+/// `FormatRegistrar`, translating into a format registered here; records of such formats that
+/// are already parsed are taken to be in the base format.
+#[derive(Default)]
+pub struct FormatRegistry {
+    formats: BTreeMap<String, Format>,
+}
+
+impl FormatRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        FormatRegistry::default()
+    }
+
+    /// Create a registry holding the formats in `trace`
+    ///
+    /// CADETS traces are registered as `cadets`, or `cadets_audit` to skip FBT records, the
+    /// other formats under the name of their module.
+    pub fn with_builtin() -> Self {
+        let mut reg = FormatRegistry::new();
+        reg.insert::<TraceEvent>("cadets");
+        reg.insert::<AuditEvent>("cadets_audit");
+        reg.insert::<AuditdEvent>("auditd");
+        reg.insert::<CamFlowDoc>("camflow");
+        reg.insert::<CloudTrailLog>("cloudtrail");
+        reg.insert::<DTraceEvent>("dtrace");
+        reg.insert::<JournalEntry>("journald");
+        reg.insert::<K8sAuditEvent>("k8s_audit");
+        reg.insert::<BsmEvent>("openbsm");
+        reg.insert::<OtelExport>("otel");
+        reg.insert::<ProvDoc>("prov_json");
+        reg.insert::<SpadeElement>("spade");
+        reg.insert::<StraceEvent>("strace");
+        reg.insert::<SysdigEvent>("sysdig");
+        reg.insert::<ZeekEvent>("zeek");
+        reg
+    }
+
+    fn insert<T: Mapped>(&mut self, name: &str) {
+        self.formats.insert(
+            name.to_string(),
+            Format::Native(Native {
+                stream: stream_fn::<T>,
+                values: values_fn::<T>,
+            }),
+        );
+    }
+
+    /// Register a format under a name, failing if the name is already taken
+    pub fn register<T: Mapped>(&mut self, name: &str) -> Result<(), FormatError> {
+        if self.formats.contains_key(name) {
+            return Err(FormatError::DuplicateFormat(name.to_string()));
+        }
+        self.insert::<T>(name);
+        Ok(())
+    }
+
+    /// The names of the registered formats in sorted order
+    pub fn names(&self) -> Vec<&str> {
+        self.formats.keys().map(String::as_str).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.formats.contains_key(name)
+    }
+
+    fn get(&self, name: &str) -> Result<&Format, FormatError> {
+        self.formats
+            .get(name)
+            .ok_or_else(|| FormatError::MissingFormat(name.to_string()))
+    }
+
+    /// Ingest a line delimited stream of records in the named format, see `ingest_stream`
+    pub fn ingest_stream<R: Read>(
+        &self,
+        name: &str,
+        mut stream: R,
+        pvm: &mut PVM,
+    ) -> Result<IngestStats, FormatError> {
+        Ok(self.get(name)?.ingest_stream(&mut stream, pvm))
+    }
+
+    /// Ingest already parsed records in the named format, see `ingest_values`
+    pub fn ingest_values<I: IntoIterator<Item = Value>>(
+        &self,
+        name: &str,
+        values: I,
+        pvm: &mut PVM,
+    ) -> Result<IngestStats, FormatError> {
+        Ok(self.get(name)?.ingest_values(&mut values.into_iter(), pvm))
+    }
+}
+
+impl FormatRegistrar for FormatRegistry {
+    fn register(&mut self, name: &str, fmt: Box<dyn TraceFormat>) -> Result<(), Box<dyn Error>> {
+        if self.formats.contains_key(name) {
+            return Err(FormatError::DuplicateFormat(name.to_string()).into());
+        }
+        let base = match self.get(fmt.base())? {
+            Format::Native(n) => *n,
+            Format::Translated(..) => {
+                return Err(FormatError::TranslatedBase(fmt.base().to_string()).into())
+            }
+        };
+        self.formats
+            .insert(name.to_string(), Format::Translated(base, fmt.into()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn builtin_formats() {
+        let reg = FormatRegistry::with_builtin();
+        assert!(reg.contains("cadets"));
+        assert!(reg.contains("zeek"));
+        assert!(reg.names().windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn register_and_select() {
+        let mut reg = FormatRegistry::new();
+        reg.register::<ZeekEvent>("network").unwrap();
+        match reg.register::<SysdigEvent>("network") {
+            Err(FormatError::DuplicateFormat(name)) => assert_eq!(name, "network"),
+            _ => panic!("expected a duplicate format error"),
+        }
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let line = r#"{"ts": 1533046200.12, "uid": "C1", "proto": "tcp"}"#;
+        let stats = reg
+            .ingest_stream("network", line.as_bytes(), &mut pvm)
+            .unwrap();
+        assert_eq!(stats.records, 1);
+        match reg.ingest_stream("cadets", line.as_bytes(), &mut pvm) {
+            Err(FormatError::MissingFormat(name)) => assert_eq!(name, "cadets"),
+            _ => panic!("expected a missing format error"),
+        }
+        drop(pvm);
+        assert!(recv.iter().count() > 0);
+    }
+
+    struct Tsv;
+
+    impl TraceFormat for Tsv {
+        fn base(&self) -> &str {
+            "zeek"
+        }
+
+        fn translate(&self, line: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+            match line.split('\t').collect::<Vec<_>>()[..] {
+                [ts, uid, proto] => Ok(vec![serde_json::json!({
+                    "ts": ts.parse::<f64>()?,
+                    "uid": uid,
+                    "proto": proto,
+                })]),
+                _ => Err("expected three fields".into()),
+            }
+        }
+    }
+
+    #[test]
+    fn translated_format() {
+        let mut reg = FormatRegistry::with_builtin();
+        FormatRegistrar::register(&mut reg, "zeek_tsv", Box::new(Tsv)).unwrap();
+        assert!(FormatRegistrar::register(&mut reg, "zeek_tsv", Box::new(Tsv)).is_err());
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let input = "1533046200.12\tC1\ttcp\nnonsense\n1533046201.5\tC2\tudp\n";
+        let stats = reg
+            .ingest_stream("zeek_tsv", input.as_bytes(), &mut pvm)
+            .unwrap();
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.parse_errors, 1);
+        drop(pvm);
+        assert!(recv.iter().count() > 0);
+    }
+
+    #[test]
+    fn translated_format_skips_unreadable_lines() {
+        let mut reg = FormatRegistry::with_builtin();
+        FormatRegistrar::register(&mut reg, "zeek_tsv", Box::new(Tsv)).unwrap();
+        let (send, _recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let input = b"1533046200.12\tC1\ttcp\n\xff\xfe\n1533046201.5\tC2\tudp\n";
+        let stats = reg.ingest_stream("zeek_tsv", &input[..], &mut pvm).unwrap();
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.parse_errors, 1);
+    }
+
+    #[test]
+    fn base_must_be_native() {
+        struct Nested;
+
+        impl TraceFormat for Nested {
+            fn base(&self) -> &str {
+                "zeek_tsv"
+            }
+
+            fn translate(&self, _: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+                Ok(Vec::new())
+            }
+        }
+
+        let mut reg = FormatRegistry::with_builtin();
+        FormatRegistrar::register(&mut reg, "zeek_tsv", Box::new(Tsv)).unwrap();
+        assert!(FormatRegistrar::register(&mut reg, "nested", Box::new(Nested)).is_err());
+        assert!(
+            FormatRegistrar::register(&mut FormatRegistry::new(), "tsv", Box::new(Tsv)).is_err()
+        );
+    }
+}
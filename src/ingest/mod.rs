@@ -1,17 +1,28 @@
 //! Various elements defining the ingestion process
 
 use std::{
+    collections::HashMap,
     fmt::Display,
     io::{BufRead, BufReader, Read},
+    mem,
 };
 
-use self::pvm::{PVMError, PVM};
+pub use self::{
+    db::DBSink,
+    format::{FormatError, FormatRegistry},
+};
+
+use self::pvm::{PVMError, VersionStats, PVM};
+use crate::trace::{cadets::CadetsVersion, TsFormat};
 
 use rayon::prelude::*;
 use serde::de::DeserializeOwned;
-use serde_json;
+use serde_json::{self, Value};
 
+#[cfg(feature = "node-arena")]
+pub mod arena;
 mod db;
+pub mod format;
 pub mod pvm;
 
 const BATCH_SIZE: usize = 0x10_000;
@@ -53,13 +64,206 @@ pub trait Mapped: DeserializeOwned + Display + Send + Sized {
     /// it's data stream that identifies where this record starts. This is allowed to vary for
     /// different data sources, but should generally be something that could sensibly be added to
     /// the context for the record.
-    fn set_offset(&mut self, offset: usize);
+    fn set_offset(&mut self, offset: TraceOffset);
+
+    /// Identify the source this record came from
+    ///
+    /// Records from the same source share an offset namespace, so a trace made by concatenating
+    /// the traces of several hosts still gives each record its offset within its own host's
+    /// trace. Called after `update`.
+    fn source(&self) -> Option<String> {
+        None
+    }
+
+    /// Cheaply decide whether a raw line could hold a record of this type
+    ///
+    /// Called by `ingest_stream` before deserialisation, lines rejected here are counted as
+    /// filtered and never parsed. Formats that share a stream with records they ignore can use
+    /// this to avoid paying for deserialising them.
+    fn accept(_line: &str) -> bool {
+        true
+    }
+
+    /// Parse a record from a line that is not JSON
+    ///
+    /// Called by `ingest_stream` for lines that do not start a JSON object, which are otherwise
+    /// skipped. Formats with a textual representation can implement this to ingest it directly.
+    fn from_text(_line: &str) -> Option<Self> {
+        None
+    }
+
+    /// Cheaply decide whether a raw line needs `upgrade` applied before deserialisation
+    ///
+    /// Called by `ingest_stream` for each JSON line, lines accepted here are parsed into a
+    /// generic JSON value and upgraded first, others are deserialised directly.
+    fn needs_upgrade(_line: &str, _opts: &IngestOptions) -> bool {
+        false
+    }
+
+    /// Rewrite a record in an older schema into the one the type deserialises
+    ///
+    /// Called before deserialisation on lines selected by `needs_upgrade` and on every value
+    /// given to `ingest_values`. Formats whose schema changed between releases can rename
+    /// fields here instead of carrying every historic spelling in their record type.
+    fn upgrade(_val: &mut Value, _opts: &IngestOptions) {}
+}
+
+/// Options of an ingest that trace formats map their records by, set from `cfg::Config`
+///
+/// They are held by the `PVM` rather than the process, so engines in the same process can
+/// ingest differently encoded traces.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IngestOptions {
+    /// Encoding of the timestamps of CADETS records
+    pub ts_format: TsFormat,
+    /// CADETS schema release records are assumed to follow
+    pub cadets_version: CadetsVersion,
+    /// Attribute CADETS events to per thread actors rather than their process
+    pub cadets_threads: bool,
+    /// Record the environment given to exec in CADETS traces, limited to a few variables
+    pub cadets_env: bool,
+}
+
+/// Location of a record within the trace it came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceOffset {
+    /// The source of the record, as given by `Mapped::source`
+    pub source: Option<String>,
+    /// Index of the record amongst those from the same source, or its line in the input for
+    /// records without a source
+    pub offset: usize,
+}
+
+/// Assigns offsets to records in input order
+#[derive(Debug, Default)]
+struct Offsets(HashMap<String, usize>);
+
+impl Offsets {
+    fn assign<T: Mapped>(&mut self, n: usize, evt: &mut T) {
+        let offset = match evt.source() {
+            Some(source) => {
+                let next = self.0.entry(source.clone()).or_insert(0);
+                *next += 1;
+                TraceOffset {
+                    source: Some(source),
+                    offset: *next - 1,
+                }
+            }
+            None => TraceOffset {
+                source: None,
+                offset: n,
+            },
+        };
+        evt.set_offset(offset);
+    }
 }
 
-pub fn ingest_stream<R: Read, T: Mapped>(stream: R, pvm: &mut PVM) {
+/// Summary of a single ingestion run
+#[derive(Clone, Debug, Default)]
+pub struct IngestStats {
+    /// Number of records successfully deserialised
+    pub records: usize,
+    /// Number of records that failed to deserialise
+    pub parse_errors: usize,
+    /// Number of lines skipped without parsing as rejected by `Mapped::accept`
+    pub filtered: usize,
+    /// Number of records rejected by the PVM mapping
+    pub process_errors: usize,
+    /// Number of object references passed over for being the nil uuid
+    pub nil_uuids: usize,
+    /// Set if a commit hook ended the run before the input was exhausted
+    pub aborted: bool,
+    /// Number of bytes discarded while re-synchronising after corrupt input
+    pub resync_bytes: usize,
+    /// Version chain lengths of the objects in the model at the end of the run
+    pub versions: VersionStats,
+}
+
+/// Outcome of parsing a single line of a trace stream
+enum Parsed<T> {
+    /// The line held a complete record
+    Record(T),
+    /// No record could be recovered from a line of the given length
+    Failed(usize),
+    /// A line of the given length that does not start a JSON object, typically the remains of
+    /// a corrupt record that spanned several lines
+    Skipped(usize),
+}
+
+/// Deserialise a record, upgrading it first if the format asks for it
+fn from_str<T: Mapped>(rec: &str, opts: &IngestOptions) -> serde_json::Result<T> {
+    if T::needs_upgrade(rec, opts) {
+        let mut val: Value = serde_json::from_str(rec)?;
+        T::upgrade(&mut val, opts);
+        serde_json::from_value(val)
+    } else {
+        serde_json::from_str(rec)
+    }
+}
+
+/// Parse a line, skipping it whole if it is corrupt
+///
+/// Lines that do not begin a JSON object are handed to `Mapped::from_text` and skipped if it
+/// cannot parse them either. A trailing comma is ignored, so traces written as a JSON array
+/// with one element per line can be read directly. A line that fails to parse is discarded, and
+/// ingestion re-synchronises on the next line that begins a valid record, so a corrupt record
+/// never pulls in part of a nested object that follows it.
+fn parse_line<T: Mapped>(n: usize, s: &str, opts: &IngestOptions) -> Parsed<T> {
+    if !s.trim_start().starts_with('{') {
+        return match T::from_text(s) {
+            Some(evt) => Parsed::Record(evt),
+            None => Parsed::Skipped(s.len()),
+        };
+    }
+    let rec = s.trim_end().trim_end_matches(',');
+    let perr = match from_str::<T>(rec, opts) {
+        Ok(evt) => return Parsed::Record(evt),
+        Err(perr) => perr,
+    };
+    eprintln!("Line: {}", n + 1);
+    eprintln!("JSON Parsing error: {}", perr);
+    eprintln!("{}", s);
+    Parsed::Failed(s.len())
+}
+
+/// Apply a record to the PVM, returns false if ingestion should stop
+fn process_record<T: Mapped>(n: usize, tr: T, pvm: &mut PVM, stats: &mut IngestStats) -> bool {
+    stats.records += 1;
+    match tr.process(pvm) {
+        Ok(()) => {}
+        Err(e @ PVMError::HookAborted { .. }) | Err(e @ PVMError::HookFailed { .. }) => {
+            eprintln!("Line: {}", n + 1);
+            eprintln!("{}", e);
+            stats.aborted = true;
+        }
+        Err(e) => {
+            eprintln!("Line: {}", n + 1);
+            eprintln!("PVM Parsing error: {}", e);
+            eprintln!("{}", tr);
+            stats.process_errors += 1;
+        }
+    }
+    !stats.aborted
+}
+
+fn report_unparsed(pvm: &mut PVM, stats: &mut IngestStats) {
+    eprintln!("Missing Events:");
+    for (evt, count) in pvm.unparsed_events.drain(..) {
+        eprintln!("{}: {}", evt, count);
+    }
+    stats.nil_uuids = mem::replace(&mut pvm.nil_uuids, 0);
+    if stats.nil_uuids > 0 {
+        eprintln!("Nil object uuids skipped: {}", stats.nil_uuids);
+    }
+}
+
+pub fn ingest_stream<R: Read, T: Mapped>(stream: R, pvm: &mut PVM) -> IngestStats {
+    let mut stats = IngestStats::default();
+    let mut offsets = Offsets::default();
     let mut pre_vec: Vec<(usize, String)> = Vec::with_capacity(BATCH_SIZE);
-    let mut post_vec: Vec<(usize, Option<T>)> = Vec::with_capacity(BATCH_SIZE);
+    let mut post_vec: Vec<(usize, Parsed<T>)> = Vec::with_capacity(BATCH_SIZE);
     let mut lines = BufReader::new(stream).lines().enumerate();
+    let opts = *pvm.options();
 
     T::init(pvm);
 
@@ -72,6 +276,7 @@ pub fn ingest_stream<R: Read, T: Mapped>(stream: R, pvm: &mut PVM) {
                     Err(perr) => {
                         eprintln!("Line: {}", n + 1);
                         eprintln!("File Reading error: {}", perr);
+                        stats.parse_errors += 1;
                         continue;
                     }
                 },
@@ -88,40 +293,218 @@ pub fn ingest_stream<R: Read, T: Mapped>(stream: R, pvm: &mut PVM) {
             if l.starts_with(", ") {
                 l.drain(0..2);
             }
+            if !T::accept(&l) {
+                stats.filtered += 1;
+                continue;
+            }
             pre_vec.push((n, l));
         }
 
         pre_vec
             .par_iter()
-            .map(|(n, s)| match serde_json::from_slice::<T>(s.as_bytes()) {
-                Ok(mut evt) => {
-                    evt.set_offset(*n);
+            .map(|(n, s)| {
+                let mut parsed = parse_line::<T>(*n, s, &opts);
+                if let Parsed::Record(ref mut evt) = parsed {
                     evt.update();
-                    (*n, Some(evt))
-                }
-                Err(perr) => {
-                    eprintln!("Line: {}", n + 1);
-                    eprintln!("JSON Parsing error: {}", perr);
-                    eprintln!("{}", s);
-                    (*n, None)
                 }
+                (*n, parsed)
             })
             .collect_into_vec(&mut post_vec);
         for (n, tr) in post_vec.drain(..) {
-            if let Some(tr) = tr {
-                if let Err(e) = tr.process(pvm) {
-                    eprintln!("Line: {}", n + 1);
-                    eprintln!("PVM Parsing error: {}", e);
-                    eprintln!("{}", tr);
+            match tr {
+                Parsed::Record(mut tr) => {
+                    offsets.assign(n, &mut tr);
+                    if !process_record(n, tr, pvm, &mut stats) {
+                        break;
+                    }
                 }
+                Parsed::Failed(skipped) => {
+                    stats.parse_errors += 1;
+                    stats.resync_bytes += skipped;
+                }
+                Parsed::Skipped(skipped) => stats.resync_bytes += skipped,
             }
         }
-        if pre_vec.len() < BATCH_SIZE {
+        if pre_vec.len() < BATCH_SIZE || stats.aborted {
             break;
         }
     }
-    println!("Missing Events:");
-    for evt in pvm.unparsed_events.drain() {
-        println!("{}", evt);
+    report_unparsed(pvm, &mut stats);
+    stats.versions = pvm.version_stats();
+    stats
+}
+
+/// Ingest records that have already been parsed into JSON values
+///
+/// This skips the string round-trip of `ingest_stream` for hosts that have already decoded
+/// their input. The position of each value in the iterator is supplied as its offset.
+pub fn ingest_values<I: IntoIterator<Item = Value>, T: Mapped>(
+    values: I,
+    pvm: &mut PVM,
+) -> IngestStats {
+    let mut stats = IngestStats::default();
+    let mut offsets = Offsets::default();
+    let opts = *pvm.options();
+
+    T::init(pvm);
+
+    for (n, mut val) in values.into_iter().enumerate() {
+        T::upgrade(&mut val, &opts);
+        match serde_json::from_value::<T>(val) {
+            Ok(mut evt) => {
+                evt.update();
+                offsets.assign(n, &mut evt);
+                if !process_record(n, evt, pvm, &mut stats) {
+                    break;
+                }
+            }
+            Err(perr) => {
+                eprintln!("Record: {}", n + 1);
+                eprintln!("JSON Parsing error: {}", perr);
+                stats.parse_errors += 1;
+            }
+        }
+    }
+    report_unparsed(pvm, &mut stats);
+    stats.versions = pvm.version_stats();
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fmt;
+
+    use serde_derive::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Rec {
+        id: u32,
+    }
+
+    impl fmt::Display for Rec {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl Mapped for Rec {
+        fn init(_: &mut PVM) {}
+        fn process(&self, _: &mut PVM) -> Result<(), PVMError> {
+            Ok(())
+        }
+        fn set_offset(&mut self, _: TraceOffset) {}
+        fn from_text(line: &str) -> Option<Self> {
+            line.strip_prefix("id=")
+                .and_then(|id| id.parse().ok())
+                .map(|id| Rec { id })
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct HostRec {
+        host: Option<String>,
+        #[serde(skip)]
+        offset: Option<TraceOffset>,
+    }
+
+    impl fmt::Display for HostRec {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl Mapped for HostRec {
+        fn init(_: &mut PVM) {}
+        fn process(&self, _: &mut PVM) -> Result<(), PVMError> {
+            Ok(())
+        }
+        fn set_offset(&mut self, offset: TraceOffset) {
+            self.offset = Some(offset);
+        }
+        fn source(&self) -> Option<String> {
+            self.host.clone()
+        }
+    }
+
+    #[test]
+    fn offsets_per_source() {
+        let mut offsets = Offsets::default();
+        let assigned: Vec<_> = [Some("a"), Some("b"), None, Some("a"), Some("b"), Some("b")]
+            .iter()
+            .enumerate()
+            .map(|(n, host)| {
+                let mut rec = HostRec {
+                    host: host.map(String::from),
+                    offset: None,
+                };
+                offsets.assign(n, &mut rec);
+                let off = rec.offset.unwrap();
+                (off.source, off.offset)
+            })
+            .collect();
+        let src = |s: &str| Some(s.to_string());
+        assert_eq!(
+            assigned,
+            vec![
+                (src("a"), 0),
+                (src("b"), 0),
+                (None, 2),
+                (src("a"), 1),
+                (src("b"), 1),
+                (src("b"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_clean_line() {
+        match parse_line::<Rec>(0, r#"{"id": 1}"#, &IngestOptions::default()) {
+            Parsed::Record(r) => assert_eq!(r, Rec { id: 1 }),
+            _ => panic!("expected a clean record"),
+        }
+    }
+
+    #[test]
+    fn truncated_record_skips_rest_of_line() {
+        // the nested object is not mistaken for the start of a record
+        let line = r#"{"id": 1, "na{"id": 2}"#;
+        match parse_line::<Rec>(0, line, &IngestOptions::default()) {
+            Parsed::Failed(skipped) => assert_eq!(skipped, line.len()),
+            _ => panic!("expected a failed line"),
+        }
+    }
+
+    #[test]
+    fn skip_continuation_line() {
+        match parse_line::<Rec>(0, r#""tail": 3}"#, &IngestOptions::default()) {
+            Parsed::Skipped(skipped) => assert_eq!(skipped, 10),
+            _ => panic!("expected a skipped line"),
+        }
+    }
+
+    #[test]
+    fn parse_text_line() {
+        match parse_line::<Rec>(0, "id=4", &IngestOptions::default()) {
+            Parsed::Record(r) => assert_eq!(r, Rec { id: 4 }),
+            _ => panic!("expected a text record"),
+        }
+    }
+
+    #[test]
+    fn parse_array_element_line() {
+        match parse_line::<Rec>(0, r#"{"id": 5},"#, &IngestOptions::default()) {
+            Parsed::Record(r) => assert_eq!(r, Rec { id: 5 }),
+            _ => panic!("expected a record"),
+        }
+    }
+
+    #[test]
+    fn fail_unrecoverable_line() {
+        match parse_line::<Rec>(0, r#"{"id": "#, &IngestOptions::default()) {
+            Parsed::Failed(skipped) => assert_eq!(skipped, 7),
+            _ => panic!("expected a failed line"),
+        }
     }
 }
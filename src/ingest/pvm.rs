@@ -1,16 +1,19 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
-    io::{Seek, SeekFrom, Write},
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
     sync::{
         atomic::{AtomicUsize, Ordering},
         mpsc::SyncSender,
+        Arc,
     },
     time::{Duration, Instant},
 };
 
 use crate::{
+    clock::{Clock, SystemClock},
     data::{
         node_types::{
             ConcreteType, ContextType, CtxNode, DataNode, Name, NameNode, PVMDataType,
@@ -19,20 +22,41 @@ use crate::{
         rel_types::{Inf, InfInit, Named, NamedInit, PVMOps, Rel},
         Denumerate, Enumerable, HasID, MetaStore, RelGenerable, ID,
     },
-    ingest::db::{DBStore, DB},
-    view::DBTr,
+    ingest::{
+        db::{DBSink, DBStore, DB},
+        IngestOptions,
+    },
+    view::{DBTr, FlushMarker},
 };
 
 use bytesize::to_string as to_human_bytes;
 use either::Either;
 use humantime::format_duration;
+use indexmap::IndexMap;
 use lending_library::{LendingLibrary, Loan};
-use maplit::hashset;
+use maplit::{hashmap, hashset};
 use transactions::{hash_wrap::HashWrap, lending_wrap::LendingWrap};
 use uuid::Uuid;
 
 use quick_error::quick_error;
 
+#[cfg(feature = "node-arena")]
+use super::arena::{ArenaLoan, ArenaWrap, NodeArena};
+
+#[cfg(not(feature = "node-arena"))]
+type NodeCache = LendingLibrary<ID, DataNode>;
+#[cfg(not(feature = "node-arena"))]
+type NodeCacheWrap<'a> = LendingWrap<'a, ID, DataNode>;
+#[cfg(not(feature = "node-arena"))]
+type NodeLoan = Loan<ID, DataNode>;
+
+#[cfg(feature = "node-arena")]
+type NodeCache = NodeArena<DataNode>;
+#[cfg(feature = "node-arena")]
+type NodeCacheWrap<'a> = ArenaWrap<'a, DataNode>;
+#[cfg(feature = "node-arena")]
+type NodeLoan = ArenaLoan<DataNode>;
+
 quick_error! {
     #[derive(Debug)]
     pub enum PVMError {
@@ -44,11 +68,43 @@ quick_error! {
             description("Event missing required field")
             display("Event {} missing needed field {}", evt, field)
         }
+        NilUuid { ty: &'static str } {
+            description("Object declared with the nil uuid")
+            display("Refusing to add {} object with the nil uuid", ty)
+        }
+        HookAborted { reason: String } {
+            description("Commit hook aborted ingestion")
+            display("Commit hook aborted ingestion, {}", reason)
+        }
+        HookFailed { err: Box<PVMError> } {
+            description("Commit hook failed")
+            display("Commit hook failed, {}", err)
+            cause(&**err)
+        }
     }
 }
 
 pub type PVMResult<T> = Result<T, PVMError>;
 
+/// The changes made by a committed transaction
+#[derive(Debug)]
+pub struct CommitSummary<'a> {
+    /// The context node of the transaction
+    pub ctx: &'a CtxNode,
+    /// IDs of the nodes and relationships created
+    pub created: Vec<ID>,
+    /// IDs of the existing nodes and relationships that were updated
+    pub updated: Vec<ID>,
+}
+
+/// A callback run synchronously after each transaction that changed the model commits
+///
+/// Hooks run on the ingest thread in registration order, so a slow hook slows ingestion. A hook
+/// returning an error stops later hooks for that transaction and ends the ingest run, though the
+/// transaction itself remains committed. Hooks stopping ingestion on purpose return
+/// `HookAborted`, any other error is passed on wrapped in `HookFailed`.
+pub type CommitHook = Box<dyn FnMut(&CommitSummary) -> PVMResult<()> + Send>;
+
 #[derive(Debug)]
 pub struct IDCounter {
     store: AtomicUsize,
@@ -95,6 +151,41 @@ impl<'a> IDWrap<'a> {
     }
 }
 
+/// The number of bytes moved by an IO call given its return value
+///
+/// Failed calls report a negative value and moved nothing, as do calls that returned 0.
+fn transferred<T: Into<i64>>(ret: T) -> Option<u64> {
+    match ret.into() {
+        n if n > 0 => Some(n as u64),
+        _ => None,
+    }
+}
+
+/// How to handle metadata set with a key its type does not declare
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetaPolicy {
+    /// Fail the transaction with an assertion failure
+    Strict,
+    /// Log the first use of each unknown key and ignore the value
+    Lenient,
+}
+
+impl Default for MetaPolicy {
+    fn default() -> Self {
+        MetaPolicy::Strict
+    }
+}
+
+/// The holder of a pid on a host
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PidHolder {
+    /// A running actor, by uuid
+    Live(Uuid),
+    /// An actor that has exited and been released but not yet reaped, by its final node
+    Exited(ID),
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum ConnectDir {
     Mono,
@@ -106,26 +197,52 @@ pub struct PVM {
     type_cache: HashSet<&'static ConcreteType>,
     ctx_type_cache: HashSet<&'static ContextType>,
     uuid_cache: HashMap<Uuid, ID>,
-    node_cache: LendingLibrary<ID, DataNode>,
+    version_count: HashMap<Uuid, usize>,
+    node_cache: NodeCache,
     rel_src_dst_cache: HashMap<(&'static str, ID, ID), ID>,
     rel_cache: LendingLibrary<ID, Rel>,
     id: IDCounter,
     open_cache: HashMap<Uuid, HashSet<Uuid>>,
+    fd_table: HashMap<Uuid, HashMap<i32, Uuid>>,
+    pid_table: HashMap<(Uuid, i32), PidHolder>,
+    conn_table: HashMap<(Name, Name), Uuid>,
     name_cache: LendingLibrary<Name, NameNode>,
-    pub unparsed_events: HashSet<String>,
-    perf_mon: RefCell<PerfMon>,
+    pub unparsed_events: IndexMap<String, usize>,
+    /// Object references passed over for being the nil uuid, see `PVMTransaction::non_nil`
+    pub nil_uuids: usize,
+    perf_mon: RefCell<Option<PerfMon>>,
+    clock: Arc<dyn Clock>,
+    schema: Vec<SchemaNode>,
+    edge_events: bool,
+    heritability: HashMap<String, bool>,
+    commit_hooks: Vec<CommitHook>,
+    meta_policy: MetaPolicy,
+    unknown_meta: HashSet<(&'static str, &'static str)>,
+    opts: IngestOptions,
 }
 
 pub struct PVMTransaction<'a> {
     db: DBStore<'a>,
     type_cache: &'a HashSet<&'static ConcreteType>,
     uuid_cache: HashWrap<'a, Uuid, ID>,
-    node_cache: LendingWrap<'a, ID, DataNode>,
+    version_count: HashWrap<'a, Uuid, usize>,
+    node_cache: NodeCacheWrap<'a>,
     rel_src_dst_cache: HashWrap<'a, (&'static str, ID, ID), ID>,
     rel_cache: LendingWrap<'a, ID, Rel>,
     id: IDWrap<'a>,
     open_cache: HashWrap<'a, Uuid, HashSet<Uuid>>,
+    fd_table: HashWrap<'a, Uuid, HashMap<i32, Uuid>>,
+    pid_table: HashWrap<'a, (Uuid, i32), PidHolder>,
+    conn_table: HashWrap<'a, (Name, Name), Uuid>,
     name_cache: LendingWrap<'a, Name, NameNode>,
+    unparsed_events: &'a mut IndexMap<String, usize>,
+    nil_uuids: &'a mut usize,
+    heritability: &'a HashMap<String, bool>,
+    commit_hooks: &'a mut Vec<CommitHook>,
+    meta_policy: MetaPolicy,
+    unknown_meta: &'a mut HashSet<(&'static str, &'static str)>,
+    opts: IngestOptions,
+    event: Option<String>,
     ctx: ID,
     ctx_node: CtxNode,
 }
@@ -138,53 +255,271 @@ impl<'a> PVMTransaction<'a> {
     ) -> Self {
         let id = IDWrap::new(&mut base.id);
         let ctx = id.get();
+        let event = if base.edge_events {
+            ctx_cont.get("event").cloned()
+        } else {
+            None
+        };
         let ctx_node = CtxNode::new(ctx, ctx_ty, ctx_cont).unwrap();
         PVMTransaction {
             db: base.db.store(),
             type_cache: &base.type_cache,
             uuid_cache: HashWrap::new(&mut base.uuid_cache),
-            node_cache: LendingWrap::new(&mut base.node_cache),
+            version_count: HashWrap::new(&mut base.version_count),
+            node_cache: NodeCacheWrap::new(&mut base.node_cache),
             rel_src_dst_cache: HashWrap::new(&mut base.rel_src_dst_cache),
             rel_cache: LendingWrap::new(&mut base.rel_cache),
             id,
             open_cache: HashWrap::new(&mut base.open_cache),
+            fd_table: HashWrap::new(&mut base.fd_table),
+            pid_table: HashWrap::new(&mut base.pid_table),
+            conn_table: HashWrap::new(&mut base.conn_table),
             name_cache: LendingWrap::new(&mut base.name_cache),
+            unparsed_events: &mut base.unparsed_events,
+            nil_uuids: &mut base.nil_uuids,
+            heritability: &base.heritability,
+            commit_hooks: &mut base.commit_hooks,
+            meta_policy: base.meta_policy,
+            unknown_meta: &mut base.unknown_meta,
+            opts: base.opts,
+            event,
             ctx,
             ctx_node,
         }
     }
 
-    pub fn commit(mut self) {
+    pub fn commit(mut self) -> PVMResult<()> {
         self.uuid_cache.commit();
+        self.version_count.commit();
         self.node_cache.commit();
         self.rel_src_dst_cache.commit();
         self.rel_cache.commit();
         self.open_cache.commit();
+        self.fd_table.commit();
+        self.pid_table.commit();
+        self.conn_table.commit();
         self.name_cache.commit();
         if self.db.len() == 0 {
-        } else {
-            self.id.commit();
+            return Ok(());
+        }
+        self.id.commit();
+        if self.commit_hooks.is_empty() {
             self.db._create_node_head(self.ctx_node);
             self.db.commit();
+            return Ok(());
         }
+        let mut summary = CommitSummary {
+            ctx: &self.ctx_node,
+            created: Vec::new(),
+            updated: Vec::new(),
+        };
+        for op in self.db.ops() {
+            match op {
+                DBTr::CreateNode(n) => summary.created.push(n.get_db_id()),
+                DBTr::CreateRel(r) => summary.created.push(r.get_db_id()),
+                DBTr::UpdateNode(n) => summary.updated.push(n.get_db_id()),
+                DBTr::UpdateRel(r) => summary.updated.push(r.get_db_id()),
+                DBTr::Flush(_) | DBTr::Tick(_) => unreachable!("Only updates are stored"),
+            }
+        }
+        self.db._create_node_head(summary.ctx.clone());
+        self.db.commit();
+        for hook in self.commit_hooks.iter_mut() {
+            hook(&summary).map_err(|err| match err {
+                PVMError::HookAborted { .. } => err,
+                err => PVMError::HookFailed { err: Box::new(err) },
+            })?;
+        }
+        Ok(())
     }
 
     pub fn rollback(self) {
         self.uuid_cache.rollback();
+        self.version_count.rollback();
         self.node_cache.commit();
         self.rel_src_dst_cache.rollback();
         self.rel_cache.commit();
         self.open_cache.rollback();
+        self.fd_table.rollback();
+        self.pid_table.rollback();
+        self.conn_table.rollback();
         self.name_cache.commit();
     }
 
+    /// The options of the ingest this transaction is part of
+    pub fn options(&self) -> &IngestOptions {
+        &self.opts
+    }
+
+    /// Record an event type that the mapping does not handle
+    pub fn unparsed(&mut self, evt: &str) {
+        match self.unparsed_events.get_mut(evt) {
+            Some(count) => *count += 1,
+            None => {
+                self.unparsed_events.insert(evt.to_string(), 1);
+            }
+        }
+    }
+
+    /// The uuid of an object, or `None` for the nil uuid
+    ///
+    /// Some traces use the nil uuid to mean no object, declaring it would merge every such
+    /// reference into a single phantom node. Mappings pass object uuids through this so that only
+    /// the declaration is skipped, and each one passed over is counted in `PVM::nil_uuids`.
+    pub fn non_nil(&mut self, uuid: Uuid) -> Option<Uuid> {
+        if uuid.is_nil() {
+            *self.nil_uuids += 1;
+            None
+        } else {
+            Some(uuid)
+        }
+    }
+
     pub fn release(&mut self, uuid: &Uuid) {
         if let Some(nid) = self.uuid_cache.remove(uuid) {
             self.node_cache.remove(&nid);
         }
+        self.fd_table.remove(uuid);
+    }
+
+    /// Bind a descriptor of an actor to an object, replacing any previous binding
+    pub fn bind_fd(&mut self, act: ID, fd: i32, ent: ID) {
+        let act = self._node(act).uuid();
+        let ent = self._node(ent).uuid();
+        match self.fd_table.get_mut(&act) {
+            Some(fds) => {
+                fds.insert(fd, ent);
+            }
+            None => {
+                self.fd_table.insert(act, hashmap!(fd => ent));
+            }
+        }
     }
 
-    fn _node(&mut self, id: ID) -> Loan<ID, DataNode> {
+    /// The current node of the object a descriptor of an actor is bound to
+    pub fn fd(&mut self, act: ID, fd: i32) -> Option<ID> {
+        let act = self._node(act).uuid();
+        let ent = *self.fd_table.get(&act)?.get(&fd)?;
+        self.lookup(&ent)
+    }
+
+    /// Remove the binding of a descriptor of an actor
+    pub fn close_fd(&mut self, act: ID, fd: i32) {
+        let act = self._node(act).uuid();
+        if let Some(fds) = self.fd_table.get_mut(&act) {
+            fds.remove(&fd);
+        }
+    }
+
+    /// Bind descriptor `new` to the object `old` is bound to, as dup2
+    ///
+    /// If `old` is not bound `new` is left unbound, as whatever it referred to before has been
+    /// closed. Returns whether `old` was bound.
+    pub fn dup_fd(&mut self, act: ID, old: i32, new: i32) -> bool {
+        let act = self._node(act).uuid();
+        match self.fd_table.get_mut(&act) {
+            Some(fds) => match fds.get(&old).cloned() {
+                Some(ent) => {
+                    fds.insert(new, ent);
+                    true
+                }
+                None => {
+                    fds.remove(&new);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    /// Give an actor a copy of the descriptor table of another, as fork
+    pub fn inherit_fds(&mut self, parent: ID, child: ID) {
+        let parent = self._node(parent).uuid();
+        let child = self._node(child).uuid();
+        if let Some(fds) = self.fd_table.get(&parent).cloned() {
+            self.fd_table.insert(child, fds);
+        }
+    }
+
+    /// Record that an actor holds a pid on a host, replacing any previous holder
+    pub fn bind_pid(&mut self, host: Uuid, pid: i32, act: ID) {
+        let act = PidHolder::Live(self._node(act).uuid());
+        if self.pid_table.get(&(host, pid)) != Some(&act) {
+            self.pid_table.insert((host, pid), act);
+        }
+    }
+
+    /// The current node of the running actor holding a pid on a host
+    pub fn pid(&mut self, host: Uuid, pid: i32) -> Option<ID> {
+        match *self.pid_table.get(&(host, pid))? {
+            PidHolder::Live(act) => self.lookup(&act),
+            PidHolder::Exited(_) => None,
+        }
+    }
+
+    /// Forget the running holder of a pid on a host, returning the uuid it was bound to
+    pub fn unbind_pid(&mut self, host: Uuid, pid: i32) -> Option<Uuid> {
+        match *self.pid_table.get(&(host, pid))? {
+            PidHolder::Live(act) => {
+                self.pid_table.remove(&(host, pid));
+                Some(act)
+            }
+            PidHolder::Exited(_) => None,
+        }
+    }
+
+    /// Release an exiting actor, keeping its final node under its pid until it is reaped
+    pub fn exit_pid(&mut self, host: Uuid, pid: i32, act: ID) {
+        let uuid = self._node(act).uuid();
+        self.release(&uuid);
+        self.pid_table.insert((host, pid), PidHolder::Exited(act));
+    }
+
+    /// Take the final node of an exited actor holding a pid on a host, as when it is reaped
+    pub fn reap_pid(&mut self, host: Uuid, pid: i32) -> Option<ID> {
+        match *self.pid_table.get(&(host, pid))? {
+            PidHolder::Exited(act) => {
+                self.pid_table.remove(&(host, pid));
+                Some(act)
+            }
+            PidHolder::Live(_) => None,
+        }
+    }
+
+    /// Record a conduit as the end of a connection from `local` to `remote`
+    ///
+    /// Returns the current node of the opposite end, if one has been recorded, so that ends
+    /// traced on different hosts can be connected.
+    pub fn pair_conduit(&mut self, local: Name, remote: Name, ent: ID) -> Option<ID> {
+        let ent = self._node(ent).uuid();
+        let peer = self
+            .conn_table
+            .get(&(remote.clone(), local.clone()))
+            .cloned();
+        if self.conn_table.get(&(local.clone(), remote.clone())) != Some(&ent) {
+            self.conn_table.insert((local, remote), ent);
+        }
+        match peer {
+            Some(peer) if peer != ent => self.lookup(&peer),
+            _ => None,
+        }
+    }
+
+    /// The concrete type of a node
+    pub fn type_of(&mut self, id: ID) -> &'static ConcreteType {
+        self._node(id).ty()
+    }
+
+    /// The current node for a uuid, if one has been declared
+    pub fn lookup(&self, uuid: &Uuid) -> Option<ID> {
+        if self.uuid_cache.contains_key(uuid) {
+            Some(self.uuid_cache[uuid])
+        } else {
+            None
+        }
+    }
+
+    fn _node(&mut self, id: ID) -> NodeLoan {
         self.node_cache.lend(&id).unwrap()
     }
 
@@ -212,17 +547,21 @@ impl<'a> PVMTransaction<'a> {
     }
 
     fn _inf(&mut self, src: impl HasID, dst: impl HasID, pvm_op: PVMOps) -> ID {
+        let event = self.event.clone();
         self._decl_rel::<Inf, _>(src.get_db_id(), dst.get_db_id(), |ctx| InfInit {
             pvm_op,
             ctx,
-            byte_count: 0,
+            bytes_read: 0,
+            bytes_written: 0,
+            event: event.clone(),
         })
     }
 
-    fn _named(&mut self, src: impl HasID, dst: &NameNode) -> ID {
+    fn _named(&mut self, src: impl HasID, dst: &NameNode, actor: Option<ID>) -> ID {
         self._decl_rel::<Named, _>(src.get_db_id(), dst.get_db_id(), |ctx| NamedInit {
             start: ctx,
             end: ID::new(0),
+            actor,
         })
     }
 
@@ -238,11 +577,17 @@ impl<'a> PVMTransaction<'a> {
                 cont: format!("Unregistered node type {:?}", ty),
             });
         }
+        // mappings are expected to filter these with `non_nil`
+        if uuid.is_nil() {
+            return Err(PVMError::NilUuid { ty: ty.name });
+        }
         let id = self.id.get();
         let node = DataNode::new(pvm_ty, ty, id, uuid, self.ctx, init);
         if let Some(nid) = self.uuid_cache.insert(uuid, id) {
             self.node_cache.remove(&nid);
         }
+        let versions = self.version_count.get(&uuid).map_or(1, |v| v + 1);
+        self.version_count.insert(uuid, versions);
         self.db.create_node(&node);
         self.node_cache.insert(id, node);
         Ok(id)
@@ -288,6 +633,20 @@ impl<'a> PVMTransaction<'a> {
         self._version(&src, Either::Left(dst))
     }
 
+    /// Record an existing object as a later version of another
+    ///
+    /// For importers whose source declares each version of an object itself, unlike `derive`
+    /// the properties of `dst` are left as they are.
+    pub fn version_of(&mut self, src: ID, dst: ID) -> PVMResult<ID> {
+        let ty = *self._node(src).pvm_ty();
+        if self._node(dst).pvm_ty() != &ty {
+            return Err(PVMError::AssertionFailure {
+                cont: "version_of between different node types".into(),
+            });
+        }
+        Ok(self._inf(src, dst, PVMOps::Version))
+    }
+
     pub fn source(&mut self, act: ID, ent: ID) -> PVMResult<ID> {
         if self._node(act).pvm_ty() != &Actor {
             return Err(PVMError::AssertionFailure {
@@ -304,9 +663,11 @@ impl<'a> PVMTransaction<'a> {
             });
         }
         let id = self.source(act, ent)?;
-        let mut r = self._rel(id);
-        Inf::denumerate_mut(&mut r).byte_count += bytes.into();
-        self.db.update_rel(&*r);
+        if let Some(bytes) = transferred(bytes) {
+            let mut r = self._rel(id);
+            Inf::denumerate_mut(&mut r).bytes_read += bytes;
+            self.db.update_rel(&*r);
+        }
         Ok(id)
     }
 
@@ -326,6 +687,26 @@ impl<'a> PVMTransaction<'a> {
         })
     }
 
+    /// Record information carried into an object by a conduit
+    ///
+    /// For observers, such as network monitors, that see data in transit but not the actors
+    /// sending or receiving it.
+    pub fn carry(&mut self, conduit: ID, ent: ID) -> PVMResult<ID> {
+        let ent = self._node(ent);
+        if self._node(conduit).pvm_ty() != &Conduit {
+            return Err(PVMError::AssertionFailure {
+                cont: "carry with non conduit".into(),
+            });
+        }
+        Ok(match ent.pvm_ty() {
+            Store => {
+                let f = self._version(&ent, Either::Right(Store))?;
+                self._inf(conduit, f, PVMOps::Sink)
+            }
+            _ => self._inf(conduit, &*ent, PVMOps::Sink),
+        })
+    }
+
     pub fn sinkstart(&mut self, act: ID, ent: ID) -> PVMResult<ID> {
         let act = self._node(act);
         let ent = self._node(ent);
@@ -358,9 +739,11 @@ impl<'a> PVMTransaction<'a> {
             });
         }
         let id = self.sinkstart(act, ent)?;
-        let mut r = self._rel(id);
-        Inf::denumerate_mut(&mut r).byte_count += bytes.into();
-        self.db.update_rel(&*r);
+        if let Some(bytes) = transferred(bytes) {
+            let mut r = self._rel(id);
+            Inf::denumerate_mut(&mut r).bytes_written += bytes;
+            self.db.update_rel(&*r);
+        }
         Ok(id)
     }
 
@@ -396,7 +779,13 @@ impl<'a> PVMTransaction<'a> {
 
     pub fn name(&mut self, obj: ID, name: Name) -> PVMResult<ID> {
         let n_node = self.decl_name(name);
-        Ok(self._named(obj, &n_node))
+        Ok(self._named(obj, &n_node, None))
+    }
+
+    /// Name an object, recording the actor responsible for the naming
+    pub fn name_by(&mut self, obj: ID, name: Name, act: ID) -> PVMResult<ID> {
+        let n_node = self.decl_name(name);
+        Ok(self._named(obj, &n_node, Some(act)))
     }
 
     pub fn unname(&mut self, obj: ID, name: Name) -> PVMResult<ID> {
@@ -417,11 +806,27 @@ impl<'a> PVMTransaction<'a> {
     ) -> PVMResult<()> {
         let mut ent = self._node(ent);
         if !ent.ty().props.contains_key(key) {
-            panic!("Setting unknown property on concrete type: {:?} does not have a property named {}.", ent.ty(), key);
+            let ty = ent.ty().name;
+            return match self.meta_policy {
+                MetaPolicy::Strict => Err(PVMError::AssertionFailure {
+                    cont: format!("{} does not have a property named {}", ty, key),
+                }),
+                MetaPolicy::Lenient => {
+                    if self.unknown_meta.insert((ty, key)) {
+                        eprintln!("Ignoring unknown property {} on {}", key, ty);
+                    }
+                    Ok(())
+                }
+            };
+        }
+        let heritable = self
+            .heritability
+            .get(key)
+            .cloned()
+            .unwrap_or(ent.ty().props[key]);
+        if ent.meta.update(key, val, self.ctx, heritable) {
+            self.db.update_node(&*ent);
         }
-        let heritable = ent.ty().props[key];
-        ent.meta.update(key, val, self.ctx, heritable);
-        self.db.update_node(&*ent);
         Ok(())
     }
 
@@ -468,21 +873,63 @@ fn use_of_hm<K, V>(v: &HashMap<K, V>) -> u64 {
     (v.len() * (size_of::<K>() + size_of::<V>() + size_of::<u64>())) as u64
 }
 
+#[cfg(not(feature = "node-arena"))]
+fn size_of_nodes(v: &NodeCache) -> u64 {
+    size_of_ll(v)
+}
+
+#[cfg(not(feature = "node-arena"))]
+fn use_of_nodes(v: &NodeCache) -> u64 {
+    use_of_ll(v)
+}
+
+#[cfg(feature = "node-arena")]
+fn size_of_nodes(v: &NodeCache) -> u64 {
+    use std::mem::size_of;
+
+    (v.index_capacity() * size_of::<u32>() + v.capacity() * size_of::<DataNode>()) as u64
+}
+
+#[cfg(feature = "node-arena")]
+fn use_of_nodes(v: &NodeCache) -> u64 {
+    use std::mem::size_of;
+
+    (v.len() * (size_of::<u32>() + size_of::<DataNode>())) as u64
+}
+
 impl PVM {
     pub fn new(db: SyncSender<DBTr>) -> Self {
+        PVM::with_sink(db)
+    }
+
+    /// Create a PVM that delivers its operations to the given sink rather than a channel
+    pub fn with_sink<S: DBSink + 'static>(sink: S) -> Self {
         PVM {
-            db: DB::create(db),
+            db: DB::create(sink),
             type_cache: HashSet::new(),
             ctx_type_cache: HashSet::new(),
             uuid_cache: HashMap::new(),
-            node_cache: LendingLibrary::new(),
+            version_count: HashMap::new(),
+            node_cache: NodeCache::new(),
             rel_src_dst_cache: HashMap::new(),
             rel_cache: LendingLibrary::new(),
             id: IDCounter::new(1),
             open_cache: HashMap::new(),
+            fd_table: HashMap::new(),
+            pid_table: HashMap::new(),
+            conn_table: HashMap::new(),
             name_cache: LendingLibrary::new(),
-            unparsed_events: HashSet::new(),
-            perf_mon: RefCell::new(PerfMon::new()),
+            unparsed_events: IndexMap::new(),
+            nil_uuids: 0,
+            perf_mon: RefCell::new(None),
+            clock: Arc::new(SystemClock),
+            schema: Vec::new(),
+            edge_events: false,
+            heritability: HashMap::new(),
+            commit_hooks: Vec::new(),
+            meta_policy: MetaPolicy::default(),
+            unknown_meta: HashSet::new(),
+            opts: IngestOptions::default(),
         }
     }
 
@@ -491,111 +938,425 @@ impl PVM {
         ctx_ty: &'static ContextType,
         ctx_cont: HashMap<&'static str, String>,
     ) -> PVMTransaction {
-        self.perf_mon.borrow_mut().tick(self);
+        let res = match self.perf_mon.borrow_mut().as_mut() {
+            Some(perf_mon) => perf_mon.tick(self),
+            None => Ok(()),
+        };
+        if let Err(e) = res {
+            eprintln!(
+                "Disabling performance monitor, failed to write report: {}",
+                e
+            );
+            self.perf_mon.replace(None);
+        }
         PVMTransaction::start(self, ctx_ty, ctx_cont)
     }
 
+    /// Periodically write performance and cache usage statistics to the file at `path`
+    pub fn enable_perf_mon<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.perf_mon
+            .replace(Some(PerfMon::new(path, self.clock.clone())?));
+        Ok(())
+    }
+
+    /// Record the event that created each Inf edge on the edge
+    ///
+    /// The event is taken from the "event" entry of the transaction context. Edges are shared
+    /// between repeated flows, so this is the event that first created the edge. Off by default
+    /// as it adds a string to every edge held in memory.
+    pub fn set_edge_events(&mut self, on: bool) {
+        self.edge_events = on;
+    }
+
+    /// Override whether a metadata key is heritable, for every type that has the key
+    ///
+    /// Takes precedence over the heritability given in the `ConcreteType` props. Only affects
+    /// values set after the call.
+    pub fn set_heritable<S: ToString>(&mut self, key: S, heritable: bool) {
+        self.heritability.insert(key.to_string(), heritable);
+    }
+
+    /// Choose how metadata keys unknown to a node's type are handled
+    pub fn set_meta_policy(&mut self, policy: MetaPolicy) {
+        self.meta_policy = policy;
+    }
+
+    /// Set the options trace formats map records by
+    pub fn set_options(&mut self, opts: IngestOptions) {
+        self.opts = opts;
+    }
+
+    pub fn options(&self) -> &IngestOptions {
+        &self.opts
+    }
+
+    /// Register a hook to run after every committed transaction, see `CommitHook`
+    pub fn add_commit_hook(&mut self, hook: CommitHook) {
+        self.commit_hooks.push(hook);
+    }
+
+    /// Replace the wall clock used for timing, only affects monitors enabled afterwards
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
     pub fn register_data_type(&mut self, ty: &'static ConcreteType) {
-        self.type_cache.insert(ty);
-        self.db
-            .create_node(SchemaNode::from_data(self.id.get(), ty));
+        if self.type_cache.insert(ty) {
+            let node = SchemaNode::from_data(self.id.get(), ty);
+            self.schema.push(node.clone());
+            self.db.create_node(node);
+        }
     }
 
     pub fn register_ctx_type(&mut self, ty: &'static ContextType) {
-        self.ctx_type_cache.insert(ty);
-        self.db.create_node(SchemaNode::from_ctx(self.id.get(), ty));
+        if self.ctx_type_cache.insert(ty) {
+            let node = SchemaNode::from_ctx(self.id.get(), ty);
+            self.schema.push(node.clone());
+            self.db.create_node(node);
+        }
+    }
+
+    /// The creation transactions for every schema node registered so far
+    ///
+    /// These are also emitted on the data stream as types are registered, this allows consumers
+    /// to capture the schema independently, e.g. to set up a database before a bulk load.
+    pub fn schema_dbtrs(&self) -> Vec<DBTr> {
+        self.schema
+            .iter()
+            .cloned()
+            .map(|n| DBTr::CreateNode(n.enumerate()))
+            .collect()
+    }
+
+    /// Summarise the lengths of the version chains of all objects seen so far
+    pub fn version_stats(&self) -> VersionStats {
+        let mut stats = VersionStats::default();
+        for (uuid, &len) in &self.version_count {
+            *stats.histogram.entry(len).or_insert(0) += 1;
+            if stats.longest.map_or(true, |(_, l)| len > l) {
+                stats.longest = Some((*uuid, len));
+            }
+        }
+        stats
+    }
+
+    /// Have the views flush once they have seen every operation committed so far
+    ///
+    /// The marker is acknowledged when they have, see `FlushMarker`.
+    pub fn flush(&mut self, marker: FlushMarker) {
+        self.db.flush(marker);
     }
 
     pub fn shutdown(self) {}
 }
 
+/// Distribution of version chain lengths across objects
+///
+/// Objects with unusually long chains often indicate write loops in the traced system or a
+/// modelling problem in the mapping.
+#[derive(Clone, Debug, Default)]
+pub struct VersionStats {
+    /// Number of objects for each version chain length
+    pub histogram: BTreeMap<usize, usize>,
+    /// The object with the longest version chain and the length of that chain
+    pub longest: Option<(Uuid, usize)>,
+}
+
 struct PerfMon {
     events: i64,
     last_rep: Instant,
     start: Instant,
     out_file: File,
+    clock: Arc<dyn Clock>,
 }
 
 impl PerfMon {
-    fn new() -> Self {
-        PerfMon {
+    fn new<P: AsRef<Path>>(path: P, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        let now = clock.now();
+        Ok(PerfMon {
             events: 0,
-            last_rep: Instant::now(),
-            start: Instant::now(),
-            out_file: File::create("./perfinfo").unwrap(),
-        }
+            last_rep: now,
+            start: now,
+            out_file: File::create(path)?,
+            clock,
+        })
     }
 
-    fn tick(&mut self, pvm: &PVM) {
+    fn tick(&mut self, pvm: &PVM) -> io::Result<()> {
         self.events += 1;
         if (self.events % 10_000) == 0 {
-            let t_step = self.last_rep.elapsed() / 10_000;
-            let t_total = self.start.elapsed() / self.events as u32;
-            writeln!(self.out_file, "Event No: {}", self.events).unwrap();
-            writeln!(self.out_file, "per event time: {}", format_duration(t_step)).unwrap();
+            let now = self.clock.now();
+            let t_step = (now - self.last_rep) / 10_000;
+            let t_total = (now - self.start) / self.events as u32;
+            writeln!(self.out_file, "Event No: {}", self.events)?;
+            writeln!(self.out_file, "per event time: {}", format_duration(t_step))?;
             writeln!(
                 self.out_file,
                 "ev per second: {:0.2}",
                 Duration::new(1, 0).div_duration_f64(t_step)
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "per event time (avg): {}",
                 format_duration(t_total)
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "ev per second (avg): {:0.2}",
                 Duration::new(1, 0).div_duration_f64(t_total)
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "Uuid_cache:\t\t {} / {}",
                 to_human_bytes(use_of_hm(&pvm.uuid_cache), true),
                 to_human_bytes(size_of_hm(&pvm.uuid_cache), true),
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "Node_cache:\t\t {} / {}",
-                to_human_bytes(use_of_ll(&pvm.node_cache), true),
-                to_human_bytes(size_of_ll(&pvm.node_cache), true),
-            )
-            .unwrap();
+                to_human_bytes(use_of_nodes(&pvm.node_cache), true),
+                to_human_bytes(size_of_nodes(&pvm.node_cache), true),
+            )?;
             writeln!(
                 self.out_file,
                 "Rel_src_dst_cache:\t {} / {}",
                 to_human_bytes(use_of_hm(&pvm.rel_src_dst_cache), true),
                 to_human_bytes(size_of_hm(&pvm.rel_src_dst_cache), true),
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "Rel_cache:\t\t {} / {}",
                 to_human_bytes(use_of_ll(&pvm.rel_cache), true),
                 to_human_bytes(size_of_ll(&pvm.rel_cache), true),
-            )
-            .unwrap();
+            )?;
             writeln!(
                 self.out_file,
                 "Open_cache:\t\t {} / {}",
                 to_human_bytes((pvm.open_cache.len() * 8) as u64, true),
                 to_human_bytes((pvm.open_cache.capacity() * 8) as u64, true),
-            )
-            .unwrap();
+            )?;
+            writeln!(
+                self.out_file,
+                "Fd_table:\t\t {} / {}",
+                to_human_bytes((pvm.fd_table.len() * 8) as u64, true),
+                to_human_bytes((pvm.fd_table.capacity() * 8) as u64, true),
+            )?;
+            writeln!(
+                self.out_file,
+                "Pid_table:\t\t {} / {}",
+                to_human_bytes((pvm.pid_table.len() * 8) as u64, true),
+                to_human_bytes((pvm.pid_table.capacity() * 8) as u64, true),
+            )?;
+            writeln!(
+                self.out_file,
+                "Conn_table:\t\t {} / {}",
+                to_human_bytes((pvm.conn_table.len() * 8) as u64, true),
+                to_human_bytes((pvm.conn_table.capacity() * 8) as u64, true),
+            )?;
             writeln!(
                 self.out_file,
                 "Name_cache:\t\t {} / {}",
                 to_human_bytes(use_of_ll(&pvm.name_cache), true),
                 to_human_bytes(size_of_ll(&pvm.name_cache), true),
-            )
+            )?;
+            self.out_file.flush()?;
+            self.out_file.seek(SeekFrom::Start(0))?;
+            self.last_rep = now;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env, fs, process,
+        sync::{mpsc::sync_channel, Mutex},
+    };
+
+    use crate::{clock::MockClock, data::node_types::Node};
+
+    use lazy_static::lazy_static;
+    use maplit::hashmap;
+
+    lazy_static! {
+        static ref PROCESS: ConcreteType = ConcreteType {
+            pvm_ty: Actor,
+            name: "process",
+            props: hashmap!("uid" => true, "pid" => false),
+        };
+        static ref FILE: ConcreteType = ConcreteType {
+            pvm_ty: Store,
+            name: "file",
+            props: HashMap::new(),
+        };
+        static ref CTX: ContextType = ContextType {
+            name: "ctx",
+            props: vec!["event"],
+        };
+    }
+
+    /// A PVM writing to `sink`, configured by `setup` before the test types are registered
+    fn pvm_with<S: DBSink + 'static>(sink: S, setup: impl FnOnce(&mut PVM)) -> PVM {
+        let mut pvm = PVM::with_sink(sink);
+        setup(&mut pvm);
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_ctx_type(&CTX);
+        pvm
+    }
+
+    fn declare_proc(tr: &mut PVMTransaction) -> ID {
+        tr.declare(&PROCESS, Uuid::new_v5(&Uuid::nil(), b"proc"), None)
+            .unwrap()
+    }
+
+    #[test]
+    fn perf_mon_uses_injected_clock() {
+        let path = env::temp_dir().join(format!("pvm-perfmon-{}", process::id()));
+        let (send, _recv) = sync_channel(1);
+        let pvm = PVM::new(send);
+        let clock = MockClock::new();
+        let mut mon = PerfMon::new(&path, Arc::new(clock.clone())).unwrap();
+        for _ in 0..10_000 {
+            clock.advance(Duration::from_millis(1));
+            mon.tick(&pvm).unwrap();
+        }
+        let report = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(report.contains("Event No: 10000"));
+        assert!(report.contains("ev per second: 1000.00"));
+        assert!(report.contains("ev per second (avg): 1000.00"));
+    }
+
+    #[test]
+    fn sink_receives_ops_on_commit() {
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let mut pvm = pvm_with(sink.clone(), |_| {});
+        assert_eq!(sink.lock().unwrap().len(), 3);
+        let mut tr = pvm.transaction(&CTX, HashMap::new());
+        declare_proc(&mut tr);
+        assert_eq!(sink.lock().unwrap().len(), 3);
+        tr.commit().unwrap();
+        let ops = sink.lock().unwrap();
+        assert_eq!(ops.len(), 5);
+        match &ops[4] {
+            DBTr::CreateNode(Node::Data(d)) => assert_eq!(d.ty().name, "process"),
+            op => panic!("unexpected op {:?}", op),
+        }
+    }
+
+    #[test]
+    fn commit_hook_sees_changes() {
+        let (send, _recv) = sync_channel(100);
+        let mut pvm = pvm_with(send, |_| {});
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        pvm.add_commit_hook(Box::new(move |s: &CommitSummary| {
+            hook_seen.lock().unwrap().push((
+                s.ctx.cont["event"].clone(),
+                s.created.len(),
+                s.updated.len(),
+            ));
+            Ok(())
+        }));
+        for (event, uid) in &[("a", "0"), ("b", "0"), ("c", "1")] {
+            let mut tr = pvm.transaction(&CTX, hashmap!("event" => event.to_string()));
+            let pro = declare_proc(&mut tr);
+            tr.meta(pro, "uid", uid).unwrap();
+            tr.commit().unwrap();
+        }
+        // The unchanged second transaction commits nothing so the hook is not run
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![("a".to_string(), 1, 0), ("c".to_string(), 0, 1)]
+        );
+    }
+
+    #[test]
+    fn unknown_meta_policy() {
+        for &policy in &[MetaPolicy::Strict, MetaPolicy::Lenient] {
+            let (send, _recv) = sync_channel(100);
+            let mut pvm = pvm_with(send, |pvm| pvm.set_meta_policy(policy));
+            let mut tr = pvm.transaction(&CTX, HashMap::new());
+            let pro = declare_proc(&mut tr);
+            let res = tr.meta(pro, "iud", "0");
+            assert_eq!(res.is_ok(), policy == MetaPolicy::Lenient);
+            assert!(tr._node(pro).meta.cur("iud").is_none());
+            tr.rollback();
+        }
+    }
+
+    #[test]
+    fn unchanged_meta_emits_no_update() {
+        let (send, recv) = sync_channel(100);
+        let mut pvm = pvm_with(send, |_| {});
+        for uid in &["0", "0", "1"] {
+            let mut tr = pvm.transaction(&CTX, HashMap::new());
+            let pro = declare_proc(&mut tr);
+            tr.meta(pro, "uid", uid).unwrap();
+            tr.commit().unwrap();
+        }
+        drop(pvm);
+        let updates = recv
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::UpdateNode(n) => Some(n),
+                _ => None,
+            })
+            .count();
+        // The first value is folded into the creation of the node, the repeat emits nothing
+        assert_eq!(updates, 1);
+    }
+
+    #[test]
+    fn heritability_override() {
+        let (send, recv) = sync_channel(100);
+        let mut pvm = pvm_with(send, |pvm| {
+            pvm.set_heritable("uid", false);
+            pvm.set_heritable("pid", true);
+        });
+        let mut tr = pvm.transaction(&CTX, HashMap::new());
+        let pro = declare_proc(&mut tr);
+        tr.meta(pro, "uid", "0").unwrap();
+        tr.meta(pro, "pid", "10").unwrap();
+        tr.commit().unwrap();
+        drop(pvm);
+        let node = recv
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) => Some(d),
+                _ => None,
+            })
+            .last()
             .unwrap();
-            self.out_file.flush().unwrap();
-            self.out_file.seek(SeekFrom::Start(0)).unwrap();
-            self.last_rep = Instant::now();
+        let mut heritable: Vec<_> = node.meta.iter().map(|(k, _, _, h)| (k, h)).collect();
+        heritable.sort();
+        assert_eq!(heritable, vec![("pid", true), ("uid", false)]);
+    }
+
+    #[test]
+    fn edge_events_recorded_when_enabled() {
+        for &on in &[false, true] {
+            let (send, recv) = sync_channel(100);
+            let mut pvm = pvm_with(send, |pvm| pvm.set_edge_events(on));
+            let mut tr = pvm.transaction(&CTX, hashmap!("event" => "read".to_string()));
+            let pro = declare_proc(&mut tr);
+            let file = tr.declare(&FILE, Uuid::new_v5(&Uuid::nil(), b"f"), None);
+            tr.source(pro, file.unwrap()).unwrap();
+            tr.commit().unwrap();
+            drop(pvm);
+            let events: Vec<_> = recv
+                .iter()
+                .filter_map(|tr| match tr {
+                    DBTr::CreateRel(Rel::Inf(i)) => Some(i.event),
+                    _ => None,
+                })
+                .collect();
+            let expected = if on { Some("read".to_string()) } else { None };
+            assert_eq!(events, vec![expected]);
         }
     }
 }
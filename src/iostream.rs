@@ -64,26 +64,39 @@ impl From<fs::File> for IOStream {
     }
 }
 
-impl FromRawFd for IOStream {
-    unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        let iotype = match get_fd_type(fd) {
-            Ok(t) => t,
-            Err(e) => IOType::Unknown(e),
-        };
-        let fd_obj = match iotype {
-            IOType::File => Box::new(fs::File::from_raw_fd(fd)) as Box<dyn Read>,
-            IOType::Pipe => Box::new(UnixPipe::from_raw_fd(fd)) as Box<dyn Read>,
-            IOType::TcpStream => Box::new(net::TcpStream::from_raw_fd(fd)) as Box<dyn Read>,
-            IOType::UdpSocket => Box::new(UdpSocketR(net::UdpSocket::from_raw_fd(fd))) as Box<dyn Read>,
-            IOType::UnixStream => Box::new(unix::net::UnixStream::from_raw_fd(fd)) as Box<dyn Read>,
-            IOType::Unknown(e) => {
-                panic!(
-                    "Unsupported input stream. You have passed a fd type that is not supported by libpvm: {}",
-                    e
-                )
+impl IOStream {
+    /// Wrap a raw file descriptor, failing if it is not a supported input type
+    ///
+    /// # Safety
+    ///
+    /// As with `FromRawFd::from_raw_fd`, the stream takes ownership of `fd`.
+    pub unsafe fn try_from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        let fd_obj = match get_fd_type(fd) {
+            Ok(IOType::File) => Box::new(fs::File::from_raw_fd(fd)) as Box<dyn Read>,
+            Ok(IOType::Pipe) => Box::new(UnixPipe::from_raw_fd(fd)) as Box<dyn Read>,
+            Ok(IOType::TcpStream) => Box::new(net::TcpStream::from_raw_fd(fd)) as Box<dyn Read>,
+            Ok(IOType::UdpSocket) => Box::new(UdpSocketR(net::UdpSocket::from_raw_fd(fd))) as Box<dyn Read>,
+            Ok(IOType::UnixStream) => Box::new(unix::net::UnixStream::from_raw_fd(fd)) as Box<dyn Read>,
+            Ok(IOType::Unknown(e)) | Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unsupported input stream. You have passed a fd type that is not supported by libpvm: {}",
+                        e
+                    ),
+                ))
             }
         };
-        IOStream { src: fd_obj }
+        Ok(IOStream { src: fd_obj })
+    }
+}
+
+impl FromRawFd for IOStream {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        match IOStream::try_from_raw_fd(fd) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        }
     }
 }
 
@@ -108,7 +121,7 @@ fn err_str(err: nix::Error) -> String {
 
 fn get_fd_type(fd: RawFd) -> Result<IOType, String> {
     let fs = fstat(fd).map_err(err_str)?;
-    println!(
+    eprintln!(
         "Mode: {}, masked: {}",
         fs.st_mode,
         u32::from(fs.st_mode) & S_IFMT
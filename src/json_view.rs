@@ -0,0 +1,122 @@
+use std::{
+    io::{self, BufWriter, ErrorKind, Stdout, Write},
+    thread,
+};
+
+use crate::view::*;
+
+use serde_json;
+
+#[derive(Debug)]
+pub struct StdoutJSONView {
+    id: usize,
+}
+
+impl View for StdoutJSONView {
+    fn new(id: usize) -> StdoutJSONView {
+        StdoutJSONView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "StdoutJSONView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for streaming newline delimited JSON to stdout."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let thr = thread::Builder::new()
+            .name("StdoutJSONView".to_string())
+            .spawn(move || stream.run(&mut JSONWriter::new(BufWriter::new(io::stdout()))))?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+    fn create_inline(
+        &self,
+        _id: usize,
+        _params: &ViewParams,
+    ) -> Result<Box<dyn InlineView>, ViewError> {
+        Ok(Box::new(JSONWriter::new(BufWriter::new(io::stdout()))))
+    }
+}
+
+/// Writes events as newline delimited JSON, going quiet once the reader hangs up
+#[derive(Debug)]
+struct JSONWriter<W: Write> {
+    out: W,
+    closed: bool,
+}
+
+impl<W: Write> JSONWriter<W> {
+    fn new(out: W) -> Self {
+        JSONWriter { out, closed: false }
+    }
+
+    fn write(&mut self, evt: &DBTr) {
+        if let DBTr::Tick(_) = evt {
+            return;
+        }
+        if self.closed {
+            return;
+        }
+        if let Err(e) = serde_json::to_writer(&mut self.out, evt) {
+            self.closed = is_closed(&e.into());
+            return;
+        }
+        if let Err(e) = writeln!(self.out) {
+            self.closed = is_closed(&e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.closed {
+            if let Err(e) = self.out.flush() {
+                self.closed = is_closed(&e);
+            }
+        }
+    }
+}
+
+impl InlineView for JSONWriter<BufWriter<Stdout>> {
+    /// Once the reading end of the pipe has gone away this keeps draining the stream so the
+    /// coordinator is not blocked.
+    fn process(&mut self, evt: &DBTr) {
+        self.write(evt);
+    }
+
+    fn on_flush(&mut self) {
+        self.flush();
+    }
+
+    /// Flush whenever we catch up so downstream consumers see records promptly rather than
+    /// when the buffer fills.
+    fn on_idle(&mut self) {
+        self.flush();
+    }
+
+    fn on_shutdown(&mut self) {
+        self.flush();
+    }
+}
+
+fn is_closed(e: &io::Error) -> bool {
+    if e.kind() == ErrorKind::BrokenPipe {
+        true
+    } else {
+        eprintln!("StdoutJSONView write error: {}", e);
+        false
+    }
+}
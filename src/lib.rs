@@ -28,15 +28,23 @@ macro_rules! timeit {
 
 #[cfg(feature = "capi")]
 pub use c_api::*;
+pub use diff::{graph_diff, GraphDiff};
 
 #[cfg(feature = "capi")]
 pub mod c_api;
 
+pub mod c_abi;
+
 pub mod cfg;
+pub mod clock;
+pub mod diff;
 pub mod engine;
 pub mod ingest;
 pub mod invbloom;
 pub mod iostream;
+pub mod json_view;
 pub mod neo4j_glue;
 pub mod query;
 pub mod trace;
+#[cfg(feature = "websocket")]
+pub mod ws_view;
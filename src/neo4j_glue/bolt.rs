@@ -0,0 +1,277 @@
+//! A blocking Neo4j client over Bolt v4+
+//!
+//! The view threads are synchronous, so this wraps the async `neo4rs` driver in a runtime per
+//! connection and exposes the small set of operations they use. Statement parameters are built
+//! as `Value`s and only converted to the driver's types when a statement is run.
+
+use std::{collections::HashMap, iter::FromIterator, path::PathBuf};
+
+use neo4rs::{
+    query, BoltType, ConfigBuilder, Graph, Neo4jClientErrorKind, Neo4jErrorKind, Query, Row, Txn,
+};
+use tokio::runtime::{Builder, Runtime};
+
+pub use neo4rs::Error;
+
+/// A statement parameter
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(HashMap<String, Value>),
+}
+
+impl From<Value> for BoltType {
+    fn from(val: Value) -> BoltType {
+        match val {
+            Value::Null => BoltType::from(None::<bool>),
+            Value::Boolean(b) => b.into(),
+            Value::Integer(i) => i.into(),
+            Value::Float(f) => f.into(),
+            Value::String(s) => s.into(),
+            Value::List(l) => l.into_iter().map(BoltType::from).collect::<Vec<_>>().into(),
+            Value::Map(m) => m
+                .into_iter()
+                .map(|(k, v)| (k, BoltType::from(v)))
+                .collect::<HashMap<_, _>>()
+                .into(),
+        }
+    }
+}
+
+macro_rules! value_from {
+    ($($t:ty => $var:ident),*) => {
+        $(impl From<$t> for Value {
+            fn from(v: $t) -> Value {
+                Value::$var(v.into())
+            }
+        })*
+    };
+}
+
+value_from!(bool => Boolean, i64 => Integer, i32 => Integer, u32 => Integer, u16 => Integer,
+            f64 => Float, String => String, &str => String);
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Value {
+        v.into_iter().collect()
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> From<HashMap<K, V>> for Value {
+    fn from(v: HashMap<K, V>) -> Value {
+        Value::Map(v.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl<T: Into<Value>> FromIterator<T> for Value {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Value {
+        Value::List(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Whether an operation that failed with this error may succeed on a fresh connection
+pub fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::IOError { .. } | Error::ConnectionError => true,
+        Error::Neo4j(e) => {
+            e.kind() == Neo4jErrorKind::Transient
+                || e.kind() == Neo4jErrorKind::Client(Neo4jClientErrorKind::SessionExpired)
+        }
+        _ => false,
+    }
+}
+
+fn statement(q: &str, params: HashMap<&str, Value>) -> Query {
+    params
+        .into_iter()
+        .fold(query(q), |q, (k, v)| q.param(k, BoltType::from(v)))
+}
+
+/// Where and how to connect to a Neo4j server
+#[derive(Clone, Debug)]
+pub struct Neo4jConfig {
+    pub addr: String,
+    pub user: String,
+    pub pass: String,
+    /// The database to use on a multi-database server, or the server's default when `None`
+    pub database: Option<String>,
+    pub tls: bool,
+    /// A PEM file of extra certificates to trust alongside the system roots
+    pub ca_cert: Option<PathBuf>,
+    /// Connect using the driver's scheme for self-signed servers
+    ///
+    /// The driver still checks the certificate chain in this mode, so a self-signed server's
+    /// certificate also has to be given as `ca_cert`.
+    pub insecure_skip_verify: bool,
+}
+
+impl Neo4jConfig {
+    /// The address to hand to the driver, with the URI scheme selecting encryption
+    ///
+    /// Without `tls` the address is used as given, so a `+s` scheme can still be passed
+    /// directly.
+    fn uri(&self) -> String {
+        if !self.tls {
+            return self.addr.clone();
+        }
+        let (scheme, rest) = match self.addr.find("://") {
+            Some(i) => (&self.addr[..i], &self.addr[i + 3..]),
+            None => ("bolt", &self.addr[..]),
+        };
+        let scheme = scheme.split('+').next().unwrap();
+        let security = if self.insecure_skip_verify {
+            "ssc"
+        } else {
+            "s"
+        };
+        format!("{}+{}://{}", scheme, security, rest)
+    }
+}
+
+/// Operations that can run either in a transaction or as their own auto-committed transaction
+pub trait Neo4jOperations {
+    fn run(&mut self, q: &str, params: HashMap<&str, Value>) -> Result<(), Error>;
+
+    /// Run a statement, reporting rather than returning any failure
+    fn run_unchecked(&mut self, q: &str, params: HashMap<&str, Value>) {
+        if let Err(e) = self.run(q, params) {
+            eprintln!("Neo4j statement failed: {}", e);
+        }
+    }
+}
+
+pub struct Neo4jDB {
+    graph: Graph,
+    rt: Runtime,
+}
+
+impl Neo4jDB {
+    pub fn connect(cfg: &Neo4jConfig) -> Result<Neo4jDB, Error> {
+        let rt = Builder::new_current_thread().enable_all().build()?;
+        let mut builder = ConfigBuilder::new()
+            .uri(cfg.uri())
+            .user(&cfg.user)
+            .password(&cfg.pass);
+        if let Some(db) = &cfg.database {
+            builder = builder.db(db.as_str());
+        }
+        if let Some(ca) = &cfg.ca_cert {
+            builder = builder.with_client_certificate(ca);
+        }
+        let graph = rt.block_on(Graph::connect(builder.build()?))?;
+        Ok(Neo4jDB { graph, rt })
+    }
+
+    /// Start an explicit transaction
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, Error> {
+        let txn = self.rt.block_on(self.graph.start_txn())?;
+        Ok(Transaction {
+            db: self,
+            txn: Some(txn),
+        })
+    }
+
+    /// Run a read query and collect every row it returns
+    pub fn rows(&mut self, q: &str, params: HashMap<&str, Value>) -> Result<Vec<Row>, Error> {
+        let graph = &self.graph;
+        self.rt.block_on(async {
+            let mut stream = graph.execute(statement(q, params)).await?;
+            let mut ret = Vec::new();
+            while let Some(row) = stream.next().await? {
+                ret.push(row);
+            }
+            Ok(ret)
+        })
+    }
+}
+
+impl Neo4jOperations for Neo4jDB {
+    fn run(&mut self, q: &str, params: HashMap<&str, Value>) -> Result<(), Error> {
+        self.rt.block_on(self.graph.run(statement(q, params)))
+    }
+}
+
+pub struct Transaction<'a> {
+    db: &'a Neo4jDB,
+    txn: Option<Txn>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Commit the work so far and carry on in a new transaction
+    pub fn commit_and_refresh(&mut self) -> Result<(), Error> {
+        if let Some(txn) = self.txn.take() {
+            self.db.rt.block_on(txn.commit())?;
+        }
+        self.txn = Some(self.db.rt.block_on(self.db.graph.start_txn())?);
+        Ok(())
+    }
+
+    pub fn commit(mut self) -> Result<(), Error> {
+        match self.txn.take() {
+            Some(txn) => self.db.rt.block_on(txn.commit()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Neo4jOperations for Transaction<'a> {
+    fn run(&mut self, q: &str, params: HashMap<&str, Value>) -> Result<(), Error> {
+        match &mut self.txn {
+            Some(txn) => self.db.rt.block_on(txn.run(statement(q, params))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(addr: &str, tls: bool, insecure_skip_verify: bool) -> Neo4jConfig {
+        Neo4jConfig {
+            addr: addr.to_string(),
+            user: String::new(),
+            pass: String::new(),
+            database: None,
+            tls,
+            ca_cert: None,
+            insecure_skip_verify,
+        }
+    }
+
+    #[test]
+    fn plaintext_uses_addr_as_given() {
+        assert_eq!(cfg("localhost:7687", false, false).uri(), "localhost:7687");
+        assert_eq!(
+            cfg("neo4j+s://db.example:7687", false, false).uri(),
+            "neo4j+s://db.example:7687"
+        );
+    }
+
+    #[test]
+    fn tls_selects_secure_scheme() {
+        assert_eq!(
+            cfg("localhost:7687", true, false).uri(),
+            "bolt+s://localhost:7687"
+        );
+        assert_eq!(
+            cfg("neo4j://db.example", true, false).uri(),
+            "neo4j+s://db.example"
+        );
+        assert_eq!(
+            cfg("neo4j+s://db.example", true, true).uri(),
+            "neo4j+ssc://db.example"
+        );
+    }
+}
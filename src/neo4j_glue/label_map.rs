@@ -0,0 +1,109 @@
+use std::{collections::HashMap, fs::File, io, path::Path};
+
+use crate::data::node_types::Node;
+
+use serde_derive::Deserialize;
+
+/// Mapping from the labels libPVM assigns to nodes onto those used in the target database
+///
+/// The mapping is read from a JSON document of the form
+///
+/// ```json
+/// {
+///     "labels": { "Store": "File" },
+///     "subtypes": { "socket": ["Socket"] }
+/// }
+/// ```
+///
+/// where `labels` renames the base labels (`Actor`, `Store`, `Context`, `Path`, ...) and
+/// `subtypes` attaches additional labels to nodes based on their concrete or context type name.
+/// The `Node` label is always kept as the persistence view relies on it for indexing.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LabelMap {
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    subtypes: HashMap<String, Vec<String>>,
+}
+
+impl LabelMap {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let f = File::open(path)?;
+        serde_json::from_reader(f).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Translate a single base label
+    pub fn label<'a>(&'a self, label: &'a str) -> &'a str {
+        if label == "Node" {
+            return label;
+        }
+        self.labels.get(label).map_or(label, String::as_str)
+    }
+
+    /// Compute the database labels for a node given its default labels
+    pub fn apply(&self, node: &Node, labels: Vec<&'static str>) -> Vec<String> {
+        let mut ret: Vec<String> = labels.into_iter().map(|l| self.label(l).into()).collect();
+        let subtype = match node {
+            Node::Data(d) => Some(d.ty().name),
+            Node::Ctx(c) => Some(c.ty().name),
+            _ => None,
+        };
+        if let Some(extra) = subtype.and_then(|ty| self.subtypes.get(ty)) {
+            for l in extra {
+                if !ret.contains(l) {
+                    ret.push(l.clone());
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{
+            node_types::{ConcreteType, DataNode, PVMDataType::*},
+            ID,
+        },
+        neo4j_glue::ToDBNode,
+    };
+
+    use lazy_static::lazy_static;
+    use maplit::hashmap;
+    use uuid::Uuid;
+
+    lazy_static! {
+        static ref SOCKET: ConcreteType = ConcreteType {
+            pvm_ty: Store,
+            name: "socket",
+            props: hashmap!(),
+        };
+    }
+
+    #[test]
+    fn maps_base_and_subtype_labels() {
+        let map: LabelMap = serde_json::from_str(
+            r#"{"labels": {"Store": "File", "Node": "Other"}, "subtypes": {"socket": ["Socket"]}}"#,
+        )
+        .unwrap();
+        let node = Node::Data(DataNode::new(
+            Store,
+            &SOCKET,
+            ID::new(1),
+            Uuid::nil(),
+            ID::new(0),
+            None,
+        ));
+        assert_eq!(
+            map.apply(&node, node.get_labels()),
+            vec!["Node", "File", "Socket"]
+        );
+        assert_eq!(
+            LabelMap::default().apply(&node, node.get_labels()),
+            vec!["Node", "Store"]
+        );
+    }
+}
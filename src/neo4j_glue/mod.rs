@@ -6,15 +6,18 @@ use crate::data::{
     HasDst, HasID, HasSrc, MetaStore, ID,
 };
 
+use self::bolt::Value;
+
 use chrono::{DateTime, Utc};
 use maplit::hashmap;
-use neo4j::Value;
 use serde_json;
 use uuid::Uuid;
 
+pub mod bolt;
+mod label_map;
 mod neo4j_view;
 
-pub use self::neo4j_view::Neo4JView;
+pub use self::{label_map::LabelMap, neo4j_view::Neo4JView};
 
 pub trait Val2UUID {
     fn into_uuid(self) -> Option<Uuid>;
@@ -53,13 +56,7 @@ impl IntoVal for DateTime<Utc> {
 
 impl IntoVal for PVMOps {
     fn into_val(self) -> Value {
-        match self {
-            PVMOps::Sink => "Sink".into(),
-            PVMOps::Source => "Source".into(),
-            PVMOps::Connect => "Connect".into(),
-            PVMOps::Version => "Version".into(),
-            PVMOps::Unknown => "Unknown".into(),
-        }
+        self.to_string().into()
     }
 }
 
@@ -170,10 +167,14 @@ impl ToDBRel for Rel {
     fn to_db(&self) -> (ID, Value) {
         match self {
             Rel::Inf(i) => {
-                let props: HashMap<&str, Value> = hashmap!("db_id" => i.get_db_id().into_val(),
-                                                           "pvm_op" => i.pvm_op.into_val(),
-                                                           "ctx" => i.ctx.into_val(),
-                                                           "byte_count" => Value::from(i.byte_count));
+                let mut props: HashMap<&str, Value> = hashmap!("db_id" => i.get_db_id().into_val(),
+                                                               "pvm_op" => i.pvm_op.into_val(),
+                                                               "ctx" => i.ctx.into_val(),
+                                                               "bytes_read" => Value::from(i.bytes_read as i64),
+                                                               "bytes_written" => Value::from(i.bytes_written as i64));
+                if let Some(ref event) = i.event {
+                    props.insert("event", event.clone().into());
+                }
                 (
                     i.get_db_id(),
                     hashmap!("src" => i.get_src().into_val(),
@@ -184,9 +185,12 @@ impl ToDBRel for Rel {
                 )
             }
             Rel::Named(n) => {
-                let props: HashMap<&str, Value> = hashmap!("db_id" => n.get_db_id().into_val(),
-                                                           "start" => n.start.into_val(),
-                                                           "end" => n.end.into_val());
+                let mut props: HashMap<&str, Value> = hashmap!("db_id" => n.get_db_id().into_val(),
+                                                               "start" => n.start.into_val(),
+                                                               "end" => n.end.into_val());
+                if let Some(actor) = n.actor {
+                    props.insert("actor", actor.into_val());
+                }
                 (
                     n.get_db_id(),
                     hashmap!("src" => n.get_src().into_val(),
@@ -1,20 +1,27 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    sync::{mpsc::Receiver, Arc},
+    cmp,
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    iter,
+    path::PathBuf,
+    sync::{mpsc::sync_channel, Arc},
     thread,
+    time::Duration,
 };
 
 use crate::{
-    data::ID,
-    neo4j_glue::{ToDBNode, ToDBRel},
+    data::{HasID, ID},
+    neo4j_glue::{
+        bolt::{is_transient, Error, Neo4jConfig, Neo4jDB, Neo4jOperations, Transaction, Value},
+        LabelMap, ToDBNode, ToDBRel,
+    },
     view::*,
 };
 
 use maplit::hashmap;
-use neo4j::{Neo4jDB, Neo4jOperations, Value};
 
 const BATCH_SIZE: usize = 1000;
 const TR_SIZE: usize = 100_000;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub struct Neo4JView {
@@ -34,116 +41,590 @@ impl View for Neo4JView {
     fn desc(&self) -> &'static str {
         "View for streaming data to a Neo4j database instance."
     }
-    fn params(&self) -> HashMap<&'static str, &'static str> {
-        hashmap!("addr" => "The Neo4j server address to connect to.",
-                 "user" => "The username to auth with.",
-                 "pass" => "The password to auth with.")
-    }
-    fn create(&self, id: usize, params: ViewParams, stream: Receiver<Arc<DBTr>>) -> ViewInst {
-        let mut db = {
-            let addr = params.get_or_def("addr", "localhost:7687");
-            let user = params.get_or_def("user", "neo4j");
-            let pass = params.get_or_def("pass", "opus");
-            Neo4jDB::connect(addr, user, pass).unwrap()
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str("addr", "The Neo4j server address to connect to.")
+                .default("localhost:7687"),
+            ParamDecl::str("user", "The username to auth with.").default("neo4j"),
+            ParamDecl::str("pass", "The password to auth with.").default("opus"),
+            ParamDecl::str(
+                "database",
+                "The database to write to, defaults to the server's default database.",
+            ),
+            ParamDecl::bool("tls", "Connect over TLS.").default("false"),
+            ParamDecl::path(
+                "ca_cert",
+                "Path to a PEM file of certificates to trust for TLS, in addition to the system \
+                 roots.",
+            ),
+            ParamDecl::bool(
+                "insecure_skip_verify",
+                "Accept a server with a self-signed certificate.",
+            )
+            .default("false"),
+            ParamDecl::path(
+                "label_map",
+                "Path to a JSON file mapping node types to database labels.",
+            ),
+            ParamDecl::int(
+                "writers",
+                "Number of writer threads, each with its own connection.",
+            )
+            .default("1"),
+            ParamDecl::bool(
+                "skip_schema",
+                "Leave the database's indexes and constraints alone.",
+            )
+            .default("false"),
+            ParamDecl::int(
+                "retries",
+                "Number of times to retry a lost connection before giving up, reset after each \
+                 commit.",
+            )
+            .default("5"),
+            ParamDecl::int(
+                "retry_delay",
+                "Milliseconds to wait before retrying, doubling with each consecutive retry.",
+            )
+            .default("500"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let tls = params.get_bool("tls").unwrap();
+        let ca_cert = params.get_path("ca_cert").map(PathBuf::from);
+        let insecure_skip_verify = params.get_bool("insecure_skip_verify").unwrap();
+        if !tls && (ca_cert.is_some() || insecure_skip_verify) {
+            return Err(ViewError::InvalidParam(
+                "tls".to_string(),
+                "must be true to use ca_cert or insecure_skip_verify".to_string(),
+            ));
+        }
+        let cfg = Neo4jConfig {
+            addr: params.get_str("addr").unwrap().to_string(),
+            user: params.get_str("user").unwrap().to_string(),
+            pass: params.get_str("pass").unwrap().to_string(),
+            database: params.get_str("database").map(String::from),
+            tls,
+            ca_cert,
+            insecure_skip_verify,
         };
-        let thr = thread::Builder::new().name("Neo4jView".to_string()).spawn(move || {
-            let mut nodes = CreateNodes::new();
-            let mut edges = CreateRels::new();
-            let mut up_node = UpdateNodes::new();
-            let mut up_rel = UpdateRels::new();
-            let mut ups = 0;
-            let mut btc = 0;
-            let mut trs = 0;
-            let mut rel_up_base = 0;
-            let mut rel_up_node = 0;
-            let mut rel_up_rel = 0;
-
-            let mut tr = db.transaction();
-
-            tr.run_unchecked("CREATE INDEX ON :Node(db_id)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Actor(uuid)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Object(uuid)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Store(uuid)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :EditSession(uuid)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Conduit(uuid)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Path(path)", HashMap::new());
-            tr.run_unchecked("CREATE INDEX ON :Net(addr)", HashMap::new());
-
-            tr.commit_and_refresh().unwrap();
-
-            tr.run_unchecked(
-                "MERGE (:DBInfo {pvm_version: 2, source: $src})",
-                hashmap!("src" => Value::from(format!("libPVM-{}", crate::VERSION))),
-            );
-
-            tr.commit_and_refresh().unwrap();
-
-            for evt in stream {
-                match *evt {
-                    DBTr::CreateNode(ref node) => {
-                        let (id, labs, props) = node.to_db();
-                        nodes.add(
-                            id,
-                            hashmap!("labels" => labs.into(), "props"  => props.into()),
-                        );
-                        ups += 1;
-                    }
-                    DBTr::CreateRel(ref rel) => {
-                        let (id, data) = rel.to_db();
-                        edges.add(id, data);
-                        ups += 1;
-                    }
-                    DBTr::UpdateNode(ref node) => {
-                        let (id, _, props) = node.to_db();
-                        if let Some(props) = nodes.update(id, props.into()) {
-                            if up_node.add(id, props) {
-                                ups += 1;
+        let retry = Retry {
+            cfg,
+            attempts: non_negative(&params, "retries")? as u32,
+            delay: Duration::from_millis(non_negative(&params, "retry_delay")?),
+        };
+        let errors = params.error_sink();
+        let connect = || {
+            Neo4jDB::connect(&retry.cfg).map_err(|e| {
+                ViewError::InvalidParam("addr".to_string(), format!("could not connect: {}", e))
+            })
+        };
+        let labels = match params.get_path("label_map") {
+            None => LabelMap::default(),
+            Some(path) => LabelMap::from_file(path)
+                .map_err(|e| ViewError::InvalidParam("label_map".to_string(), e.to_string()))?,
+        };
+        let writers = match params.get_int("writers").unwrap() {
+            n if n > 0 => n as usize,
+            n => {
+                return Err(ViewError::InvalidParam(
+                    "writers".to_string(),
+                    format!("must be a positive integer, not {}", n),
+                ))
+            }
+        };
+
+        let mut db = connect()?;
+        setup(&mut db, &labels, !params.get_bool("skip_schema").unwrap()).map_err(|e| {
+            ViewError::InvalidParam("addr".to_string(), format!("could not set up: {}", e))
+        })?;
+
+        let thr = if writers == 1 {
+            thread::Builder::new()
+                .name("Neo4jView".to_string())
+                .spawn(move || write(&retry, db, updates(stream), Batcher::new(labels), &errors))?
+        } else {
+            let mut dbs = vec![db];
+            for _ in 1..writers {
+                dbs.push(connect()?);
+            }
+            thread::Builder::new()
+                .name("Neo4jView".to_string())
+                .spawn(move || {
+                    let (sends, handles): (Vec<_>, Vec<_>) = dbs
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, db)| {
+                            let (send, recv) = sync_channel(1000);
+                            let batch = Batcher::shared(labels.clone());
+                            let retry = retry.clone();
+                            let errors = errors.clone();
+                            let handle = thread::Builder::new()
+                                .name(format!("Neo4jWriter{}", i))
+                                .spawn(move || write(&retry, db, recv, batch, &errors))
+                                .unwrap();
+                            (send, handle)
+                        })
+                        .unzip();
+                    for evt in updates(stream) {
+                        match *evt {
+                            // acknowledged once every writer has committed up to it
+                            DBTr::Flush(ref marker) => {
+                                for send in &sends {
+                                    send.send(Arc::new(DBTr::Flush(marker.clone()))).unwrap();
+                                }
                             }
+                            _ => sends[partition(&evt, writers)].send(evt).unwrap(),
                         }
                     }
-                    DBTr::UpdateRel(ref rel) => {
-                        rel_up_base += 1;
-                        let (id, data) = rel.to_db();
-                        if let Some(data) = edges.update(id, data) {
-                            rel_up_node += 1;
-                            if up_rel.add(id, data) {
-                                ups += 1;
-                                rel_up_rel += 1;
-                            }
-                        }
+                    drop(sends);
+                    for handle in handles {
+                        handle.join().unwrap();
                     }
-                }
-                if ups > (btc + 1) * BATCH_SIZE {
-                    nodes.execute(&mut tr);
-                    edges.execute(&mut tr);
-                    up_node.execute(&mut tr);
-                    up_rel.execute(&mut tr);
-                    btc += 1;
-                }
-                if ups > (trs + 1) * TR_SIZE {
-                    tr.commit_and_refresh().unwrap();
-                    trs += 1;
-                }
-            }
-            nodes.execute(&mut tr);
-            edges.execute(&mut tr);
-            up_node.execute(&mut tr);
-            up_rel.execute(&mut tr);
-            println!("Final Commit");
-            tr.commit().unwrap();
-            trs += 1;
-            println!("Neo4J Updates Issued: {}", ups);
-            println!("Neo4J Batches Issued: {}", btc * 4);
-            println!("Neo4J Transactions Issued: {}", trs);
-            println!("Rel Updates: {}, Absorbed into Nodes: {}, Absorbed into other updates: {}, Finally executed: {}", rel_up_base, rel_up_base - rel_up_node, rel_up_node - rel_up_rel, rel_up_rel);
-        }).unwrap();
-        ViewInst {
+                })?
+        };
+        Ok(ViewInst {
             id,
             vtype: self.id,
             params,
             handle: thr,
+        })
+    }
+}
+
+fn non_negative(params: &ViewParams, key: &str) -> Result<u64, ViewError> {
+    match params.get_int(key).unwrap() {
+        n if n >= 0 => Ok(n as u64),
+        n => Err(ViewError::InvalidParam(
+            key.to_string(),
+            format!("must not be negative, not {}", n),
+        )),
+    }
+}
+
+/// The updates of the stream, with each flush passed on as a `DBTr::Flush` holding its marker
+///
+/// Ticks are dropped here, as the view has nothing to do on them and the writers only expect
+/// updates and flushes.
+fn updates(mut stream: ViewStream) -> impl Iterator<Item = Arc<DBTr>> {
+    iter::from_fn(move || loop {
+        match stream.recv_msg()? {
+            ViewMsg::Event(evt) => match *evt {
+                DBTr::Tick(_) => continue,
+                _ => return Some(evt),
+            },
+            ViewMsg::Flush(marker) => return Some(Arc::new(DBTr::Flush(marker))),
+            ViewMsg::Shutdown => return None,
+        }
+    })
+}
+
+/// Pick the writer responsible for an update
+///
+/// Work is split on the id of the node or relationship being written, so every update to an
+/// element is issued by the same writer, in the order it was received.
+fn partition(evt: &DBTr, writers: usize) -> usize {
+    let id = match evt {
+        DBTr::CreateNode(n) | DBTr::UpdateNode(n) => n.get_db_id(),
+        DBTr::CreateRel(r) | DBTr::UpdateRel(r) => r.get_db_id(),
+        DBTr::Flush(_) | DBTr::Tick(_) => unreachable!("Only updates are passed to writers"),
+    };
+    (id.inner() % writers as u64) as usize
+}
+
+/// The labels of data nodes, all of which are looked up by uuid and type
+const DATA_LABELS: &[&str] = &["Actor", "Object", "Store", "EditSession", "Conduit"];
+
+/// Statements creating the constraints and indexes the view and later queries rely on
+///
+/// `db_id` is unique across all nodes, which also indexes it for the matches and merges done
+/// when writing relationships. A `uuid` is shared by every version of an object, so it is only
+/// indexed.
+fn schema(labels: &LabelMap) -> Vec<String> {
+    let index = |label: &str, prop: &str| {
+        format!(
+            "CREATE INDEX IF NOT EXISTS FOR (n:{}) ON (n.{})",
+            labels.label(label),
+            prop
+        )
+    };
+    let mut ret =
+        vec!["CREATE CONSTRAINT IF NOT EXISTS FOR (n:Node) REQUIRE n.db_id IS UNIQUE".to_string()];
+    for label in DATA_LABELS {
+        for prop in &["uuid", "type"] {
+            ret.push(index(label, prop));
+        }
+    }
+    for (label, prop) in &[("Context", "type"), ("Path", "path"), ("Net", "addr")] {
+        ret.push(index(label, prop));
+    }
+    ret
+}
+
+/// Create the schema, unless told not to, and record the database source
+///
+/// With several writers a node may be first referenced by a relationship issued from a
+/// different writer than the one creating it, so nodes are merged on the `db_id` constraint.
+fn setup(db: &mut Neo4jDB, labels: &LabelMap, create_schema: bool) -> Result<(), Error> {
+    let mut tr = db.transaction()?;
+
+    if create_schema {
+        for stmt in schema(labels) {
+            tr.run_unchecked(&stmt, HashMap::new());
+        }
+        tr.commit_and_refresh()?;
+    }
+
+    tr.run_unchecked(
+        "MERGE (:DBInfo {pvm_version: 2, source: $src})",
+        hashmap!("src" => Value::from(format!("libPVM-{}", crate::VERSION))),
+    );
+
+    tr.commit()
+}
+
+/// How a writer reconnects after losing its connection
+#[derive(Clone, Debug)]
+struct Retry {
+    cfg: Neo4jConfig,
+    attempts: u32,
+    delay: Duration,
+}
+
+impl Retry {
+    /// The wait before a retry, doubling with each consecutive failure
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        cmp::min(self.delay.saturating_mul(factor), MAX_RETRY_DELAY)
+    }
+}
+
+fn write<I: IntoIterator<Item = Arc<DBTr>>>(
+    retry: &Retry,
+    db: Neo4jDB,
+    stream: I,
+    batch: Batcher,
+    errors: &ErrorSink,
+) {
+    let mut stream = stream.into_iter();
+    let mut w = Writer::new(batch);
+
+    let mut conn = Ok(db);
+    let failure = loop {
+        let err = match conn {
+            Ok(mut db) => match w.session(&mut db, &mut stream) {
+                Ok(()) => break None,
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+        if !is_transient(&err) || w.failures >= retry.attempts {
+            break Some(err);
+        }
+        w.failures += 1;
+        let delay = retry.delay(w.failures);
+        eprintln!("Neo4j connection lost: {}, retrying in {:?}", err, delay);
+        thread::sleep(delay);
+        conn = Neo4jDB::connect(&retry.cfg);
+    };
+
+    if let Some(err) = failure {
+        errors.report(format!(
+            "Neo4j writes failed, discarding further updates: {}",
+            err
+        ));
+        let lost = w.pending.len() + stream.count();
+        eprintln!("Neo4J Updates Discarded: {}", lost);
+    }
+    eprintln!("Neo4J Updates Issued: {}", w.batch.ups);
+    eprintln!("Neo4J Batches Issued: {}", w.btc * 4);
+    eprintln!("Neo4J Transactions Issued: {}", w.trs);
+    eprintln!(
+        "Rel Updates: {}, Absorbed into Nodes: {}, Absorbed into other updates: {}, Finally executed: {}",
+        w.batch.rel_up_base,
+        w.batch.rel_up_base - w.batch.rel_up_node,
+        w.batch.rel_up_node - w.batch.rel_up_rel,
+        w.batch.rel_up_rel
+    );
+}
+
+/// Writes a stream of updates over connections that may be lost part way through
+///
+/// Updates are kept from when they are received until the transaction they were added to
+/// commits, and are replayed into the next transaction if it is lost. A flush commits the
+/// transaction, and is kept with the updates before it so that it is only acknowledged once
+/// they have been committed. While reconnecting the
+/// stream is not read, so the view's channel fills and holds back ingestion rather than
+/// updates being dropped.
+struct Writer {
+    batch: Batcher,
+    pending: VecDeque<Arc<DBTr>>,
+    /// How many of the pending updates have been added to the current transaction
+    applied: usize,
+    /// Consecutive failures since the last commit
+    failures: u32,
+    btc: usize,
+    trs: usize,
+}
+
+impl Writer {
+    fn new(batch: Batcher) -> Self {
+        Writer {
+            batch,
+            pending: VecDeque::new(),
+            applied: 0,
+            failures: 0,
+            btc: 0,
+            trs: 0,
+        }
+    }
+
+    /// Write updates until the stream ends or a transient error is hit
+    fn session<I: Iterator<Item = Arc<DBTr>>>(
+        &mut self,
+        db: &mut Neo4jDB,
+        stream: &mut I,
+    ) -> Result<(), Error> {
+        let mut tr = db.transaction()?;
+        self.batch.reset();
+        self.applied = 0;
+        self.catch_up(&mut tr)?;
+        for evt in stream {
+            self.pending.push_back(evt);
+            self.catch_up(&mut tr)?;
+        }
+        self.batch.execute(&mut tr)?;
+        eprintln!("Final Commit");
+        tr.commit()?;
+        self.committed();
+        Ok(())
+    }
+
+    /// Add the pending updates that are not yet in the transaction
+    fn catch_up(&mut self, tr: &mut Transaction) -> Result<(), Error> {
+        while self.applied < self.pending.len() {
+            if let DBTr::Flush(_) = *self.pending[self.applied] {
+                self.applied += 1;
+                self.batch.execute(tr)?;
+                tr.commit_and_refresh()?;
+                self.committed();
+                continue;
+            }
+            self.batch.add(&self.pending[self.applied]);
+            self.applied += 1;
+            if self.batch.ups > (self.btc + 1) * BATCH_SIZE {
+                self.batch.execute(tr)?;
+                self.btc += 1;
+            }
+            if self.batch.ups > (self.trs + 1) * TR_SIZE {
+                self.batch.execute(tr)?;
+                tr.commit_and_refresh()?;
+                self.committed();
+            }
+        }
+        Ok(())
+    }
+
+    fn committed(&mut self) {
+        self.pending.drain(..self.applied);
+        self.applied = 0;
+        self.failures = 0;
+        self.trs += 1;
+    }
+}
+
+type Statement = (&'static str, HashMap<&'static str, Value>);
+
+const CREATE_NODES: &str = "UNWIND $nodes AS n
+     CALL apoc.create.node(n.labels, n.props) YIELD node
+     RETURN 0";
+
+// Relationships are merged on their db_id rather than created so that an update that has
+// already been flushed before the matching create does not result in a duplicate.
+const CREATE_RELS: &str = "UNWIND $rels AS r
+     MATCH (s:Node {db_id: r.src}),
+           (d:Node {db_id: r.dst})
+     CALL apoc.merge.relationship(s, r.type, {db_id: r.props.db_id}, r.props, d) YIELD rel
+     RETURN 0";
+
+const UPDATE_NODES: &str = "UNWIND $upds AS props
+     MATCH (p:Node {db_id: props.db_id})
+     SET p += props";
+
+// Updates can arrive for relationships whose create has not been flushed yet, so upsert
+// them rather than relying on a match.
+const UPDATE_RELS: &str = "UNWIND $upds AS up
+     MATCH (s:Node {db_id: up.src}),
+           (d:Node {db_id: up.dst})
+     CALL apoc.merge.relationship(s, up.type, {db_id: up.props.db_id}, up.props, d) YIELD rel
+     SET rel += up.props
+     RETURN 0";
+
+// Variants used when several writers share a database. Either end of a relationship may not
+// have been written yet by its own writer, so endpoints are merged and completed later by the
+// node creation.
+const MERGE_NODES: &str = "UNWIND $nodes AS n
+     MERGE (p:Node {db_id: n.props.db_id})
+     SET p += n.props
+     WITH p, n
+     CALL apoc.create.addLabels(p, n.labels) YIELD node
+     RETURN 0";
+
+const MERGE_RELS: &str = "UNWIND $rels AS r
+     MERGE (s:Node {db_id: r.src})
+     MERGE (d:Node {db_id: r.dst})
+     WITH s, d, r
+     CALL apoc.merge.relationship(s, r.type, {db_id: r.props.db_id}, r.props, d) YIELD rel
+     RETURN 0";
+
+const MERGE_UPDATE_RELS: &str = "UNWIND $upds AS up
+     MERGE (s:Node {db_id: up.src})
+     MERGE (d:Node {db_id: up.dst})
+     WITH s, d, up
+     CALL apoc.merge.relationship(s, up.type, {db_id: up.props.db_id}, up.props, d) YIELD rel
+     SET rel += up.props
+     RETURN 0";
+
+struct Queries {
+    create_nodes: &'static str,
+    create_rels: &'static str,
+    update_nodes: &'static str,
+    update_rels: &'static str,
+}
+
+const EXCLUSIVE: Queries = Queries {
+    create_nodes: CREATE_NODES,
+    create_rels: CREATE_RELS,
+    update_nodes: UPDATE_NODES,
+    update_rels: UPDATE_RELS,
+};
+
+const SHARED: Queries = Queries {
+    create_nodes: MERGE_NODES,
+    create_rels: MERGE_RELS,
+    update_nodes: UPDATE_NODES,
+    update_rels: MERGE_UPDATE_RELS,
+};
+
+/// Accumulates database transactions into batched statements
+///
+/// Statements are produced in dependency order, nodes before the relationships between them
+/// and creations before updates.
+struct Batcher {
+    labels: LabelMap,
+    queries: &'static Queries,
+    nodes: CreateNodes,
+    edges: CreateRels,
+    up_node: UpdateNodes,
+    up_rel: UpdateRels,
+    ups: usize,
+    rel_up_base: usize,
+    rel_up_node: usize,
+    rel_up_rel: usize,
+}
+
+impl Batcher {
+    fn new(labels: LabelMap) -> Self {
+        Batcher::with_queries(labels, &EXCLUSIVE)
+    }
+
+    /// A batcher for one of several writers to the same database
+    fn shared(labels: LabelMap) -> Self {
+        Batcher::with_queries(labels, &SHARED)
+    }
+
+    fn with_queries(labels: LabelMap, queries: &'static Queries) -> Self {
+        Batcher {
+            labels,
+            queries,
+            nodes: CreateNodes::new(),
+            edges: CreateRels::new(),
+            up_node: UpdateNodes::new(),
+            up_rel: UpdateRels::new(),
+            ups: 0,
+            rel_up_base: 0,
+            rel_up_node: 0,
+            rel_up_rel: 0,
         }
     }
+
+    fn add(&mut self, evt: &DBTr) {
+        match *evt {
+            DBTr::CreateNode(ref node) => {
+                let (id, labs, props) = node.to_db();
+                let labs = self.labels.apply(node, labs);
+                self.nodes.add(
+                    id,
+                    hashmap!("labels" => labs.into(), "props"  => props.into()),
+                );
+                self.ups += 1;
+            }
+            DBTr::CreateRel(ref rel) => {
+                let (id, data) = rel.to_db();
+                self.edges.add(id, data);
+                self.ups += 1;
+            }
+            DBTr::UpdateNode(ref node) => {
+                let (id, _, props) = node.to_db();
+                if let Some(props) = self.nodes.update(id, props.into()) {
+                    if self.up_node.add(id, props) {
+                        self.ups += 1;
+                    }
+                }
+            }
+            DBTr::UpdateRel(ref rel) => {
+                self.rel_up_base += 1;
+                let (id, data) = rel.to_db();
+                if let Some(data) = self.edges.update(id, data) {
+                    self.rel_up_node += 1;
+                    if self.up_rel.add(id, data) {
+                        self.ups += 1;
+                        self.rel_up_rel += 1;
+                    }
+                }
+            }
+            DBTr::Flush(_) | DBTr::Tick(_) => unreachable!("Only updates are passed to writers"),
+        }
+    }
+
+    fn statements(&mut self) -> Vec<Statement> {
+        vec![
+            self.nodes.statement(self.queries.create_nodes),
+            self.edges.statement(self.queries.create_rels),
+            self.up_node.statement(self.queries.update_nodes),
+            self.up_rel.statement(self.queries.update_rels),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Run the batched statements, failing only on errors a new connection might not hit
+    fn execute(&mut self, db: &mut impl Neo4jOperations) -> Result<(), Error> {
+        for (query, params) in self.statements() {
+            match db.run(query, params) {
+                Err(e) if is_transient(&e) => return Err(e),
+                Err(e) => eprintln!("Neo4j statement failed: {}", e),
+                Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop any statements not yet executed, to be rebuilt by replaying their updates
+    fn reset(&mut self) {
+        self.nodes = CreateNodes::new();
+        self.edges = CreateRels::new();
+        self.up_node = UpdateNodes::new();
+        self.up_rel = UpdateRels::new();
+    }
 }
 
 struct CreateNodes {
@@ -156,17 +637,12 @@ impl CreateNodes {
             nodes: HashMap::new(),
         }
     }
-    fn execute(&mut self, db: &mut impl Neo4jOperations) {
+    fn statement(&mut self, query: &'static str) -> Option<Statement> {
+        if self.nodes.is_empty() {
+            return None;
+        }
         let nodes: Value = self.nodes.drain().map(|(_k, v)| v).collect();
-        self._execute(db, nodes);
-    }
-    fn _execute(&mut self, db: &mut impl Neo4jOperations, nodes: Value) {
-        db.run_unchecked(
-            "UNWIND $nodes AS n
-             CALL apoc.create.node(n.labels, n.props) YIELD node
-             RETURN 0",
-            hashmap!("nodes" => nodes),
-        );
+        Some((query, hashmap!("nodes" => nodes)))
     }
     fn add(&mut self, id: ID, data: HashMap<&'static str, Value>) {
         self.nodes.insert(id, data);
@@ -192,16 +668,12 @@ impl CreateRels {
             rels: HashMap::new(),
         }
     }
-    fn execute(&mut self, db: &mut impl Neo4jOperations) {
+    fn statement(&mut self, query: &'static str) -> Option<Statement> {
+        if self.rels.is_empty() {
+            return None;
+        }
         let rels: Value = self.rels.drain().map(|(_k, v)| v).collect();
-        db.run_unchecked(
-            "UNWIND $rels AS r
-             MATCH (s:Node {db_id: r.src}),
-                   (d:Node {db_id: r.dst})
-             CALL apoc.create.relationship(s, r.type, r.props, d) YIELD rel
-             RETURN 0",
-            hashmap!("rels" => rels),
-        );
+        Some((query, hashmap!("rels" => rels)))
     }
     fn add(&mut self, id: ID, data: Value) {
         self.rels.insert(id, data);
@@ -226,14 +698,12 @@ impl UpdateNodes {
             props: HashMap::new(),
         }
     }
-    fn execute(&mut self, db: &mut impl Neo4jOperations) {
+    fn statement(&mut self, query: &'static str) -> Option<Statement> {
+        if self.props.is_empty() {
+            return None;
+        }
         let nodes: Value = self.props.drain().map(|(_k, v)| v).collect();
-        db.run_unchecked(
-            "UNWIND $upds AS props
-             MATCH (p:Node {db_id: props.db_id})
-             SET p += props",
-            hashmap!("upds" => nodes),
-        );
+        Some((query, hashmap!("upds" => nodes)))
     }
     fn add(&mut self, id: ID, value: Value) -> bool {
         self.props.insert(id, value).is_none()
@@ -250,16 +720,183 @@ impl UpdateRels {
             props: HashMap::new(),
         }
     }
-    fn execute(&mut self, db: &mut impl Neo4jOperations) {
+    fn statement(&mut self, query: &'static str) -> Option<Statement> {
+        if self.props.is_empty() {
+            return None;
+        }
         let rels: Value = self.props.drain().map(|(_k, v)| v).collect();
-        db.run_unchecked(
-            "UNWIND $upds AS up
-             MATCH (s:Node {db_id: up.src})-[r {db_id: up.id}]->()
-             SET r += up.props",
-            hashmap!("upds" => rels),
-        );
+        Some((query, hashmap!("upds" => rels)))
     }
     fn add(&mut self, id: ID, value: Value) -> bool {
         self.props.insert(id, value).is_none()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    use chrono::Utc;
+
+    use crate::data::{
+        rel_types::{Inf, InfInit, PVMOps, Rel},
+        RelGenerable,
+    };
+
+    fn inf(bytes_written: u64) -> Rel {
+        Rel::Inf(Inf::new(
+            ID::new(3),
+            ID::new(1),
+            ID::new(2),
+            InfInit {
+                pvm_op: PVMOps::Sink,
+                ctx: ID::new(4),
+                bytes_read: 0,
+                bytes_written,
+                event: None,
+            },
+        ))
+    }
+
+    fn queries(batch: &mut Batcher) -> Vec<&'static str> {
+        batch.statements().into_iter().map(|(q, _)| q).collect()
+    }
+
+    #[test]
+    fn update_rel_before_create_rel() {
+        let mut batch = Batcher::new(LabelMap::default());
+        batch.add(&DBTr::UpdateRel(inf(10)));
+        assert_eq!(queries(&mut batch), vec![UPDATE_RELS]);
+        batch.add(&DBTr::CreateRel(inf(0)));
+        assert_eq!(queries(&mut batch), vec![CREATE_RELS]);
+        assert!(UPDATE_RELS.contains("apoc.merge.relationship"));
+        assert!(CREATE_RELS.contains("apoc.merge.relationship"));
+    }
+
+    #[test]
+    fn update_rel_before_create_rel_same_batch() {
+        let mut batch = Batcher::new(LabelMap::default());
+        batch.add(&DBTr::UpdateRel(inf(10)));
+        batch.add(&DBTr::CreateRel(inf(0)));
+        assert_eq!(queries(&mut batch), vec![CREATE_RELS, UPDATE_RELS]);
+        assert!(batch.statements().is_empty());
+    }
+
+    #[test]
+    fn update_rel_absorbed_into_create() {
+        let mut batch = Batcher::new(LabelMap::default());
+        batch.add(&DBTr::CreateRel(inf(0)));
+        batch.add(&DBTr::UpdateRel(inf(10)));
+        let stmts = batch.statements();
+        assert_eq!(stmts.len(), 1);
+        let (query, params) = &stmts[0];
+        assert_eq!(*query, CREATE_RELS);
+        assert_eq!(
+            params["rels"],
+            vec![inf(10).to_db().1].into_iter().collect()
+        );
+        assert_eq!(batch.rel_up_base, 1);
+        assert_eq!(batch.rel_up_node, 0);
+    }
+
+    #[test]
+    fn shared_writers_merge_endpoints() {
+        let mut batch = Batcher::shared(LabelMap::default());
+        batch.add(&DBTr::UpdateRel(inf(10)));
+        batch.add(&DBTr::CreateRel(inf(0)));
+        assert_eq!(queries(&mut batch), vec![MERGE_RELS, MERGE_UPDATE_RELS]);
+    }
+
+    #[test]
+    fn schema_uses_mapped_labels() {
+        let labels: LabelMap = serde_json::from_str(r#"{"labels": {"Store": "File"}}"#).unwrap();
+        let stmts = schema(&labels);
+        assert_eq!(
+            stmts[0],
+            "CREATE CONSTRAINT IF NOT EXISTS FOR (n:Node) REQUIRE n.db_id IS UNIQUE"
+        );
+        assert!(stmts.contains(&"CREATE INDEX IF NOT EXISTS FOR (n:File) ON (n.uuid)".to_string()));
+        assert!(stmts.contains(&"CREATE INDEX IF NOT EXISTS FOR (n:File) ON (n.type)".to_string()));
+        assert!(stmts.contains(&"CREATE INDEX IF NOT EXISTS FOR (n:Path) ON (n.path)".to_string()));
+        assert!(!stmts.iter().any(|s| s.contains(":Store)")));
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_limit() {
+        let retry = Retry {
+            cfg: Neo4jConfig {
+                addr: String::new(),
+                user: String::new(),
+                pass: String::new(),
+                database: None,
+                tls: false,
+                ca_cert: None,
+                insecure_skip_verify: false,
+            },
+            attempts: 5,
+            delay: Duration::from_millis(500),
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(500));
+        assert_eq!(retry.delay(2), Duration::from_secs(1));
+        assert_eq!(retry.delay(4), Duration::from_secs(4));
+        assert_eq!(retry.delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn partition_is_stable_per_element() {
+        let create = DBTr::CreateRel(inf(0));
+        let update = DBTr::UpdateRel(inf(10));
+        for writers in 1..8 {
+            assert_eq!(partition(&create, writers), partition(&update, writers));
+            assert!(partition(&create, writers) < writers);
+        }
+    }
+
+    #[test]
+    fn tls_options_need_tls() {
+        let view = Neo4JView::new(0);
+        let mut params = ViewParams::new();
+        params.insert_param("insecure_skip_verify", "true");
+        params.resolve(&view.params()).unwrap();
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "tls"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created with insecure_skip_verify but no tls"),
+        }
+    }
+
+    #[test]
+    fn refused_connection_is_an_error() {
+        let view = Neo4JView::new(0);
+        let mut params = ViewParams::new();
+        params.insert_param("addr", "127.0.0.1:1");
+        params.resolve(&view.params()).unwrap();
+        let (_send, recv) = channel();
+        match view.create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) => assert_eq!(name, "addr"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("created without a server to connect to"),
+        }
+    }
+
+    #[test]
+    fn ticks_are_not_written() {
+        let (send, recv) = channel();
+        send.send(ViewMsg::Event(Arc::new(DBTr::Tick(Utc::now()))))
+            .unwrap();
+        send.send(ViewMsg::Event(Arc::new(DBTr::CreateRel(inf(0)))))
+            .unwrap();
+        send.send(ViewMsg::Event(Arc::new(DBTr::Tick(Utc::now()))))
+            .unwrap();
+        send.send(ViewMsg::Shutdown).unwrap();
+        // as passed to the single writer, which would panic on a tick
+        let mut batch = Batcher::new(LabelMap::default());
+        for evt in updates(ViewStream::new(recv)) {
+            batch.add(&evt);
+        }
+        assert_eq!(queries(&mut batch), vec![CREATE_RELS]);
+    }
+}
@@ -1,7 +1,7 @@
 //use data::node_types::DataNode;
 
+use crate::neo4j_glue::bolt::Neo4jDB;
 use maplit::hashmap;
-use neo4j::{Neo4jDB, Neo4jOperations};
 
 //use neo4j_glue::{FromDB, IntoVal};
 
@@ -9,7 +9,7 @@ use neo4j::{Neo4jDB, Neo4jOperations};
 
 /*pub fn nodes_by_uuid(cypher: &mut Neo4jDB, uuid: Uuid) -> Vec<DataNode> {
     cypher
-        .run(
+        .rows(
             "MATCH (n {uuid: {uuid}})
               RETURN n",
             hashmap!("uuid" => uuid.into_val()),
@@ -22,14 +22,13 @@ use neo4j::{Neo4jDB, Neo4jOperations};
 
 pub fn count_processes(cypher: &mut Neo4jDB) -> i64 {
     cypher
-        .run(
+        .rows(
             "MATCH (n:Actor {type: \"process\"})
-              RETURN count(n)",
+              RETURN count(n) AS n",
             hashmap!(),
         )
         .unwrap()
         .first()
-        .map(|data| data.into_int().unwrap())
-        .next()
+        .map(|row| row.get::<i64>("n").unwrap())
         .unwrap()
 }
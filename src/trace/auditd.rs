@@ -0,0 +1,635 @@
+//! Linux audit daemon traces
+//!
+//! auditd logs each event as a group of text records that share an `audit(time:serial)` id. This
+//! mapping takes events with their group already assembled into a single JSON object per line,
+//! as produced by laurel and similar auditd plugins. The records used are:
+//!
+//! ```json
+//! {"ID": "1533046200.125:1042", "NODE": "web01",
+//!  "SYSCALL": {"syscall": "openat", "success": "yes", "exit": 3, "pid": 10, "ppid": 1,
+//!              "uid": 0, "euid": 0, "suid": 0, "gid": 0, "egid": 0, "sgid": 0,
+//!              "comm": "cat", "exe": "/usr/bin/cat", "a0": "ffffff9c", "a1": "7ffc1000"},
+//!  "CWD": {"cwd": "/root"},
+//!  "PATH": [{"name": "/etc/passwd", "inode": 1312, "dev": "fd:01", "nametype": "NORMAL"}],
+//!  "EXECVE": {"ARGV": ["cat", "/etc/passwd"]},
+//!  "SOCKADDR": {"family": "inet", "addr": "10.0.0.2", "port": 80}}
+//! ```
+//!
+//! The syscall may be given by name or by number, numbers are interpreted as x86_64 syscalls.
+//! Syscall arguments are hex strings as logged by auditd.
+//!
+//! Audit records carry no object identifiers, so uuids are derived from what is available:
+//! processes from their host and pid, files from their device and inode and sockets from the
+//! owning process and descriptor. Processes are released on `exit_group` so a reused pid starts a
+//! new process. A plain `exit` only ends the calling thread, and as records carry the pid of the
+//! process rather than the thread it is ignored. Failed syscalls are skipped.
+//!
+//! Threads created by `clone` with `CLONE_THREAD` are not modelled, their records carry the pid
+//! of their process. `clone3` takes its flags in a `struct clone_args` that auditd does not log,
+//! so a thread it creates, as glibc's `pthread_create` does, cannot be told apart from a child
+//! process. It is modelled as a child process with the thread id as its pid, which sees no
+//! further events.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ContextType, Name},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PROCESS, SOCKET},
+        parse_ts, MapFmt, RawTs, TsFormat,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "auditd_context",
+        props: vec!["time", "event", "host", "serial", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids synthesised for audit objects
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:auditd");
+}
+
+/// A syscall as identified in a SYSCALL record
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SyscallId {
+    Num(i64),
+    Name(String),
+}
+
+impl SyscallId {
+    fn name(&self) -> &str {
+        match self {
+            SyscallId::Name(n) => n,
+            SyscallId::Num(n) => match n {
+                2 => "open",
+                42 => "connect",
+                56 => "clone",
+                57 => "fork",
+                58 => "vfork",
+                59 => "execve",
+                60 => "exit",
+                82 => "rename",
+                85 => "creat",
+                86 => "link",
+                87 => "unlink",
+                90 => "chmod",
+                92 => "chown",
+                94 => "lchown",
+                105 => "setuid",
+                106 => "setgid",
+                113 => "setreuid",
+                114 => "setregid",
+                117 => "setresuid",
+                119 => "setresgid",
+                231 => "exit_group",
+                257 => "openat",
+                260 => "fchownat",
+                263 => "unlinkat",
+                264 => "renameat",
+                265 => "linkat",
+                268 => "fchmodat",
+                316 => "renameat2",
+                322 => "execveat",
+                435 => "clone3",
+                437 => "openat2",
+                _ => "unknown",
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SyscallRecord {
+    pub syscall: SyscallId,
+    pub success: Option<String>,
+    pub exit: Option<i64>,
+    pub a0: Option<String>,
+    pub a1: Option<String>,
+    pub a2: Option<String>,
+    pub a3: Option<String>,
+    pub pid: i32,
+    pub ppid: Option<i32>,
+    pub uid: Option<i64>,
+    pub euid: Option<i64>,
+    pub suid: Option<i64>,
+    pub gid: Option<i64>,
+    pub egid: Option<i64>,
+    pub sgid: Option<i64>,
+    pub comm: Option<String>,
+    pub exe: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PathRecord {
+    pub name: Option<String>,
+    pub inode: Option<u64>,
+    pub dev: Option<String>,
+    pub nametype: Option<String>,
+}
+
+impl PathRecord {
+    fn is(&self, nametypes: &[&str]) -> bool {
+        match &self.nametype {
+            Some(t) => nametypes.contains(&&t[..]),
+            None => false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CwdRecord {
+    pub cwd: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExecveRecord {
+    #[serde(rename = "ARGV", default)]
+    pub argv: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SockaddrRecord {
+    pub family: String,
+    pub addr: Option<String>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+}
+
+/// An assembled auditd event
+#[derive(Deserialize, Debug)]
+pub struct AuditdEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "NODE")]
+    pub node: Option<String>,
+    #[serde(rename = "SYSCALL")]
+    pub syscall: SyscallRecord,
+    #[serde(rename = "CWD")]
+    pub cwd: Option<CwdRecord>,
+    #[serde(rename = "PATH", default)]
+    pub paths: Vec<PathRecord>,
+    #[serde(rename = "EXECVE")]
+    pub execve: Option<ExecveRecord>,
+    #[serde(rename = "SOCKADDR")]
+    pub sockaddr: Option<SockaddrRecord>,
+}
+
+impl fmt::Display for AuditdEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.id,
+            self.node,
+            self.syscall,
+            self.cwd,
+            self.paths,
+            self.execve,
+            self.sockaddr,
+        );
+        ret.finish()
+    }
+}
+
+impl AuditdEvent {
+    fn event(&self) -> &str {
+        self.syscall.syscall.name()
+    }
+
+    fn missing(&self, field: &'static str) -> PVMError {
+        PVMError::MissingField {
+            evt: self.event().to_string(),
+            field,
+        }
+    }
+
+    fn host(&self) -> &str {
+        self.node.as_ref().map_or("", String::as_str)
+    }
+
+    fn uuid(&self, kind: &str, key: &str) -> Uuid {
+        Uuid::new_v5(&NS, format!("{}:{}:{}", self.host(), kind, key).as_bytes())
+    }
+
+    fn proc_uuid(&self, pid: i64) -> Uuid {
+        self.uuid("proc", &pid.to_string())
+    }
+
+    fn file_uuid(&self, path: &PathRecord) -> PVMResult<Uuid> {
+        match (&path.dev, path.inode) {
+            (Some(dev), Some(inode)) => Ok(self.uuid("file", &format!("{}:{}", dev, inode))),
+            _ => Ok(self.uuid("path", &self.path_name(path)?)),
+        }
+    }
+
+    /// Syscall argument `n`, auditd logs these as hex
+    fn arg(&self, n: usize) -> PVMResult<i64> {
+        let (field, val) = match n {
+            0 => ("a0", &self.syscall.a0),
+            1 => ("a1", &self.syscall.a1),
+            2 => ("a2", &self.syscall.a2),
+            _ => ("a3", &self.syscall.a3),
+        };
+        val.as_ref()
+            .and_then(|v| u64::from_str_radix(v, 16).ok())
+            .map(|v| v as i64)
+            .ok_or_else(|| self.missing(field))
+    }
+
+    /// The path of a PATH record, resolved against the working directory if relative
+    fn path_name(&self, path: &PathRecord) -> PVMResult<String> {
+        let name = path.name.clone().ok_or_else(|| self.missing("PATH.name"))?;
+        Ok(match &self.cwd {
+            Some(cwd) if !name.starts_with('/') => {
+                format!("{}/{}", cwd.cwd.trim_end_matches('/'), name)
+            }
+            _ => name,
+        })
+    }
+
+    /// The first PATH record with one of the given name types
+    fn path(&self, nametypes: &[&str]) -> PVMResult<&PathRecord> {
+        self.paths
+            .iter()
+            .find(|p| p.is(nametypes))
+            .ok_or_else(|| self.missing("PATH"))
+    }
+
+    fn declare_file(&self, path: &PathRecord, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let uuid = self.file_uuid(path)?;
+        pvm.declare(&FILE, uuid, None)
+    }
+
+    fn exit(&self) -> PVMResult<i64> {
+        self.syscall.exit.ok_or_else(|| self.missing("exit"))
+    }
+
+    fn sys_exec(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = self.path(&["NORMAL"])?;
+        let bin = self.declare_file(path, pvm)?;
+        pvm.name(bin, Name::Path(self.path_name(path)?))?;
+        if let Some(execve) = &self.execve {
+            pvm.meta(pro, "cmdline", &execve.argv.join(" "))?;
+        }
+        pvm.source(pro, bin)?;
+        Ok(())
+    }
+
+    /// A `clone3` is always taken to create a process, see the module documentation
+    fn sys_fork(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        const CLONE_THREAD: i64 = 0x10000;
+        if self.event() == "clone" && self.arg(0)? & CLONE_THREAD != 0 {
+            return Ok(());
+        }
+        let child = self.exit()?;
+        let ch = pvm.derive(pro, self.proc_uuid(child))?;
+        pvm.meta(ch, "pid", &child)?;
+        pvm.source(ch, pro)?;
+        Ok(())
+    }
+
+    fn sys_exit(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        pvm.release(&self.proc_uuid(i64::from(self.syscall.pid)));
+        Ok(())
+    }
+
+    fn sys_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = self.path(&["NORMAL", "CREATE"])?;
+        let f = self.declare_file(path, pvm)?;
+        pvm.name_by(f, Name::Path(self.path_name(path)?), pro)?;
+        Ok(())
+    }
+
+    fn sys_rename(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let src = self.path(&["DELETE"])?;
+        let dst = self.path(&["CREATE"])?;
+        let dst_name = self.path_name(dst)?;
+        let f = self.declare_file(src, pvm)?;
+        pvm.unname(f, Name::Path(self.path_name(src)?))?;
+        // A second deleted item is the file the rename replaced
+        if let Some(ovr) = self.paths.iter().filter(|p| p.is(&["DELETE"])).nth(1) {
+            let fovr = self.declare_file(ovr, pvm)?;
+            pvm.unname(fovr, Name::Path(dst_name.clone()))?;
+        }
+        pvm.name_by(f, Name::Path(dst_name), pro)?;
+        Ok(())
+    }
+
+    fn sys_unlink(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = self.path(&["DELETE"])?;
+        let f = self.declare_file(path, pvm)?;
+        pvm.unname(f, Name::Path(self.path_name(path)?))?;
+        Ok(())
+    }
+
+    fn sys_link(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let old = self.path(&["NORMAL"])?;
+        let new = self.path(&["CREATE"])?;
+        let f = self.declare_file(old, pvm)?;
+        pvm.name(f, Name::Path(self.path_name(old)?))?;
+        pvm.name_by(f, Name::Path(self.path_name(new)?), pro)?;
+        Ok(())
+    }
+
+    fn sys_chmod(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let mode = match self.event() {
+            "fchmodat" => self.arg(2)?,
+            _ => self.arg(1)?,
+        };
+        let path = self.path(&["NORMAL"])?;
+        let f = self.declare_file(path, pvm)?;
+        pvm.meta(f, "mode", &format!("{:o}", mode))?;
+        pvm.name(f, Name::Path(self.path_name(path)?))?;
+        pvm.sink(pro, f)?;
+        Ok(())
+    }
+
+    fn sys_chown(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let (uid, gid) = match self.event() {
+            "fchownat" => (self.arg(2)?, self.arg(3)?),
+            _ => (self.arg(1)?, self.arg(2)?),
+        };
+        let path = self.path(&["NORMAL"])?;
+        let f = self.declare_file(path, pvm)?;
+        // -1 leaves the id unchanged, it is logged as an unsigned 32 bit value
+        if uid != 0xffff_ffff {
+            pvm.meta(f, "owner_uid", &uid)?;
+        }
+        if gid != 0xffff_ffff {
+            pvm.meta(f, "owner_gid", &gid)?;
+        }
+        pvm.name(f, Name::Path(self.path_name(path)?))?;
+        pvm.sink(pro, f)?;
+        Ok(())
+    }
+
+    fn sys_connect(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let fd = self.arg(0)?;
+        let addr = self
+            .sockaddr
+            .as_ref()
+            .ok_or_else(|| self.missing("SOCKADDR"))?;
+        let name = match (&addr.path, &addr.addr, addr.port) {
+            (Some(path), _, _) => Name::Path(path.clone()),
+            (None, Some(host), Some(port)) => Name::Net(host.clone(), port),
+            _ => return Err(self.missing("SOCKADDR.addr")),
+        };
+        let uuid = self.uuid("sock", &format!("{}:{}", self.syscall.pid, fd));
+        let s = pvm.declare(&SOCKET, uuid, None)?;
+        pvm.name(s, name)?;
+        Ok(())
+    }
+
+    /// Record the credentials the SYSCALL record reports after a set*id call
+    fn sys_setid(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let sc = &self.syscall;
+        let ids = [
+            ("ruid", sc.uid),
+            ("euid", sc.euid),
+            ("suid", sc.suid),
+            ("rgid", sc.gid),
+            ("egid", sc.egid),
+            ("sgid", sc.sgid),
+        ];
+        for (key, val) in ids.iter() {
+            if let Some(val) = val {
+                pvm.meta(pro, key, val)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        if let Some(ref success) = self.syscall.success {
+            if success == "no" {
+                return Ok(());
+            }
+        }
+        let (ts, serial) = {
+            let mut parts = self.id.splitn(2, ':');
+            let ts = parts.next().unwrap_or_default();
+            (ts, parts.next().ok_or_else(|| self.missing("ID"))?)
+        };
+        let time = parse_ts(&RawTs::Str(ts.to_string()), TsFormat::Seconds)
+            .map_err(|_| self.missing("ID"))?;
+        let mut ctx = hashmap!(
+            "event" => self.event().to_string(),
+            "host" => self.host().to_string(),
+            "serial" => serial.to_string(),
+            "time" => time.to_rfc3339(),
+        );
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let mut init = hashmap!("pid" => self.syscall.pid.to_string());
+        if let Some(comm) = &self.syscall.comm {
+            init.insert("cmdline", comm.clone());
+        }
+        match {
+            let pro = tr.declare(
+                &PROCESS,
+                self.proc_uuid(i64::from(self.syscall.pid)),
+                Some(init),
+            )?;
+            match self.event() {
+                "execve" | "execveat" => self.sys_exec(pro, &mut tr),
+                "clone" | "clone3" | "fork" | "vfork" => self.sys_fork(pro, &mut tr),
+                // see the module documentation
+                "exit" => Ok(()),
+                "exit_group" => self.sys_exit(pro, &mut tr),
+                "open" | "openat" | "openat2" | "creat" => self.sys_open(pro, &mut tr),
+                "rename" | "renameat" | "renameat2" => self.sys_rename(pro, &mut tr),
+                "unlink" | "unlinkat" => self.sys_unlink(pro, &mut tr),
+                "link" | "linkat" => self.sys_link(pro, &mut tr),
+                "chmod" | "fchmodat" => self.sys_chmod(pro, &mut tr),
+                "chown" | "lchown" | "fchownat" => self.sys_chown(pro, &mut tr),
+                "connect" => self.sys_connect(pro, &mut tr),
+                "setuid" | "setreuid" | "setresuid" | "setgid" | "setregid" | "setresgid" => {
+                    self.sys_setid(pro, &mut tr)
+                }
+                evt => {
+                    tr.unparsed(evt);
+                    Ok(())
+                }
+            }
+        } {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for AuditdEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn source(&self) -> Option<String> {
+        self.node.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{data, names, run},
+        view::DBTr,
+    };
+
+    fn event(serial: u32, syscall: &str, pid: i32, exit: i64, extra: &str) -> String {
+        format!(
+            r#"{{"ID": "1533046200.125:{}", "NODE": "web01",
+                 "SYSCALL": {{"syscall": {}, "success": "yes", "exit": {}, "pid": {},
+                              "ppid": 1, "uid": 0, "euid": 0, "comm": "sh", "a0": "0"}}{}}}"#,
+            serial, syscall, exit, pid, extra
+        )
+    }
+
+    fn open(serial: u32) -> String {
+        event(
+            serial,
+            r#""openat""#,
+            10,
+            3,
+            r#", "PATH": [{"name": "/etc/passwd", "inode": 9, "dev": "fd:01", "nametype": "NORMAL"}]"#,
+        )
+    }
+
+    #[test]
+    fn fork_exec_open() {
+        let trs = run::<AuditdEvent>(&[
+            &event(1, "57", 10, 11, ""),
+            &event(
+                2,
+                r#""execve""#,
+                11,
+                0,
+                r#", "PATH": [{"name": "/usr/bin/cat", "inode": 7, "dev": "fd:01", "nametype": "NORMAL"}],
+                   "EXECVE": {"ARGV": ["cat", "passwd"]}"#,
+            ),
+            &event(
+                3,
+                r#""openat""#,
+                11,
+                3,
+                r#", "CWD": {"cwd": "/etc"},
+                   "PATH": [{"name": "passwd", "inode": 9, "dev": "fd:01", "nametype": "NORMAL"}]"#,
+            ),
+        ]);
+        assert_eq!(data(&trs, "process"), 2);
+        assert_eq!(data(&trs, "file"), 2);
+        let names = names(&trs);
+        assert!(names.iter().any(|n| n.contains("\"/usr/bin/cat\"")));
+        assert!(names.iter().any(|n| n.contains("\"/etc/passwd\"")));
+        let cmdline = trs.iter().rev().find_map(|tr| match tr {
+            DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d))
+                if d.ty().name == "process" =>
+            {
+                d.meta.cur("cmdline").map(String::from)
+            }
+            _ => None,
+        });
+        assert_eq!(cmdline, Some("cat passwd".to_string()));
+    }
+
+    #[test]
+    fn failed_and_thread_clones_are_skipped() {
+        let failed = event(1, r#""openat""#, 10, -2, "").replace(r#""yes""#, r#""no""#);
+        let thread =
+            event(2, r#""clone""#, 10, 12, "").replace(r#""a0": "0""#, r#""a0": "3d0f00""#);
+        let trs = run::<AuditdEvent>(&[&failed, &thread]);
+        assert_eq!(data(&trs, "process"), 1);
+        assert_eq!(data(&trs, "file"), 0);
+    }
+
+    #[test]
+    fn clone3_threads_are_modelled_as_processes() {
+        // a0 points to the clone_args, so CLONE_THREAD cannot be seen
+        let thread =
+            event(1, r#""clone3""#, 10, 12, "").replace(r#""a0": "0""#, r#""a0": "7ffc1000""#);
+        let trs = run::<AuditdEvent>(&[&thread]);
+        assert_eq!(data(&trs, "process"), 2);
+    }
+
+    #[test]
+    fn only_exit_group_releases() {
+        let thread_exit = run::<AuditdEvent>(&[&event(1, "60", 10, 0, ""), &open(2)]);
+        assert_eq!(data(&thread_exit, "process"), 1);
+        let process_exit = run::<AuditdEvent>(&[&event(1, "231", 10, 0, ""), &open(2)]);
+        assert_eq!(data(&process_exit, "process"), 2);
+    }
+
+    #[test]
+    fn time_keeps_fraction() {
+        let trs = run::<AuditdEvent>(&[&open(1)]);
+        let time = trs.iter().find_map(|tr| match tr {
+            DBTr::CreateNode(Node::Ctx(c)) => c.cont.get("time").cloned(),
+            _ => None,
+        });
+        assert_eq!(time, Some("2018-07-31T14:10:00.125+00:00".to_string()));
+    }
+
+    #[test]
+    fn rename_moves_name() {
+        let trs = run::<AuditdEvent>(&[&event(
+            1,
+            r#""rename""#,
+            10,
+            0,
+            r#", "PATH": [
+                {"name": "/tmp/", "inode": 1, "dev": "fd:01", "nametype": "PARENT"},
+                {"name": "/tmp/", "inode": 1, "dev": "fd:01", "nametype": "PARENT"},
+                {"name": "/tmp/a", "inode": 5, "dev": "fd:01", "nametype": "DELETE"},
+                {"name": "/tmp/b", "inode": 5, "dev": "fd:01", "nametype": "CREATE"}]"#,
+        )]);
+        assert_eq!(data(&trs, "file"), 1);
+        let ended = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::UpdateRel(Rel::Named(n)) | DBTr::CreateRel(Rel::Named(n))
+                    if n.end != ID::new(0) =>
+                {
+                    Some(n)
+                }
+                _ => None,
+            })
+            .count();
+        assert_eq!(ended, 1);
+    }
+}
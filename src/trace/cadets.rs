@@ -2,7 +2,7 @@
 //!
 //! This module contains the definition of the PVM mapping for the CADETS trace format.
 
-use std::fmt;
+use std::{collections::HashMap, convert::TryFrom, fmt, str::FromStr};
 
 use crate::{
     data::{
@@ -11,19 +11,20 @@ use crate::{
     },
     ingest::{
         pvm::{ConnectDir, PVMError, PVMResult, PVMTransaction, PVM},
-        Mapped,
+        IngestOptions, Mapped, TraceOffset,
     },
-    trace::MapFmt,
+    trace::{parse_ts, MapFmt, RawTs},
 };
 
-use chrono::{serde::ts_nanoseconds, DateTime, Utc};
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use maplit::hashmap;
 use serde_derive::Deserialize;
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
 lazy_static! {
-    static ref PROCESS: ConcreteType = ConcreteType {
+    pub(crate) static ref PROCESS: ConcreteType = ConcreteType {
         pvm_ty: Actor,
         name: "process",
         props: hashmap!("euid" => true,
@@ -34,21 +35,30 @@ lazy_static! {
                         "sgid" => true,
                         "pid" => false,
                         "cmdline" => true,
+                        "argv" => true,
+                        "exit_status" => false,
+                        "exit_signal" => false,
+                        "root" => true,
+                        "jail" => true,
+                        "env" => true,
                         "login_name" => true),
     };
-    static ref FILE: ConcreteType = ConcreteType {
+    pub(crate) static ref FILE: ConcreteType = ConcreteType {
         pvm_ty: Store,
         name: "file",
         props: hashmap!("owner_uid" => true,
                         "owner_gid" => true,
-                        "mode" => true),
+                        "mode" => true,
+                        "file_flags" => true,
+                        "times_set" => true,
+                        "extattr" => true),
     };
-    static ref SOCKET: ConcreteType = ConcreteType {
+    pub(crate) static ref SOCKET: ConcreteType = ConcreteType {
         pvm_ty: Conduit,
         name: "socket",
-        props: hashmap!(),
+        props: hashmap!("state" => false),
     };
-    static ref PIPE: ConcreteType = ConcreteType {
+    pub(crate) static ref PIPE: ConcreteType = ConcreteType {
         pvm_ty: Conduit,
         name: "pipe",
         props: hashmap!(),
@@ -60,19 +70,174 @@ lazy_static! {
                         "owner_gid" => true,
                         "mode" => true),
     };
+    static ref DIRECTORY: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "directory",
+        props: hashmap!("owner_uid" => true,
+                        "owner_gid" => true,
+                        "mode" => true),
+    };
+    static ref SYMLINK: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "symlink",
+        props: hashmap!("target" => false),
+    };
+    static ref THREAD: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "thread",
+        props: hashmap!("tid" => false),
+    };
+    static ref FILESYSTEM: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "filesystem",
+        props: hashmap!("fstype" => false,
+                        "source" => false),
+    };
+    static ref KQUEUE: ConcreteType = ConcreteType {
+        pvm_ty: Conduit,
+        name: "kqueue",
+        props: hashmap!(),
+    };
+    static ref SHM: ConcreteType = ConcreteType {
+        pvm_ty: Conduit,
+        name: "shm",
+        props: hashmap!(),
+    };
     static ref CTX: ContextType = ContextType {
         name: "cadets_context",
-        props: vec!["time", "event", "host", "trace_offset"],
+        props: vec!["time", "event", "host", "trace_offset", "trace_source"],
     };
 }
 
+/// The object uuid in a field of an event, returning from the handler if the object is absent
+///
+/// The nil uuid is taken as no object, see `PVMTransaction::non_nil`, so only what the handler
+/// does with the object is skipped and the rest of the record is kept. Given an `Option`
+/// instead, returns from the handler on `None`.
+macro_rules! object {
+    ($pvm:ident, $TR:ident. $F:ident) => {
+        object!($pvm.non_nil(field!($TR.$F)))
+    };
+    ($e:expr) => {
+        match $e {
+            Some(o) => o,
+            None => return Ok(()),
+        }
+    };
+}
+
+/// The fcntl command duplicating a descriptor, as numbered by FreeBSD
+const F_DUPFD: i32 = 0;
+/// As `F_DUPFD`, setting close-on-exec on the new descriptor
+const F_DUPFD_CLOEXEC: i32 = 17;
+
+/// Release of the CADETS schema a trace was recorded with
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CadetsVersion {
+    /// Recognise the schema of each record from the fields it carries
+    Auto,
+    /// The schema of the first CADETS releases
+    V1,
+    /// The current schema, as deserialised by `AuditEvent`
+    V2,
+}
+
+impl Default for CadetsVersion {
+    fn default() -> Self {
+        CadetsVersion::Auto
+    }
+}
+
+impl FromStr for CadetsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "auto" => Ok(CadetsVersion::Auto),
+            "v1" => Ok(CadetsVersion::V1),
+            "v2" => Ok(CadetsVersion::V2),
+            _ => Err(format!("Unknown CADETS version {}", s)),
+        }
+    }
+}
+
+/// Environment variables recorded from exec, as those shaping what the new image runs
+const EXEC_ENV_VARS: &[&str] = &[
+    "PATH",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+    "HOME",
+    "USER",
+    "PWD",
+    "SHELL",
+];
+
+/// Fields renamed since V1, by their V1 and current names
+const V1_FIELDS: &[(&str, &str)] = &[
+    ("proc_uuid", "subjprocuuid"),
+    ("thread_uuid", "subjthruuid"),
+    ("host_uuid", "host"),
+    ("ret", "retval"),
+    ("path", "upath1"),
+    ("path2", "upath2"),
+    ("arg_objuuid", "arg_objuuid1"),
+    ("ret_objuuid", "ret_objuuid1"),
+    ("ret_fd", "ret_fd1"),
+];
+
+/// Whether a record uses the V1 schema, recognised by its subject process field
+fn is_v1(rec: &Map<String, Value>) -> bool {
+    rec.contains_key("proc_uuid") && !rec.contains_key("subjprocuuid")
+}
+
+/// Rename the fields of a V1 record, V1 audit event names also lack the trailing ':'
+fn upgrade_v1(rec: &mut Map<String, Value>) {
+    for (old, new) in V1_FIELDS {
+        if let Some(val) = rec.remove(*old) {
+            rec.entry(*new).or_insert(val);
+        }
+    }
+    if let Some(Value::String(evt)) = rec.get_mut("event") {
+        if evt.starts_with("audit:") && !evt.ends_with(':') {
+            evt.push(':');
+        }
+    }
+}
+
+fn needs_upgrade(line: &str, opts: &IngestOptions) -> bool {
+    match opts.cadets_version {
+        CadetsVersion::Auto => line.contains("\"proc_uuid\""),
+        CadetsVersion::V1 => true,
+        CadetsVersion::V2 => false,
+    }
+}
+
+fn upgrade(val: &mut Value, opts: &IngestOptions) {
+    if let Value::Object(rec) = val {
+        match opts.cadets_version {
+            CadetsVersion::Auto if is_v1(rec) => upgrade_v1(rec),
+            CadetsVersion::V1 => upgrade_v1(rec),
+            _ => {}
+        }
+    }
+}
+
+/// The time of a record, in the encoding given by the ingest options
+fn record_time(evt: &str, raw: &RawTs, pvm: &PVM) -> PVMResult<DateTime<Utc>> {
+    parse_ts(raw, pvm.options().ts_format).map_err(|_| PVMError::MissingField {
+        evt: evt.to_string(),
+        field: "time",
+    })
+}
+
 /// An Audit event
 #[derive(Deserialize, Debug)]
 pub struct AuditEvent {
-    pub offset: Option<usize>,
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
     pub event: String,
-    #[serde(with = "ts_nanoseconds")]
-    pub time: DateTime<Utc>,
+    pub time: RawTs,
     pub pid: i32,
     pub ppid: i32,
     pub tid: i32,
@@ -109,6 +274,16 @@ pub struct AuditEvent {
     pub arg_sgid: Option<i64>,
     pub login: Option<String>,
     pub mode: Option<u32>,
+    pub fcntl_cmd: Option<i32>,
+    pub arg_pid: Option<i32>,
+    pub argv: Option<Vec<String>>,
+    pub envp: Option<Vec<String>>,
+    pub exit_status: Option<i32>,
+    pub fstype: Option<String>,
+    pub sbytes: Option<i64>,
+    pub attrname: Option<String>,
+    pub jid: Option<i32>,
+    pub signum: Option<i32>,
 }
 
 impl fmt::Display for AuditEvent {
@@ -154,6 +329,7 @@ impl fmt::Display for AuditEvent {
             self.arg_sgid,
             self.login,
             self.mode,
+            self.fcntl_cmd,
         );
         ret.finish()
     }
@@ -182,18 +358,109 @@ impl AuditEvent {
         }
     }
 
+    /// The descriptor a call returned, in `ret_fd1` or as its return value
+    fn ret_fd(&self) -> Option<i32> {
+        self.ret_fd1
+            .or_else(|| Some(self.retval).filter(|fd| *fd >= 0))
+    }
+
+    /// The actor holding the descriptor table, which threads share with their process
+    fn fd_owner(&self, act: ID, pvm: &PVMTransaction) -> ID {
+        pvm.lookup(&self.subjprocuuid).unwrap_or(act)
+    }
+
+    /// The actor for the thread making the call, declared on first sight as sharing the address
+    /// space of its process. Single threaded processes may report the process uuid for the
+    /// thread, in which case the process acts itself.
+    fn thread(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        if self.subjthruuid.is_nil() || self.subjthruuid == self.subjprocuuid {
+            return Ok(pro);
+        }
+        if let Some(thr) = pvm.lookup(&self.subjthruuid) {
+            return Ok(thr);
+        }
+        let thr = pvm.declare(
+            &THREAD,
+            self.subjthruuid,
+            Some(hashmap!("tid" => self.tid.to_string())),
+        )?;
+        pvm.source(thr, pro)?;
+        pvm.sink(thr, pro)?;
+        Ok(thr)
+    }
+
+    /// The object an event operates on, by its uuid or else through the descriptor it was given
+    ///
+    /// Events carrying both also refresh the binding of the descriptor. `None` if the uuid is
+    /// nil and the descriptor, if any, is not bound.
+    fn arg_object(
+        &self,
+        pro: ID,
+        ty: &'static ConcreteType,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<Option<ID>> {
+        match (self.arg_objuuid1, self.fd) {
+            (Some(uuid), fd) => {
+                let uuid = match pvm.non_nil(uuid) {
+                    Some(uuid) => uuid,
+                    None => return Ok(fd.and_then(|fd| pvm.fd(self.fd_owner(pro, pvm), fd))),
+                };
+                let o = pvm.declare(ty, uuid, None)?;
+                if let Some(fd) = fd {
+                    pvm.bind_fd(self.fd_owner(pro, pvm), fd, o);
+                }
+                Ok(Some(o))
+            }
+            (None, Some(fd)) => {
+                pvm.fd(self.fd_owner(pro, pvm), fd)
+                    .map(Some)
+                    .ok_or(PVMError::MissingField {
+                        evt: self.event.clone(),
+                        field: "arg_objuuid1",
+                    })
+            }
+            (None, None) => Err(PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "arg_objuuid1",
+            }),
+        }
+    }
+
+    /// Record the arguments and, if enabled, the selected environment of a new process image
+    ///
+    /// Both are stored as JSON arrays, as neither joins back unambiguously from a string.
+    fn exec_meta(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(ref argv) = self.argv {
+            pvm.meta(pro, "argv", &serde_json::to_string(argv).unwrap())?;
+        }
+        if let (Some(envp), true) = (&self.envp, pvm.options().cadets_env) {
+            let env: Vec<&String> = envp
+                .iter()
+                .filter(|var| match var.find('=') {
+                    Some(i) => EXEC_ENV_VARS.contains(&&var[..i]),
+                    None => false,
+                })
+                .collect();
+            pvm.meta(pro, "env", &serde_json::to_string(&env).unwrap())?;
+        }
+        Ok(())
+    }
+
     fn posix_exec(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
         let cmdline = field!(&self.cmdline);
         let binuuid = field!(self.arg_objuuid1);
         let binname = field!(self.upath1);
 
-        let bin = pvm.declare(&FILE, binuuid, None)?;
-        pvm.name(bin, Name::Path(binname))?;
-
         pvm.meta(pro, "cmdline", cmdline)?;
-        pvm.source(pro, bin)?;
+        self.exec_meta(pro, pvm)?;
 
-        if let Some(lduuid) = self.arg_objuuid2 {
+        if let Some(binuuid) = pvm.non_nil(binuuid) {
+            let bin = pvm.declare(&FILE, binuuid, None)?;
+            pvm.name(bin, Name::Path(binname))?;
+            pvm.source(pro, bin)?;
+        }
+
+        if let Some(lduuid) = self.arg_objuuid2.and_then(|u| pvm.non_nil(u)) {
             let ldname = field!(self.upath2);
 
             let ld = pvm.declare(&FILE, lduuid, None)?;
@@ -206,34 +473,131 @@ impl AuditEvent {
     }
 
     fn posix_fork(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let ret_objuuid1 = field!(self.ret_objuuid1);
+        let ret_objuuid1 = object!(pvm, self.ret_objuuid1);
 
         let ch = pvm.derive(pro, ret_objuuid1)?;
 
         pvm.meta(ch, "pid", &self.retval)?;
         pvm.source(ch, pro)?;
+        pvm.inherit_fds(pro, ch);
+        pvm.bind_pid(field!(self.host), self.retval, ch);
+        Ok(())
+    }
+
+    fn posix_posix_spawn(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let ret_objuuid1 = object!(pvm, self.ret_objuuid1);
+        let binuuid = field!(self.arg_objuuid1);
+        let binname = field!(self.upath1);
+
+        let ch = pvm.derive(pro, ret_objuuid1)?;
+        pvm.meta(ch, "pid", &self.retval)?;
+        pvm.source(ch, pro)?;
+        pvm.inherit_fds(pro, ch);
+        pvm.bind_pid(field!(self.host), self.retval, ch);
+
+        if let Some(cmdline) = &self.cmdline {
+            pvm.meta(ch, "cmdline", cmdline)?;
+        }
+        self.exec_meta(ch, pvm)?;
+
+        if let Some(binuuid) = pvm.non_nil(binuuid) {
+            let bin = pvm.declare(&FILE, binuuid, None)?;
+            pvm.name(bin, Name::Path(binname))?;
+            pvm.source(ch, bin)?;
+        }
+
         Ok(())
     }
 
-    fn posix_exit(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        pvm.release(&self.subjprocuuid);
+    /// An exited process is released, its final node is kept under its pid for wait to link
+    ///
+    /// The exit status recorded is the low byte of the argument to `exit`, as a waiting parent
+    /// would see it.
+    fn posix_exit(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(status) = self.exit_status {
+            pvm.meta(pro, "exit_status", &(status & 0xff))?;
+        }
+        pvm.exit_pid(field!(self.host), self.pid, pro);
         Ok(())
     }
 
-    fn posix_open(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        if let Some(fuuid) = self.ret_objuuid1 {
+    /// The parent learns how the reaped child ended
+    ///
+    /// Waits reporting a stopped or continued child leave it in place, as do WNOHANG polls that
+    /// found nothing to reap. A child whose exit was not traced is released here instead, with
+    /// the status decoded as FreeBSD's `sys/wait.h` encodes it: the code it exited with, or the
+    /// signal that killed it.
+    fn posix_wait(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if self.retval <= 0 {
+            return Ok(());
+        }
+        let host = field!(self.host);
+        if let Some(ch) = pvm.reap_pid(host, self.retval) {
+            pvm.source(pro, ch)?;
+            return Ok(());
+        }
+        let ch = match pvm.pid(host, self.retval) {
+            Some(ch) => ch,
+            None => return Ok(()),
+        };
+        pvm.source(pro, ch)?;
+        if let Some(status) = self.exit_status {
+            // stopped, or continued (_WCONTINUED)
+            if status & 0x7f == 0x7f || status == 0x13 {
+                return Ok(());
+            }
+            match status & 0x7f {
+                0 => pvm.meta(ch, "exit_status", &((status >> 8) & 0xff))?,
+                sig => pvm.meta(ch, "exit_signal", &sig)?,
+            }
+        }
+        if let Some(chuuid) = pvm.unbind_pid(host, self.retval) {
+            pvm.release(&chuuid);
+        }
+        Ok(())
+    }
+
+    /// The other process a successful call targets by `arg_pid`, found among the processes seen on
+    /// the same host. Process group and broadcast targets are not attributed.
+    fn arg_process(&self, pvm: &mut PVMTransaction) -> PVMResult<Option<ID>> {
+        let target = field!(self.arg_pid);
+        if self.retval != 0 || target <= 0 || target == self.pid {
+            return Ok(None);
+        }
+        Ok(pvm.pid(field!(self.host), target))
+    }
+
+    /// A delivered signal lets the sender influence the target
+    fn posix_kill(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(tgt) = self.arg_process(pvm)? {
+            pvm.source(tgt, pro)?;
+        }
+        Ok(())
+    }
+
+    /// A tracer can rewrite the memory and registers of the tracee, so it sinks into it
+    fn posix_ptrace(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(tgt) = self.arg_process(pvm)? {
+            pvm.sink(pro, tgt)?;
+        }
+        Ok(())
+    }
+
+    fn posix_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(fuuid) = self.ret_objuuid1.and_then(|u| pvm.non_nil(u)) {
             let fname = field!(self.upath1);
 
             let f = pvm.declare(&FILE, fuuid, None)?;
-            pvm.name(f, Name::Path(fname))?;
+            pvm.name_by(f, Name::Path(fname), pro)?;
+            if let Some(fd) = self.ret_fd() {
+                pvm.bind_fd(self.fd_owner(pro, pvm), fd, f);
+            }
         }
         Ok(())
     }
 
     fn posix_read(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
-
-        let f = pvm.declare(&FILE, fuuid, None)?;
+        let f = object!(self.arg_object(pro, &FILE, pvm)?);
         if let Some(pth) = self.fdpath.clone() {
             if pth != "<unknown>" {
                 pvm.name(f, Name::Path(pth))?;
@@ -244,9 +608,7 @@ impl AuditEvent {
     }
 
     fn posix_write(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
-
-        let f = pvm.declare(&FILE, fuuid, None)?;
+        let f = object!(self.arg_object(pro, &FILE, pvm)?);
         if let Some(pth) = self.fdpath.clone() {
             if pth != "<unknown>" {
                 pvm.name(f, Name::Path(pth))?;
@@ -257,50 +619,110 @@ impl AuditEvent {
     }
 
     fn posix_close(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        if let Some(fuuid) = self.arg_objuuid1 {
-            let f = pvm.declare(&FILE, fuuid, None)?;
+        let f = match self.arg_objuuid1.map(|u| pvm.non_nil(u)) {
+            Some(Some(fuuid)) => Some(pvm.declare(&FILE, fuuid, None)?),
+            Some(None) => None,
+            None => self.fd.and_then(|fd| pvm.fd(self.fd_owner(pro, pvm), fd)),
+        };
+        if let Some(f) = f {
             pvm.sinkend(pro, f)?;
+            if pvm.type_of(f).name == SOCKET.name {
+                pvm.meta(f, "state", "closed")?;
+            }
+        }
+        if let Some(fd) = self.fd {
+            pvm.close_fd(self.fd_owner(pro, pvm), fd);
         }
         Ok(())
     }
 
-    fn posix_socket(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.ret_objuuid1);
-        pvm.declare(&SOCKET, suuid, None)?;
+    fn posix_dup(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let fd = field!(self.fd);
+        if self.retval >= 0 {
+            pvm.dup_fd(self.fd_owner(pro, pvm), fd, self.retval);
+        }
+        Ok(())
+    }
+
+    fn posix_fcntl(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        match self.fcntl_cmd {
+            Some(F_DUPFD) | Some(F_DUPFD_CLOEXEC) => self.posix_dup(pro, pvm),
+            _ => Ok(()),
+        }
+    }
+
+    fn posix_socket(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let suuid = object!(pvm, self.ret_objuuid1);
+        let s = pvm.declare(&SOCKET, suuid, None)?;
+        if let Some(fd) = self.ret_fd() {
+            pvm.bind_fd(self.fd_owner(pro, pvm), fd, s);
+        }
         Ok(())
     }
 
     fn posix_listen(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
+        let suuid = object!(pvm, self.arg_objuuid1);
         pvm.declare(&SOCKET, suuid, None)?;
         Ok(())
     }
 
     fn posix_bind(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
+        let suuid = object!(pvm, self.arg_objuuid1);
         let s = pvm.declare(&SOCKET, suuid, None)?;
         pvm.name(s, self.sock_name()?)?;
         Ok(())
     }
 
-    fn posix_accept(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let luuid = field!(self.arg_objuuid1);
-        let ruuid = field!(self.ret_objuuid1);
-        pvm.declare(&SOCKET, luuid, None)?;
+    fn posix_accept(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(luuid) = pvm.non_nil(field!(self.arg_objuuid1)) {
+            pvm.declare(&SOCKET, luuid, None)?;
+        }
+        let ruuid = object!(pvm, self.ret_objuuid1);
         let r = pvm.declare(&SOCKET, ruuid, None)?;
         pvm.name(r, self.sock_name()?)?;
+        pvm.meta(r, "state", "connected")?;
+        if let Some(fd) = self.ret_fd() {
+            pvm.bind_fd(self.fd_owner(pro, pvm), fd, r);
+        }
         Ok(())
     }
 
     fn posix_connect(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
+        let suuid = object!(pvm, self.arg_objuuid1);
         let s = pvm.declare(&SOCKET, suuid, None)?;
         pvm.name(s, self.sock_name()?)?;
+        pvm.meta(s, "state", "connected")?;
         Ok(())
     }
 
+    /// Names of a socket outlive its connection, so the context of each change of `state` is what
+    /// bounds the lifetime of the connection
+    fn posix_shutdown(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let s = object!(self.arg_object(pro, &SOCKET, pvm)?);
+        pvm.meta(s, "state", "shutdown")?;
+        Ok(())
+    }
+
+    /// Whether an mmap has no backing file
+    ///
+    /// Anonymous mappings carry MAP_ANON in their flags, but not every trace records the flags,
+    /// so a missing object uuid is treated as anonymous too.
+    fn is_anon_mmap(&self) -> bool {
+        let anon_flag = |flags: &Option<Vec<String>>| {
+            flags.as_ref().map_or(false, |f| {
+                f.iter().any(|f| f == "MAP_ANON" || f == "MAP_ANONYMOUS")
+            })
+        };
+        anon_flag(&self.arg_sharing_flags)
+            || anon_flag(&self.arg_mem_flags)
+            || self.arg_objuuid1.is_none()
+    }
+
     fn posix_mmap(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        if self.is_anon_mmap() {
+            return Ok(());
+        }
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let mut f = pvm.declare(&FILE, fuuid, None)?;
         if let Some(fdpath) = self.fdpath.clone() {
             pvm.name(f, Name::Path(fdpath))?;
@@ -325,27 +747,43 @@ impl AuditEvent {
         Ok(())
     }
 
-    fn posix_socketpair(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let ruuid1 = field!(self.ret_objuuid1);
-        let ruuid2 = field!(self.ret_objuuid2);
-        let s1 = pvm.declare(&SOCKET, ruuid1, None)?;
-        let s2 = pvm.declare(&SOCKET, ruuid2, None)?;
-        pvm.connect(s1, s2, ConnectDir::BiDirectional)?;
+    /// Declare the two connected ends returned by pipe or socketpair and bind their descriptors
+    ///
+    /// An end given by the nil uuid is left out, along with the connection between the two.
+    fn ret_pair(
+        &self,
+        pro: ID,
+        ty: &'static ConcreteType,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        let uuids = [field!(self.ret_objuuid1), field!(self.ret_objuuid2)];
+        let mut ends = [None, None];
+        for (end, uuid) in ends.iter_mut().zip(&uuids) {
+            if let Some(uuid) = pvm.non_nil(*uuid) {
+                *end = Some(pvm.declare(ty, uuid, None)?);
+            }
+        }
+        if let [Some(e1), Some(e2)] = ends {
+            pvm.connect(e1, e2, ConnectDir::BiDirectional)?;
+        }
+        for (end, fd) in ends.iter().zip(&[self.ret_fd1, self.ret_fd2]) {
+            if let (Some(end), Some(fd)) = (end, fd) {
+                pvm.bind_fd(self.fd_owner(pro, pvm), *fd, *end);
+            }
+        }
         Ok(())
     }
 
-    fn posix_pipe(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let ruuid1 = field!(self.ret_objuuid1);
-        let ruuid2 = field!(self.ret_objuuid2);
-        let p1 = pvm.declare(&PIPE, ruuid1, None)?;
-        let p2 = pvm.declare(&PIPE, ruuid2, None)?;
-        pvm.connect(p1, p2, ConnectDir::BiDirectional)?;
-        Ok(())
+    fn posix_socketpair(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        self.ret_pair(pro, &SOCKET, pvm)
+    }
+
+    fn posix_pipe(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        self.ret_pair(pro, &PIPE, pvm)
     }
 
     fn posix_sendmsg(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
-        let s = pvm.declare(&SOCKET, suuid, None)?;
+        let s = object!(self.arg_object(pro, &SOCKET, pvm)?);
         if let Some(n) = self.opt_sock_name()? {
             pvm.name(s, n)?;
         }
@@ -354,8 +792,7 @@ impl AuditEvent {
     }
 
     fn posix_sendto(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
-        let s = pvm.declare(&SOCKET, suuid, None)?;
+        let s = object!(self.arg_object(pro, &SOCKET, pvm)?);
         if let Some(n) = self.opt_sock_name()? {
             pvm.name(s, n)?;
         }
@@ -363,9 +800,25 @@ impl AuditEvent {
         Ok(())
     }
 
-    fn posix_recvmsg(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
+    /// The kernel copies from the file to the socket directly, which is recorded as the process
+    /// reading the one and writing the other. The byte count comes back through `sbytes` rather
+    /// than the return value, and a call that fails part way, such as when interrupted, still
+    /// reports what it sent there. Nothing is recorded for calls that sent nothing.
+    fn posix_sendfile(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let sbytes = match self.sbytes {
+            Some(sbytes) if sbytes > 0 => sbytes,
+            _ => return Ok(()),
+        };
+        let f = object!(self.arg_object(pro, &FILE, pvm)?);
+        let suuid = object!(pvm, self.arg_objuuid2);
         let s = pvm.declare(&SOCKET, suuid, None)?;
+        pvm.source_nbytes(pro, f, sbytes)?;
+        pvm.sinkstart_nbytes(pro, s, sbytes)?;
+        Ok(())
+    }
+
+    fn posix_recvmsg(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let s = object!(self.arg_object(pro, &SOCKET, pvm)?);
         if let Some(n) = self.opt_sock_name()? {
             pvm.name(s, n)?;
         }
@@ -374,8 +827,7 @@ impl AuditEvent {
     }
 
     fn posix_recvfrom(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let suuid = field!(self.arg_objuuid1);
-        let s = pvm.declare(&SOCKET, suuid, None)?;
+        let s = object!(self.arg_object(pro, &SOCKET, pvm)?);
         if let Some(n) = self.opt_sock_name()? {
             pvm.name(s, n)?;
         }
@@ -384,7 +836,7 @@ impl AuditEvent {
     }
 
     fn posix_chdir(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let duuid = field!(self.arg_objuuid1);
+        let duuid = object!(pvm, self.arg_objuuid1);
         let d = pvm.declare(&FILE, duuid, None)?;
         if let Some(dpath) = self.upath1.clone() {
             pvm.name(d, Name::Path(dpath))?;
@@ -393,7 +845,7 @@ impl AuditEvent {
     }
 
     fn posix_chmod(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let fpath = field!(self.upath1);
         let mode = field!(self.mode);
         let f = pvm.declare(&FILE, fuuid, None)?;
@@ -404,7 +856,7 @@ impl AuditEvent {
     }
 
     fn posix_chown(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let fpath = field!(self.upath1);
         let arg_uid = field!(self.arg_uid);
         let arg_gid = field!(self.arg_gid);
@@ -417,7 +869,7 @@ impl AuditEvent {
     }
 
     fn posix_fchmod(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let mode = field!(self.mode);
         let f = pvm.declare(&FILE, fuuid, None)?;
         pvm.meta(f, "mode", &format!("{:o}", mode))?;
@@ -426,7 +878,7 @@ impl AuditEvent {
     }
 
     fn posix_fchown(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let arg_uid = field!(self.arg_uid);
         let arg_gid = field!(self.arg_gid);
         let f = pvm.declare(&FILE, fuuid, None)?;
@@ -436,44 +888,290 @@ impl AuditEvent {
         Ok(())
     }
 
+    /// Set an attribute of a file, by path or descriptor, as a write to it
+    fn file_attr<T: ToString + ?Sized>(
+        &self,
+        pro: ID,
+        key: &'static str,
+        val: &T,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        let f = object!(self.arg_object(pro, &FILE, pvm)?);
+        if let Some(fpath) = self.upath1.clone() {
+            pvm.name(f, Name::Path(fpath))?;
+        }
+        pvm.meta(f, key, val)?;
+        pvm.sink(pro, f)?;
+        Ok(())
+    }
+
+    fn posix_chflags(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let flags = field!(self.flags);
+        self.file_attr(pro, "file_flags", &format!("{:#x}", flags), pvm)
+    }
+
+    /// The requested times are not in the trace, so the time they were set at is recorded, which
+    /// is enough to tell a file was backdated
+    fn posix_utimes(
+        &self,
+        pro: ID,
+        time: DateTime<Utc>,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        self.file_attr(pro, "times_set", &time.to_rfc3339(), pvm)
+    }
+
+    fn posix_extattr_set(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let attrname = field!(&self.attrname);
+        self.file_attr(pro, "extattr", attrname, pvm)
+    }
+
     fn posix_posix_openpt(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let ttyuuid = field!(self.ret_objuuid1);
+        let ttyuuid = object!(pvm, self.ret_objuuid1);
         pvm.declare(&PTTY, ttyuuid, None)?;
         Ok(())
     }
 
-    fn posix_link(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+    fn posix_kqueue(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let kquuid = object!(pvm, self.ret_objuuid1);
+        let kq = pvm.declare(&KQUEUE, kquuid, None)?;
+        if let Some(fd) = self.ret_fd() {
+            pvm.bind_fd(self.fd_owner(pro, pvm), fd, kq);
+        }
+        Ok(())
+    }
+
+    /// The change list is not in the trace, so every call is taken to register filters with the
+    /// queue, and those returning events to have read from it
+    fn posix_kevent(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if self.retval < 0 {
+            return Ok(());
+        }
+        let kq = object!(self.arg_object(pro, &KQUEUE, pvm)?);
+        pvm.sink(pro, kq)?;
+        if self.retval > 0 {
+            pvm.source(pro, kq)?;
+        }
+        Ok(())
+    }
+
+    fn posix_shmget(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let shmuuid = self
+            .ret_objuuid1
+            .or(self.arg_objuuid1)
+            .ok_or(PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "ret_objuuid1",
+            })?;
+        let shmuuid = object!(pvm.non_nil(shmuuid));
+        pvm.declare(&SHM, shmuuid, None)?;
+        Ok(())
+    }
+
+    /// Attaching a segment lets the process both read and write it, unless read only
+    fn posix_shmat(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let shmuuid = object!(pvm, self.arg_objuuid1);
+        let shm = pvm.declare(&SHM, shmuuid, None)?;
+        pvm.source(pro, shm)?;
+        let rdonly = match self.arg_mem_flags {
+            Some(ref flags) => flags.iter().any(|f| f == "SHM_RDONLY"),
+            None => false,
+        };
+        if !rdonly {
+            pvm.sink(pro, shm)?;
+        }
+        Ok(())
+    }
+
+    /// Data only moves once the object is mapped, which is recorded by mmap on the descriptor
+    fn posix_shm_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let shmuuid = object!(pvm, self.ret_objuuid1);
+        let shm = pvm.declare(&SHM, shmuuid, None)?;
+        if let Some(shmname) = self.upath1.clone() {
+            pvm.name(shm, Name::Path(shmname))?;
+        }
+        if let Some(fd) = self.ret_fd() {
+            pvm.bind_fd(self.fd_owner(pro, pvm), fd, shm);
+        }
+        Ok(())
+    }
+
+    /// Posting and waiting on a named semaphore are not audited, so the opener is taken to do both
+    fn posix_sem_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let semuuid = object!(pvm, self.ret_objuuid1);
+        let sem = pvm.declare(&SHM, semuuid, None)?;
+        if let Some(semname) = self.upath1.clone() {
+            pvm.name(sem, Name::Path(semname))?;
+        }
+        pvm.source(pro, sem)?;
+        pvm.sink(pro, sem)?;
+        Ok(())
+    }
+
+    fn posix_link(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let upath1 = field!(self.upath1);
         let upath2 = field!(self.upath2);
         let f = pvm.declare(&FILE, fuuid, None)?;
         pvm.name(f, Name::Path(upath1))?;
-        pvm.name(f, Name::Path(upath2))?;
+        pvm.name_by(f, Name::Path(upath2), pro)?;
         Ok(())
     }
 
-    fn posix_rename(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let src_uuid = field!(self.arg_objuuid1);
+    /// Create a symbolic link at `upath2` pointing to `upath1`
+    ///
+    /// The link is a store of its own holding the target path, so that later accesses made
+    /// through the link path can be told apart from those naming the target directly.
+    fn posix_symlink(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let luuid = self
+            .ret_objuuid1
+            .or(self.arg_objuuid1)
+            .ok_or(PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "ret_objuuid1",
+            })?;
+        let luuid = object!(pvm.non_nil(luuid));
+        let target = field!(self.upath1);
+        let lpath = field!(self.upath2);
+        let l = pvm.declare(&SYMLINK, luuid, Some(hashmap!("target" => target)))?;
+        pvm.name_by(l, Name::Path(lpath), pro)?;
+        Ok(())
+    }
+
+    fn posix_readlink(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let luuid = object!(pvm, self.arg_objuuid1);
+        let l = pvm.declare(&SYMLINK, luuid, None)?;
+        if let Some(lpath) = self.upath1.clone() {
+            pvm.name(l, Name::Path(lpath))?;
+        }
+        pvm.source_nbytes(pro, l, self.retval)?;
+        Ok(())
+    }
+
+    fn posix_rename(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let src_uuid = object!(pvm, self.arg_objuuid1);
         let src = field!(self.upath1);
         let dst = field!(self.upath2);
         let fsrc = pvm.declare(&FILE, src_uuid, None)?;
         pvm.unname(fsrc, Name::Path(src))?;
-        if let Some(ovr_uuid) = self.arg_objuuid2 {
+        if let Some(ovr_uuid) = self.arg_objuuid2.and_then(|u| pvm.non_nil(u)) {
             let fovr = pvm.declare(&FILE, ovr_uuid, None)?;
             pvm.unname(fovr, Name::Path(dst.clone()))?;
         }
-        pvm.name(fsrc, Name::Path(dst))?;
+        pvm.name_by(fsrc, Name::Path(dst), pro)?;
+        Ok(())
+    }
+
+    fn posix_mkdir(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let duuid = self
+            .ret_objuuid1
+            .or(self.arg_objuuid1)
+            .ok_or(PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "ret_objuuid1",
+            })?;
+        let duuid = object!(pvm.non_nil(duuid));
+        let dpath = field!(self.upath1);
+        let init = self
+            .mode
+            .map(|mode| hashmap!("mode" => format!("{:o}", mode)));
+        let d = pvm.declare(&DIRECTORY, duuid, init)?;
+        pvm.name_by(d, Name::Path(dpath), pro)?;
+        Ok(())
+    }
+
+    fn posix_rmdir(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let duuid = object!(pvm, self.arg_objuuid1);
+        let dpath = field!(self.upath1);
+        let d = pvm.declare(&DIRECTORY, duuid, None)?;
+        pvm.unname(d, Name::Path(dpath))?;
+        Ok(())
+    }
+
+    /// The uuid of whatever filesystem is mounted on a path of the host
+    ///
+    /// Unmounting releases it, so a later mount on the same path is a node of its own.
+    fn mount_uuid(&self, path: &str) -> PVMResult<Uuid> {
+        Ok(Uuid::new_v5(
+            &field!(self.host),
+            format!("mount:{}", path).as_bytes(),
+        ))
+    }
+
+    fn posix_mount(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if self.retval != 0 {
+            return Ok(());
+        }
+        let mntpath = field!(self.upath1);
+        let mut init = HashMap::new();
+        if let Some(fstype) = self.fstype.clone() {
+            init.insert("fstype", fstype);
+        }
+        if let Some(source) = self.upath2.clone() {
+            init.insert("source", source);
+        }
+        let fs = pvm.declare(&FILESYSTEM, self.mount_uuid(&mntpath)?, Some(init))?;
+        pvm.name_by(fs, Name::Path(mntpath), pro)?;
+        Ok(())
+    }
+
+    fn posix_unmount(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if self.retval != 0 {
+            return Ok(());
+        }
+        let mntpath = field!(self.upath1);
+        let fsuuid = self.mount_uuid(&mntpath)?;
+        if let Some(fs) = pvm.lookup(&fsuuid) {
+            pvm.unname(fs, Name::Path(mntpath))?;
+            pvm.release(&fsuuid);
+        }
+        Ok(())
+    }
+
+    /// Truncation replaces the whole content of the file, so it versions the file immediately
+    /// rather than joining any editing session open on it
+    fn posix_truncate(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let f = object!(self.arg_object(pro, &FILE, pvm)?);
+        if let Some(fpath) = self.upath1.clone() {
+            pvm.name(f, Name::Path(fpath))?;
+        }
+        pvm.sink(pro, f)?;
         Ok(())
     }
 
     fn posix_unlink(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
-        let fuuid = field!(self.arg_objuuid1);
+        let fuuid = object!(pvm, self.arg_objuuid1);
         let upath1 = field!(self.upath1);
         let f = pvm.declare(&FILE, fuuid, None)?;
         pvm.unname(f, Name::Path(upath1))?;
         Ok(())
     }
 
+    /// Containment is inherited over fork, so it is kept as heritable metadata of the process
+    /// rather than a node of its own
+    fn posix_chroot(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if self.retval != 0 {
+            return Ok(());
+        }
+        let root = field!(&self.upath1);
+        pvm.meta(pro, "root", root)?;
+        Ok(())
+    }
+
+    /// jail returns the id of the jail created, jail_attach is given that of an existing one
+    fn posix_jail(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let jid = match &self.event[..] {
+            "audit:event:aue_jail_attach:" if self.retval == 0 => field!(self.jid),
+            "audit:event:aue_jail:" if self.retval > 0 => self.retval,
+            _ => return Ok(()),
+        };
+        pvm.meta(pro, "jail", &jid)?;
+        if let Some(root) = &self.upath1 {
+            pvm.meta(pro, "root", root)?;
+        }
+        Ok(())
+    }
+
     fn posix_setuid(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
         let uid = field!(&self.arg_uid);
         pvm.meta(pro, "euid", uid)?;
@@ -565,14 +1263,19 @@ impl AuditEvent {
     }
 
     fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let time = record_time(&self.event, &self.time, pvm)?;
         let mut ctx = hashmap!(
             "event" => self.event.clone(),
             "host" => field!(self.host).to_hyphenated_ref().to_string(),
-            "time" => self.time.to_rfc3339(),
+            "time" => time.to_rfc3339(),
         );
-        if let Some(offset) = self.offset {
-            ctx.insert("trace_offset", offset.to_string());
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
         }
+        let threads = pvm.options().cadets_threads;
         let mut tr = pvm.transaction(&CTX, ctx);
         match {
             let pro = tr.declare(
@@ -581,39 +1284,79 @@ impl AuditEvent {
                 Some(hashmap!("cmdline" => self.exec.clone(),
                          "pid" => self.pid.to_string())),
             )?;
+            tr.bind_pid(field!(self.host), self.pid, pro);
+            // process wide state stays on the process, everything else is done by the thread
+            let act = if threads {
+                self.thread(pro, &mut tr)?
+            } else {
+                pro
+            };
             match &self.event[..] {
-                "audit:event:aue_accept:" => self.posix_accept(pro, &mut tr),
-                "audit:event:aue_bind:" => self.posix_bind(pro, &mut tr),
+                "audit:event:aue_accept:" => self.posix_accept(act, &mut tr),
+                "audit:event:aue_bind:" => self.posix_bind(act, &mut tr),
                 "audit:event:aue_chdir:" | "audit:event:aue_fchdir:" => {
-                    self.posix_chdir(pro, &mut tr)
+                    self.posix_chdir(act, &mut tr)
                 }
                 "audit:event:aue_chmod:" | "audit:event:aue_fchmodat:" => {
-                    self.posix_chmod(pro, &mut tr)
+                    self.posix_chmod(act, &mut tr)
                 }
-                "audit:event:aue_chown:" => self.posix_chown(pro, &mut tr),
-                "audit:event:aue_close:" => self.posix_close(pro, &mut tr),
-                "audit:event:aue_connect:" => self.posix_connect(pro, &mut tr),
+                "audit:event:aue_chflags:"
+                | "audit:event:aue_chflagsat:"
+                | "audit:event:aue_fchflags:"
+                | "audit:event:aue_lchflags:" => self.posix_chflags(act, &mut tr),
+                "audit:event:aue_chroot:" => self.posix_chroot(pro, &mut tr),
+                "audit:event:aue_chown:" => self.posix_chown(act, &mut tr),
+                "audit:event:aue_close:" => self.posix_close(act, &mut tr),
+                "audit:event:aue_connect:" => self.posix_connect(act, &mut tr),
                 "audit:event:aue_execve:" => self.posix_exec(pro, &mut tr),
                 "audit:event:aue_exit:" => self.posix_exit(pro, &mut tr),
                 "audit:event:aue_fork:" | "audit:event:aue_pdfork:" | "audit:event:aue_vfork:" => {
                     self.posix_fork(pro, &mut tr)
                 }
-                "audit:event:aue_fchmod:" => self.posix_fchmod(pro, &mut tr),
-                "audit:event:aue_fchown:" => self.posix_fchown(pro, &mut tr),
-                "audit:event:aue_link:" => self.posix_link(pro, &mut tr),
-                "audit:event:aue_listen:" => self.posix_listen(pro, &mut tr),
-                "audit:event:aue_mmap:" => self.posix_mmap(pro, &mut tr),
+                "audit:event:aue_extattr_set_fd:"
+                | "audit:event:aue_extattr_set_file:"
+                | "audit:event:aue_extattr_set_link:" => self.posix_extattr_set(act, &mut tr),
+                "audit:event:aue_fchmod:" => self.posix_fchmod(act, &mut tr),
+                "audit:event:aue_fchown:" => self.posix_fchown(act, &mut tr),
+                "audit:event:aue_fcntl:" => self.posix_fcntl(act, &mut tr),
+                "audit:event:aue_jail:" | "audit:event:aue_jail_attach:" => {
+                    self.posix_jail(pro, &mut tr)
+                }
+                "audit:event:aue_kevent:" => self.posix_kevent(act, &mut tr),
+                "audit:event:aue_kill:" => self.posix_kill(act, &mut tr),
+                "audit:event:aue_kqueue:" => self.posix_kqueue(act, &mut tr),
+                "audit:event:aue_link:" => self.posix_link(act, &mut tr),
+                "audit:event:aue_listen:" => self.posix_listen(act, &mut tr),
+                "audit:event:aue_mkdir:" | "audit:event:aue_mkdirat:" => {
+                    self.posix_mkdir(act, &mut tr)
+                }
+                "audit:event:aue_mount:" | "audit:event:aue_nmount:" => {
+                    self.posix_mount(act, &mut tr)
+                }
+                "audit:event:aue_mmap:" => self.posix_mmap(act, &mut tr),
                 "audit:event:aue_open_rwtc:" | "audit:event:aue_openat_rwtc:" => {
-                    self.posix_open(pro, &mut tr)
+                    self.posix_open(act, &mut tr)
                 }
-                "audit:event:aue_pipe:" => self.posix_pipe(pro, &mut tr),
-                "audit:event:aue_posix_openpt:" => self.posix_posix_openpt(pro, &mut tr),
-                "audit:event:aue_read:" | "audit:event:aue_pread:" => self.posix_read(pro, &mut tr),
-                "audit:event:aue_recvmsg:" => self.posix_recvmsg(pro, &mut tr),
-                "audit:event:aue_recvfrom:" => self.posix_recvfrom(pro, &mut tr),
-                "audit:event:aue_rename:" => self.posix_rename(pro, &mut tr),
-                "audit:event:aue_sendmsg:" => self.posix_sendmsg(pro, &mut tr),
-                "audit:event:aue_sendto:" => self.posix_sendto(pro, &mut tr),
+                "audit:event:aue_pipe:" => self.posix_pipe(act, &mut tr),
+                "audit:event:aue_posix_openpt:" => self.posix_posix_openpt(act, &mut tr),
+                "audit:event:aue_posix_spawn:" => self.posix_posix_spawn(pro, &mut tr),
+                "audit:event:aue_ptrace:" => self.posix_ptrace(act, &mut tr),
+                // retval of the vectored calls is the total across all buffers
+                "audit:event:aue_read:"
+                | "audit:event:aue_pread:"
+                | "audit:event:aue_readv:"
+                | "audit:event:aue_preadv:" => self.posix_read(act, &mut tr),
+                "audit:event:aue_recvmsg:" => self.posix_recvmsg(act, &mut tr),
+                "audit:event:aue_recvfrom:" => self.posix_recvfrom(act, &mut tr),
+                "audit:event:aue_readlink:" | "audit:event:aue_readlinkat:" => {
+                    self.posix_readlink(act, &mut tr)
+                }
+                "audit:event:aue_rename:" => self.posix_rename(act, &mut tr),
+                "audit:event:aue_rmdir:" => self.posix_rmdir(act, &mut tr),
+                "audit:event:aue_semopen:" => self.posix_sem_open(act, &mut tr),
+                "audit:event:aue_sendmsg:" => self.posix_sendmsg(act, &mut tr),
+                "audit:event:aue_sendfile:" => self.posix_sendfile(act, &mut tr),
+                "audit:event:aue_sendto:" => self.posix_sendto(act, &mut tr),
                 "audit:event:aue_setegid:" => self.posix_setegid(pro, &mut tr),
                 "audit:event:aue_seteuid:" => self.posix_seteuid(pro, &mut tr),
                 "audit:event:aue_setlogin:" => self.posix_setlogin(pro, &mut tr),
@@ -623,23 +1366,45 @@ impl AuditEvent {
                 "audit:event:aue_setresuid:" => self.posix_setresuid(pro, &mut tr),
                 "audit:event:aue_setreuid:" => self.posix_setreuid(pro, &mut tr),
                 "audit:event:aue_setuid:" => self.posix_setuid(pro, &mut tr),
-                "audit:event:aue_socket:" => self.posix_socket(pro, &mut tr),
-                "audit:event:aue_socketpair:" => self.posix_socketpair(pro, &mut tr),
-                "audit:event:aue_unlink:" => self.posix_unlink(pro, &mut tr),
+                // installing a handler only affects the caller, influence is recorded on delivery
+                "audit:event:aue_sigaction:" => Ok(()),
+                "audit:event:aue_shmat:" => self.posix_shmat(act, &mut tr),
+                "audit:event:aue_shmget:" => self.posix_shmget(act, &mut tr),
+                "audit:event:aue_shmopen:" => self.posix_shm_open(act, &mut tr),
+                "audit:event:aue_shutdown:" => self.posix_shutdown(act, &mut tr),
+                "audit:event:aue_socket:" => self.posix_socket(act, &mut tr),
+                "audit:event:aue_socketpair:" => self.posix_socketpair(act, &mut tr),
+                "audit:event:aue_symlink:" | "audit:event:aue_symlinkat:" => {
+                    self.posix_symlink(act, &mut tr)
+                }
+                "audit:event:aue_truncate:" | "audit:event:aue_ftruncate:" => {
+                    self.posix_truncate(act, &mut tr)
+                }
+                "audit:event:aue_umount:" | "audit:event:aue_unmount:" => {
+                    self.posix_unmount(act, &mut tr)
+                }
+                "audit:event:aue_unlink:" => self.posix_unlink(act, &mut tr),
+                "audit:event:aue_utimes:"
+                | "audit:event:aue_futimes:"
+                | "audit:event:aue_futimesat:"
+                | "audit:event:aue_lutimes:"
+                | "audit:event:aue_utimensat:"
+                | "audit:event:aue_futimens:" => self.posix_utimes(act, time, &mut tr),
+                "audit:event:aue_wait4:" | "audit:event:aue_wait6:" => {
+                    self.posix_wait(pro, &mut tr)
+                }
                 "audit:event:aue_write:"
                 | "audit:event:aue_pwrite:"
-                | "audit:event:aue_writev:" => self.posix_write(pro, &mut tr),
-                "audit:event:aue_dup2:" => Ok(()), /* IGNORE */
+                | "audit:event:aue_writev:"
+                | "audit:event:aue_pwritev:" => self.posix_write(act, &mut tr),
+                "audit:event:aue_dup:" | "audit:event:aue_dup2:" => self.posix_dup(act, &mut tr),
                 _ => {
-                    //tr.unparsed_events.insert(self.event.clone());
+                    tr.unparsed(&self.event);
                     Ok(())
                 }
             }
         } {
-            Ok(_) => {
-                tr.commit();
-                Ok(())
-            }
+            Ok(_) => tr.commit(),
             Err(e) => {
                 tr.rollback();
                 Err(e)
@@ -651,11 +1416,11 @@ impl AuditEvent {
 /// A FBT type event
 #[derive(Deserialize, Debug)]
 pub struct FBTEvent {
-    pub offset: Option<usize>,
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
     pub event: String,
     pub host: Uuid,
-    #[serde(with = "ts_nanoseconds")]
-    pub time: DateTime<Utc>,
+    pub time: RawTs,
     pub so_uuid: Uuid,
     pub lport: i32,
     pub fport: i32,
@@ -681,6 +1446,66 @@ impl fmt::Display for FBTEvent {
     }
 }
 
+impl FBTEvent {
+    fn update(&mut self) {
+        if !self.so_uuid.is_nil() {
+            self.so_uuid = Uuid::new_v5(&self.host, self.so_uuid.as_bytes());
+        }
+    }
+
+    /// The network name of one end of the socket, if that end is bound
+    fn endpoint(addr: &str, port: i32) -> Option<Name> {
+        match (addr, u16::try_from(port)) {
+            ("", _) | ("0.0.0.0", _) | ("::", _) | (_, Ok(0)) | (_, Err(_)) => None,
+            (addr, Ok(port)) => Some(Name::Net(addr.to_string(), port)),
+        }
+    }
+
+    /// Name the socket by both of its endpoints
+    ///
+    /// The two sockets of a connection traced on different hosts share both names, and once both
+    /// have been seen they are connected to each other.
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let time = record_time(&self.event, &self.time, pvm)?;
+        let mut ctx = hashmap!(
+            "event" => self.event.clone(),
+            "host" => self.host.to_hyphenated_ref().to_string(),
+            "time" => time.to_rfc3339(),
+        );
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let so_uuid = match tr.non_nil(self.so_uuid) {
+            Some(uuid) => uuid,
+            None => return tr.commit(),
+        };
+        let res = tr.declare(&SOCKET, so_uuid, None).and_then(|s| {
+            let local = FBTEvent::endpoint(&self.laddr, self.lport);
+            let remote = FBTEvent::endpoint(&self.faddr, self.fport);
+            for name in local.iter().chain(remote.iter()) {
+                tr.name(s, name.clone())?;
+            }
+            if let (Some(local), Some(remote)) = (local, remote) {
+                if let Some(peer) = tr.pair_conduit(local, remote, s) {
+                    tr.connect(s, peer, ConnectDir::BiDirectional)?;
+                }
+            }
+            Ok(())
+        });
+        match res {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
 /// A CADETS trace event
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
@@ -708,37 +1533,24 @@ impl fmt::Display for TraceEvent {
 
 impl Mapped for TraceEvent {
     fn init(pvm: &mut PVM) {
-        pvm.register_data_type(&PROCESS);
-        pvm.register_data_type(&FILE);
-        pvm.register_data_type(&SOCKET);
-        pvm.register_data_type(&PIPE);
-        pvm.register_data_type(&PTTY);
-        pvm.register_ctx_type(&CTX);
+        AuditEvent::init(pvm);
     }
 
     fn update(&mut self) {
-        if let TraceEvent::Audit(e) = self {
-            if let Some(host) = e.host {
-                let map_uuid = |u: Uuid| Uuid::new_v5(&host, u.as_bytes());
-
-                e.arg_objuuid1 = e.arg_objuuid1.map(map_uuid);
-                e.arg_objuuid2 = e.arg_objuuid2.map(map_uuid);
-                e.ret_objuuid1 = e.ret_objuuid1.map(map_uuid);
-                e.ret_objuuid2 = e.ret_objuuid2.map(map_uuid);
-                e.subjprocuuid = map_uuid(e.subjprocuuid);
-                e.subjthruuid = map_uuid(e.subjthruuid);
-            }
+        match self {
+            TraceEvent::Audit(e) => e.update(),
+            TraceEvent::FBT(e) => e.update(),
         }
     }
 
     fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
         match self {
             TraceEvent::Audit(box tr) => tr.parse(pvm),
-            TraceEvent::FBT(_) => Ok(()),
+            TraceEvent::FBT(e) => e.parse(pvm),
         }
     }
 
-    fn set_offset(&mut self, offset: usize) {
+    fn set_offset(&mut self, offset: TraceOffset) {
         match self {
             TraceEvent::Audit(e) => {
                 e.offset = Some(offset);
@@ -748,4 +1560,1009 @@ impl Mapped for TraceEvent {
             }
         }
     }
+
+    fn source(&self) -> Option<String> {
+        match self {
+            TraceEvent::Audit(e) => e.source(),
+            TraceEvent::FBT(e) => Some(e.host.to_hyphenated_ref().to_string()),
+        }
+    }
+
+    fn needs_upgrade(line: &str, opts: &IngestOptions) -> bool {
+        needs_upgrade(line, opts)
+    }
+
+    fn upgrade(val: &mut Value, opts: &IngestOptions) {
+        upgrade(val, opts)
+    }
+}
+
+/// Audit events ingested on their own
+///
+/// Deserialising straight into an `AuditEvent` avoids the buffering and fallback attempts of the
+/// untagged `TraceEvent`, FBT records are recognised from their raw line and skipped unparsed.
+impl Mapped for AuditEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_data_type(&PIPE);
+        pvm.register_data_type(&PTTY);
+        pvm.register_data_type(&DIRECTORY);
+        pvm.register_data_type(&SYMLINK);
+        pvm.register_data_type(&SHM);
+        pvm.register_data_type(&THREAD);
+        pvm.register_data_type(&FILESYSTEM);
+        pvm.register_data_type(&KQUEUE);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn update(&mut self) {
+        if let Some(host) = self.host {
+            let map_uuid = |u: Uuid| {
+                if u.is_nil() {
+                    u
+                } else {
+                    Uuid::new_v5(&host, u.as_bytes())
+                }
+            };
+
+            self.arg_objuuid1 = self.arg_objuuid1.map(map_uuid);
+            self.arg_objuuid2 = self.arg_objuuid2.map(map_uuid);
+            self.ret_objuuid1 = self.ret_objuuid1.map(map_uuid);
+            self.ret_objuuid2 = self.ret_objuuid2.map(map_uuid);
+            self.subjprocuuid = map_uuid(self.subjprocuuid);
+            self.subjthruuid = map_uuid(self.subjthruuid);
+        }
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn source(&self) -> Option<String> {
+        self.host.map(|h| h.to_hyphenated_ref().to_string())
+    }
+
+    fn accept(line: &str) -> bool {
+        !is_fbt_line(line)
+    }
+
+    fn needs_upgrade(line: &str, opts: &IngestOptions) -> bool {
+        needs_upgrade(line, opts)
+    }
+
+    fn upgrade(val: &mut Value, opts: &IngestOptions) {
+        upgrade(val, opts)
+    }
+}
+
+/// Check if the "event" field of a raw record names an FBT probe
+fn is_fbt_line(line: &str) -> bool {
+    match line.find("\"event\"") {
+        Some(idx) => line[idx + 7..]
+            .trim_start()
+            .trim_start_matches(':')
+            .trim_start()
+            .starts_with("\"fbt:"),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::sync_channel;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel, HasID},
+        ingest::{ingest_stream, IngestOptions},
+        trace::{
+            test_util::{run, run_with},
+            TsFormat,
+        },
+        view::DBTr,
+    };
+
+    fn created_types(trs: &[DBTr]) -> Vec<&'static str> {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) => Some(d.ty().name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn created_rels(trs: &[DBTr]) -> usize {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(r) => Some(r),
+                _ => None,
+            })
+            .count()
+    }
+
+    fn event(name: &str, extra: &str) -> String {
+        format!(
+            r#"{{"event": "audit:event:{}:", "time": 1533046200000000000,
+                "pid": 10, "ppid": 1, "tid": 100, "uid": 0, "exec": "test", "retval": 0,
+                "subjprocuuid": "3f7a9a54-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                "subjthruuid": "3f7a9a55-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6"{}}}"#,
+            name, extra
+        )
+    }
+
+    fn mmap(extra: &str) -> String {
+        event(
+            "aue_mmap",
+            &format!(r#", "arg_mem_flags": ["PROT_READ", "PROT_WRITE"]{}"#, extra),
+        )
+    }
+
+    fn file_io(name: &str, retval: i32) -> String {
+        event(
+            name,
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "fdpath": "/tmp/a""#,
+        )
+        .replace(r#""retval": 0"#, &format!(r#""retval": {}"#, retval))
+    }
+
+    /// The final (bytes_read, bytes_written) of every Inf edge, by edge id
+    fn inf_bytes(trs: &[DBTr]) -> Vec<(u64, u64)> {
+        let mut edges = std::collections::BTreeMap::new();
+        for tr in trs {
+            if let DBTr::CreateRel(Rel::Inf(i)) | DBTr::UpdateRel(Rel::Inf(i)) = tr {
+                edges.insert(i.get_db_id().inner(), (i.bytes_read, i.bytes_written));
+            }
+        }
+        edges.into_iter().map(|(_, b)| b).collect()
+    }
+
+    #[test]
+    fn readv_accumulates_bytes() {
+        let trs = run::<TraceEvent>(&[&file_io("aue_readv", 100), &file_io("aue_readv", 28)]);
+        assert_eq!(inf_bytes(&trs), vec![(128, 0)]);
+    }
+
+    #[test]
+    fn failed_io_moves_no_bytes() {
+        let trs = run::<TraceEvent>(&[
+            &file_io("aue_read", -1),
+            &file_io("aue_read", 10),
+            &file_io("aue_write", -1),
+            &file_io("aue_write", 5),
+        ]);
+        let bytes = inf_bytes(&trs);
+        assert!(bytes.contains(&(10, 0)));
+        assert!(bytes.contains(&(0, 5)));
+        assert!(bytes
+            .iter()
+            .all(|&b| b == (10, 0) || b == (0, 5) || b == (0, 0)));
+    }
+
+    #[test]
+    fn file_backed_mmap() {
+        let trs = run::<TraceEvent>(&[&mmap(
+            r#", "arg_sharing_flags": ["MAP_SHARED"],
+               "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "fdpath": "/tmp/shared""#,
+        )]);
+        let files = created_types(&trs)
+            .into_iter()
+            .filter(|ty| *ty == "file")
+            .count();
+        assert_eq!(files, 2);
+        assert!(created_rels(&trs) > 0);
+    }
+
+    #[test]
+    fn anonymous_mmap() {
+        for extra in &[
+            r#", "arg_sharing_flags": ["MAP_PRIVATE", "MAP_ANON"]"#,
+            r#", "arg_objuuid1": "00000000-0000-0000-0000-000000000000""#,
+            "",
+        ] {
+            let trs = run::<TraceEvent>(&[&mmap(extra)]);
+            assert_eq!(created_types(&trs), vec!["process"], "{}", extra);
+            assert_eq!(created_rels(&trs), 0);
+        }
+    }
+
+    #[test]
+    fn schema_registered_once() {
+        let (send, recv) = sync_channel(100);
+        let mut pvm = PVM::new(send);
+        TraceEvent::init(&mut pvm);
+        TraceEvent::init(&mut pvm);
+        let schema = pvm.schema_dbtrs();
+        drop(pvm);
+        assert_eq!(schema.len(), 12);
+        assert_eq!(recv.iter().count(), schema.len());
+    }
+
+    #[test]
+    fn nil_uuids() {
+        const NIL: &str = "00000000-0000-0000-0000-000000000000";
+        let open = event(
+            "aue_open_rwtc",
+            &format!(r#", "ret_objuuid1": "{}", "upath1": "/tmp/a""#, NIL),
+        );
+        let socket = event("aue_socket", &format!(r#", "ret_objuuid1": "{}""#, NIL));
+        let pipe = event(
+            "aue_pipe",
+            &format!(
+                r#", "ret_objuuid1": "{}",
+                   "ret_objuuid2": "7d3e4f11-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+                NIL
+            ),
+        );
+        let trace = [open, socket.clone(), socket, pipe]
+            .iter()
+            .map(|rec| rec.replace('\n', " "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let stats = ingest_stream::<_, AuditEvent>(trace.as_bytes(), &mut pvm);
+        drop(pvm);
+        assert_eq!(stats.records, 4);
+        assert_eq!(stats.process_errors, 0);
+        assert_eq!(stats.nil_uuids, 4);
+        let trs: Vec<_> = recv.iter().collect();
+        assert_eq!(created_types(&trs), vec!["process", "pipe"]);
+    }
+
+    #[test]
+    fn audit_only_skips_fbt() {
+        let fbt = r#"{"event": "fbt:kernel:cc_conn_init:", "host": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6", "time": 1533046200000000000, "so_uuid": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "lport": 22, "fport": 5000, "laddr": "10.0.0.1", "faddr": "10.0.0.2"}"#;
+        assert!(serde_json::from_str::<TraceEvent>(fbt).is_ok());
+        let open = event(
+            "aue_open_rwtc",
+            r#", "ret_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/tmp/a""#,
+        )
+        .replace('\n', " ");
+        let trace = [fbt, &open, fbt].join("\n");
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let stats = ingest_stream::<_, AuditEvent>(trace.as_bytes(), &mut pvm);
+        drop(pvm);
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.filtered, 2);
+        assert_eq!(stats.parse_errors, 0);
+        let trs: Vec<_> = recv.iter().collect();
+        assert_eq!(created_types(&trs), vec!["process", "file"]);
+    }
+
+    #[test]
+    fn fbt_links_sockets_across_hosts() {
+        let fbt = |host: &str, local: (&str, i32), foreign: (&str, i32)| {
+            format!(
+                r#"{{"event": "fbt:kernel:cc_conn_init:", "host": "{}",
+                    "time": 1533046200000000000,
+                    "so_uuid": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                    "laddr": "{}", "lport": {}, "faddr": "{}", "fport": {}}}"#,
+                host, local.0, local.1, foreign.0, foreign.1
+            )
+        };
+        let client = fbt(
+            "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            ("10.0.0.1", 5000),
+            ("10.0.0.2", 22),
+        );
+        let server = fbt(
+            "e1c74b41-94b8-11e8-8f3e-a1b2c3d4e5f6",
+            ("10.0.0.2", 22),
+            ("10.0.0.1", 5000),
+        );
+        let connect = event(
+            "aue_connect",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "address": "10.0.0.2", "port": 22"#,
+        );
+        let trs = run::<TraceEvent>(&[&client, &server, &connect]);
+        assert_eq!(created_types(&trs), vec!["socket", "socket", "process"]);
+        let names = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Name(n)) => Some(n),
+                _ => None,
+            })
+            .count();
+        assert_eq!(names, 2);
+        let connects = trs
+            .iter()
+            .filter(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => format!("{:?}", i.pvm_op) == "Connect",
+                _ => false,
+            })
+            .count();
+        assert_eq!(connects, 2);
+    }
+
+    #[test]
+    fn ts_format_from_options() {
+        let times = |opts, rec: &str| {
+            let (res, trs) = run_with::<TraceEvent>(opts, &[rec]);
+            assert!(res[0].is_ok(), "{:?}", res[0]);
+            trs.into_iter()
+                .filter_map(|tr| match tr {
+                    DBTr::CreateNode(Node::Ctx(c)) => Some(c.cont["time"].clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let nanos = event("aue_setlogin", r#", "login": "root""#);
+        let millis = nanos.replace("1533046200000000000", "1533046200000");
+        let rfc3339 = nanos.replace("1533046200000000000", r#""2018-07-31T14:10:00Z""#);
+        let expected = vec!["2018-07-31T14:10:00+00:00".to_string()];
+        assert_eq!(times(IngestOptions::default(), &nanos), expected);
+        let opts = |ts_format| IngestOptions {
+            ts_format,
+            ..IngestOptions::default()
+        };
+        assert_eq!(times(opts(TsFormat::Millis), &millis), expected);
+        assert_eq!(times(opts(TsFormat::Rfc3339), &rfc3339), expected);
+        let (res, _) = run_with::<TraceEvent>(IngestOptions::default(), &[&rfc3339]);
+        assert!(res[0].is_err());
+    }
+
+    #[test]
+    fn v1_records_upgraded() {
+        let v1 = r#"{"event": "audit:event:aue_open_rwtc", "time": 1533046200000000000, "pid": 10, "ppid": 1, "tid": 100, "uid": 0, "exec": "test", "ret": 3, "proc_uuid": "3f7a9a54-94b8-11e8-8f3e-a1b2c3d4e5f6", "thread_uuid": "3f7a9a55-94b8-11e8-8f3e-a1b2c3d4e5f6", "host_uuid": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6", "ret_objuuid": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "path": "/tmp/a"}"#;
+        assert!(serde_json::from_str::<AuditEvent>(v1).is_err());
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let stats = ingest_stream::<_, TraceEvent>(v1.as_bytes(), &mut pvm);
+        drop(pvm);
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.parse_errors, 0);
+        let trs: Vec<_> = recv.iter().collect();
+        assert_eq!(created_types(&trs), vec!["process", "file"]);
+        assert_eq!("V1".parse::<CadetsVersion>(), Ok(CadetsVersion::V1));
+    }
+
+    #[test]
+    fn cadets_version_from_options() {
+        let v1 = r#"{"event": "audit:event:aue_open_rwtc", "time": 1533046200000000000, "pid": 10, "ppid": 1, "tid": 100, "uid": 0, "exec": "test", "ret": 3, "proc_uuid": "3f7a9a54-94b8-11e8-8f3e-a1b2c3d4e5f6", "thread_uuid": "3f7a9a55-94b8-11e8-8f3e-a1b2c3d4e5f6", "host_uuid": "d0b63a30-94b8-11e8-8f3e-a1b2c3d4e5f6", "ret_objuuid": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "path": "/tmp/a"}"#;
+        let ingest = |cadets_version| {
+            let (send, _recv) = sync_channel(10_000);
+            let mut pvm = PVM::new(send);
+            pvm.set_options(IngestOptions {
+                cadets_version,
+                ..IngestOptions::default()
+            });
+            ingest_stream::<_, TraceEvent>(v1.as_bytes(), &mut pvm)
+        };
+        assert_eq!(ingest(CadetsVersion::V1).parse_errors, 0);
+        assert_eq!(ingest(CadetsVersion::V2).parse_errors, 1);
+    }
+
+    #[test]
+    fn dup2_keeps_fd_bindings() {
+        let open = event(
+            "aue_open_rwtc",
+            r#", "ret_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/tmp/a",
+               "retval": 3"#,
+        )
+        .replace(r#""retval": 0,"#, "");
+        let dup2 = event("aue_dup2", r#", "fd": 3"#).replace(r#""retval": 0"#, r#""retval": 1"#);
+        let fork = event(
+            "aue_fork",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0"#, r#""retval": 11"#);
+        let close = event("aue_close", r#", "fd": 3"#);
+        let write = |fd: i32| {
+            event("aue_write", &format!(r#", "fd": {}"#, fd))
+                .replace(r#""retval": 0"#, r#""retval": 5"#)
+        };
+        let child_write = write(1).replace("3f7a9a54", "3f7a9a56");
+        let (res, trs) = run_with::<TraceEvent>(
+            IngestOptions::default(),
+            &[
+                &open,
+                &dup2,
+                &fork,
+                &close,
+                &write(1),
+                &child_write,
+                &write(3),
+            ],
+        );
+        for r in &res[..6] {
+            assert!(r.is_ok(), "{:?}", r);
+        }
+        match res[6] {
+            Err(PVMError::MissingField {
+                field: "arg_objuuid1",
+                ..
+            }) => {}
+            ref r => panic!("unexpected result {:?}", r),
+        }
+        let writes = inf_bytes(&trs).into_iter().filter(|b| *b == (0, 5)).count();
+        assert_eq!(writes, 2);
+    }
+
+    #[test]
+    fn symlink_is_own_store() {
+        let symlink = event(
+            "aue_symlink",
+            r#", "ret_objuuid1": "6c0b5c33-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "upath1": "/etc/shadow", "upath2": "/tmp/innocent""#,
+        );
+        let readlink = event(
+            "aue_readlink",
+            r#", "arg_objuuid1": "6c0b5c33-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "upath1": "/tmp/innocent""#,
+        )
+        .replace(r#""retval": 0"#, r#""retval": 11"#);
+        let trs = run::<TraceEvent>(&[&symlink, &readlink]);
+        assert_eq!(created_types(&trs), vec!["process", "symlink"]);
+        assert_eq!(inf_bytes(&trs), vec![(11, 0)]);
+        let names = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Name(n)) => Some(format!("{:?}", n)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].contains("/tmp/innocent"));
+    }
+
+    #[test]
+    fn mkdir_and_rmdir_name_directory() {
+        let dir = r#", "ret_objuuid1": "6c0b5c34-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                     "arg_objuuid1": "6c0b5c34-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                     "upath1": "/tmp/d", "mode": 493"#;
+        let trs = run::<TraceEvent>(&[&event("aue_mkdir", dir), &event("aue_rmdir", dir)]);
+        assert_eq!(created_types(&trs), vec!["process", "directory"]);
+        let named = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Named(n)) | DBTr::UpdateRel(Rel::Named(n)) => Some(n),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(named.len(), 2);
+        assert!(named[0].actor.is_some());
+        assert_eq!(named[0].end, ID::new(0));
+        assert_ne!(named[1].end, ID::new(0));
+    }
+
+    #[test]
+    fn truncate_versions_file() {
+        let open = event(
+            "aue_open_rwtc",
+            r#", "ret_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/var/log/auth",
+               "retval": 4"#,
+        )
+        .replace(r#""retval": 0,"#, "");
+        let truncate = event(
+            "aue_truncate",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "upath1": "/var/log/auth""#,
+        );
+        let ftruncate = event("aue_ftruncate", r#", "fd": 4"#);
+        let trs = run::<TraceEvent>(&[&open, &truncate, &ftruncate]);
+        assert_eq!(created_types(&trs), vec!["process", "file", "file", "file"]);
+        let sinks = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .filter(|op| op == "Sink")
+            .count();
+        assert_eq!(sinks, 2);
+    }
+
+    #[test]
+    fn kill_links_processes_by_pid() {
+        let fork = event(
+            "aue_fork",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        // sent from an unrelated process so the edge is not the one fork already made
+        let kill = |pid: i32| {
+            event("aue_kill", &format!(r#", "arg_pid": {}, "signum": 9"#, pid))
+                .replace(r#""pid": 10,"#, r#""pid": 20,"#)
+                .replace("3f7a9a54-", "3f7a9a57-")
+        };
+        let trs = run::<TraceEvent>(&[&fork, &kill(11), &kill(12), &kill(-1)]);
+        let edges = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(i.pvm_op),
+                _ => None,
+            })
+            .count();
+        // one from the fork, one from the kill of the child
+        assert_eq!(edges, 2);
+        assert_eq!(created_types(&trs), vec!["process", "process", "process"]);
+    }
+
+    #[test]
+    fn kill_reaches_spawned_process() {
+        let spawn = event(
+            "aue_posix_spawn",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/bin/sh""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let kill = event("aue_kill", r#", "arg_pid": 11, "signum": 9"#)
+            .replace(r#""pid": 10,"#, r#""pid": 20,"#)
+            .replace("3f7a9a54-", "3f7a9a57-");
+        let trs = run::<TraceEvent>(&[&spawn, &kill]);
+        let ops: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        // the spawn from its parent and binary, then the kill of the child by its pid
+        assert_eq!(ops, vec!["Version", "Source", "Source"]);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d)) => {
+                d.meta.cur("pid") == Some("11")
+            }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn ptrace_sinks_into_tracee() {
+        let tracee = event("aue_sigaction", "")
+            .replace(r#""pid": 10,"#, r#""pid": 20,"#)
+            .replace("3f7a9a54-", "3f7a9a57-");
+        let ptrace = event("aue_ptrace", r#", "arg_pid": 20"#);
+        let trs = run::<TraceEvent>(&[&tracee, &ptrace]);
+        let ops: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops, vec!["Sink"]);
+    }
+
+    #[test]
+    fn shm_links_attached_processes() {
+        let shmget = event(
+            "aue_shmget",
+            r#", "ret_objuuid1": "9a1e7c10-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        );
+        let shmat = |rdonly: bool| {
+            let flags = if rdonly { r#"["SHM_RDONLY"]"# } else { "[]" };
+            event(
+                "aue_shmat",
+                &format!(
+                    r#", "arg_objuuid1": "9a1e7c10-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                       "arg_mem_flags": {}"#,
+                    flags
+                ),
+            )
+        };
+        let reader = shmat(true)
+            .replace(r#""pid": 10,"#, r#""pid": 20,"#)
+            .replace("3f7a9a54-", "3f7a9a57-");
+        let trs = run::<TraceEvent>(&[&shmget, &shmat(false), &reader]);
+        assert_eq!(created_types(&trs), vec!["process", "shm", "process"]);
+        let ops: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops, vec!["Source", "Sink", "Source"]);
+    }
+
+    #[test]
+    fn threads_act_for_their_process() {
+        let evt: AuditEvent = serde_json::from_str(&event("aue_read", "")).unwrap();
+        let (send, recv) = sync_channel(100);
+        let mut pvm = PVM::new(send);
+        AuditEvent::init(&mut pvm);
+        let mut tr = pvm.transaction(&CTX, hashmap!("event" => evt.event.clone()));
+        let pro = tr.declare(&PROCESS, evt.subjprocuuid, None).unwrap();
+        let thr = evt.thread(pro, &mut tr).unwrap();
+        assert_ne!(thr, pro);
+        assert_eq!(evt.thread(pro, &mut tr).unwrap(), thr);
+        assert_eq!(evt.fd_owner(thr, &tr), pro);
+        tr.commit().unwrap();
+        drop(pvm);
+        let trs: Vec<_> = recv.iter().collect();
+        assert_eq!(created_types(&trs), vec!["process", "thread"]);
+        assert_eq!(created_rels(&trs), 2);
+    }
+
+    #[test]
+    fn cadets_threads_from_options() {
+        let sigaction = event("aue_sigaction", "");
+        let (_, trs) = run_with::<TraceEvent>(IngestOptions::default(), &[&sigaction]);
+        assert_eq!(created_types(&trs), vec!["process"]);
+        let opts = IngestOptions {
+            cadets_threads: true,
+            ..IngestOptions::default()
+        };
+        let (_, trs) = run_with::<TraceEvent>(opts, &[&sigaction]);
+        assert_eq!(created_types(&trs), vec!["process", "thread"]);
+    }
+
+    #[test]
+    fn exec_records_argv() {
+        let exec = event(
+            "aue_execve",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/bin/sh",
+               "cmdline": "sh -c id", "argv": ["sh", "-c", "id"],
+               "envp": ["PATH=/bin", "SECRET=hunter2"]"#,
+        );
+        let trs = run::<TraceEvent>(&[&exec]);
+        let metas: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) if d.ty().name == "process" => {
+                    Some(format!("{:?}", d.meta))
+                }
+                DBTr::UpdateNode(Node::Data(d)) if d.ty().name == "process" => {
+                    Some(format!("{:?}", d.meta))
+                }
+                _ => None,
+            })
+            .collect();
+        let last = metas.last().unwrap();
+        assert!(last.contains(r#"[\"sh\",\"-c\",\"id\"]"#));
+        // environment capture is off by default
+        assert!(!last.contains("PATH"));
+        assert!(!last.contains("hunter2"));
+    }
+
+    #[test]
+    fn cadets_env_from_options() {
+        let exec = event(
+            "aue_execve",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6", "upath1": "/bin/sh",
+               "cmdline": "sh", "argv": ["sh"], "envp": ["PATH=/bin", "SECRET=hunter2"]"#,
+        );
+        let opts = IngestOptions {
+            cadets_env: true,
+            ..IngestOptions::default()
+        };
+        let (res, trs) = run_with::<TraceEvent>(opts, &[&exec]);
+        res[0].as_ref().unwrap();
+        let metas: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) if d.ty().name == "process" => {
+                    Some(format!("{:?}", d.meta))
+                }
+                DBTr::UpdateNode(Node::Data(d)) if d.ty().name == "process" => {
+                    Some(format!("{:?}", d.meta))
+                }
+                _ => None,
+            })
+            .collect();
+        let last = metas.last().unwrap();
+        assert!(last.contains(r#"[\"PATH=/bin\"]"#));
+        assert!(!last.contains("hunter2"));
+    }
+
+    #[test]
+    fn wait_reaps_exited_child() {
+        let fork = event(
+            "aue_fork",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let exit = event("aue_exit", r#", "exit_status": 1"#)
+            .replace(r#""pid": 10,"#, r#""pid": 11,"#)
+            .replace("3f7a9a54-", "3f7a9a56-");
+        let wait = event("aue_wait4", r#", "exit_status": 256"#)
+            .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let kill = event("aue_kill", r#", "arg_pid": 11"#);
+        let trs = run::<TraceEvent>(&[&fork, &exit, &kill, &wait, &kill]);
+        let ops: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        // the fork, then the wait; neither the exited nor the reaped pid resolves for a kill
+        assert_eq!(ops, vec!["Version", "Source"]);
+        assert_eq!(exit_statuses(&trs), vec!["1"]);
+    }
+
+    fn exit_statuses(trs: &[DBTr]) -> Vec<&str> {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::UpdateNode(Node::Data(d)) => d.meta.cur("exit_status"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The ops emitted for a fork of pid 11, a wait for it reporting `status`, then a kill of it
+    fn wait_with_status(status: i32) -> (Vec<String>, Vec<DBTr>) {
+        let fork = event(
+            "aue_fork",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let wait = event("aue_wait4", &format!(r#", "exit_status": {}"#, status))
+            .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let kill = event("aue_kill", r#", "arg_pid": 11"#);
+        let trs = run::<TraceEvent>(&[&fork, &wait, &kill]);
+        let ops = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        (ops, trs)
+    }
+
+    #[test]
+    fn wait_decodes_status() {
+        let (ops, trs) = wait_with_status(3 << 8);
+        // the child is released, so the kill does not resolve
+        assert_eq!(ops, vec!["Version", "Source"]);
+        assert_eq!(exit_statuses(&trs), vec!["3"]);
+        let (_, trs) = wait_with_status(9);
+        assert!(exit_statuses(&trs).is_empty());
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::UpdateNode(Node::Data(d)) => d.meta.cur("exit_signal") == Some("9"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn wait_for_stopped_or_continued_child_keeps_it() {
+        // stopped by SIGSTOP, then continued
+        for status in &[(17 << 8) | 0x7f, 0x13] {
+            let (ops, trs) = wait_with_status(*status);
+            assert_eq!(ops, vec!["Version", "Source", "Source"], "{:#x}", status);
+            assert!(exit_statuses(&trs).is_empty());
+        }
+    }
+
+    #[test]
+    fn exit_releases_process() {
+        let exit = event("aue_exit", "");
+        let trs = run::<TraceEvent>(&[&exit, &exit]);
+        // the second record declares the released process afresh
+        assert_eq!(created_types(&trs), vec!["process", "process"]);
+    }
+
+    #[test]
+    fn remount_is_new_filesystem() {
+        let mount = event(
+            "aue_nmount",
+            r#", "upath1": "/mnt", "upath2": "/dev/da0p1", "fstype": "ufs""#,
+        );
+        let unmount = event("aue_unmount", r#", "upath1": "/mnt""#);
+        let trs = run::<TraceEvent>(&[&mount, &unmount, &mount]);
+        assert_eq!(
+            created_types(&trs),
+            vec!["process", "filesystem", "filesystem"]
+        );
+        let ends: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Named(n)) | DBTr::UpdateRel(Rel::Named(n)) => {
+                    Some(n.end == ID::new(0))
+                }
+                _ => None,
+            })
+            .collect();
+        // named by the first mount, ended by the unmount, named again by the second
+        assert_eq!(ends, vec![true, false, true]);
+    }
+
+    #[test]
+    fn socket_state_bounds_connection() {
+        let socket = event(
+            "aue_socket",
+            r#", "ret_objuuid1": "7d3e4f10-94b8-11e8-8f3e-a1b2c3d4e5f6", "ret_fd1": 3"#,
+        );
+        let connect = event(
+            "aue_connect",
+            r#", "arg_objuuid1": "7d3e4f10-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "address": "10.0.0.2", "port": 80"#,
+        );
+        let shutdown = event("aue_shutdown", r#", "fd": 3"#);
+        let close = event("aue_close", r#", "fd": 3"#);
+        let trs = run::<TraceEvent>(&[&socket, &connect, &shutdown, &close]);
+        let states: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::UpdateNode(Node::Data(d)) if d.ty().name == "socket" => {
+                    let meta = format!("{:?}", d.meta);
+                    ["connected", "shutdown", "closed"]
+                        .iter()
+                        .rev()
+                        .find(|s| meta.contains(*s))
+                        .cloned()
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(states, vec!["connected", "shutdown", "closed"]);
+    }
+
+    #[test]
+    fn sendfile_moves_file_to_socket() {
+        let sendfile = event(
+            "aue_sendfile",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "arg_objuuid2": "7d3e4f10-94b8-11e8-8f3e-a1b2c3d4e5f6", "sbytes": 4096"#,
+        );
+        let trs = run::<TraceEvent>(&[&sendfile]);
+        assert_eq!(created_types(&trs), vec!["process", "file", "socket"]);
+        assert_eq!(inf_bytes(&trs), vec![(4096, 0), (0, 4096)]);
+    }
+
+    #[test]
+    fn sendfile_records_only_bytes_sent() {
+        let sendfile = |retval: i64, sbytes: i64| {
+            event(
+                "aue_sendfile",
+                &format!(
+                    r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+                       "arg_objuuid2": "7d3e4f10-94b8-11e8-8f3e-a1b2c3d4e5f6", "sbytes": {}"#,
+                    sbytes
+                ),
+            )
+            .replace(r#""retval": 0,"#, &format!(r#""retval": {},"#, retval))
+        };
+        // interrupted part way through
+        let trs = run::<TraceEvent>(&[&sendfile(-1, 100)]);
+        assert_eq!(created_types(&trs), vec!["process", "file", "socket"]);
+        assert_eq!(inf_bytes(&trs), vec![(100, 0), (0, 100)]);
+        let trs = run::<TraceEvent>(&[&sendfile(-1, 0)]);
+        assert_eq!(created_types(&trs), vec!["process"]);
+        assert!(inf_bytes(&trs).is_empty());
+    }
+
+    #[test]
+    fn utimes_versions_file() {
+        let utimes = event(
+            "aue_utimes",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "upath1": "/var/log/auth""#,
+        );
+        let extattr = event(
+            "aue_extattr_set_file",
+            r#", "arg_objuuid1": "6c0b5c32-94b8-11e8-8f3e-a1b2c3d4e5f6",
+               "upath1": "/var/log/auth", "attrname": "user.origin""#,
+        );
+        let trs = run::<TraceEvent>(&[&utimes, &extattr]);
+        assert_eq!(created_types(&trs), vec!["process", "file", "file", "file"]);
+        let last = trs
+            .iter()
+            .rev()
+            .find_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d))
+                    if d.ty().name == "file" =>
+                {
+                    Some(format!("{:?}", d.meta))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(last.contains("times_set"));
+        assert!(last.contains("user.origin"));
+    }
+
+    #[test]
+    fn jail_carries_over_fork() {
+        let jail = event("aue_jail", r#", "upath1": "/jails/www""#)
+            .replace(r#""retval": 0,"#, r#""retval": 3,"#);
+        let fork = event(
+            "aue_fork",
+            r#", "ret_objuuid1": "3f7a9a56-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 11,"#);
+        let trs = run::<TraceEvent>(&[&jail, &fork]);
+        // the child is the last process written, by fork recording its pid
+        let child = trs
+            .iter()
+            .rev()
+            .find_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d))
+                    if d.ty().name == "process" =>
+                {
+                    Some(format!("{:?}", d.meta))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(child.contains("11"));
+        assert!(child.contains("/jails/www"));
+        assert!(child.contains("jail"));
+    }
+
+    #[test]
+    fn kevent_connects_to_kqueue() {
+        let kqueue = event(
+            "aue_kqueue",
+            r#", "ret_objuuid1": "8e4f5a20-94b8-11e8-8f3e-a1b2c3d4e5f6""#,
+        )
+        .replace(r#""retval": 0,"#, r#""retval": 5,"#);
+        let kevent = |ret: i32| {
+            event("aue_kevent", r#", "fd": 5"#)
+                .replace(r#""retval": 0,"#, &format!(r#""retval": {},"#, ret))
+        };
+        let trs = run::<TraceEvent>(&[&kqueue, &kevent(0), &kevent(2)]);
+        assert_eq!(created_types(&trs), vec!["process", "kqueue"]);
+        let ops: Vec<_> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops, vec!["Sink", "Source"]);
+    }
+
+    #[test]
+    fn commit_hook_aborts_ingest() {
+        let trace = [
+            file_io("aue_read", 1),
+            file_io("aue_read", 2),
+            file_io("aue_read", 3),
+        ]
+        .iter()
+        .map(|l| l.replace('\n', " "))
+        .collect::<Vec<_>>()
+        .join("\n");
+        let (send, _recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        let mut commits = 0;
+        pvm.add_commit_hook(Box::new(move |_| {
+            commits += 1;
+            if commits == 2 {
+                Err(PVMError::HookAborted {
+                    reason: "enough".into(),
+                })
+            } else {
+                Ok(())
+            }
+        }));
+        let stats = ingest_stream::<_, TraceEvent>(trace.as_bytes(), &mut pvm);
+        assert!(stats.aborted);
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.process_errors, 0);
+    }
+
+    #[test]
+    fn commit_hook_errors_end_ingest() {
+        let trace = [file_io("aue_read", 1), file_io("aue_read", 2)]
+            .iter()
+            .map(|l| l.replace('\n', " "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (send, _recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        pvm.add_commit_hook(Box::new(|_| {
+            Err(PVMError::AssertionFailure {
+                cont: "alert sink unreachable".into(),
+            })
+        }));
+        let stats = ingest_stream::<_, TraceEvent>(trace.as_bytes(), &mut pvm);
+        assert!(stats.aborted);
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.process_errors, 0);
+    }
 }
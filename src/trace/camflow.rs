@@ -0,0 +1,508 @@
+//! CamFlow whole-system provenance
+//!
+//! CamFlow publishes its graph as W3C PROV-JSON documents, this mapping takes one document per
+//! line:
+//!
+//! ```json
+//! {"prefix": {...},
+//!  "activity": {"cf:AQAA...": {"prov:type": "task", "cf:pid": 10, "cf:boot_id": 1, ...}},
+//!  "entity": {"cf:AgAA...": {"prov:type": "inode_file", "cf:uid": 0, ...},
+//!             "cf:AwAA...": {"prov:type": "path", "cf:pathname": "/etc/passwd", ...}},
+//!  "used": {"cf:BAAA...": {"prov:type": "read", "cf:id": 42, "cf:date": "2018:07:31T14:10:00",
+//!                          "prov:entity": "cf:AgAA...", "prov:activity": "cf:AQAA...", ...}},
+//!  "wasGeneratedBy": {...}, "wasInformedBy": {...}, "wasDerivedFrom": {...}}
+//! ```
+//!
+//! CamFlow creates a new node for each version of an object, the PVM does its own versioning so
+//! nodes are instead identified by the machine, boot, type and id encoded in their identifier.
+//! Tasks become processes, inodes become files, sockets and pipes, while path and address
+//! entities only supply names. Relations are applied in the order of their `cf:id`, each in its
+//! own context. Relations may refer to nodes from earlier documents, those referring to nodes
+//! that were never reported are skipped.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{
+    data::node_types::{ConcreteType, ContextType, Name},
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PIPE, PROCESS, SOCKET},
+        MapFmt,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "camflow_context",
+        props: vec![
+            "time",
+            "event",
+            "jiffies",
+            "boot_id",
+            "machine_id",
+            "trace_offset",
+            "trace_source"
+        ],
+    };
+    /// Namespace for the uuids derived from CamFlow node identifiers
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:camflow");
+}
+
+/// The attributes of a PROV node or relation
+pub type Attrs = serde_json::Map<String, Value>;
+
+/// A PROV-JSON document as published by CamFlow
+#[derive(Deserialize, Debug)]
+pub struct CamFlowDoc {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(default)]
+    pub activity: BTreeMap<String, Attrs>,
+    #[serde(default)]
+    pub entity: BTreeMap<String, Attrs>,
+    #[serde(default)]
+    pub used: BTreeMap<String, Attrs>,
+    #[serde(default, rename = "wasGeneratedBy")]
+    pub was_generated_by: BTreeMap<String, Attrs>,
+    #[serde(default, rename = "wasInformedBy")]
+    pub was_informed_by: BTreeMap<String, Attrs>,
+    #[serde(default, rename = "wasDerivedFrom")]
+    pub was_derived_from: BTreeMap<String, Attrs>,
+}
+
+impl fmt::Display for CamFlowDoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.activity,
+            self.entity,
+            self.used,
+            self.was_generated_by,
+            self.was_informed_by,
+            self.was_derived_from,
+        );
+        ret.finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Class {
+    Used,
+    WasGeneratedBy,
+    WasInformedBy,
+    WasDerivedFrom,
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::Used => "used",
+            Class::WasGeneratedBy => "wasGeneratedBy",
+            Class::WasInformedBy => "wasInformedBy",
+            Class::WasDerivedFrom => "wasDerivedFrom",
+        }
+    }
+
+    /// The attributes holding the source and destination of the information flow
+    fn ends(self) -> (&'static str, &'static str) {
+        match self {
+            Class::Used => ("prov:entity", "prov:activity"),
+            Class::WasGeneratedBy => ("prov:activity", "prov:entity"),
+            Class::WasInformedBy => ("prov:informant", "prov:informed"),
+            Class::WasDerivedFrom => ("prov:usedEntity", "prov:generatedEntity"),
+        }
+    }
+}
+
+/// How a CamFlow node is represented in the PVM
+enum Kind {
+    Data(&'static ConcreteType),
+    Name(Name),
+    Ignored,
+}
+
+/// An attribute as a string, CamFlow is inconsistent about quoting numbers
+pub(crate) fn attr(attrs: &Attrs, key: &str) -> Option<String> {
+    match attrs.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        v => Some(v.to_string()),
+    }
+}
+
+fn kind(attrs: &Attrs) -> Kind {
+    match &attr(attrs, "prov:type").unwrap_or_default()[..] {
+        "task" => Kind::Data(&PROCESS),
+        "inode_file" | "inode_directory" | "inode_link" | "inode_char" | "inode_block"
+        | "inode_unknown" => Kind::Data(&FILE),
+        "inode_socket" => Kind::Data(&SOCKET),
+        "inode_pipe" => Kind::Data(&PIPE),
+        "path" => match attr(attrs, "cf:pathname") {
+            Some(path) => Kind::Name(Name::Path(path)),
+            None => Kind::Ignored,
+        },
+        "address" => {
+            let addr = match attrs.get("cf:address").and_then(Value::as_object) {
+                Some(addr) => addr,
+                None => return Kind::Ignored,
+            };
+            match (attr(addr, "path"), attr(addr, "host"), attr(addr, "serv")) {
+                (Some(path), _, _) => Kind::Name(Name::Path(path)),
+                (None, Some(host), Some(serv)) => match serv.parse() {
+                    Ok(port) => Kind::Name(Name::Net(host, port)),
+                    Err(_) => Kind::Ignored,
+                },
+                _ => Kind::Ignored,
+            }
+        }
+        _ => Kind::Ignored,
+    }
+}
+
+/// The uuid of the object a CamFlow node identifier refers to
+///
+/// Identifiers are the base64 encoded `node_identifier` struct: a 64 bit type and id followed by
+/// 32 bit boot id, machine id and version, all little endian. The version is left out so that
+/// every version of an object maps to the same uuid. Identifiers that cannot be decoded are
+/// used verbatim.
+fn node_uuid(ident: &str) -> Uuid {
+    let raw = ident.trim_start_matches("cf:");
+    let key = match base64::decode(raw) {
+        Ok(ref b) if b.len() >= 28 => {
+            let u64_at = |i: usize| {
+                let mut v = [0u8; 8];
+                v.copy_from_slice(&b[i..i + 8]);
+                u64::from_le_bytes(v)
+            };
+            let u32_at = |i: usize| {
+                let mut v = [0u8; 4];
+                v.copy_from_slice(&b[i..i + 4]);
+                u32::from_le_bytes(v)
+            };
+            format!(
+                "{}:{}:{:x}:{}",
+                u32_at(20),
+                u32_at(16),
+                u64_at(0),
+                u64_at(8)
+            )
+        }
+        _ => ident.to_string(),
+    };
+    Uuid::new_v5(&NS, key.as_bytes())
+}
+
+impl CamFlowDoc {
+    fn node(&self, ident: &str) -> Option<&Attrs> {
+        self.activity.get(ident).or_else(|| self.entity.get(ident))
+    }
+
+    fn relations(&self) -> Vec<(Class, &Attrs)> {
+        let mut ret: Vec<(Class, &Attrs)> = Vec::new();
+        for (class, rels) in &[
+            (Class::Used, &self.used),
+            (Class::WasGeneratedBy, &self.was_generated_by),
+            (Class::WasInformedBy, &self.was_informed_by),
+            (Class::WasDerivedFrom, &self.was_derived_from),
+        ] {
+            ret.extend(rels.values().map(|attrs| (*class, attrs)));
+        }
+        ret.sort_by_key(|(_, attrs)| {
+            attr(attrs, "cf:id")
+                .and_then(|id| id.parse::<u64>().ok())
+                .unwrap_or_default()
+        });
+        ret
+    }
+
+    fn ctx(&self, event: String, attrs: &Attrs) -> HashMap<&'static str, String> {
+        let mut ctx = hashmap!("event" => event);
+        for (prop, key) in &[
+            ("time", "cf:date"),
+            ("jiffies", "cf:jiffies"),
+            ("boot_id", "cf:boot_id"),
+            ("machine_id", "cf:machine_id"),
+        ] {
+            if let Some(val) = attr(attrs, key) {
+                ctx.insert(prop, val);
+            }
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        ctx
+    }
+
+    /// Declare the objects in the document, updating the properties of those already known
+    fn declare(&self, tr: &mut PVMTransaction) -> PVMResult<()> {
+        for (ident, attrs) in self.activity.iter().chain(self.entity.iter()) {
+            let ty = match kind(attrs) {
+                Kind::Data(ty) => ty,
+                _ => continue,
+            };
+            let id = tr.declare(ty, node_uuid(ident), None)?;
+            let props: &[(&'static str, &str)] = match ty.name {
+                "process" => &[("pid", "cf:pid"), ("euid", "cf:uid"), ("egid", "cf:gid")],
+                "file" => &[("owner_uid", "cf:uid"), ("owner_gid", "cf:gid")],
+                _ => &[],
+            };
+            for (prop, key) in props {
+                if let Some(val) = attr(attrs, key) {
+                    tr.meta(id, prop, &val)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn relation(
+        &self,
+        class: Class,
+        ty: &str,
+        attrs: &Attrs,
+        tr: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        let (src_key, dst_key) = class.ends();
+        let end = |key: &'static str| {
+            attr(attrs, key).ok_or(PVMError::MissingField {
+                evt: format!("{}:{}", class.name(), ty),
+                field: key,
+            })
+        };
+        let (src, dst) = (end(src_key)?, end(dst_key)?);
+        match (class, ty) {
+            (_, "version_entity") | (_, "version_activity") => {}
+            (Class::WasDerivedFrom, "named") => {
+                let name = match self.node(&src).map(kind) {
+                    Some(Kind::Name(name)) => name,
+                    _ => return self.skip(class, ty, tr),
+                };
+                match tr.lookup(&node_uuid(&dst)) {
+                    Some(obj) => {
+                        tr.name(obj, name)?;
+                    }
+                    None => return self.skip(class, ty, tr),
+                }
+            }
+            (Class::WasInformedBy, "clone") => {
+                let par = match tr.lookup(&node_uuid(&src)) {
+                    Some(par) => par,
+                    None => return self.skip(class, ty, tr),
+                };
+                tr.derive(par, node_uuid(&dst))?;
+            }
+            (Class::Used, _) | (Class::WasGeneratedBy, _) if !is_flow(ty) => {}
+            (Class::Used, _) | (Class::WasGeneratedBy, _) => {
+                let (src, dst) = match (tr.lookup(&node_uuid(&src)), tr.lookup(&node_uuid(&dst))) {
+                    (Some(src), Some(dst)) => (src, dst),
+                    _ => return self.skip(class, ty, tr),
+                };
+                if let Class::Used = class {
+                    tr.source(dst, src)?;
+                } else {
+                    tr.sink(src, dst)?;
+                }
+            }
+            _ => return self.skip(class, ty, tr),
+        }
+        Ok(())
+    }
+
+    fn skip(&self, class: Class, ty: &str, tr: &mut PVMTransaction) -> PVMResult<()> {
+        tr.unparsed(&format!("{}:{}", class.name(), ty));
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let rels = self.relations();
+        let first = rels.first().map(|(_, attrs)| *attrs);
+        let mut tr = pvm.transaction(
+            &CTX,
+            self.ctx("declare".to_string(), first.unwrap_or(&Attrs::new())),
+        );
+        match self.declare(&mut tr) {
+            Ok(_) => tr.commit()?,
+            Err(e) => {
+                tr.rollback();
+                return Err(e);
+            }
+        }
+        for (class, attrs) in rels {
+            let ty = attr(attrs, "prov:type").unwrap_or_default();
+            let mut tr = pvm.transaction(&CTX, self.ctx(format!("{}:{}", class.name(), ty), attrs));
+            match self.relation(class, &ty, attrs, &mut tr) {
+                Ok(_) => tr.commit()?,
+                Err(e) => {
+                    tr.rollback();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a used or wasGeneratedBy relation moves information, as opposed to only checking
+/// permissions or attributes
+fn is_flow(ty: &str) -> bool {
+    match ty {
+        "open" | "search" | "getattr" | "getxattr" | "listxattr" => false,
+        ty => !ty.starts_with("perm_"),
+    }
+}
+
+impl Mapped for CamFlowDoc {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_data_type(&PIPE);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn source(&self) -> Option<String> {
+        self.activity
+            .values()
+            .chain(self.entity.values())
+            .filter_map(|attrs| attr(attrs, "cf:machine_id"))
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{names, ops, run},
+        view::DBTr,
+    };
+
+    fn ident(ty: u64, id: u64, version: u32) -> String {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&ty.to_le_bytes());
+        raw.extend_from_slice(&id.to_le_bytes());
+        raw.extend_from_slice(&1u32.to_le_bytes());
+        raw.extend_from_slice(&7u32.to_le_bytes());
+        raw.extend_from_slice(&version.to_le_bytes());
+        raw.extend_from_slice(&[0; 4]);
+        format!("cf:{}", base64::encode(&raw))
+    }
+
+    fn task(id: u64, version: u32) -> String {
+        ident(1, id, version)
+    }
+
+    fn inode(id: u64, version: u32) -> String {
+        ident(2, id, version)
+    }
+
+    fn rel(id: u64, ty: &str, ends: (&str, &str), src: &str, dst: &str) -> String {
+        format!(
+            r#""cf:rel{}": {{"cf:id": "{}", "prov:type": "{}", "cf:date": "2018:07:31T14:10:00",
+                             "cf:boot_id": 1, "cf:machine_id": 7, "{}": "{}", "{}": "{}"}}"#,
+            id, id, ty, ends.0, src, ends.1, dst
+        )
+    }
+
+    fn uuids(trs: &[DBTr], ty: &str) -> Vec<Uuid> {
+        let mut ret: Vec<Uuid> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) if d.ty().name == ty => Some(d.uuid()),
+                _ => None,
+            })
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    #[test]
+    fn versions_collapse_to_one_object() {
+        let (t, f1, f2, p) = (task(10, 0), inode(20, 0), inode(20, 1), ident(3, 30, 0));
+        let first = format!(
+            r#"{{"activity": {{"{}": {{"prov:type": "task", "cf:pid": 10}}}},
+                "entity": {{"{}": {{"prov:type": "inode_file", "cf:uid": 0}},
+                           "{}": {{"prov:type": "path", "cf:pathname": "/etc/passwd"}}}},
+                "used": {{{}}},
+                "wasDerivedFrom": {{{}}}}}"#,
+            t,
+            f1,
+            p,
+            rel(2, "read", ("prov:entity", "prov:activity"), &f1, &t),
+            rel(
+                1,
+                "named",
+                ("prov:usedEntity", "prov:generatedEntity"),
+                &p,
+                &f1
+            ),
+        );
+        let second = format!(
+            r#"{{"entity": {{"{}": {{"prov:type": "inode_file", "cf:uid": 0}}}},
+                "wasGeneratedBy": {{{}}},
+                "wasDerivedFrom": {{{}}}}}"#,
+            f2,
+            rel(3, "write", ("prov:activity", "prov:entity"), &t, &f2),
+            rel(
+                4,
+                "version_entity",
+                ("prov:usedEntity", "prov:generatedEntity"),
+                &f1,
+                &f2
+            ),
+        );
+        let trs = run::<CamFlowDoc>(&[first, second]);
+        assert_eq!(uuids(&trs, "file").len(), 1);
+        assert_eq!(uuids(&trs, "process").len(), 1);
+        assert_eq!(names(&trs).len(), 1);
+        assert!(names(&trs)[0].ends_with("\"/etc/passwd\")"));
+        assert_eq!(ops(&trs), vec!["Source", "Version", "Sink"]);
+    }
+
+    #[test]
+    fn clone_and_dangling_relations() {
+        let (par, ch, f) = (task(10, 0), task(11, 0), inode(20, 0));
+        let doc = format!(
+            r#"{{"activity": {{"{}": {{"prov:type": "task", "cf:pid": 10}},
+                             "{}": {{"prov:type": "task", "cf:pid": 11}}}},
+                "wasInformedBy": {{{}}},
+                "used": {{{}, {}}}}}"#,
+            par,
+            ch,
+            rel(1, "clone", ("prov:informant", "prov:informed"), &par, &ch),
+            rel(2, "read", ("prov:entity", "prov:activity"), &f, &ch),
+            rel(3, "perm_read", ("prov:entity", "prov:activity"), &f, &ch),
+        );
+        let trs = run::<CamFlowDoc>(&[doc]);
+        assert_eq!(uuids(&trs, "process").len(), 2);
+        assert!(uuids(&trs, "file").is_empty());
+        assert_eq!(ops(&trs), vec!["Version"]);
+    }
+}
@@ -0,0 +1,454 @@
+//! AWS CloudTrail logs
+//!
+//! Takes CloudTrail records, either a whole log file as delivered to S3 on a single line or one
+//! record per line as forwarded by CloudWatch Logs:
+//!
+//! ```json
+//! {"Records": [{"eventVersion": "1.05", "eventTime": "2018-07-31T14:10:00Z",
+//!   "eventSource": "s3.amazonaws.com", "eventName": "GetObject", "awsRegion": "eu-west-2",
+//!   "sourceIPAddress": "10.0.0.7", "userAgent": "aws-cli/1.16.10",
+//!   "userIdentity": {"type": "IAMUser", "principalId": "AIDA...",
+//!                    "arn": "arn:aws:iam::123456789012:user/alice",
+//!                    "accountId": "123456789012", "userName": "alice"},
+//!   "requestParameters": {"bucketName": "builds", "key": "app.tar.gz"},
+//!   "additionalEventData": {"bytesTransferredOut": 1024.0},
+//!   "eventID": "3f9c...", "readOnly": true, "recipientAccountId": "123456789012"}]}
+//! ```
+//!
+//! Each IAM principal, or AWS service acting on its own behalf, becomes an actor. S3 objects
+//! become stores named by their `s3://` URL so that copies, uploads and downloads link up,
+//! resources of other services become stores named by their ARN. Read only API calls source
+//! from the resources they name and all other calls sink into them, deleting an S3 object also
+//! removes its name. Failed calls and calls naming no resource are not mapped.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{parse_ts, MapFmt, RawTs, TsFormat},
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref PRINCIPAL: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "aws_principal",
+        props: hashmap!("arn" => true,
+                        "principal_type" => true,
+                        "account_id" => true,
+                        "user_name" => true,
+                        "session_issuer" => true),
+    };
+    static ref S3_OBJECT: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "s3_object",
+        props: hashmap!("bucket" => false,
+                        "key" => false),
+    };
+    static ref RESOURCE: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "aws_resource",
+        props: hashmap!("arn" => false,
+                        "resource_type" => true,
+                        "account_id" => true),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "cloudtrail_context",
+        props: vec![
+            "time",
+            "event",
+            "event_source",
+            "event_id",
+            "region",
+            "source_ip",
+            "user_agent",
+            "account_id",
+            "trace_offset",
+            "trace_source",
+        ],
+    };
+    /// Namespace for the uuids derived from principals and resource names
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:cloudtrail");
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdentity {
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    pub principal_id: Option<String>,
+    pub arn: Option<String>,
+    pub account_id: Option<String>,
+    pub user_name: Option<String>,
+    pub invoked_by: Option<String>,
+    #[serde(default)]
+    pub session_context: Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    #[serde(rename = "ARN")]
+    pub arn: Option<String>,
+    pub account_id: Option<String>,
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudTrailRecord {
+    pub event_time: RawTs,
+    pub event_source: String,
+    pub event_name: String,
+    pub aws_region: Option<String>,
+    #[serde(rename = "sourceIPAddress")]
+    pub source_ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub user_identity: UserIdentity,
+    #[serde(default)]
+    pub request_parameters: Value,
+    #[serde(default)]
+    pub additional_event_data: Value,
+    pub error_code: Option<String>,
+    #[serde(rename = "eventID")]
+    pub event_id: Option<String>,
+    pub read_only: Option<bool>,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    pub recipient_account_id: Option<String>,
+}
+
+/// The records of a line, a log file or a single record
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Records {
+    File {
+        #[serde(rename = "Records")]
+        records: Vec<CloudTrailRecord>,
+    },
+    Single(Box<CloudTrailRecord>),
+}
+
+/// A line of a CloudTrail log
+#[derive(Deserialize, Debug)]
+pub struct CloudTrailLog {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(flatten)]
+    pub records: Records,
+}
+
+impl fmt::Display for CloudTrailLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.records,
+        );
+        ret.finish()
+    }
+}
+
+fn string(val: &Value, key: &str) -> Option<String> {
+    match val.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        v => Some(v.to_string()),
+    }
+}
+
+impl CloudTrailRecord {
+    fn read_only(&self) -> bool {
+        match self.read_only {
+            Some(ro) => ro,
+            None => ["Get", "Head", "List", "Describe"]
+                .iter()
+                .any(|p| self.event_name.starts_with(p)),
+        }
+    }
+
+    fn declare_principal(&self, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let id = &self.user_identity;
+        let key = id
+            .arn
+            .clone()
+            .or_else(|| id.invoked_by.clone())
+            .or_else(|| id.principal_id.clone())
+            .ok_or(PVMError::MissingField {
+                evt: self.event_name.clone(),
+                field: "userIdentity",
+            })?;
+        let uuid = Uuid::new_v5(&NS, format!("principal:{}", key).as_bytes());
+        let mut init = HashMap::new();
+        for (prop, val) in &[
+            ("arn", &id.arn),
+            ("principal_type", &id.ty),
+            ("account_id", &id.account_id),
+            ("user_name", &id.user_name),
+        ] {
+            if let Some(val) = val {
+                init.insert(*prop, val.clone());
+            }
+        }
+        if let Some(issuer) = self
+            .user_identity
+            .session_context
+            .get("sessionIssuer")
+            .and_then(|i| string(i, "arn"))
+        {
+            init.insert("session_issuer", issuer);
+        }
+        pvm.declare(&PRINCIPAL, uuid, Some(init))
+    }
+
+    fn declare_object(&self, bucket: &str, key: &str, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let url = format!("s3://{}/{}", bucket, key);
+        let uuid = Uuid::new_v5(&NS, url.as_bytes());
+        let o = pvm.declare(
+            &S3_OBJECT,
+            uuid,
+            Some(hashmap!("bucket" => bucket.to_string(), "key" => key.to_string())),
+        )?;
+        pvm.name(o, Name::Path(url))?;
+        Ok(o)
+    }
+
+    fn s3(&self, act: ID, bucket: &str, key: &str, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let o = self.declare_object(bucket, key, pvm)?;
+        match &self.event_name[..] {
+            "DeleteObject" => {
+                pvm.sink(act, o)?;
+                let url = format!("s3://{}/{}", bucket, key);
+                let uuid = Uuid::new_v5(&NS, url.as_bytes());
+                let o = pvm.lookup(&uuid).unwrap_or(o);
+                pvm.unname(o, Name::Path(url))?;
+                pvm.release(&uuid);
+            }
+            "CopyObject" | "UploadPartCopy" => {
+                let src = string(&self.request_parameters, "x-amz-copy-source").ok_or(
+                    PVMError::MissingField {
+                        evt: self.event_name.clone(),
+                        field: "x-amz-copy-source",
+                    },
+                )?;
+                let src = src.trim_start_matches('/');
+                let mut parts = src.splitn(2, '/');
+                if let (Some(sb), Some(sk)) = (parts.next(), parts.next()) {
+                    let s = self.declare_object(sb, sk, pvm)?;
+                    pvm.source(act, s)?;
+                }
+                pvm.sink(act, o)?;
+            }
+            _ if self.read_only() => {
+                let bytes = self
+                    .additional_event_data
+                    .get("bytesTransferredOut")
+                    .and_then(Value::as_f64)
+                    .unwrap_or(0.0);
+                pvm.source_nbytes(act, o, bytes as i64)?;
+            }
+            _ => {
+                pvm.sink(act, o)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resource(&self, act: ID, res: &Resource, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let arn = match res.arn {
+            Some(ref arn) => arn.clone(),
+            None => return Ok(()),
+        };
+        let uuid = Uuid::new_v5(&NS, format!("arn:{}", arn).as_bytes());
+        let mut init = hashmap!("arn" => arn.clone());
+        if let Some(ref ty) = res.ty {
+            init.insert("resource_type", ty.clone());
+        }
+        if let Some(ref account) = res.account_id {
+            init.insert("account_id", account.clone());
+        }
+        let r = pvm.declare(&RESOURCE, uuid, Some(init))?;
+        pvm.name(r, Name::Path(arn))?;
+        if self.read_only() {
+            pvm.source(act, r)?;
+        } else {
+            pvm.sink(act, r)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let bucket = string(&self.request_parameters, "bucketName");
+        let key = string(&self.request_parameters, "key");
+        match (&self.event_source[..], bucket, key) {
+            ("s3.amazonaws.com", Some(bucket), Some(key)) => {
+                let act = self.declare_principal(pvm)?;
+                self.s3(act, &bucket, &key, pvm)
+            }
+            _ if self.resources.iter().any(|r| r.arn.is_some()) => {
+                let act = self.declare_principal(pvm)?;
+                for res in &self.resources {
+                    self.resource(act, res, pvm)?;
+                }
+                Ok(())
+            }
+            _ => {
+                pvm.unparsed(&self.event_name);
+                Ok(())
+            }
+        }
+    }
+
+    fn ctx(&self, offset: &Option<TraceOffset>) -> PVMResult<HashMap<&'static str, String>> {
+        let fmt = match self.event_time {
+            RawTs::Str(_) => TsFormat::Rfc3339,
+            _ => TsFormat::Millis,
+        };
+        let time = parse_ts(&self.event_time, fmt).map_err(|_| PVMError::MissingField {
+            evt: self.event_name.clone(),
+            field: "eventTime",
+        })?;
+        let mut ctx = hashmap!(
+            "event" => self.event_name.clone(),
+            "event_source" => self.event_source.clone(),
+            "time" => time.to_rfc3339(),
+        );
+        for (key, val) in &[
+            ("event_id", &self.event_id),
+            ("region", &self.aws_region),
+            ("source_ip", &self.source_ip_address),
+            ("user_agent", &self.user_agent),
+            ("account_id", &self.recipient_account_id),
+        ] {
+            if let Some(val) = val {
+                ctx.insert(*key, val.clone());
+            }
+        }
+        if let Some(ref offset) = offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        Ok(ctx)
+    }
+}
+
+impl CloudTrailLog {
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let records = match &self.records {
+            Records::File { records } => records.iter().collect(),
+            Records::Single(record) => vec![&**record],
+        };
+        for record in records {
+            if record.error_code.is_some() {
+                continue;
+            }
+            let mut tr = pvm.transaction(&CTX, record.ctx(&self.offset)?);
+            match record.apply(&mut tr) {
+                Ok(_) => tr.commit()?,
+                Err(e) => {
+                    tr.rollback();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Mapped for CloudTrailLog {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PRINCIPAL);
+        pvm.register_data_type(&S3_OBJECT);
+        pvm.register_data_type(&RESOURCE);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    fn record(name: &str, params: &str, extra: &str) -> String {
+        format!(
+            r#"{{"eventTime": "2018-07-31T14:10:00Z", "eventSource": "s3.amazonaws.com",
+                "eventName": "{}", "userIdentity": {{"type": "IAMUser",
+                "arn": "arn:aws:iam::123456789012:user/alice"}},
+                "requestParameters": {}{}}}"#,
+            name, params, extra
+        )
+    }
+
+    #[test]
+    fn s3_copy_and_read() {
+        let file = format!(
+            r#"{{"Records": [{}, {}]}}"#,
+            record(
+                "CopyObject",
+                r#"{"bucketName": "b", "key": "copy", "x-amz-copy-source": "b/orig"}"#,
+                ""
+            ),
+            record(
+                "GetObject",
+                r#"{"bucketName": "b", "key": "copy"}"#,
+                r#", "additionalEventData": {"bytesTransferredOut": 10.0}"#
+            ),
+        );
+        let failed = record(
+            "PutObject",
+            r#"{"bucketName": "b", "key": "copy"}"#,
+            r#", "errorCode": "AccessDenied""#,
+        );
+        let trs = run::<CloudTrailLog>(&[file, failed]);
+        assert_eq!(data(&trs, "aws_principal"), 1);
+        assert_eq!(data(&trs, "s3_object"), 3);
+        let ops = ops(&trs);
+        assert_eq!(ops.iter().filter(|op| *op == "Source").count(), 2);
+        assert_eq!(ops.iter().filter(|op| *op == "Sink").count(), 1);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) => format!("{:?}", n).contains("s3://b/orig"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn resources_of_other_services() {
+        let trs = run::<CloudTrailLog>(&[r#"{"eventTime": "2018-07-31T14:10:00Z",
+            "eventSource": "kms.amazonaws.com", "eventName": "Decrypt", "readOnly": true,
+            "userIdentity": {"type": "AWSService", "invokedBy": "lambda.amazonaws.com"},
+            "resources": [{"ARN": "arn:aws:kms:eu-west-2:123456789012:key/k1",
+                           "type": "AWS::KMS::Key"}]}"#
+            .to_string()]);
+        assert_eq!(data(&trs, "aws_principal"), 1);
+        assert_eq!(data(&trs, "aws_resource"), 1);
+        assert_eq!(ops(&trs), vec!["Source".to_string()]);
+    }
+}
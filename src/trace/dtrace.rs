@@ -0,0 +1,429 @@
+//! Generic DTrace probe events
+//!
+//! A schema for custom DTrace scripts to emit, one JSON object per line, so that new capture
+//! scripts can be written without a dedicated mapping:
+//!
+//! ```json
+//! {"probe": "syscall::openat:return", "time": 1533046200125000000, "host": "box",
+//!  "pid": 10, "ppid": 1, "execname": "cat", "uid": 0, "ret": 3, "fd": 3, "path": "/etc/motd"}
+//! ```
+//!
+//! | field       | used for                                                            |
+//! |-------------|---------------------------------------------------------------------|
+//! | `probe`     | required, the event is the probe's function, `openat` above, or its |
+//! |             | name for probes without a function such as `proc:::exit`            |
+//! | `time`      | required, `walltimestamp` in nanoseconds                            |
+//! | `host`      | optional, distinguishes processes from different hosts              |
+//! | `pid`       | required, the calling process                                       |
+//! | `ppid`, `execname`, `uid` | optional properties of the calling process            |
+//! | `ret`       | return value, the child pid for `fork` and the byte count for IO    |
+//! | `errno`     | events with a non zero `errno` are skipped                          |
+//! | `fd`        | descriptor the call used or returned                                |
+//! | `path`      | path the call used, or the path of `fd`                             |
+//! | `path2`     | the new path for `rename` and `link`                                |
+//! | `bytes`     | bytes moved by IO calls, defaults to `ret`                          |
+//! | `argv`      | command line for `exec` calls                                       |
+//! | `address`, `port` | remote end of a socket for `connect` and `accept`             |
+//! | `proc_uuid`, `uuid`, `ret_uuid` | stable ids for the process, the object used and |
+//! |             | the object or child process created, where the script has them      |
+//!
+//! Without uuids processes are identified by host and pid and are released when they exit,
+//! objects by path, or by their owning process and descriptor if no path is given.
+//!
+//! The events understood are `fork`, `vfork`, `rfork`, `pdfork`, `clone`, the `exec` calls,
+//! `exit`, the `open` calls, `read`, `write` and their variants, `close`, `rename`, `link`,
+//! `unlink`, `connect` and `accept`, as well as the `create`, `exec-success` and `exit` probes
+//! of the `proc` provider.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PROCESS, SOCKET},
+        parse_ts, MapFmt, RawTs, TsFormat,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "dtrace_context",
+        props: vec!["time", "event", "probe", "host", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids synthesised for objects without one
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:dtrace");
+}
+
+/// A DTrace probe firing
+#[derive(Deserialize, Debug)]
+pub struct DTraceEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    /// The event the probe refers to, set by `update`
+    #[serde(skip)]
+    pub event: String,
+    pub probe: String,
+    pub time: i64,
+    pub host: Option<String>,
+    pub pid: i32,
+    pub ppid: Option<i32>,
+    pub execname: Option<String>,
+    pub uid: Option<i64>,
+    pub ret: Option<i64>,
+    pub errno: Option<i32>,
+    pub fd: Option<i64>,
+    pub path: Option<String>,
+    pub path2: Option<String>,
+    pub bytes: Option<i64>,
+    pub argv: Option<String>,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub proc_uuid: Option<Uuid>,
+    pub uuid: Option<Uuid>,
+    pub ret_uuid: Option<Uuid>,
+}
+
+impl fmt::Display for DTraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.probe,
+            self.time,
+            self.host,
+            self.pid,
+            self.ppid,
+            self.execname,
+            self.uid,
+            self.ret,
+            self.errno,
+            self.fd,
+            self.path,
+            self.path2,
+            self.bytes,
+            self.argv,
+            self.address,
+            self.port,
+            self.proc_uuid,
+            self.uuid,
+            self.ret_uuid,
+        );
+        ret.finish()
+    }
+}
+
+/// The event a `provider:module:function:name` probe description refers to
+///
+/// This is the function, or the name for probes without one such as those of `proc`.
+fn probe_event(probe: &str) -> &str {
+    let parts: Vec<&str> = probe.split(':').collect();
+    match parts.len() {
+        4 if parts[2].is_empty() => parts[3],
+        4 => parts[2],
+        3 => parts[1],
+        _ => probe,
+    }
+}
+
+impl DTraceEvent {
+    fn uuid(&self, kind: &str, key: &str) -> Uuid {
+        let host = match &self.host {
+            Some(host) => &host[..],
+            None => "",
+        };
+        Uuid::new_v5(&NS, format!("{}:{}:{}", host, kind, key).as_bytes())
+    }
+
+    fn proc_uuid(&self) -> Uuid {
+        match self.proc_uuid {
+            Some(uuid) => uuid,
+            None => self.uuid("proc", &self.pid.to_string()),
+        }
+    }
+
+    /// The object the event used, if it identifies one
+    fn object(&self, ty: &'static ConcreteType, pvm: &mut PVMTransaction) -> PVMResult<Option<ID>> {
+        let uuid = match (self.uuid, &self.path, self.fd) {
+            (Some(uuid), _, _) => uuid,
+            (None, Some(path), _) if ty.name == "file" => self.uuid("path", path),
+            (None, _, Some(fd)) => self.uuid("fd", &format!("{}:{}", self.pid, fd)),
+            _ => return Ok(None),
+        };
+        Ok(Some(pvm.declare(ty, uuid, None)?))
+    }
+
+    fn sys_fork(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let child = match (self.ret_uuid, self.ret) {
+            (Some(uuid), _) => uuid,
+            (None, Some(pid)) if pid > 0 => self.uuid("proc", &pid.to_string()),
+            // The child sees the call return 0
+            _ => return Ok(()),
+        };
+        let ch = pvm.derive(pro, child)?;
+        if let Some(pid) = self.ret {
+            pvm.meta(ch, "pid", &pid)?;
+        }
+        Ok(())
+    }
+
+    fn sys_exec(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = field!(self.path);
+        if let Some(argv) = &self.argv {
+            pvm.meta(pro, "cmdline", argv)?;
+        }
+        if let Some(bin) = self.object(&FILE, pvm)? {
+            pvm.name(bin, Name::Path(path))?;
+            pvm.source(pro, bin)?;
+        }
+        Ok(())
+    }
+
+    fn sys_exit(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        pvm.release(&self.proc_uuid());
+        Ok(())
+    }
+
+    fn sys_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = field!(self.path);
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.name_by(f, Name::Path(path), pro)?;
+        }
+        Ok(())
+    }
+
+    fn io_bytes(&self) -> i64 {
+        self.bytes.or(self.ret).unwrap_or(0)
+    }
+
+    fn sys_read(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.source_nbytes(pro, f, self.io_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn sys_write(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.sinkstart_nbytes(pro, f, self.io_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn sys_close(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.sinkend(pro, f)?;
+        }
+        Ok(())
+    }
+
+    fn sys_rename(&self, pro: ID, pvm: &mut PVMTransaction, keep_old: bool) -> PVMResult<()> {
+        let path = field!(self.path);
+        let path2 = field!(self.path2);
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.name_by(f, Name::Path(path2), pro)?;
+            if !keep_old {
+                pvm.unname(f, Name::Path(path))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sys_unlink(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = field!(self.path);
+        if let Some(f) = self.object(&FILE, pvm)? {
+            pvm.unname(f, Name::Path(path))?;
+        }
+        Ok(())
+    }
+
+    fn sys_connect(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let address = field!(self.address);
+        let port = field!(self.port);
+        if let Some(s) = self.object(&SOCKET, pvm)? {
+            pvm.name(s, Name::Net(address, port))?;
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        if self.errno.unwrap_or(0) != 0 {
+            return Ok(());
+        }
+        let time = parse_ts(&RawTs::Int(self.time), TsFormat::Nanos).map_err(|_| {
+            PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "time",
+            }
+        })?;
+        let mut ctx = hashmap!(
+            "event" => self.event.clone(),
+            "probe" => self.probe.clone(),
+            "time" => time.to_rfc3339(),
+        );
+        if let Some(host) = &self.host {
+            ctx.insert("host", host.clone());
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let mut init = hashmap!("pid" => self.pid.to_string());
+        if let Some(execname) = &self.execname {
+            init.insert("cmdline", execname.clone());
+        }
+        if let Some(uid) = self.uid {
+            init.insert("euid", uid.to_string());
+        }
+        match {
+            let pro = tr.declare(&PROCESS, self.proc_uuid(), Some(init))?;
+            match &self.event[..] {
+                "fork" | "vfork" | "rfork" | "pdfork" | "clone" | "create" => {
+                    self.sys_fork(pro, &mut tr)
+                }
+                "exec" | "execve" | "fexecve" | "execveat" | "exec-success" => {
+                    self.sys_exec(pro, &mut tr)
+                }
+                "exit" => self.sys_exit(pro, &mut tr),
+                "open" | "openat" | "creat" => self.sys_open(pro, &mut tr),
+                "read" | "pread" | "readv" | "preadv" | "recvfrom" | "recvmsg" => {
+                    self.sys_read(pro, &mut tr)
+                }
+                "write" | "pwrite" | "writev" | "pwritev" | "sendto" | "sendmsg" => {
+                    self.sys_write(pro, &mut tr)
+                }
+                "close" => self.sys_close(pro, &mut tr),
+                "rename" | "renameat" => self.sys_rename(pro, &mut tr, false),
+                "link" | "linkat" => self.sys_rename(pro, &mut tr, true),
+                "unlink" | "unlinkat" => self.sys_unlink(pro, &mut tr),
+                "connect" | "accept" | "accept4" => self.sys_connect(pro, &mut tr),
+                evt => {
+                    tr.unparsed(evt);
+                    Ok(())
+                }
+            }
+        } {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for DTraceEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn update(&mut self) {
+        self.event = probe_event(&self.probe).to_string();
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn source(&self) -> Option<String> {
+        self.host.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{names, run},
+        view::DBTr,
+    };
+
+    fn event(probe: &str, pid: i32, extra: &str) -> String {
+        format!(
+            r#"{{"probe": "{}", "time": 1533046200125000000, "host": "box", "pid": {},
+                 "execname": "sh", "uid": 0{}}}"#,
+            probe, pid, extra
+        )
+    }
+
+    #[test]
+    fn probe_events() {
+        assert_eq!(probe_event("syscall::openat:return"), "openat");
+        assert_eq!(probe_event("proc:::exec-success"), "exec-success");
+        assert_eq!(probe_event("fbt:read:entry"), "read");
+        assert_eq!(probe_event("exit"), "exit");
+    }
+
+    #[test]
+    fn fork_exec_and_io() {
+        let trs = run::<DTraceEvent>(&[
+            event("syscall::fork:return", 10, r#", "ret": 11"#),
+            event("syscall::fork:return", 11, r#", "ret": 0"#),
+            event(
+                "syscall::execve:return",
+                11,
+                r#", "path": "/bin/cat", "argv": "cat /etc/motd""#,
+            ),
+            event(
+                "syscall::openat:return",
+                11,
+                r#", "ret": 3, "fd": 3, "path": "/etc/motd""#,
+            ),
+            event(
+                "syscall::read:return",
+                11,
+                r#", "ret": 120, "fd": 3, "path": "/etc/motd""#,
+            ),
+            event(
+                "syscall::open:return",
+                11,
+                r#", "errno": 2, "path": "/nonexistent""#,
+            ),
+            event(
+                "syscall::connect:return",
+                11,
+                r#", "fd": 4, "address": "10.0.0.2", "port": 80"#,
+            ),
+        ]);
+        assert_eq!(
+            trs.iter()
+                .filter(|tr| match tr {
+                    DBTr::CreateNode(Node::Data(d)) => d.ty().name == "process",
+                    _ => false,
+                })
+                .count(),
+            2
+        );
+        let names = names(&trs);
+        assert!(names.iter().any(|n| n.contains("/etc/motd")));
+        assert!(names.iter().any(|n| n.contains("10.0.0.2")));
+        assert!(!names.iter().any(|n| n.contains("/nonexistent")));
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateRel(Rel::Inf(i)) | DBTr::UpdateRel(Rel::Inf(i)) => i.bytes_read == 120,
+            _ => false,
+        }));
+    }
+}
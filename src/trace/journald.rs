@@ -0,0 +1,298 @@
+//! systemd-journald exports
+//!
+//! Takes the JSON export produced by `journalctl -o json`, one entry per line:
+//!
+//! ```json
+//! {"__CURSOR": "s=6c1b...;i=1a2", "__REALTIME_TIMESTAMP": "1533046200120000",
+//!  "_BOOT_ID": "8d6f...", "_MACHINE_ID": "5c2e...", "_HOSTNAME": "host1", "_TRANSPORT": "stdout",
+//!  "_STREAM_ID": "b7d2...", "_PID": "812", "_UID": "0", "_GID": "0", "_COMM": "sshd",
+//!  "_EXE": "/usr/sbin/sshd", "_CMDLINE": "sshd: user [priv]", "_SYSTEMD_UNIT": "ssh.service",
+//!  "_AUDIT_SESSION": "3", "_AUDIT_LOGINUID": "1000", "MESSAGE": "Accepted publickey for user"}
+//! ```
+//!
+//! The logging process becomes an actor keyed by boot and pid, which reads its executable and
+//! writes the message into the socket of the transport it logged over. Entries forwarded from
+//! the kernel audit subsystem carry the audited process' credentials in their `_AUDIT_FIELD_*`
+//! fields but were not written by it, so only the process and its executable are recorded.
+//! Field values that journald exports as byte arrays are decoded lossily, and for fields that
+//! repeat within an entry the first value is used.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ContextType, Name},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PROCESS, SOCKET},
+        camflow::Attrs,
+        parse_ts, MapFmt, RawTs, TsFormat,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "journald_context",
+        props: vec![
+            "time",
+            "event",
+            "host",
+            "boot_id",
+            "cursor",
+            "unit",
+            "syslog_identifier",
+            "audit_session",
+            "audit_loginuid",
+            "message",
+            "trace_offset",
+            "trace_source",
+        ],
+    };
+    /// Namespace for the uuids derived from journal fields
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:journald");
+}
+
+/// A journal entry, keyed by journal field name
+#[derive(Deserialize, Debug)]
+pub struct JournalEntry {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(flatten)]
+    pub fields: Attrs,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(ret; self.fields);
+        ret.finish()
+    }
+}
+
+fn text(val: &Value) -> Option<String> {
+    match val {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        Value::Array(vs) if vs.iter().all(Value::is_u64) => {
+            let bytes: Vec<u8> = vs
+                .iter()
+                .filter_map(Value::as_u64)
+                .map(|b| b as u8)
+                .collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        Value::Array(vs) => vs.first().and_then(text),
+        v => Some(v.to_string()),
+    }
+}
+
+/// The socket journald receives entries on for each transport that has one
+fn transport_socket(transport: &str) -> Option<&'static str> {
+    match transport {
+        "journal" => Some("/run/systemd/journal/socket"),
+        "stdout" => Some("/run/systemd/journal/stdout"),
+        "syslog" => Some("/run/systemd/journal/dev-log"),
+        _ => None,
+    }
+}
+
+impl JournalEntry {
+    fn field(&self, key: &str) -> Option<String> {
+        self.fields.get(key).and_then(text)
+    }
+
+    fn event(&self) -> String {
+        self.field("_TRANSPORT").unwrap_or_default()
+    }
+
+    fn require(&self, key: &'static str) -> PVMResult<String> {
+        self.field(key).ok_or_else(|| PVMError::MissingField {
+            evt: self.event(),
+            field: key,
+        })
+    }
+
+    fn declare_process(&self, boot: &str, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let pid = self.require("_PID")?;
+        let uuid = Uuid::new_v5(&NS, format!("proc:{}:{}", boot, pid).as_bytes());
+        let mut init = hashmap!("pid" => pid);
+        for (key, fields) in &[
+            ("cmdline", &["_CMDLINE", "_COMM", "_AUDIT_FIELD_COMM"][..]),
+            ("ruid", &["_AUDIT_FIELD_UID"]),
+            ("euid", &["_AUDIT_FIELD_EUID", "_UID"]),
+            ("suid", &["_AUDIT_FIELD_SUID"]),
+            ("rgid", &["_AUDIT_FIELD_GID"]),
+            ("egid", &["_AUDIT_FIELD_EGID", "_GID"]),
+            ("sgid", &["_AUDIT_FIELD_SGID"]),
+        ] {
+            if let Some(val) = fields.iter().filter_map(|f| self.field(f)).next() {
+                init.insert(*key, val);
+            }
+        }
+        let pro = pvm.declare(&PROCESS, uuid, Some(init))?;
+        let exe = self
+            .field("_EXE")
+            .or_else(|| self.field("_AUDIT_FIELD_EXE"));
+        if let Some(exe) = exe {
+            let host = self.source().unwrap_or_default();
+            let uuid = Uuid::new_v5(&NS, format!("file:{}:{}", host, exe).as_bytes());
+            let f = pvm.declare(&FILE, uuid, None)?;
+            pvm.name(f, Name::Path(exe))?;
+            pvm.source(pro, f)?;
+        }
+        Ok(pro)
+    }
+
+    fn write(&self, pro: ID, boot: &str, path: &str, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let stream = self.field("_STREAM_ID").unwrap_or_default();
+        let uuid = Uuid::new_v5(
+            &NS,
+            format!("socket:{}:{}:{}", boot, self.event(), stream).as_bytes(),
+        );
+        let s = pvm.declare(&SOCKET, uuid, None)?;
+        pvm.name(s, Name::Path(path.to_string()))?;
+        let len = self.field("MESSAGE").map_or(0, |m| m.len());
+        pvm.sinkstart_nbytes(pro, s, len as i64)?;
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let ts = self.require("__REALTIME_TIMESTAMP")?;
+        let time = parse_ts(&RawTs::Str(format!("{}000", ts)), TsFormat::Nanos).map_err(|_| {
+            PVMError::MissingField {
+                evt: self.event(),
+                field: "__REALTIME_TIMESTAMP",
+            }
+        })?;
+        let event = self.event();
+        let mut ctx = hashmap!(
+            "event" => event.clone(),
+            "time" => time.to_rfc3339(),
+        );
+        for (key, field) in &[
+            ("host", "_HOSTNAME"),
+            ("boot_id", "_BOOT_ID"),
+            ("cursor", "__CURSOR"),
+            ("unit", "_SYSTEMD_UNIT"),
+            ("syslog_identifier", "SYSLOG_IDENTIFIER"),
+            ("audit_session", "_AUDIT_SESSION"),
+            ("audit_loginuid", "_AUDIT_LOGINUID"),
+            ("message", "MESSAGE"),
+        ] {
+            if let Some(val) = self.field(field) {
+                ctx.insert(key, val);
+            }
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let res = match (&event[..], transport_socket(&event)) {
+            (_, Some(path)) if self.fields.contains_key("_PID") => {
+                let boot = self.field("_BOOT_ID").unwrap_or_default();
+                self.declare_process(&boot, &mut tr)
+                    .and_then(|pro| self.write(pro, &boot, path, &mut tr))
+            }
+            ("audit", _) if self.fields.contains_key("_PID") => {
+                let boot = self.field("_BOOT_ID").unwrap_or_default();
+                self.declare_process(&boot, &mut tr).map(|_| ())
+            }
+            _ => {
+                tr.unparsed(&event);
+                Ok(())
+            }
+        };
+        match res {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for JournalEntry {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn source(&self) -> Option<String> {
+        self.field("_MACHINE_ID")
+            .or_else(|| self.field("_HOSTNAME"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    #[test]
+    fn stdout_entries_share_process_and_stream() {
+        let entry = r#"{"__REALTIME_TIMESTAMP": "1533046200120000", "_BOOT_ID": "b1",
+            "_MACHINE_ID": "m1", "_TRANSPORT": "stdout", "_STREAM_ID": "s1", "_PID": "812",
+            "_UID": "0", "_GID": "0", "_COMM": "sshd", "_EXE": "/usr/sbin/sshd",
+            "MESSAGE": "Accepted publickey"}"#;
+        let trs = run::<JournalEntry>(&[entry, entry]);
+        assert_eq!(data(&trs, "process"), 1);
+        assert_eq!(data(&trs, "file"), 1);
+        assert_eq!(data(&trs, "socket"), 1);
+        let ops = ops(&trs);
+        assert!(ops.contains(&"Source".to_string()));
+        assert!(ops.contains(&"Sink".to_string()));
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) =>
+                format!("{:?}", n).contains("/run/systemd/journal/stdout"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn audit_and_kernel_entries() {
+        let trs = run::<JournalEntry>(&[
+            r#"{"__REALTIME_TIMESTAMP": "1533046200120000", "_BOOT_ID": "b1",
+                "_TRANSPORT": "audit", "_PID": "900", "_AUDIT_FIELD_EUID": "1000",
+                "_AUDIT_FIELD_EXE": [47, 98, 105, 110, 47, 115, 104],
+                "MESSAGE": "SYSCALL arch=c000003e"}"#,
+            r#"{"__REALTIME_TIMESTAMP": "1533046200130000", "_BOOT_ID": "b1",
+                "_TRANSPORT": "kernel", "MESSAGE": "eth0: link up"}"#,
+        ]);
+        assert_eq!(data(&trs, "process"), 1);
+        assert_eq!(data(&trs, "socket"), 0);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) => format!("{:?}", n).contains("/bin/sh"),
+            _ => false,
+        }));
+    }
+}
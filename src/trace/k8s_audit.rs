@@ -0,0 +1,408 @@
+//! Kubernetes API server audit logs
+//!
+//! Takes `audit.k8s.io/v1` events as written by the API server's JSON log backend, one event
+//! per line:
+//!
+//! ```json
+//! {"kind": "Event", "apiVersion": "audit.k8s.io/v1", "level": "Metadata",
+//!  "auditID": "5d9b...", "stage": "ResponseComplete", "verb": "get",
+//!  "requestURI": "/api/v1/namespaces/default/secrets/db-creds",
+//!  "user": {"username": "system:serviceaccount:default:builder", "uid": "91a2...",
+//!           "groups": ["system:serviceaccounts"]},
+//!  "sourceIPs": ["10.0.0.7"], "userAgent": "kubectl/v1.13.0",
+//!  "objectRef": {"resource": "secrets", "namespace": "default", "name": "db-creds",
+//!                "apiVersion": "v1"},
+//!  "responseStatus": {"code": 200},
+//!  "requestReceivedTimestamp": "2018-07-31T14:10:00.120000Z",
+//!  "stageTimestamp": "2018-07-31T14:10:00.125000Z"}
+//! ```
+//!
+//! The requesting user or service account becomes an actor, impersonated users taking the
+//! place of the user that impersonated them. Secrets and config maps become stores of their own
+//! types and other API objects generic object stores, each named by its REST path. Reading
+//! verbs source from the object, modifying verbs sink into it and deletion also removes its
+//! name. Only the `ResponseComplete` stage of successful requests on a named object is mapped,
+//! as the earlier stages of a request repeat it and collection requests name no object.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{parse_ts, MapFmt, RawTs, TsFormat},
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref ACCOUNT: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "k8s_account",
+        props: hashmap!("username" => false,
+                        "uid" => true,
+                        "groups" => true),
+    };
+    static ref SECRET: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "k8s_secret",
+        props: object_props(),
+    };
+    static ref CONFIG_MAP: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "k8s_configmap",
+        props: object_props(),
+    };
+    static ref OBJECT: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "k8s_object",
+        props: object_props(),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "k8s_audit_context",
+        props: vec![
+            "time",
+            "event",
+            "audit_id",
+            "request_uri",
+            "source_ips",
+            "user_agent",
+            "response_code",
+            "trace_offset",
+            "trace_source",
+        ],
+    };
+    /// Namespace for the uuids derived from user names and object paths
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:k8s_audit");
+}
+
+fn object_props() -> HashMap<&'static str, bool> {
+    hashmap!("resource" => false,
+             "namespace" => false,
+             "name" => false,
+             "api_group" => false,
+             "api_version" => true,
+             "object_uid" => true)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserInfo {
+    pub username: Option<String>,
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectReference {
+    pub resource: Option<String>,
+    pub namespace: Option<String>,
+    pub name: Option<String>,
+    pub uid: Option<String>,
+    pub api_group: Option<String>,
+    pub api_version: Option<String>,
+    pub subresource: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseStatus {
+    pub code: Option<i64>,
+}
+
+/// An audit event from the API server
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct K8sAuditEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(rename = "verb")]
+    pub event: String,
+    #[serde(rename = "auditID")]
+    pub audit_id: Option<String>,
+    pub stage: Option<String>,
+    pub request_uri: Option<String>,
+    pub user: UserInfo,
+    pub impersonated_user: Option<UserInfo>,
+    #[serde(default, rename = "sourceIPs")]
+    pub source_ips: Vec<String>,
+    pub user_agent: Option<String>,
+    pub object_ref: Option<ObjectReference>,
+    pub response_status: Option<ResponseStatus>,
+    pub request_received_timestamp: Option<RawTs>,
+    pub stage_timestamp: Option<RawTs>,
+}
+
+impl fmt::Display for K8sAuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.event,
+            self.audit_id,
+            self.stage,
+            self.request_uri,
+            self.user,
+            self.impersonated_user,
+            self.source_ips,
+            self.user_agent,
+            self.object_ref,
+            self.response_status,
+            self.request_received_timestamp,
+            self.stage_timestamp,
+        );
+        ret.finish()
+    }
+}
+
+impl ObjectReference {
+    /// The REST path of the object, as it appears in request URIs
+    fn path(&self, name: &str) -> String {
+        let mut path = match self.api_group.as_ref().filter(|g| !g.is_empty()) {
+            Some(group) => format!("/apis/{}", group),
+            None => "/api".to_string(),
+        };
+        path.push_str(&format!(
+            "/{}",
+            self.api_version.clone().unwrap_or_else(|| "v1".to_string())
+        ));
+        if let Some(ns) = self.namespace.as_ref().filter(|ns| !ns.is_empty()) {
+            path.push_str(&format!("/namespaces/{}", ns));
+        }
+        path.push_str(&format!(
+            "/{}/{}",
+            self.resource.clone().unwrap_or_default(),
+            name
+        ));
+        path
+    }
+}
+
+impl K8sAuditEvent {
+    fn declare_account(&self, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let user = self.impersonated_user.as_ref().unwrap_or(&self.user);
+        let username = user.username.clone().ok_or(PVMError::MissingField {
+            evt: self.event.clone(),
+            field: "username",
+        })?;
+        let uuid = Uuid::new_v5(&NS, format!("user:{}", username).as_bytes());
+        let mut init = hashmap!("username" => username);
+        if let Some(ref uid) = user.uid {
+            init.insert("uid", uid.clone());
+        }
+        if !user.groups.is_empty() {
+            init.insert("groups", user.groups.join(","));
+        }
+        pvm.declare(&ACCOUNT, uuid, Some(init))
+    }
+
+    fn declare_object(
+        &self,
+        obj: &ObjectReference,
+        name: &str,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<(Uuid, ID, String)> {
+        let resource = obj.resource.clone().ok_or(PVMError::MissingField {
+            evt: self.event.clone(),
+            field: "resource",
+        })?;
+        let ty: &ConcreteType = match &resource[..] {
+            "secrets" => &SECRET,
+            "configmaps" => &CONFIG_MAP,
+            _ => &OBJECT,
+        };
+        let path = obj.path(name);
+        let uuid = Uuid::new_v5(&NS, format!("obj:{}", path).as_bytes());
+        let mut init = hashmap!(
+            "resource" => resource.clone(),
+            "namespace" => obj.namespace.clone().unwrap_or_default(),
+            "name" => name.to_string(),
+            "api_group" => obj.api_group.clone().unwrap_or_default(),
+        );
+        if let Some(ref version) = obj.api_version {
+            init.insert("api_version", version.clone());
+        }
+        if let Some(ref uid) = obj.uid {
+            init.insert("object_uid", uid.clone());
+        }
+        let o = pvm.declare(ty, uuid, Some(init))?;
+        pvm.name(o, Name::Path(path.clone()))?;
+        Ok((uuid, o, path))
+    }
+
+    fn request(
+        &self,
+        obj: &ObjectReference,
+        name: &str,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        let act = self.declare_account(pvm)?;
+        let (uuid, o, path) = self.declare_object(obj, name, pvm)?;
+        match &self.event[..] {
+            "get" | "list" | "watch" => {
+                pvm.source(act, o)?;
+            }
+            "create" | "update" | "patch" => {
+                pvm.sink(act, o)?;
+            }
+            "delete" => {
+                pvm.sink(act, o)?;
+                let o = pvm.lookup(&uuid).unwrap_or(o);
+                pvm.unname(o, Name::Path(path))?;
+                pvm.release(&uuid);
+            }
+            _ => pvm.unparsed(&self.event),
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let ts = self
+            .stage_timestamp
+            .as_ref()
+            .or(self.request_received_timestamp.as_ref())
+            .ok_or(PVMError::MissingField {
+                evt: self.event.clone(),
+                field: "stage_timestamp",
+            })?;
+        let time = parse_ts(ts, TsFormat::Rfc3339).map_err(|_| PVMError::MissingField {
+            evt: self.event.clone(),
+            field: "stage_timestamp",
+        })?;
+        let code = self.response_status.as_ref().and_then(|s| s.code);
+        let mut ctx = hashmap!(
+            "event" => self.event.clone(),
+            "time" => time.to_rfc3339(),
+        );
+        if let Some(ref id) = self.audit_id {
+            ctx.insert("audit_id", id.clone());
+        }
+        if let Some(ref uri) = self.request_uri {
+            ctx.insert("request_uri", uri.clone());
+        }
+        if !self.source_ips.is_empty() {
+            ctx.insert("source_ips", self.source_ips.join(","));
+        }
+        if let Some(ref agent) = self.user_agent {
+            ctx.insert("user_agent", agent.clone());
+        }
+        if let Some(code) = code {
+            ctx.insert("response_code", code.to_string());
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let res = match self.object_ref.as_ref() {
+            Some(obj) => match obj.name.as_ref() {
+                Some(name) => self.request(obj, name, &mut tr),
+                None => {
+                    tr.unparsed(&self.event);
+                    Ok(())
+                }
+            },
+            None => {
+                tr.unparsed(&self.event);
+                Ok(())
+            }
+        };
+        match res {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for K8sAuditEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&ACCOUNT);
+        pvm.register_data_type(&SECRET);
+        pvm.register_data_type(&CONFIG_MAP);
+        pvm.register_data_type(&OBJECT);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let complete = match self.stage {
+            Some(ref stage) => stage == "ResponseComplete",
+            None => true,
+        };
+        match self.response_status.as_ref().and_then(|s| s.code) {
+            Some(code) if code >= 400 => Ok(()),
+            _ if complete => self.parse(pvm),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    fn event(verb: &str, stage: &str, resource: &str, code: i64) -> String {
+        format!(
+            r#"{{"kind": "Event", "auditID": "a1", "stage": "{}", "verb": "{}",
+                "user": {{"username": "system:serviceaccount:default:builder"}},
+                "objectRef": {{"resource": "{}", "namespace": "default", "name": "db",
+                               "apiVersion": "v1"}},
+                "responseStatus": {{"code": {}}},
+                "stageTimestamp": "2018-07-31T14:10:00.125Z"}}"#,
+            stage, verb, resource, code
+        )
+    }
+
+    #[test]
+    fn account_reads_secret() {
+        let get = event("get", "ResponseComplete", "secrets", 200);
+        let trs = run::<K8sAuditEvent>(&[
+            &event("get", "RequestReceived", "secrets", 200),
+            &get,
+            &event("get", "ResponseComplete", "secrets", 403),
+        ]);
+        assert_eq!(data(&trs, "k8s_account"), 1);
+        assert_eq!(data(&trs, "k8s_secret"), 1);
+        assert_eq!(ops(&trs), vec!["Source".to_string()]);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) =>
+                format!("{:?}", n).contains("/api/v1/namespaces/default/secrets/db"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn update_and_delete_configmap() {
+        let trs = run::<K8sAuditEvent>(&[
+            &event("update", "ResponseComplete", "configmaps", 200),
+            &event("delete", "ResponseComplete", "configmaps", 200),
+            &event("create", "ResponseComplete", "configmaps", 201),
+        ]);
+        assert_eq!(data(&trs, "k8s_configmap"), 5);
+        let ops = ops(&trs);
+        assert_eq!(ops.iter().filter(|op| *op == "Sink").count(), 3);
+        assert!(ops.contains(&"Version".to_string()));
+    }
+}
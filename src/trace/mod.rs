@@ -1,8 +1,9 @@
 //! Tools for writing trace descriptions
 
-use chrono::{DateTime, Utc};
-use std::fmt;
+use std::{fmt, str::FromStr};
 
+use chrono::{DateTime, LocalResult, TimeZone, Utc};
+use serde_derive::Deserialize;
 use uuid::Uuid;
 
 /// Macro for easy extraction of potentially absent fields from trace event structures.
@@ -76,4 +77,267 @@ macro_rules! fields_to_map {
     };
 }
 
+/// Encoding used for timestamps in a trace
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TsFormat {
+    /// Integer nanoseconds since the epoch
+    Nanos,
+    /// Integer milliseconds since the epoch
+    Millis,
+    /// Integer or fractional seconds since the epoch
+    Seconds,
+    /// RFC 3339 formatted strings
+    Rfc3339,
+}
+
+impl Default for TsFormat {
+    fn default() -> Self {
+        TsFormat::Nanos
+    }
+}
+
+impl FromStr for TsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "nanos" => Ok(TsFormat::Nanos),
+            "millis" => Ok(TsFormat::Millis),
+            "seconds" => Ok(TsFormat::Seconds),
+            "rfc3339" => Ok(TsFormat::Rfc3339),
+            _ => Err(format!("Unknown timestamp format {}", s)),
+        }
+    }
+}
+
+/// A timestamp as it appears in a trace, prior to interpretation
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RawTs {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl MapFmt for RawTs {
+    fn entry(&self, f: &mut fmt::DebugMap, key: &str) {
+        match self {
+            RawTs::Int(v) => f.entry(&key, v),
+            RawTs::Float(v) => f.entry(&key, v),
+            RawTs::Str(v) => f.entry(&key, v),
+        };
+    }
+}
+
+fn from_parts(secs: i64, nsecs: i64) -> Result<DateTime<Utc>, String> {
+    match Utc.timestamp_opt(secs, nsecs as u32) {
+        LocalResult::Single(dt) => Ok(dt),
+        _ => Err(format!("Timestamp out of range: {}s {}ns", secs, nsecs)),
+    }
+}
+
+/// Interpret a raw trace timestamp according to the given format
+///
+/// Numeric formats also accept their value encoded as a string.
+pub fn parse_ts(raw: &RawTs, fmt: TsFormat) -> Result<DateTime<Utc>, String> {
+    match (fmt, raw) {
+        (TsFormat::Rfc3339, RawTs::Str(s)) => DateTime::parse_from_rfc3339(s)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|e| format!("Invalid RFC 3339 timestamp {}: {}", s, e)),
+        (TsFormat::Rfc3339, _) => Err(format!("Expected RFC 3339 string, got {:?}", raw)),
+        (TsFormat::Seconds, RawTs::Float(f)) => {
+            let secs = f.floor();
+            let nsecs = ((f - secs) * 1e9).round() as i64;
+            // a fraction just below 1 can round up to a whole second
+            from_parts(secs as i64 + nsecs / 1_000_000_000, nsecs % 1_000_000_000)
+        }
+        (_, RawTs::Float(f)) => Err(format!("Expected integer timestamp, got {}", f)),
+        (_, RawTs::Str(s)) => match s.parse::<i64>() {
+            Ok(v) => parse_ts(&RawTs::Int(v), fmt),
+            Err(_) => match s.parse::<f64>() {
+                Ok(v) if fmt == TsFormat::Seconds => parse_ts(&RawTs::Float(v), fmt),
+                _ => Err(format!("Invalid numeric timestamp {}", s)),
+            },
+        },
+        (TsFormat::Nanos, RawTs::Int(v)) => {
+            from_parts(v.div_euclid(1_000_000_000), v.rem_euclid(1_000_000_000))
+        }
+        (TsFormat::Millis, RawTs::Int(v)) => {
+            from_parts(v.div_euclid(1_000), v.rem_euclid(1_000) * 1_000_000)
+        }
+        (TsFormat::Seconds, RawTs::Int(v)) => from_parts(*v, 0),
+    }
+}
+
+pub mod auditd;
 pub mod cadets;
+pub mod camflow;
+pub mod cloudtrail;
+pub mod dtrace;
+pub mod journald;
+pub mod k8s_audit;
+pub mod openbsm;
+pub mod otel;
+pub mod prov_json;
+pub mod spade;
+pub mod strace;
+pub mod sysdig;
+pub mod zeek;
+
+/// Harness shared by the tests of the trace formats
+#[cfg(test)]
+pub(crate) mod test_util {
+    use std::sync::mpsc::sync_channel;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        ingest::{
+            pvm::{PVMResult, PVM},
+            IngestOptions, Mapped,
+        },
+        view::DBTr,
+    };
+
+    /// Map already parsed records through a fresh PVM with the given options, returning the
+    /// result of mapping each record alongside the operations emitted
+    pub(crate) fn run_events_with<T: Mapped, I: IntoIterator<Item = T>>(
+        opts: IngestOptions,
+        evts: I,
+    ) -> (Vec<PVMResult<()>>, Vec<DBTr>) {
+        let (send, recv) = sync_channel(10_000);
+        let mut pvm = PVM::new(send);
+        pvm.set_options(opts);
+        T::init(&mut pvm);
+        let res = evts
+            .into_iter()
+            .map(|mut evt| {
+                evt.update();
+                evt.process(&mut pvm)
+            })
+            .collect();
+        drop(pvm);
+        (res, recv.iter().collect())
+    }
+
+    /// Map already parsed records through a fresh PVM, returning the operations it emitted
+    pub(crate) fn run_events<T: Mapped, I: IntoIterator<Item = T>>(evts: I) -> Vec<DBTr> {
+        let (res, trs) = run_events_with(IngestOptions::default(), evts);
+        for r in res {
+            r.unwrap();
+        }
+        trs
+    }
+
+    /// Parse the lines accepted by the format and map them as `run_events_with` does
+    pub(crate) fn run_with<T: Mapped>(
+        opts: IngestOptions,
+        lines: &[impl AsRef<str>],
+    ) -> (Vec<PVMResult<()>>, Vec<DBTr>) {
+        run_events_with(opts, parse::<T>(lines))
+    }
+
+    /// Parse the lines accepted by the format and map them as `run_events` does
+    pub(crate) fn run<T: Mapped>(lines: &[impl AsRef<str>]) -> Vec<DBTr> {
+        run_events(parse::<T>(lines))
+    }
+
+    fn parse<T: Mapped>(lines: &[impl AsRef<str>]) -> Vec<T> {
+        lines
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|line| T::accept(line))
+            .map(|line| serde_json::from_str::<T>(line).unwrap())
+            .collect()
+    }
+
+    /// Number of data nodes of type `ty` created
+    pub(crate) fn data(trs: &[DBTr], ty: &str) -> usize {
+        trs.iter()
+            .filter(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) => d.ty().name == ty,
+                _ => false,
+            })
+            .count()
+    }
+
+    /// The name nodes created, in order
+    pub(crate) fn names(trs: &[DBTr]) -> Vec<String> {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Name(n)) => Some(format!("{:?}", n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The operations of the influence edges created, in order
+    pub(crate) fn ops(trs: &[DBTr]) -> Vec<String> {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn expected() -> DateTime<Utc> {
+        utc("2018-04-06T12:30:15.25Z")
+    }
+
+    #[test]
+    fn parse_nanos() {
+        let raw = RawTs::Int(1_523_017_815_250_000_000);
+        assert_eq!(parse_ts(&raw, TsFormat::Nanos), Ok(expected()));
+    }
+
+    #[test]
+    fn parse_millis() {
+        let raw = RawTs::Int(1_523_017_815_250);
+        assert_eq!(parse_ts(&raw, TsFormat::Millis), Ok(expected()));
+    }
+
+    #[test]
+    fn parse_seconds() {
+        let raw = RawTs::Float(1_523_017_815.25);
+        assert_eq!(parse_ts(&raw, TsFormat::Seconds), Ok(expected()));
+        let raw = RawTs::Int(1_523_017_815);
+        assert_eq!(
+            parse_ts(&raw, TsFormat::Seconds),
+            Ok(utc("2018-04-06T12:30:15Z"))
+        );
+    }
+
+    #[test]
+    fn parse_seconds_rounding_up() {
+        let raw = RawTs::Float(1.999_999_999_9);
+        assert_eq!(
+            parse_ts(&raw, TsFormat::Seconds),
+            Ok(utc("1970-01-01T00:00:02Z"))
+        );
+    }
+
+    #[test]
+    fn parse_rfc3339() {
+        let raw = RawTs::Str("2018-04-06T13:30:15.25+01:00".to_string());
+        assert_eq!(parse_ts(&raw, TsFormat::Rfc3339), Ok(expected()));
+        assert!(parse_ts(&RawTs::Int(0), TsFormat::Rfc3339).is_err());
+    }
+
+    #[test]
+    fn parse_numeric_string() {
+        let raw = RawTs::Str("1523017815250".to_string());
+        assert_eq!(parse_ts(&raw, TsFormat::Millis), Ok(expected()));
+        let raw = RawTs::Str("garbage".to_string());
+        assert!(parse_ts(&raw, TsFormat::Nanos).is_err());
+    }
+}
@@ -0,0 +1,615 @@
+//! macOS OpenBSM audit traces
+//!
+//! Recent macOS releases deliver their BSM audit events through the Endpoint Security framework,
+//! this mapping takes its JSON serialisation, one message per line, as written by `eslogger`:
+//!
+//! ```json
+//! {"time": "2023-05-01T12:00:00.123456789Z", "global_seq_num": 42, "action_type": 1,
+//!  "process": {"audit_token": {"pid": 10, "euid": 501, "ruid": 501, "egid": 20, "rgid": 20},
+//!              "executable": {"path": "/bin/zsh"}, "ppid": 1,
+//!              "start_time": "2023-05-01T11:59:00.000000Z"},
+//!  "event": {"open": {"fflag": 1,
+//!                     "file": {"path": "/etc/hosts", "stat": {"st_dev": 16777230,
+//!                                                            "st_ino": 1152921500312116354}}}}}
+//! ```
+//!
+//! Processes are identified by pid and start time, which stay the same across an exec, files by
+//! device and inode where a stat is given and by path otherwise. Endpoint Security messages do
+//! not name the machine they came from, so traces from different machines should be ingested
+//! into separate graphs. Authorisation requests are skipped as the operation may be denied, only
+//! notifications are mapped.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    data::{
+        node_types::{ContextType, Name},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PROCESS, SOCKET},
+        MapFmt,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "openbsm_context",
+        props: vec!["time", "event", "seq_num", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids synthesised for Endpoint Security objects
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:openbsm");
+}
+
+/// `es_action_type_t` value of authorisation requests
+const ES_ACTION_TYPE_AUTH: i32 = 0;
+
+#[derive(Deserialize, Debug)]
+pub struct AuditToken {
+    pub pid: i32,
+    pub euid: Option<i64>,
+    pub ruid: Option<i64>,
+    pub egid: Option<i64>,
+    pub rgid: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Stat {
+    pub st_dev: i64,
+    pub st_ino: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EsFile {
+    pub path: String,
+    pub stat: Option<Stat>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EsProcess {
+    pub audit_token: AuditToken,
+    pub executable: Option<EsFile>,
+    pub ppid: Option<i32>,
+    pub start_time: Option<String>,
+}
+
+/// An Endpoint Security message
+#[derive(Deserialize, Debug)]
+pub struct BsmEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    pub time: Option<String>,
+    pub global_seq_num: Option<u64>,
+    pub action_type: Option<i32>,
+    pub process: EsProcess,
+    /// The event payload, keyed by the event name
+    pub event: BTreeMap<String, Value>,
+}
+
+impl fmt::Display for BsmEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.time,
+            self.global_seq_num,
+            self.action_type,
+            self.process,
+            self.event,
+        );
+        ret.finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct Exec {
+    target: EsProcess,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Fork {
+    child: EsProcess,
+}
+
+#[derive(Deserialize)]
+struct Open {
+    file: EsFile,
+}
+
+#[derive(Deserialize)]
+struct Close {
+    modified: bool,
+    target: EsFile,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    target: EsFile,
+}
+
+#[derive(Deserialize)]
+struct NewPath {
+    dir: EsFile,
+    filename: String,
+}
+
+/// Where a create or rename leaves its file, `destination_type` selects the field that is set
+#[derive(Deserialize)]
+struct Destination {
+    existing_file: Option<EsFile>,
+    new_path: Option<NewPath>,
+}
+
+#[derive(Deserialize)]
+struct Create {
+    destination: Destination,
+}
+
+#[derive(Deserialize)]
+struct Rename {
+    source: EsFile,
+    destination: Destination,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    source: EsFile,
+    target_dir: EsFile,
+    target_filename: String,
+}
+
+#[derive(Deserialize)]
+struct SetMode {
+    mode: u32,
+    target: EsFile,
+}
+
+#[derive(Deserialize)]
+struct SetOwner {
+    uid: i64,
+    gid: i64,
+    target: EsFile,
+}
+
+#[derive(Deserialize)]
+struct Mmap {
+    source: EsFile,
+}
+
+#[derive(Deserialize)]
+struct UipcConnect {
+    file: EsFile,
+}
+
+#[derive(Deserialize)]
+struct SetId {
+    uid: Option<i64>,
+    gid: Option<i64>,
+}
+
+impl BsmEvent {
+    fn event(&self) -> &str {
+        self.event.keys().next().map_or("", String::as_str)
+    }
+
+    /// The payload of the event
+    fn payload<T: DeserializeOwned>(&self) -> PVMResult<T> {
+        self.event
+            .values()
+            .next()
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .ok_or_else(|| PVMError::MissingField {
+                evt: self.event().to_string(),
+                field: "event",
+            })
+    }
+
+    fn proc_uuid(&self, proc: &EsProcess) -> Uuid {
+        let key = format!(
+            "proc:{}:{}",
+            proc.audit_token.pid,
+            proc.start_time.as_ref().map_or("", String::as_str)
+        );
+        Uuid::new_v5(&NS, key.as_bytes())
+    }
+
+    fn file_uuid(&self, file: &EsFile) -> Uuid {
+        let key = match &file.stat {
+            Some(st) => format!("file:{}:{}", st.st_dev, st.st_ino),
+            None => format!("path:{}", file.path),
+        };
+        Uuid::new_v5(&NS, key.as_bytes())
+    }
+
+    fn declare_proc(&self, proc: &EsProcess, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let tok = &proc.audit_token;
+        let mut init = hashmap!("pid" => tok.pid.to_string());
+        for (key, val) in &[
+            ("euid", tok.euid),
+            ("ruid", tok.ruid),
+            ("egid", tok.egid),
+            ("rgid", tok.rgid),
+        ] {
+            if let Some(val) = val {
+                init.insert(key, val.to_string());
+            }
+        }
+        pvm.declare(&PROCESS, self.proc_uuid(proc), Some(init))
+    }
+
+    fn declare_file(&self, file: &EsFile, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let f = pvm.declare(&FILE, self.file_uuid(file), None)?;
+        pvm.name(f, Name::Path(file.path.clone()))?;
+        Ok(f)
+    }
+
+    fn es_exec(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let exec: Exec = self.payload()?;
+        if let Some(exe) = &exec.target.executable {
+            let bin = self.declare_file(exe, pvm)?;
+            pvm.source(pro, bin)?;
+        }
+        if !exec.args.is_empty() {
+            pvm.meta(pro, "cmdline", &exec.args.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn es_fork(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let fork: Fork = self.payload()?;
+        let ch = pvm.derive(pro, self.proc_uuid(&fork.child))?;
+        pvm.meta(ch, "pid", &fork.child.audit_token.pid)?;
+        pvm.source(ch, pro)?;
+        Ok(())
+    }
+
+    fn es_exit(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        pvm.release(&self.proc_uuid(&self.process));
+        Ok(())
+    }
+
+    fn es_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let open: Open = self.payload()?;
+        let f = pvm.declare(&FILE, self.file_uuid(&open.file), None)?;
+        pvm.name_by(f, Name::Path(open.file.path), pro)?;
+        Ok(())
+    }
+
+    fn es_write(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let write: Target = self.payload()?;
+        let f = self.declare_file(&write.target, pvm)?;
+        pvm.sinkstart(pro, f)?;
+        Ok(())
+    }
+
+    fn es_close(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let close: Close = self.payload()?;
+        if close.modified {
+            let f = pvm.declare(&FILE, self.file_uuid(&close.target), None)?;
+            pvm.sinkend(pro, f)?;
+        }
+        Ok(())
+    }
+
+    fn es_mmap(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let mmap: Mmap = self.payload()?;
+        let f = self.declare_file(&mmap.source, pvm)?;
+        pvm.source(pro, f)?;
+        Ok(())
+    }
+
+    fn es_create(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let create: Create = self.payload()?;
+        // Notifications are sent once the file exists, only authorisation requests see a new path
+        if let Some(file) = create.destination.existing_file {
+            let f = pvm.declare(&FILE, self.file_uuid(&file), None)?;
+            pvm.name_by(f, Name::Path(file.path), pro)?;
+        }
+        Ok(())
+    }
+
+    fn es_rename(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let rename: Rename = self.payload()?;
+        let f = pvm.declare(&FILE, self.file_uuid(&rename.source), None)?;
+        pvm.unname(f, Name::Path(rename.source.path.clone()))?;
+        let dst = match (
+            rename.destination.existing_file,
+            rename.destination.new_path,
+        ) {
+            (Some(ovr), _) => {
+                let fovr = pvm.declare(&FILE, self.file_uuid(&ovr), None)?;
+                pvm.unname(fovr, Name::Path(ovr.path.clone()))?;
+                ovr.path
+            }
+            (None, Some(new)) => join(&new.dir.path, &new.filename),
+            (None, None) => {
+                return Err(PVMError::MissingField {
+                    evt: self.event().to_string(),
+                    field: "destination",
+                })
+            }
+        };
+        pvm.name_by(f, Name::Path(dst), pro)?;
+        Ok(())
+    }
+
+    fn es_unlink(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let unlink: Target = self.payload()?;
+        let f = pvm.declare(&FILE, self.file_uuid(&unlink.target), None)?;
+        pvm.unname(f, Name::Path(unlink.target.path))?;
+        Ok(())
+    }
+
+    fn es_link(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let link: Link = self.payload()?;
+        let f = self.declare_file(&link.source, pvm)?;
+        let name = join(&link.target_dir.path, &link.target_filename);
+        pvm.name_by(f, Name::Path(name), pro)?;
+        Ok(())
+    }
+
+    fn es_setmode(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let setmode: SetMode = self.payload()?;
+        let f = self.declare_file(&setmode.target, pvm)?;
+        pvm.meta(f, "mode", &format!("{:o}", setmode.mode))?;
+        pvm.sink(pro, f)?;
+        Ok(())
+    }
+
+    fn es_setowner(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let setowner: SetOwner = self.payload()?;
+        let f = self.declare_file(&setowner.target, pvm)?;
+        // -1 leaves the id unchanged
+        if setowner.uid != -1 {
+            pvm.meta(f, "owner_uid", &setowner.uid)?;
+        }
+        if setowner.gid != -1 {
+            pvm.meta(f, "owner_gid", &setowner.gid)?;
+        }
+        pvm.sink(pro, f)?;
+        Ok(())
+    }
+
+    fn es_uipc_connect(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let connect: UipcConnect = self.payload()?;
+        let s = pvm.declare(&SOCKET, self.file_uuid(&connect.file), None)?;
+        pvm.name(s, Name::Path(connect.file.path))?;
+        Ok(())
+    }
+
+    fn es_setid(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let setid: SetId = self.payload()?;
+        let keys: &[&'static str] = match self.event() {
+            "setuid" => &["ruid", "euid"],
+            "seteuid" => &["euid"],
+            "setgid" => &["rgid", "egid"],
+            _ => &["egid"],
+        };
+        if let Some(id) = setid.uid.or(setid.gid) {
+            for key in keys {
+                pvm.meta(pro, key, &id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        if self.action_type == Some(ES_ACTION_TYPE_AUTH) {
+            return Ok(());
+        }
+        let mut ctx = hashmap!("event" => self.event().to_string());
+        if let Some(time) = &self.time {
+            ctx.insert("time", time.clone());
+        }
+        if let Some(seq) = self.global_seq_num {
+            ctx.insert("seq_num", seq.to_string());
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        match {
+            let pro = self.declare_proc(&self.process, &mut tr)?;
+            match self.event() {
+                "exec" => self.es_exec(pro, &mut tr),
+                "fork" => self.es_fork(pro, &mut tr),
+                "exit" => self.es_exit(pro, &mut tr),
+                "open" => self.es_open(pro, &mut tr),
+                "write" => self.es_write(pro, &mut tr),
+                "close" => self.es_close(pro, &mut tr),
+                "mmap" => self.es_mmap(pro, &mut tr),
+                "create" => self.es_create(pro, &mut tr),
+                "rename" => self.es_rename(pro, &mut tr),
+                "unlink" => self.es_unlink(pro, &mut tr),
+                "link" => self.es_link(pro, &mut tr),
+                "setmode" => self.es_setmode(pro, &mut tr),
+                "setowner" => self.es_setowner(pro, &mut tr),
+                "uipc_connect" => self.es_uipc_connect(pro, &mut tr),
+                "setuid" | "seteuid" | "setgid" | "setegid" => self.es_setid(pro, &mut tr),
+                evt => {
+                    tr.unparsed(evt);
+                    Ok(())
+                }
+            }
+        } {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+impl Mapped for BsmEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{names, ops, run},
+        view::DBTr,
+    };
+
+    fn process(pid: i32) -> String {
+        format!(
+            r#"{{"audit_token": {{"pid": {}, "euid": 501, "ruid": 501}},
+                 "executable": {{"path": "/bin/zsh"}}, "ppid": 1,
+                 "start_time": "2023-05-01T11:59:0{}.000000Z"}}"#,
+            pid,
+            pid % 10
+        )
+    }
+
+    fn file(path: &str, ino: u64) -> String {
+        format!(
+            r#"{{"path": "{}", "stat": {{"st_dev": 16777230, "st_ino": {}}}}}"#,
+            path, ino
+        )
+    }
+
+    fn event(pid: i32, action: i32, name: &str, payload: &str) -> String {
+        format!(
+            r#"{{"time": "2023-05-01T12:00:00.123456789Z", "global_seq_num": 1,
+                 "action_type": {}, "process": {}, "event": {{"{}": {}}}}}"#,
+            action,
+            process(pid),
+            name,
+            payload
+        )
+    }
+
+    fn notify(pid: i32, name: &str, payload: &str) -> String {
+        event(pid, 1, name, payload)
+    }
+
+    fn pvm_tys(trs: &[DBTr], ty: &str) -> Vec<String> {
+        trs.iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Data(d)) if d.ty().name == ty => {
+                    Some(format!("{:?}", d.pvm_ty()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fork_exec_write() {
+        let trs = run::<BsmEvent>(&[
+            notify(10, "fork", &format!(r#"{{"child": {}}}"#, process(11))),
+            notify(
+                11,
+                "exec",
+                &format!(
+                    r#"{{"target": {{"audit_token": {{"pid": 11}},
+                                     "executable": {}}},
+                         "args": ["cat", "/etc/hosts"]}}"#,
+                    file("/bin/cat", 1)
+                ),
+            ),
+            notify(
+                11,
+                "write",
+                &format!(r#"{{"target": {}}}"#, file("/tmp/out", 2)),
+            ),
+            notify(
+                11,
+                "close",
+                &format!(r#"{{"modified": true, "target": {}}}"#, file("/tmp/out", 2)),
+            ),
+        ]);
+        assert_eq!(pvm_tys(&trs, "process").len(), 2);
+        assert_eq!(
+            pvm_tys(&trs, "file"),
+            vec!["Store", "Store", "EditSession", "Store"]
+        );
+        assert_eq!(
+            ops(&trs),
+            vec!["Version", "Source", "Version", "Sink", "Version"]
+        );
+        let cmdline = trs.iter().rev().find_map(|tr| match tr {
+            DBTr::UpdateNode(Node::Data(d)) | DBTr::CreateNode(Node::Data(d))
+                if d.ty().name == "process" =>
+            {
+                d.meta.cur("cmdline").map(String::from)
+            }
+            _ => None,
+        });
+        assert_eq!(cmdline, Some("cat /etc/hosts".to_string()));
+    }
+
+    #[test]
+    fn rename_and_auth_requests() {
+        let trs = run::<BsmEvent>(&[
+            notify(
+                10,
+                "open",
+                &format!(r#"{{"fflag": 1, "file": {}}}"#, file("/tmp/a", 3)),
+            ),
+            event(
+                10,
+                ES_ACTION_TYPE_AUTH,
+                "unlink",
+                &format!(r#"{{"target": {}}}"#, file("/tmp/a", 3)),
+            ),
+            notify(
+                10,
+                "rename",
+                &format!(
+                    r#"{{"source": {}, "destination_type": 1,
+                         "destination": {{"new_path": {{"dir": {}, "filename": "b"}}}}}}"#,
+                    file("/tmp/a", 3),
+                    file("/tmp/", 4)
+                ),
+            ),
+        ]);
+        assert_eq!(names(&trs).len(), 2);
+        assert!(names(&trs)[1].ends_with("\"/tmp/b\")"));
+        let ended = trs
+            .iter()
+            .filter(|tr| match tr {
+                DBTr::UpdateRel(Rel::Named(n)) => n.end != ID::new(0),
+                _ => false,
+            })
+            .count();
+        assert_eq!(ended, 1);
+    }
+}
@@ -0,0 +1,421 @@
+//! OpenTelemetry spans
+//!
+//! Takes OTLP/JSON trace export requests, one per line, as written by the collector's file
+//! exporter:
+//!
+//! ```json
+//! {"resourceSpans": [{
+//!   "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "frontend"}}]},
+//!   "scopeSpans": [{"spans": [{
+//!     "traceId": "5b8efff798038103d269b633813fc60c", "spanId": "eee19b7ec3c1b174",
+//!     "parentSpanId": "", "name": "GET /cart", "kind": 3,
+//!     "startTimeUnixNano": "1533046200125000000", "endTimeUnixNano": "1533046200250000000",
+//!     "attributes": [{"key": "server.address", "value": {"stringValue": "10.0.0.2"}},
+//!                    {"key": "server.port", "value": {"intValue": "8080"}}]}]}]}]}
+//! ```
+//!
+//! Each service becomes an actor. A remote call becomes a conduit identified by its client
+//! span, the service making the call writes to and reads from it, as does the service handling
+//! the call through the server span whose parent is the client span, so that a chain of calls
+//! links every service involved. Producer and consumer spans are treated likewise but carry
+//! data in one direction only. Conduits are named by the peer address where the span records
+//! one, so they share name nodes with sockets from host traces. Internal spans do not cross a
+//! service boundary and are ignored.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{parse_ts, MapFmt, RawTs, TsFormat},
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref SERVICE: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "otel_service",
+        props: hashmap!("service_name" => true,
+                        "service_namespace" => true,
+                        "service_instance" => true,
+                        "host_name" => true),
+    };
+    static ref RPC: ConcreteType = ConcreteType {
+        pvm_ty: Conduit,
+        name: "otel_rpc",
+        props: hashmap!("name" => true,
+                        "trace_id" => true,
+                        "client_span" => true,
+                        "status" => false),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "otel_context",
+        props: vec!["time", "event", "trace_id", "span_id", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids derived from services and span ids
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:otel");
+}
+
+#[derive(Deserialize, Debug)]
+pub struct KeyValue {
+    pub key: String,
+    #[serde(default)]
+    pub value: Value,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Resource {
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Span {
+    #[serde(rename = "traceId")]
+    pub trace_id: String,
+    #[serde(rename = "spanId")]
+    pub span_id: String,
+    #[serde(default, rename = "parentSpanId")]
+    pub parent_span_id: String,
+    #[serde(default)]
+    pub name: String,
+    /// The span kind, either the enum value or its name
+    #[serde(default)]
+    pub kind: Value,
+    #[serde(rename = "startTimeUnixNano")]
+    pub start_time: Option<RawTs>,
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+    #[serde(default)]
+    pub status: Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScopeSpans {
+    #[serde(default)]
+    pub spans: Vec<Span>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResourceSpans {
+    #[serde(default)]
+    pub resource: Resource,
+    #[serde(default, rename = "scopeSpans")]
+    pub scope_spans: Vec<ScopeSpans>,
+    /// The name used for `scope_spans` before OTLP 0.19
+    #[serde(default, rename = "instrumentationLibrarySpans")]
+    pub library_spans: Vec<ScopeSpans>,
+}
+
+/// An OTLP trace export request
+#[derive(Deserialize, Debug)]
+pub struct OtelExport {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(default, rename = "resourceSpans")]
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+impl fmt::Display for OtelExport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.resource_spans,
+        );
+        ret.finish()
+    }
+}
+
+/// The value of an attribute as a string, unwrapping its `AnyValue` encoding
+fn any_value(val: &Value) -> Option<String> {
+    let val = match val {
+        Value::Object(o) => o.values().next()?,
+        v => v,
+    };
+    match val {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        v => Some(v.to_string()),
+    }
+}
+
+fn attr(attrs: &[KeyValue], key: &str) -> Option<String> {
+    attrs
+        .iter()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| any_value(&kv.value))
+}
+
+/// How a span takes part in a remote call
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Internal,
+    Server,
+    Client,
+    Producer,
+    Consumer,
+}
+
+impl Span {
+    fn kind(&self) -> Kind {
+        match &self.kind {
+            Value::Number(n) => match n.as_u64() {
+                Some(2) => Kind::Server,
+                Some(3) => Kind::Client,
+                Some(4) => Kind::Producer,
+                Some(5) => Kind::Consumer,
+                _ => Kind::Internal,
+            },
+            Value::String(s) => match &s[..] {
+                "SPAN_KIND_SERVER" => Kind::Server,
+                "SPAN_KIND_CLIENT" => Kind::Client,
+                "SPAN_KIND_PRODUCER" => Kind::Producer,
+                "SPAN_KIND_CONSUMER" => Kind::Consumer,
+                _ => Kind::Internal,
+            },
+            _ => Kind::Internal,
+        }
+    }
+
+    fn status(&self) -> Option<String> {
+        match self.status.get("code")? {
+            Value::Number(n) => match n.as_u64()? {
+                1 => Some("ok".to_string()),
+                2 => Some("error".to_string()),
+                _ => None,
+            },
+            Value::String(s) => match &s[..] {
+                "STATUS_CODE_OK" => Some("ok".to_string()),
+                "STATUS_CODE_ERROR" => Some("error".to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The address of the remote end of the call, under current or legacy attribute names
+    fn peer(&self) -> Option<Name> {
+        let host = attr(&self.attributes, "server.address")
+            .or_else(|| attr(&self.attributes, "net.peer.ip"))
+            .or_else(|| attr(&self.attributes, "net.peer.name"))?;
+        let port = attr(&self.attributes, "server.port")
+            .or_else(|| attr(&self.attributes, "net.peer.port"))?;
+        Some(Name::Net(host, port.parse().ok()?))
+    }
+
+    fn rpc(&self, span_id: &str, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let uuid = Uuid::new_v5(
+            &NS,
+            format!("span:{}:{}", self.trace_id, span_id).as_bytes(),
+        );
+        let c = pvm.declare(&RPC, uuid, None)?;
+        pvm.meta(c, "trace_id", &self.trace_id)?;
+        pvm.meta(c, "client_span", span_id)?;
+        Ok(c)
+    }
+
+    fn apply(&self, svc: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let kind = self.kind();
+        let c = match kind {
+            Kind::Client | Kind::Producer => {
+                let c = self.rpc(&self.span_id, pvm)?;
+                pvm.meta(c, "name", &self.name)?;
+                if let Some(status) = self.status() {
+                    pvm.meta(c, "status", &status)?;
+                }
+                if let Some(peer) = self.peer() {
+                    pvm.name(c, peer)?;
+                }
+                c
+            }
+            Kind::Server | Kind::Consumer if !self.parent_span_id.is_empty() => {
+                self.rpc(&self.parent_span_id, pvm)?
+            }
+            _ => {
+                pvm.unparsed(&format!("{:?}", kind).to_lowercase());
+                return Ok(());
+            }
+        };
+        match kind {
+            Kind::Client | Kind::Server => {
+                pvm.sink(svc, c)?;
+                pvm.source(svc, c)?;
+            }
+            Kind::Producer => {
+                pvm.sink(svc, c)?;
+            }
+            _ => {
+                pvm.source(svc, c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ResourceSpans {
+    fn service(&self, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let attrs = &self.resource.attributes;
+        let name = attr(attrs, "service.name").unwrap_or_else(|| "unknown_service".to_string());
+        let namespace = attr(attrs, "service.namespace");
+        let instance = attr(attrs, "service.instance.id");
+        let key = format!(
+            "service:{}:{}:{}",
+            namespace.clone().unwrap_or_default(),
+            name,
+            instance.clone().unwrap_or_default()
+        );
+        let svc = pvm.declare(&SERVICE, Uuid::new_v5(&NS, key.as_bytes()), None)?;
+        pvm.meta(svc, "service_name", &name)?;
+        for (prop, val) in &[
+            ("service_namespace", namespace),
+            ("service_instance", instance),
+            ("host_name", attr(attrs, "host.name")),
+        ] {
+            if let Some(val) = val {
+                pvm.meta(svc, prop, val)?;
+            }
+        }
+        Ok(svc)
+    }
+}
+
+impl OtelExport {
+    fn ctx(&self, span: &Span) -> PVMResult<HashMap<&'static str, String>> {
+        let mut ctx = hashmap!(
+            "event" => span.name.clone(),
+            "trace_id" => span.trace_id.clone(),
+            "span_id" => span.span_id.clone(),
+        );
+        if let Some(ref start) = span.start_time {
+            let time = parse_ts(start, TsFormat::Nanos).map_err(|_| PVMError::MissingField {
+                evt: span.name.clone(),
+                field: "startTimeUnixNano",
+            })?;
+            ctx.insert("time", time.to_rfc3339());
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        Ok(ctx)
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        for rs in &self.resource_spans {
+            for span in rs
+                .scope_spans
+                .iter()
+                .chain(rs.library_spans.iter())
+                .flat_map(|ss| ss.spans.iter())
+            {
+                let mut tr = pvm.transaction(&CTX, self.ctx(span)?);
+                match rs.service(&mut tr).and_then(|svc| span.apply(svc, &mut tr)) {
+                    Ok(_) => tr.commit()?,
+                    Err(e) => {
+                        tr.rollback();
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Mapped for OtelExport {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&SERVICE);
+        pvm.register_data_type(&RPC);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{data, run},
+        view::DBTr,
+    };
+
+    fn export(service: &str, spans: &str) -> String {
+        format!(
+            r#"{{"resourceSpans": [{{"resource": {{"attributes": [
+                {{"key": "service.name", "value": {{"stringValue": "{}"}}}}]}},
+                "scopeSpans": [{{"spans": [{}]}}]}}]}}"#,
+            service, spans
+        )
+    }
+
+    fn span(id: &str, parent: &str, kind: &str, extra: &str) -> String {
+        format!(
+            r#"{{"traceId": "t1", "spanId": "{}", "parentSpanId": "{}", "name": "GET /cart",
+                "kind": {}, "startTimeUnixNano": "1533046200125000000"{}}}"#,
+            id, parent, kind, extra
+        )
+    }
+
+    #[test]
+    fn client_and_server_share_conduit() {
+        let trs = run::<OtelExport>(&[
+            export(
+                "frontend",
+                &span(
+                    "c1",
+                    "",
+                    "3",
+                    r#", "attributes": [
+                        {"key": "server.address", "value": {"stringValue": "10.0.0.2"}},
+                        {"key": "server.port", "value": {"intValue": "8080"}}]"#,
+                ),
+            ),
+            export("cart", &span("s1", "c1", r#""SPAN_KIND_SERVER""#, "")),
+        ]);
+        assert_eq!(data(&trs, "otel_service"), 2);
+        assert_eq!(data(&trs, "otel_rpc"), 1);
+        let ops: Vec<String> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateRel(Rel::Inf(i)) => Some(format!("{:?}", i.pvm_op)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops.iter().filter(|op| *op == "Sink").count(), 2);
+        assert_eq!(ops.iter().filter(|op| *op == "Source").count(), 2);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) => format!("{:?}", n).contains("10.0.0.2"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn internal_spans_ignored() {
+        let trs = run::<OtelExport>(&[export("frontend", &span("i1", "", "1", ""))]);
+        assert_eq!(data(&trs, "otel_service"), 1);
+        assert_eq!(data(&trs, "otel_rpc"), 0);
+    }
+}
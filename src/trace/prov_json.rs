@@ -0,0 +1,334 @@
+//! W3C PROV-JSON documents
+//!
+//! Imports provenance recorded by other tools in the PROV-JSON serialisation, one document per
+//! line:
+//!
+//! ```json
+//! {"prefix": {"ex": "http://example.org/"},
+//!  "entity": {"ex:input": {"prov:label": "input.csv"}, "ex:output": {}},
+//!  "activity": {"ex:run": {"prov:startTime": "2018-07-31T14:10:00Z"}},
+//!  "agent": {"ex:alice": {"prov:type": "prov:Person"}},
+//!  "used": {"_:u1": {"prov:activity": "ex:run", "prov:entity": "ex:input"}},
+//!  "wasGeneratedBy": {"_:g1": {"prov:entity": "ex:output", "prov:activity": "ex:run"}}}
+//! ```
+//!
+//! Entities become stores while activities and agents become actors. Nodes are identified by
+//! their qualified name expanded with the document's prefixes, so documents may refer to nodes
+//! declared in earlier ones. `used` and `wasGeneratedBy` relations are applied in the order of
+//! their `prov:time`, each in its own context, other relation types are recorded as unparsed.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{camflow::Attrs, MapFmt},
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref ENTITY: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "prov_entity",
+        props: hashmap!("prov_id" => true,
+                        "prov_type" => true,
+                        "label" => true,
+                        "attributes" => false),
+    };
+    static ref ACTIVITY: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "prov_activity",
+        props: hashmap!("prov_id" => true,
+                        "prov_type" => true,
+                        "label" => true,
+                        "start_time" => false,
+                        "end_time" => false,
+                        "attributes" => false),
+    };
+    static ref AGENT: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "prov_agent",
+        props: hashmap!("prov_id" => true,
+                        "prov_type" => true,
+                        "label" => true,
+                        "attributes" => false),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "prov_context",
+        props: vec!["time", "event", "relation", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids derived from PROV qualified names
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:prov");
+}
+
+/// A PROV-JSON document
+#[derive(Deserialize, Debug)]
+pub struct ProvDoc {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(default)]
+    pub prefix: BTreeMap<String, String>,
+    #[serde(default)]
+    pub entity: BTreeMap<String, Attrs>,
+    #[serde(default)]
+    pub activity: BTreeMap<String, Attrs>,
+    #[serde(default)]
+    pub agent: BTreeMap<String, Attrs>,
+    #[serde(default)]
+    pub used: BTreeMap<String, Attrs>,
+    #[serde(default, rename = "wasGeneratedBy")]
+    pub was_generated_by: BTreeMap<String, Attrs>,
+    /// Relation types and bundles that are not mapped
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl fmt::Display for ProvDoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.prefix,
+            self.entity,
+            self.activity,
+            self.agent,
+            self.used,
+            self.was_generated_by,
+            self.other,
+        );
+        ret.finish()
+    }
+}
+
+/// An attribute as a string, unwrapping typed literals such as `{"$": "1", "type": "xsd:int"}`
+fn literal(attrs: &Attrs, key: &str) -> Option<String> {
+    match attrs.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(o) => match o.get("$") {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(v) => Some(v.to_string()),
+            None => Some(Value::Object(o.clone()).to_string()),
+        },
+        Value::Null => None,
+        v => Some(v.to_string()),
+    }
+}
+
+impl ProvDoc {
+    /// Expand a qualified name using the document's prefixes
+    fn expand(&self, qname: &str) -> String {
+        if let Some(i) = qname.find(':') {
+            if let Some(ns) = self.prefix.get(&qname[..i]) {
+                return format!("{}{}", ns, &qname[i + 1..]);
+            }
+        }
+        qname.to_string()
+    }
+
+    fn uuid(&self, qname: &str) -> Uuid {
+        Uuid::new_v5(&NS, self.expand(qname).as_bytes())
+    }
+
+    fn ctx(&self, event: &str, relation: &str, attrs: &Attrs) -> HashMap<&'static str, String> {
+        let mut ctx = hashmap!(
+            "event" => event.to_string(),
+            "relation" => relation.to_string(),
+        );
+        if let Some(time) = literal(attrs, "prov:time") {
+            ctx.insert("time", time);
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        ctx
+    }
+
+    /// Declare the nodes in the document, updating the properties of those already known
+    fn declare(&self, tr: &mut PVMTransaction) -> PVMResult<()> {
+        for (ty, nodes) in &[
+            (&*ENTITY, &self.entity),
+            (&*ACTIVITY, &self.activity),
+            (&*AGENT, &self.agent),
+        ] {
+            for (qname, attrs) in nodes.iter() {
+                let id = tr.declare(ty, self.uuid(qname), None)?;
+                tr.meta(id, "prov_id", &self.expand(qname))?;
+                let mut rest = attrs.clone();
+                for (prop, key) in &[
+                    ("prov_type", "prov:type"),
+                    ("label", "prov:label"),
+                    ("start_time", "prov:startTime"),
+                    ("end_time", "prov:endTime"),
+                ] {
+                    if !ty.props.contains_key(prop) {
+                        continue;
+                    }
+                    if let Some(val) = literal(attrs, key) {
+                        rest.remove(*key);
+                        tr.meta(id, prop, &val)?;
+                    }
+                }
+                if !rest.is_empty() {
+                    tr.meta(id, "attributes", &Value::Object(rest))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn end(
+        &self,
+        event: &str,
+        attrs: &Attrs,
+        key: &'static str,
+        tr: &PVMTransaction,
+    ) -> PVMResult<Option<ID>> {
+        let qname = literal(attrs, key).ok_or_else(|| PVMError::MissingField {
+            evt: event.to_string(),
+            field: key,
+        })?;
+        Ok(tr.lookup(&self.uuid(&qname)))
+    }
+
+    fn relation(&self, event: &str, attrs: &Attrs, tr: &mut PVMTransaction) -> PVMResult<()> {
+        let act = self.end(event, attrs, "prov:activity", tr)?;
+        let ent = self.end(event, attrs, "prov:entity", tr)?;
+        let (act, ent) = match (act, ent) {
+            (Some(act), Some(ent)) => (act, ent),
+            _ => {
+                tr.unparsed(event);
+                return Ok(());
+            }
+        };
+        if event == "used" {
+            tr.source(act, ent)?;
+        } else {
+            tr.sink(act, ent)?;
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let mut tr = pvm.transaction(&CTX, self.ctx("declare", "", &Attrs::new()));
+        match self.declare(&mut tr) {
+            Ok(_) => tr.commit()?,
+            Err(e) => {
+                tr.rollback();
+                return Err(e);
+            }
+        }
+        let mut rels: Vec<(&str, &String, &Attrs)> = self
+            .used
+            .iter()
+            .map(|(id, attrs)| ("used", id, attrs))
+            .chain(
+                self.was_generated_by
+                    .iter()
+                    .map(|(id, attrs)| ("wasGeneratedBy", id, attrs)),
+            )
+            .collect();
+        rels.sort_by_key(|(_, _, attrs)| literal(attrs, "prov:time"));
+        for (event, id, attrs) in rels {
+            let mut tr = pvm.transaction(&CTX, self.ctx(event, id, attrs));
+            match self.relation(event, attrs, &mut tr) {
+                Ok(_) => tr.commit()?,
+                Err(e) => {
+                    tr.rollback();
+                    return Err(e);
+                }
+            }
+        }
+        if !self.other.is_empty() {
+            let mut tr = pvm.transaction(&CTX, self.ctx("unmapped", "", &Attrs::new()));
+            for key in self.other.keys() {
+                tr.unparsed(key);
+            }
+            tr.commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl Mapped for ProvDoc {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&ENTITY);
+        pvm.register_data_type(&ACTIVITY);
+        pvm.register_data_type(&AGENT);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    #[test]
+    fn used_and_generated() {
+        let trs = run::<ProvDoc>(&[r#"{"prefix": {"ex": "http://example.org/"},
+            "entity": {"ex:in": {"prov:label": "input.csv", "ex:size": {"$": "10", "type": "xsd:int"}},
+                       "ex:out": {}},
+            "activity": {"ex:run": {"prov:startTime": "2018-07-31T14:10:00Z"}},
+            "agent": {"ex:alice": {"prov:type": "prov:Person"}},
+            "used": {"_:u1": {"prov:activity": "ex:run", "prov:entity": "ex:in",
+                              "prov:time": "2018-07-31T14:10:01Z"}},
+            "wasGeneratedBy": {"_:g1": {"prov:entity": "ex:out", "prov:activity": "ex:run",
+                                        "prov:time": "2018-07-31T14:10:02Z"}},
+            "wasAssociatedWith": {"_:a1": {"prov:activity": "ex:run", "prov:agent": "ex:alice"}}}"#]);
+        assert_eq!(data(&trs, "prov_activity"), 1);
+        assert_eq!(data(&trs, "prov_agent"), 1);
+        // The generated entity gains a new version
+        assert_eq!(data(&trs, "prov_entity"), 3);
+        let ops = ops(&trs);
+        assert!(ops.contains(&"Source".to_string()));
+        assert!(ops.contains(&"Sink".to_string()));
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d)) => {
+                format!("{:?}", d).contains("http://example.org/in")
+            }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn refers_to_earlier_documents() {
+        let trs = run::<ProvDoc>(&[
+            r#"{"entity": {"ex:in": {}}, "activity": {"ex:run": {}}}"#,
+            r#"{"used": {"_:u1": {"prov:activity": "ex:run", "prov:entity": "ex:in"}},
+                "wasGeneratedBy": {"_:g1": {"prov:entity": "ex:missing", "prov:activity": "ex:run"}}}"#,
+        ]);
+        let ops = ops(&trs);
+        assert!(ops.contains(&"Source".to_string()));
+        assert!(!ops.contains(&"Sink".to_string()));
+    }
+}
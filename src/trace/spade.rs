@@ -0,0 +1,353 @@
+//! SPADE provenance graphs
+//!
+//! Takes the graph written by SPADE's JSON storage, a JSON array with one vertex or edge per
+//! line:
+//!
+//! ```json
+//! [
+//! {"type": "Process", "id": "a1", "annotations": {"pid": "10", "name": "cat", "command line": "cat /etc/motd"}},
+//! {"type": "Artifact", "id": "b2", "annotations": {"subtype": "file", "path": "/etc/motd", "version": "0"}},
+//! {"type": "Used", "from": "a1", "to": "b2", "annotations": {"operation": "read", "time": "1533046200.125", "size": "120"}},
+//! ]
+//! ```
+//!
+//! SPADE creates a new vertex for each version of an object and links the versions with
+//! `WasTriggeredBy` and `WasDerivedFrom` edges, these are kept as versions in the PVM. Vertices
+//! are identified by their SPADE id, so edges may refer to vertices from earlier in the stream,
+//! edges whose ends were never seen are recorded as unparsed. Processes, files, sockets and
+//! pipes use the same types as the CADETS mapping, agents are kept as separate actors that are
+//! linked to the processes they control.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PIPE, PROCESS, SOCKET},
+        camflow::{attr, Attrs},
+        parse_ts, MapFmt, RawTs, TsFormat,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref AGENT: ConcreteType = ConcreteType {
+        pvm_ty: Actor,
+        name: "spade_agent",
+        props: hashmap!("uid" => true,
+                        "euid" => true,
+                        "suid" => true,
+                        "fsuid" => true,
+                        "gid" => true,
+                        "egid" => true,
+                        "sgid" => true,
+                        "fsgid" => true),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "spade_context",
+        props: vec!["time", "event", "event_id", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids derived from SPADE vertex ids
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:spade");
+}
+
+/// A vertex or edge of a SPADE graph
+#[derive(Deserialize, Debug)]
+pub struct SpadeElement {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The id of a vertex
+    pub id: Option<String>,
+    /// The id of the vertex an edge starts from
+    pub from: Option<String>,
+    /// The id of the vertex an edge points to
+    pub to: Option<String>,
+    #[serde(default)]
+    pub annotations: Attrs,
+}
+
+impl fmt::Display for SpadeElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.ty,
+            self.id,
+            self.from,
+            self.to,
+            self.annotations,
+        );
+        ret.finish()
+    }
+}
+
+fn vertex_uuid(id: &str) -> Uuid {
+    Uuid::new_v5(&NS, id.as_bytes())
+}
+
+impl SpadeElement {
+    fn ann(&self, key: &str) -> Option<String> {
+        attr(&self.annotations, key)
+    }
+
+    fn event(&self) -> String {
+        match self.ann("operation") {
+            Some(op) => format!("{}:{}", self.ty, op),
+            None => self.ty.clone(),
+        }
+    }
+
+    fn field(&self, val: &Option<String>, field: &'static str) -> PVMResult<Uuid> {
+        match val {
+            Some(id) => Ok(vertex_uuid(id)),
+            None => Err(PVMError::MissingField {
+                evt: self.event(),
+                field,
+            }),
+        }
+    }
+
+    fn set_props(
+        &self,
+        id: ID,
+        props: &[(&'static str, &str)],
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<()> {
+        for (prop, key) in props {
+            if let Some(val) = self.ann(key) {
+                pvm.meta(id, prop, &val)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_process(&self, uuid: Uuid, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let p = pvm.declare(&PROCESS, uuid, None)?;
+        self.set_props(
+            p,
+            &[
+                ("pid", "pid"),
+                ("ruid", "uid"),
+                ("euid", "euid"),
+                ("suid", "suid"),
+                ("rgid", "gid"),
+                ("egid", "egid"),
+                ("sgid", "sgid"),
+            ],
+            pvm,
+        )?;
+        if let Some(cmdline) = self
+            .ann("command line")
+            .or_else(|| self.ann("commandline"))
+            .or_else(|| self.ann("name"))
+        {
+            pvm.meta(p, "cmdline", &cmdline)?;
+        }
+        Ok(())
+    }
+
+    fn declare_artifact(&self, uuid: Uuid, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let subtype = self.ann("subtype").unwrap_or_default();
+        let ty = match &subtype[..] {
+            "file" | "directory" | "link" | "character device" | "block device" | "unknown" => {
+                &*FILE
+            }
+            "network socket" | "unix socket" => &*SOCKET,
+            "pipe" | "unnamed pipe" => &*PIPE,
+            _ => {
+                pvm.unparsed(&format!("Artifact:{}", subtype));
+                return Ok(());
+            }
+        };
+        let a = pvm.declare(ty, uuid, None)?;
+        if ty.name == "file" {
+            self.set_props(a, &[("mode", "permissions")], pvm)?;
+        }
+        if let Some(path) = self.ann("path") {
+            pvm.name(a, Name::Path(path))?;
+        }
+        if let (Some(host), Some(port)) = (self.ann("remote address"), self.ann("remote port")) {
+            if let Ok(port) = port.parse() {
+                pvm.name(a, Name::Net(host, port))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_agent(&self, uuid: Uuid, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let a = pvm.declare(&AGENT, uuid, None)?;
+        self.set_props(
+            a,
+            &[
+                ("uid", "uid"),
+                ("euid", "euid"),
+                ("suid", "suid"),
+                ("fsuid", "fsuid"),
+                ("gid", "gid"),
+                ("egid", "egid"),
+                ("sgid", "sgid"),
+                ("fsgid", "fsgid"),
+            ],
+            pvm,
+        )
+    }
+
+    fn edge(&self, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let from = self.field(&self.from, "from")?;
+        let to = self.field(&self.to, "to")?;
+        let (from, to) = match (pvm.lookup(&from), pvm.lookup(&to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => {
+                pvm.unparsed(&self.event());
+                return Ok(());
+            }
+        };
+        match &self.ty[..] {
+            "Used" => match self.ann("size") {
+                Some(size) => {
+                    pvm.source_nbytes(from, to, size.parse::<i64>().unwrap_or_default())?;
+                }
+                None => {
+                    pvm.source(from, to)?;
+                }
+            },
+            "WasGeneratedBy" => {
+                pvm.sink(to, from)?;
+            }
+            "WasTriggeredBy" | "WasDerivedFrom" => {
+                pvm.version_of(to, from)?;
+            }
+            "WasControlledBy" => {
+                pvm.source(from, to)?;
+            }
+            _ => pvm.unparsed(&self.event()),
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let mut ctx = hashmap!("event" => self.event());
+        if let Some(time) = self.ann("time") {
+            if let Ok(time) = parse_ts(&RawTs::Str(time), TsFormat::Seconds) {
+                ctx.insert("time", time.to_rfc3339());
+            }
+        }
+        if let Some(event_id) = self.ann("event id") {
+            ctx.insert("event_id", event_id);
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let res = match &self.ty[..] {
+            "Process" => self
+                .field(&self.id, "id")
+                .and_then(|uuid| self.declare_process(uuid, &mut tr)),
+            "Artifact" => self
+                .field(&self.id, "id")
+                .and_then(|uuid| self.declare_artifact(uuid, &mut tr)),
+            "Agent" => self
+                .field(&self.id, "id")
+                .and_then(|uuid| self.declare_agent(uuid, &mut tr)),
+            _ => self.edge(&mut tr),
+        };
+        match res {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for SpadeElement {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_data_type(&PIPE);
+        pvm.register_data_type(&AGENT);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    #[test]
+    fn process_reads_file() {
+        let trs = run::<SpadeElement>(&[
+            r#"{"type": "Process", "id": "p1", "annotations": {"pid": "10", "name": "cat"}}"#,
+            r#"{"type": "Agent", "id": "u1", "annotations": {"uid": "1000", "euid": "1000"}}"#,
+            r#"{"type": "Artifact", "id": "f1",
+                "annotations": {"subtype": "file", "path": "/etc/motd", "version": "0"}}"#,
+            r#"{"type": "WasControlledBy", "from": "p1", "to": "u1", "annotations": {}}"#,
+            r#"{"type": "Used", "from": "p1", "to": "f1",
+                "annotations": {"operation": "read", "time": "1533046200.125", "size": "120"}}"#,
+            r#"{"type": "Used", "from": "p1", "to": "missing", "annotations": {}}"#,
+        ]);
+        assert_eq!(data(&trs, "process"), 1);
+        assert_eq!(data(&trs, "spade_agent"), 1);
+        assert_eq!(data(&trs, "file"), 1);
+        assert_eq!(ops(&trs).iter().filter(|op| *op == "Source").count(), 2);
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateRel(Rel::Inf(i)) | DBTr::UpdateRel(Rel::Inf(i)) => i.bytes_read == 120,
+            _ => false,
+        }));
+        assert!(trs.iter().any(|tr| match tr {
+            DBTr::CreateNode(Node::Name(n)) => format!("{:?}", n).contains("/etc/motd"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn versions_linked() {
+        let trs = run::<SpadeElement>(&[
+            r#"{"type": "Process", "id": "p1", "annotations": {"pid": "10"}}"#,
+            r#"{"type": "Process", "id": "p2", "annotations": {"pid": "11"}}"#,
+            r#"{"type": "WasTriggeredBy", "from": "p2", "to": "p1",
+                "annotations": {"operation": "fork"}}"#,
+            r#"{"type": "Artifact", "id": "f1", "annotations": {"subtype": "file", "version": "0"}}"#,
+            r#"{"type": "Artifact", "id": "f2", "annotations": {"subtype": "file", "version": "1"}}"#,
+            r#"{"type": "WasGeneratedBy", "from": "f2", "to": "p2",
+                "annotations": {"operation": "write"}}"#,
+            r#"{"type": "WasDerivedFrom", "from": "f2", "to": "f1",
+                "annotations": {"operation": "update"}}"#,
+        ]);
+        let ops = ops(&trs);
+        assert!(ops.contains(&"Sink".to_string()));
+        assert!(ops.iter().filter(|op| *op == "Version").count() >= 2);
+    }
+}
@@ -0,0 +1,594 @@
+//! strace system call logs
+//!
+//! A best effort mapping for the output of `strace -f -ttt -y`, useful for capturing a single
+//! application without any kernel support. Lines look like:
+//!
+//! ```text
+//! 1234  1533046200.125431 openat(AT_FDCWD, "/etc/passwd", O_RDONLY) = 3</etc/passwd>
+//! [pid  1235] 1533046200.125502 read(3</etc/passwd>, "root:x:0:0"..., 4096) = 1024
+//! ```
+//!
+//! The same records may also be given as JSON, one per line, with the arguments and result as
+//! strace prints them:
+//!
+//! ```json
+//! {"pid": 1234, "time": 1533046200.125431, "syscall": "openat",
+//!  "args": ["AT_FDCWD", "\"/etc/passwd\"", "O_RDONLY"], "result": "3</etc/passwd>"}
+//! ```
+//!
+//! strace reports no object identities, so uuids are synthesised: processes from their pid,
+//! files from their path, pipes and sockets from the inode strace decorates them with or failing
+//! that from the owning pid and descriptor. Renaming a file derives a new object for the new
+//! path. Calls that strace splits into unfinished and resumed halves are skipped, as are signal
+//! and exit notices, so a trace captured with `-ff` into per process files loses less.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{
+        cadets::{FILE, PIPE, PROCESS, SOCKET},
+        parse_ts, MapFmt, RawTs, TsFormat,
+    },
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CTX: ContextType = ContextType {
+        name: "strace_context",
+        props: vec!["time", "event", "pid", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids synthesised for strace objects
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:strace");
+}
+
+/// A completed system call
+#[derive(Deserialize, Debug)]
+pub struct StraceEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    /// Absent when strace was not following forks
+    pub pid: Option<i32>,
+    pub time: f64,
+    #[serde(rename = "syscall")]
+    pub event: String,
+    pub args: Vec<String>,
+    pub result: String,
+}
+
+impl fmt::Display for StraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.pid,
+            self.time,
+            self.event,
+            self.args,
+            self.result,
+        );
+        ret.finish()
+    }
+}
+
+/// Split the argument list of a call, returning the arguments and the text after the closing
+/// parenthesis
+fn split_args(s: &str) -> Option<(Vec<String>, &str)> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (idx, c) in s.char_indices() {
+        if in_str {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_str = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => {
+                let last = s[start..idx].trim();
+                if !last.is_empty() {
+                    args.push(last.to_string());
+                }
+                return Some((args, &s[idx + 1..]));
+            }
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(s[start..idx].trim().to_string());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The contents of a quoted string argument, truncated strings are returned as far as shown
+fn unquote(arg: &str) -> Option<String> {
+    let mut chars = arg.strip_prefix('"')?.chars();
+    let mut ret = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(ret),
+            '\\' => match chars.next()? {
+                'n' => ret.push('\n'),
+                't' => ret.push('\t'),
+                c => ret.push(c),
+            },
+            c => ret.push(c),
+        }
+    }
+    None
+}
+
+/// A descriptor and the description `-y` decorates it with, as in `3</etc/passwd>`
+fn fd_desc(arg: &str) -> Option<(i64, Option<&str>)> {
+    match arg.find('<') {
+        Some(idx) if arg.ends_with('>') => {
+            Some((arg[..idx].parse().ok()?, Some(&arg[idx + 1..arg.len() - 1])))
+        }
+        Some(_) => None,
+        None => Some((arg.parse().ok()?, None)),
+    }
+}
+
+/// The value following `key=` in a structure argument, as in `sin_port=htons(80)`
+fn struct_field<'a>(arg: &'a str, key: &str) -> Option<&'a str> {
+    let idx = arg.find(key)? + key.len();
+    let rest = arg[idx..].strip_prefix('=')?;
+    let end = rest.find(&[',', '}'][..]).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+/// The text inside the outermost parentheses of a value such as `htons(80)`
+fn inner(val: &str) -> &str {
+    match (val.find('('), val.rfind(')')) {
+        (Some(start), Some(end)) if start < end => &val[start + 1..end],
+        _ => val,
+    }
+}
+
+/// Parse the remote endpoint of a socket description such as `TCP:[10.0.0.1:5000->10.0.0.2:80]`
+fn peer(desc: &str) -> Option<Name> {
+    let peer = desc[desc.find("->")? + 2..].trim_end_matches(']');
+    let idx = peer.rfind(':')?;
+    let host = peer[..idx].trim_start_matches('[').trim_end_matches(']');
+    Some(Name::Net(host.to_string(), peer[idx + 1..].parse().ok()?))
+}
+
+impl StraceEvent {
+    fn pid(&self) -> i32 {
+        self.pid.unwrap_or(0)
+    }
+
+    fn missing(&self, field: &'static str) -> PVMError {
+        PVMError::MissingField {
+            evt: self.event.clone(),
+            field,
+        }
+    }
+
+    fn uuid(&self, kind: &str, key: &str) -> Uuid {
+        Uuid::new_v5(&NS, format!("{}:{}", kind, key).as_bytes())
+    }
+
+    fn proc_uuid(&self, pid: i64) -> Uuid {
+        self.uuid("proc", &pid.to_string())
+    }
+
+    fn path_uuid(&self, path: &str) -> Uuid {
+        self.uuid("path", path)
+    }
+
+    /// The numeric result of the call, `None` for calls that do not return
+    fn ret(&self) -> Option<i64> {
+        let val = self.result.split(&[' ', '<'][..]).next()?;
+        match val.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16).ok(),
+            None => val.parse().ok(),
+        }
+    }
+
+    fn arg(&self, n: usize) -> PVMResult<&str> {
+        self.args
+            .get(n)
+            .map(String::as_str)
+            .ok_or_else(|| self.missing("args"))
+    }
+
+    fn str_arg(&self, n: usize) -> PVMResult<String> {
+        unquote(self.arg(n)?).ok_or_else(|| self.missing("args"))
+    }
+
+    /// The `nth` quoted argument, for paths of calls that may take a directory descriptor first
+    fn path_arg(&self, nth: usize) -> PVMResult<String> {
+        self.args
+            .iter()
+            .filter_map(|a| unquote(a))
+            .nth(nth)
+            .ok_or_else(|| self.missing("args"))
+    }
+
+    /// Declare the object a decorated descriptor refers to, returning it and its name if any
+    fn fd_object(
+        &self,
+        arg: &str,
+        pvm: &mut PVMTransaction,
+    ) -> PVMResult<Option<(ID, Option<Name>)>> {
+        let (fd, desc) = match fd_desc(arg) {
+            Some((fd, Some(desc))) => (fd, desc),
+            _ => return Ok(None),
+        };
+        let inode = |desc: &str| {
+            desc.find(":[")
+                .map(|idx| desc[idx + 2..].trim_end_matches(']'))
+                .filter(|ino| ino.chars().all(|c| c.is_ascii_digit()))
+                .map(String::from)
+        };
+        let (ty, uuid, name): (&'static ConcreteType, _, _) = if desc.starts_with('/') {
+            (
+                &FILE,
+                self.path_uuid(desc),
+                Some(Name::Path(desc.to_string())),
+            )
+        } else if desc.starts_with("pipe:") {
+            match inode(desc) {
+                Some(ino) => (&PIPE, self.uuid("pipe", &ino), None),
+                None => return Ok(None),
+            }
+        } else if desc.starts_with("socket:")
+            || desc.starts_with("TCP")
+            || desc.starts_with("UDP")
+            || desc.starts_with("UNIX")
+        {
+            let uuid = match inode(desc) {
+                Some(ino) => self.uuid("sock", &ino),
+                None => self.uuid("sock", &format!("{}:{}", self.pid(), fd)),
+            };
+            (&SOCKET, uuid, peer(desc))
+        } else {
+            return Ok(None);
+        };
+        Ok(Some((pvm.declare(ty, uuid, None)?, name)))
+    }
+
+    fn sys_clone(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let child = self.ret().ok_or_else(|| self.missing("result"))?;
+        if self.args.iter().any(|a| a.contains("CLONE_THREAD")) {
+            return Ok(());
+        }
+        let ch = pvm.derive(pro, self.proc_uuid(child))?;
+        pvm.meta(ch, "pid", &child)?;
+        pvm.source(ch, pro)?;
+        Ok(())
+    }
+
+    fn sys_execve(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let (path_idx, argv_idx) = match &self.event[..] {
+            "execveat" => (1, 2),
+            _ => (0, 1),
+        };
+        let path = self.str_arg(path_idx)?;
+        let bin = pvm.declare(&FILE, self.path_uuid(&path), None)?;
+        pvm.name(bin, Name::Path(path))?;
+        let argv = self
+            .args
+            .get(argv_idx)
+            .and_then(|a| a.strip_prefix('['))
+            .and_then(|a| split_args(&format!("{})", a.trim_end_matches(']'))).map(|r| r.0));
+        if let Some(argv) = argv {
+            let cmdline: Vec<String> = argv.iter().filter_map(|a| unquote(a)).collect();
+            pvm.meta(pro, "cmdline", &cmdline.join(" "))?;
+        }
+        pvm.source(pro, bin)?;
+        Ok(())
+    }
+
+    fn sys_exit(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        pvm.release(&self.proc_uuid(i64::from(self.pid())));
+        Ok(())
+    }
+
+    fn sys_open(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        // The decorated result holds the resolved path
+        let path = match fd_desc(&self.result) {
+            Some((_, Some(desc))) if desc.starts_with('/') => desc.to_string(),
+            _ => self.path_arg(0)?,
+        };
+        let f = pvm.declare(&FILE, self.path_uuid(&path), None)?;
+        pvm.name_by(f, Name::Path(path), pro)?;
+        Ok(())
+    }
+
+    fn sys_read(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some((obj, name)) = self.fd_object(self.arg(0)?, pvm)? {
+            if let Some(name) = name {
+                pvm.name(obj, name)?;
+            }
+            pvm.source_nbytes(pro, obj, self.ret().unwrap_or(0))?;
+        }
+        Ok(())
+    }
+
+    fn sys_write(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some((obj, name)) = self.fd_object(self.arg(0)?, pvm)? {
+            if let Some(name) = name {
+                pvm.name(obj, name)?;
+            }
+            pvm.sinkstart_nbytes(pro, obj, self.ret().unwrap_or(0))?;
+        }
+        Ok(())
+    }
+
+    fn sys_close(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        if let Some((obj, _)) = self.fd_object(self.arg(0)?, pvm)? {
+            pvm.sinkend(pro, obj)?;
+        }
+        Ok(())
+    }
+
+    fn sys_rename(&self, pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let src = self.path_arg(0)?;
+        let dst = self.path_arg(1)?;
+        let f = pvm.declare(&FILE, self.path_uuid(&src), None)?;
+        pvm.unname(f, Name::Path(src.clone()))?;
+        pvm.release(&self.path_uuid(&dst));
+        let moved = pvm.derive(f, self.path_uuid(&dst))?;
+        pvm.release(&self.path_uuid(&src));
+        pvm.name_by(moved, Name::Path(dst), pro)?;
+        Ok(())
+    }
+
+    fn sys_unlink(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let path = self.path_arg(0)?;
+        let f = pvm.declare(&FILE, self.path_uuid(&path), None)?;
+        pvm.unname(f, Name::Path(path.clone()))?;
+        pvm.release(&self.path_uuid(&path));
+        Ok(())
+    }
+
+    fn sys_connect(&self, _pro: ID, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let addr = self.arg(1)?;
+        let name = match (
+            struct_field(addr, "sun_path").and_then(unquote),
+            struct_field(addr, "sin_addr").or_else(|| struct_field(addr, "sin6_addr")),
+            struct_field(addr, "sin_port").or_else(|| struct_field(addr, "sin6_port")),
+        ) {
+            (Some(path), _, _) => Name::Path(path),
+            (None, Some(host), Some(port)) => {
+                let host = unquote(inner(host)).ok_or_else(|| self.missing("args"))?;
+                let port = inner(port).parse().map_err(|_| self.missing("args"))?;
+                Name::Net(host, port)
+            }
+            _ => return Ok(()),
+        };
+        let s = match self.fd_object(self.arg(0)?, pvm)? {
+            Some((s, _)) => s,
+            None => {
+                let (fd, _) = fd_desc(self.arg(0)?).ok_or_else(|| self.missing("args"))?;
+                let uuid = self.uuid("sock", &format!("{}:{}", self.pid(), fd));
+                pvm.declare(&SOCKET, uuid, None)?
+            }
+        };
+        pvm.name(s, name)?;
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        // exit_group never returns so has no result to check
+        if self.ret().map_or(self.result != "?", |r| r < 0) {
+            return Ok(());
+        }
+        let time = parse_ts(&RawTs::Float(self.time), TsFormat::Seconds)
+            .map_err(|_| self.missing("time"))?;
+        let mut ctx = hashmap!(
+            "event" => self.event.clone(),
+            "time" => time.to_rfc3339(),
+            "pid" => self.pid().to_string(),
+        );
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let init = hashmap!("pid" => self.pid().to_string());
+        match {
+            let pro = tr.declare(&PROCESS, self.proc_uuid(i64::from(self.pid())), Some(init))?;
+            match &self.event[..] {
+                "clone" | "clone3" | "fork" | "vfork" => self.sys_clone(pro, &mut tr),
+                "execve" | "execveat" => self.sys_execve(pro, &mut tr),
+                "exit" | "exit_group" => self.sys_exit(pro, &mut tr),
+                "open" | "openat" | "openat2" | "creat" => self.sys_open(pro, &mut tr),
+                "read" | "readv" | "pread64" | "preadv" | "recvfrom" | "recvmsg" => {
+                    self.sys_read(pro, &mut tr)
+                }
+                "write" | "writev" | "pwrite64" | "pwritev" | "sendto" | "sendmsg" => {
+                    self.sys_write(pro, &mut tr)
+                }
+                "close" => self.sys_close(pro, &mut tr),
+                "rename" | "renameat" | "renameat2" => self.sys_rename(pro, &mut tr),
+                "unlink" | "unlinkat" => self.sys_unlink(pro, &mut tr),
+                "connect" => self.sys_connect(pro, &mut tr),
+                evt => {
+                    tr.unparsed(evt);
+                    Ok(())
+                }
+            }
+        } {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for StraceEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&PROCESS);
+        pvm.register_data_type(&FILE);
+        pvm.register_data_type(&PIPE);
+        pvm.register_data_type(&SOCKET);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+
+    fn from_text(line: &str) -> Option<Self> {
+        let mut rest = line.trim();
+        let mut pid = None;
+        if rest.starts_with("[pid") {
+            let end = rest.find(']')?;
+            pid = Some(rest[4..end].trim().parse().ok()?);
+            rest = rest[end + 1..].trim_start();
+        }
+        let mut next = || {
+            let end = rest.find(' ')?;
+            let tok = &rest[..end];
+            rest = rest[end..].trim_start();
+            Some(tok)
+        };
+        let mut tok = next()?;
+        if pid.is_none() && !tok.contains('.') {
+            pid = Some(tok.parse().ok()?);
+            tok = next()?;
+        }
+        let time = tok.parse().ok()?;
+        let open = rest.find('(')?;
+        let event = &rest[..open];
+        if event.is_empty() || !event.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+        let (args, tail) = split_args(&rest[open + 1..])?;
+        let result = tail.trim_start().strip_prefix('=')?.trim();
+        Some(StraceEvent {
+            offset: None,
+            pid,
+            time,
+            event: event.to_string(),
+            args,
+            result: result.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::{node_types::Node, rel_types::Rel},
+        trace::test_util::{data, names, run_events},
+        view::DBTr,
+    };
+
+    fn lines(text: &str) -> Vec<StraceEvent> {
+        text.lines().filter_map(StraceEvent::from_text).collect()
+    }
+
+    #[test]
+    fn parse_text_lines() {
+        let evt = StraceEvent::from_text(
+            r#"1234  1533046200.125431 write(1</dev/pts/0>, "a, (b)\"", 7) = 7"#,
+        )
+        .unwrap();
+        assert_eq!(evt.pid, Some(1234));
+        assert_eq!(evt.event, "write");
+        assert_eq!(evt.args, vec!["1</dev/pts/0>", r#""a, (b)\"""#, "7"]);
+        assert_eq!(evt.ret(), Some(7));
+        let evt = StraceEvent::from_text(
+            "[pid  1235] 1533046200.2 openat(AT_FDCWD, \"/nope\", O_RDONLY) = -1 ENOENT (No such \
+             file or directory)",
+        )
+        .unwrap();
+        assert_eq!(evt.pid, Some(1235));
+        assert_eq!(evt.ret(), Some(-1));
+        let evt = StraceEvent::from_text("1533046200.3 exit_group(0) = ?").unwrap();
+        assert_eq!((evt.pid, evt.ret()), (None, None));
+        for skipped in &[
+            "1234 1533046200.4 read(3</etc/passwd>,  <unfinished ...>",
+            "1234 1533046200.5 <... read resumed>\"root\", 4096) = 4",
+            "1234 1533046200.6 +++ exited with 0 +++",
+            "1234 1533046200.7 --- SIGCHLD {si_signo=SIGCHLD} ---",
+        ] {
+            assert!(StraceEvent::from_text(skipped).is_none(), "{}", skipped);
+        }
+    }
+
+    #[test]
+    fn fork_exec_and_files() {
+        let trs = run_events(lines(
+            r#"1234 1533046200.1 clone(child_stack=NULL, flags=CLONE_CHILD_SETTID|SIGCHLD) = 1235
+1235 1533046200.2 execve("/bin/cat", ["cat", "/tmp/a"], 0x7ffd /* 20 vars */) = 0
+1235 1533046200.3 openat(AT_FDCWD, "/tmp/a", O_RDONLY) = 3</tmp/a>
+1235 1533046200.4 read(3</tmp/a>, "hello", 4096) = 5
+1235 1533046200.5 write(1<pipe:[5501]>, "hello", 5) = 5
+1235 1533046200.6 openat(AT_FDCWD, "/tmp/missing", O_RDONLY) = -1 ENOENT (No such file)
+1234 1533046200.7 clone(child_stack=0x7f, flags=CLONE_VM|CLONE_THREAD) = 1236
+1235 1533046200.8 rename("/tmp/a", "/tmp/b") = 0
+1235 1533046200.9 exit_group(0) = ?"#,
+        ));
+        assert_eq!(data(&trs, "process"), 2);
+        assert_eq!(data(&trs, "pipe"), 1);
+        let names = names(&trs);
+        assert!(names.iter().any(|n| n.contains("\"/tmp/b\"")));
+        assert!(!names.iter().any(|n| n.contains("missing")));
+        let cmdline = trs.iter().rev().find_map(|tr| match tr {
+            DBTr::UpdateNode(Node::Data(d)) | DBTr::CreateNode(Node::Data(d))
+                if d.ty().name == "process" =>
+            {
+                d.meta.cur("cmdline").map(String::from)
+            }
+            _ => None,
+        });
+        assert_eq!(cmdline, Some("cat /tmp/a".to_string()));
+        let read = trs.iter().any(|tr| match tr {
+            DBTr::CreateRel(Rel::Inf(i)) | DBTr::UpdateRel(Rel::Inf(i)) => i.bytes_read == 5,
+            _ => false,
+        });
+        assert!(read);
+    }
+
+    #[test]
+    fn json_connect() {
+        let evt: StraceEvent = serde_json::from_str(
+            r#"{"pid": 99, "time": 1533046200.5, "syscall": "connect",
+                "args": ["4<socket:[8812]>",
+                         "{sa_family=AF_INET, sin_port=htons(443), sin_addr=inet_addr(\"10.1.1.1\")}",
+                         "16"],
+                "result": "0"}"#,
+        )
+        .unwrap();
+        let trs = run_events(vec![evt]);
+        assert_eq!(data(&trs, "socket"), 1);
+        assert_eq!(names(&trs).len(), 1);
+        assert!(names(&trs)[0].contains("\"10.1.1.1\", 443"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Zeek network monitor logs
+//!
+//! Takes the JSON form of Zeek's `conn.log` and `files.log`, records from both may be mixed in
+//! a single stream:
+//!
+//! ```json
+//! {"ts": 1533046200.12, "uid": "CHhAvVGS1DHFjwGM9", "id.orig_h": "10.0.0.1", "id.orig_p": 51234,
+//!  "id.resp_h": "10.0.0.2", "id.resp_p": 80, "proto": "tcp", "service": "http",
+//!  "duration": 0.25, "orig_bytes": 73, "resp_bytes": 1024, "conn_state": "SF"}
+//! {"ts": 1533046200.2, "fuid": "FhBhmV1Aw3PL2Alfa", "conn_uids": ["CHhAvVGS1DHFjwGM9"],
+//!  "source": "HTTP", "mime_type": "text/html", "filename": "index.html", "total_bytes": 1024,
+//!  "sha1": "..."}
+//! ```
+//!
+//! Each connection becomes a conduit named by the network address of both of its endpoints, so
+//! it shares name nodes with sockets from host traces in the same graph. Each transferred file
+//! becomes a store carried in by the connections it was seen on. Timestamps may be numeric
+//! seconds or, with Zeek's `JSON::TS_ISO8601` setting, RFC 3339 strings.
+
+use std::fmt;
+
+use crate::{
+    data::{
+        node_types::{ConcreteType, ContextType, Name, PVMDataType::*},
+        ID,
+    },
+    ingest::{
+        pvm::{PVMError, PVMResult, PVMTransaction, PVM},
+        Mapped, TraceOffset,
+    },
+    trace::{parse_ts, MapFmt, RawTs, TsFormat},
+};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref CONNECTION: ConcreteType = ConcreteType {
+        pvm_ty: Conduit,
+        name: "network_connection",
+        props: hashmap!("proto" => true,
+                        "service" => true,
+                        "conn_state" => true,
+                        "duration" => true,
+                        "orig_bytes" => true,
+                        "resp_bytes" => true),
+    };
+    static ref NET_FILE: ConcreteType = ConcreteType {
+        pvm_ty: Store,
+        name: "network_file",
+        props: hashmap!("filename" => true,
+                        "mime_type" => true,
+                        "source" => true,
+                        "total_bytes" => true,
+                        "md5" => true,
+                        "sha1" => true,
+                        "sha256" => true),
+    };
+    static ref CTX: ContextType = ContextType {
+        name: "zeek_context",
+        props: vec!["time", "event", "uid", "trace_offset", "trace_source"],
+    };
+    /// Namespace for the uuids derived from Zeek's connection and file ids
+    static ref NS: Uuid = Uuid::new_v5(&Uuid::nil(), b"libpvm:zeek");
+}
+
+/// A `conn.log` or `files.log` record
+#[derive(Deserialize, Debug)]
+pub struct ZeekEvent {
+    #[serde(skip)]
+    pub offset: Option<TraceOffset>,
+    pub ts: RawTs,
+    /// The connection id, the connection a file was seen on in newer `files.log` versions
+    pub uid: Option<String>,
+    #[serde(rename = "id.orig_h")]
+    pub orig_h: Option<String>,
+    #[serde(rename = "id.orig_p")]
+    pub orig_p: Option<u16>,
+    #[serde(rename = "id.resp_h")]
+    pub resp_h: Option<String>,
+    #[serde(rename = "id.resp_p")]
+    pub resp_p: Option<u16>,
+    pub proto: Option<String>,
+    pub service: Option<String>,
+    pub duration: Option<f64>,
+    pub orig_bytes: Option<u64>,
+    pub resp_bytes: Option<u64>,
+    pub conn_state: Option<String>,
+    pub fuid: Option<String>,
+    #[serde(default)]
+    pub conn_uids: Vec<String>,
+    pub source: Option<String>,
+    pub mime_type: Option<String>,
+    pub filename: Option<String>,
+    pub total_bytes: Option<u64>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl fmt::Display for ZeekEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ret = f.debug_map();
+        fields_to_map!(
+            ret;
+            self.ts,
+            self.uid,
+            self.orig_h,
+            self.orig_p,
+            self.resp_h,
+            self.resp_p,
+            self.proto,
+            self.service,
+            self.duration,
+            self.orig_bytes,
+            self.resp_bytes,
+            self.conn_state,
+            self.fuid,
+            self.conn_uids,
+            self.source,
+            self.mime_type,
+            self.filename,
+            self.total_bytes,
+            self.md5,
+            self.sha1,
+            self.sha256,
+        );
+        ret.finish()
+    }
+}
+
+impl ZeekEvent {
+    fn event(&self) -> &'static str {
+        if self.fuid.is_some() {
+            "files"
+        } else {
+            "conn"
+        }
+    }
+
+    fn declare_conn(&self, uid: &str, pvm: &mut PVMTransaction) -> PVMResult<ID> {
+        let uuid = Uuid::new_v5(&NS, format!("conn:{}", uid).as_bytes());
+        pvm.declare(&CONNECTION, uuid, None)
+    }
+
+    fn conn(&self, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let uid = self.uid.as_ref().ok_or_else(|| PVMError::MissingField {
+            evt: self.event().to_string(),
+            field: "uid",
+        })?;
+        let c = self.declare_conn(uid, pvm)?;
+        for (key, val) in &[
+            ("proto", &self.proto),
+            ("service", &self.service),
+            ("conn_state", &self.conn_state),
+        ] {
+            if let Some(val) = val {
+                pvm.meta(c, key, val)?;
+            }
+        }
+        if let Some(duration) = self.duration {
+            pvm.meta(c, "duration", &duration)?;
+        }
+        for (key, val) in &[
+            ("orig_bytes", self.orig_bytes),
+            ("resp_bytes", self.resp_bytes),
+        ] {
+            if let Some(val) = val {
+                pvm.meta(c, key, val)?;
+            }
+        }
+        for (host, port) in &[(&self.orig_h, self.orig_p), (&self.resp_h, self.resp_p)] {
+            if let (Some(host), Some(port)) = (host, port) {
+                pvm.name(c, Name::Net(host.clone(), *port))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file(&self, fuid: &str, pvm: &mut PVMTransaction) -> PVMResult<()> {
+        let uuid = Uuid::new_v5(&NS, format!("file:{}", fuid).as_bytes());
+        let mut init = hashmap!();
+        for (key, val) in &[
+            ("filename", &self.filename),
+            ("mime_type", &self.mime_type),
+            ("source", &self.source),
+            ("md5", &self.md5),
+            ("sha1", &self.sha1),
+            ("sha256", &self.sha256),
+        ] {
+            if let Some(val) = val {
+                init.insert(*key, val.clone());
+            }
+        }
+        if let Some(total) = self.total_bytes {
+            init.insert("total_bytes", total.to_string());
+        }
+        let mut f = pvm.declare(&NET_FILE, uuid, Some(init))?;
+        for uid in self.conn_uids.iter().chain(self.uid.iter()) {
+            let c = self.declare_conn(uid, pvm)?;
+            pvm.carry(c, f)?;
+            f = pvm.lookup(&uuid).unwrap_or(f);
+        }
+        Ok(())
+    }
+
+    fn parse(&self, pvm: &mut PVM) -> PVMResult<()> {
+        let fmt = match self.ts {
+            RawTs::Str(_) => TsFormat::Rfc3339,
+            _ => TsFormat::Seconds,
+        };
+        let time = parse_ts(&self.ts, fmt).map_err(|_| PVMError::MissingField {
+            evt: self.event().to_string(),
+            field: "ts",
+        })?;
+        let mut ctx = hashmap!(
+            "event" => self.event().to_string(),
+            "time" => time.to_rfc3339(),
+        );
+        if let Some(uid) = self.fuid.as_ref().or(self.uid.as_ref()) {
+            ctx.insert("uid", uid.clone());
+        }
+        if let Some(ref offset) = self.offset {
+            ctx.insert("trace_offset", offset.offset.to_string());
+            if let Some(ref source) = offset.source {
+                ctx.insert("trace_source", source.clone());
+            }
+        }
+        let mut tr = pvm.transaction(&CTX, ctx);
+        let res = match &self.fuid {
+            Some(fuid) => self.file(fuid, &mut tr),
+            None => self.conn(&mut tr),
+        };
+        match res {
+            Ok(_) => tr.commit(),
+            Err(e) => {
+                tr.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Mapped for ZeekEvent {
+    fn init(pvm: &mut PVM) {
+        pvm.register_data_type(&CONNECTION);
+        pvm.register_data_type(&NET_FILE);
+        pvm.register_ctx_type(&CTX);
+    }
+
+    fn process(&self, pvm: &mut PVM) -> PVMResult<()> {
+        self.parse(pvm)
+    }
+
+    fn set_offset(&mut self, offset: TraceOffset) {
+        self.offset = Some(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data::node_types::Node,
+        trace::test_util::{data, ops, run},
+        view::DBTr,
+    };
+
+    const CONN: &str = r#"{"ts": 1533046200.12, "uid": "C1", "id.orig_h": "10.0.0.1",
+        "id.orig_p": 51234, "id.resp_h": "10.0.0.2", "id.resp_p": 80, "proto": "tcp",
+        "service": "http", "duration": 0.25, "orig_bytes": 73, "resp_bytes": 1024,
+        "conn_state": "SF"}"#;
+
+    #[test]
+    fn conn_names_both_endpoints() {
+        let trs = run::<ZeekEvent>(&[CONN]);
+        assert_eq!(data(&trs, "network_connection"), 1);
+        let names: Vec<String> = trs
+            .iter()
+            .filter_map(|tr| match tr {
+                DBTr::CreateNode(Node::Name(n)) => Some(format!("{:?}", n)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().any(|n| n.contains("10.0.0.1")));
+        assert!(names.iter().any(|n| n.contains("10.0.0.2")));
+    }
+
+    #[test]
+    fn file_carried_by_conn() {
+        let trs = run::<ZeekEvent>(&[
+            r#"{"ts": "2018-07-31T14:10:00.2Z", "fuid": "F1", "conn_uids": ["C1"],
+                "source": "HTTP", "mime_type": "text/html", "total_bytes": 1024}"#,
+            CONN,
+        ]);
+        assert_eq!(data(&trs, "network_connection"), 1);
+        assert_eq!(data(&trs, "network_file"), 2);
+        let ops = ops(&trs);
+        assert!(ops.contains(&"Sink".to_string()));
+        assert!(ops.contains(&"Version".to_string()));
+    }
+}
@@ -0,0 +1,459 @@
+//! A view that streams graph deltas to websocket clients
+//!
+//! Every update is sent to each connected client as a JSON text message in the same format as
+//! the `StdoutJSONView`. Each client has its own bounded queue, a client that falls behind
+//! misses the messages that would overflow its queue rather than holding up ingestion. Newly
+//! connected clients can optionally be sent the most recent node events first, so a dashboard
+//! starts from a populated graph.
+//!
+//! A client can narrow what it is sent with the query string of the URL it connects to.
+//! `types` takes a comma separated list of concrete or PVM data types, and restricts the data
+//! nodes sent to those types, while `ops` does the same for relations by their PVM operation
+//! (or `Named`). For example `ws://host:9001/?types=process,file&ops=Source,Sink`. Other nodes,
+//! such as contexts and names, are always sent.
+//!
+//! Clients are only written to, messages from them (including pings) are not read.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    data::{node_types::Node, rel_types::Rel},
+    view::*,
+};
+
+use serde_json;
+use tungstenite::{
+    handshake::server::{Callback, ErrorResponse, Request, Response},
+    server::accept_hdr,
+    Message,
+};
+
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct WebSocketView {
+    id: usize,
+}
+
+impl View for WebSocketView {
+    fn new(id: usize) -> WebSocketView {
+        WebSocketView { id }
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn name(&self) -> &'static str {
+        "WebSocketView"
+    }
+    fn desc(&self) -> &'static str {
+        "View for streaming JSON graph deltas to websocket clients."
+    }
+    fn params(&self) -> Vec<ParamDecl> {
+        vec![
+            ParamDecl::str(
+                "bind_addr",
+                "The address to listen for websocket clients on.",
+            )
+            .default("127.0.0.1:9001"),
+            ParamDecl::int(
+                "max_client_buffer",
+                "The number of messages queued for a client before it starts missing them, at \
+                 least 1.",
+            )
+            .default("1024"),
+            ParamDecl::int(
+                "replay",
+                "The number of recent node events to send to a newly connected client.",
+            )
+            .default("0"),
+        ]
+    }
+    fn create(
+        &self,
+        id: usize,
+        params: ViewParams,
+        stream: ViewStream,
+    ) -> Result<ViewInst, ViewError> {
+        let addr = params.get_str("bind_addr").unwrap().to_string();
+        let max_buffer = non_negative(&params, "max_client_buffer")?;
+        if max_buffer == 0 {
+            return Err(ViewError::InvalidParam(
+                "max_client_buffer".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        let replay = non_negative(&params, "replay")?;
+        let listener = TcpListener::bind(&addr)
+            .map_err(|e| ViewError::InvalidParam("bind_addr".to_string(), e.to_string()))?;
+        listener
+            .set_nonblocking(true)
+            .expect("Failed to configure websocket listener");
+        let hub = Arc::new(Mutex::new(Hub::new(max_buffer, replay)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let hub = hub.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("WebSocketView-accept".to_string())
+                .spawn(move || listen(listener, &hub, &stop))
+                .unwrap()
+        };
+        let thr = thread::Builder::new()
+            .name("WebSocketView".to_string())
+            .spawn(move || {
+                stream.run(&mut Publisher(hub.clone()));
+                // clients are let go however the stream ends
+                stop.store(true, Ordering::SeqCst);
+                acceptor.join().unwrap();
+                hub.lock().unwrap().close();
+            })?;
+        Ok(ViewInst {
+            id,
+            vtype: self.id,
+            params,
+            handle: thr,
+        })
+    }
+}
+
+fn non_negative(params: &ViewParams, key: &str) -> Result<usize, ViewError> {
+    match params.get_int(key).unwrap() {
+        n if n >= 0 => Ok(n as usize),
+        n => Err(ViewError::InvalidParam(
+            key.to_string(),
+            format!("must not be negative, not {}", n),
+        )),
+    }
+}
+
+/// Passes every update on to the clients as it arrives, so a flush has nothing to write out
+#[derive(Debug)]
+struct Publisher(Arc<Mutex<Hub>>);
+
+impl InlineView for Publisher {
+    fn process(&mut self, evt: &DBTr) {
+        if let DBTr::Tick(_) = evt {
+            return;
+        }
+        match serde_json::to_string(evt) {
+            Ok(msg) => self.0.lock().unwrap().publish(evt, msg),
+            Err(e) => eprintln!("WebSocketView serialisation error: {}", e),
+        }
+    }
+}
+
+/// Accept connections until told to stop, giving each client a writer thread
+fn listen(listener: TcpListener, hub: &Arc<Mutex<Hub>>, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((sock, peer)) => {
+                let hub = hub.clone();
+                thread::Builder::new()
+                    .name(format!("WebSocketView-{}", peer))
+                    .spawn(move || serve(sock, peer, &hub))
+                    .unwrap();
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL);
+            }
+            Err(e) => eprintln!("WebSocketView accept error: {}", e),
+        }
+    }
+}
+
+/// Complete the handshake with a client then forward its queue until either side hangs up
+fn serve(sock: TcpStream, peer: SocketAddr, hub: &Mutex<Hub>) {
+    if let Err(e) = sock.set_nonblocking(false) {
+        eprintln!("WebSocketView client {} error: {}", peer, e);
+        return;
+    }
+    let mut filter = Filter::default();
+    let handshake = accept_hdr(sock, ReadFilter(&mut filter));
+    let mut ws = match handshake {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("WebSocketView handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    let queue = hub.lock().unwrap().register(peer.to_string(), filter);
+    for msg in queue {
+        if ws.write_message(Message::Text(msg.to_string())).is_err() {
+            return;
+        }
+    }
+    ws.close(None).ok();
+    ws.write_pending().ok();
+}
+
+/// Handshake callback taking a client's filter from the URL it requested
+struct ReadFilter<'a>(&'a mut Filter);
+
+impl<'a> Callback for ReadFilter<'a> {
+    fn on_request(self, req: &Request, resp: Response) -> Result<Response, ErrorResponse> {
+        *self.0 = Filter::from_query(req.uri().query().unwrap_or(""));
+        Ok(resp)
+    }
+}
+
+/// The events a client has asked to be sent, `None` admitting everything
+#[derive(Debug, Default)]
+struct Filter {
+    types: Option<HashSet<String>>,
+    ops: Option<HashSet<String>>,
+}
+
+impl Filter {
+    fn from_query(query: &str) -> Self {
+        let mut ret = Filter::default();
+        for pair in query.split('&') {
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let val = kv.next().unwrap_or("");
+            let vals = || {
+                val.split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            };
+            match key {
+                "types" => ret.types = Some(vals()),
+                "ops" => ret.ops = Some(vals()),
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    fn admits(&self, evt: &DBTr) -> bool {
+        match evt {
+            DBTr::CreateNode(Node::Data(d)) | DBTr::UpdateNode(Node::Data(d)) => {
+                match &self.types {
+                    Some(types) => {
+                        types.contains(d.ty().name) || types.contains(&d.pvm_ty().to_string())
+                    }
+                    None => true,
+                }
+            }
+            DBTr::CreateNode(_) | DBTr::UpdateNode(_) => true,
+            DBTr::CreateRel(r) | DBTr::UpdateRel(r) => match &self.ops {
+                Some(ops) => match r {
+                    Rel::Inf(i) => ops.contains(&i.pvm_op.to_string()),
+                    Rel::Named(_) => ops.contains("Named"),
+                },
+                None => true,
+            },
+            DBTr::Flush(_) | DBTr::Tick(_) => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    name: String,
+    filter: Filter,
+    queue: SyncSender<Arc<String>>,
+    dropped: usize,
+}
+
+/// The connected clients and recent history shared between the view and its client threads
+#[derive(Debug)]
+struct Hub {
+    clients: Vec<Client>,
+    recent: VecDeque<(Arc<DBTr>, Arc<String>)>,
+    max_buffer: usize,
+    replay: usize,
+}
+
+impl Hub {
+    fn new(max_buffer: usize, replay: usize) -> Self {
+        Hub {
+            clients: Vec::new(),
+            recent: VecDeque::with_capacity(replay),
+            max_buffer,
+            replay,
+        }
+    }
+
+    /// Add a client, its queue starts with the recent node history it admits
+    fn register(&mut self, name: String, filter: Filter) -> Receiver<Arc<String>> {
+        let (send, recv) = sync_channel(self.max_buffer + self.recent.len());
+        for (evt, msg) in &self.recent {
+            if filter.admits(evt) {
+                send.try_send(msg.clone()).unwrap();
+            }
+        }
+        self.clients.push(Client {
+            name,
+            filter,
+            queue: send,
+            dropped: 0,
+        });
+        recv
+    }
+
+    fn publish(&mut self, evt: &DBTr, msg: String) {
+        let msg = Arc::new(msg);
+        if self.replay > 0 {
+            if let DBTr::CreateNode(_) | DBTr::UpdateNode(_) = evt {
+                if self.recent.len() == self.replay {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back((Arc::new(evt.clone()), msg.clone()));
+            }
+        }
+        let mut i = 0;
+        while i < self.clients.len() {
+            if !self.clients[i].filter.admits(evt) {
+                i += 1;
+                continue;
+            }
+            match self.clients[i].queue.try_send(msg.clone()) {
+                Ok(()) => i += 1,
+                Err(TrySendError::Full(_)) => {
+                    self.clients[i].dropped += 1;
+                    i += 1;
+                }
+                Err(TrySendError::Disconnected(_)) => report(&self.clients.swap_remove(i)),
+            }
+        }
+    }
+
+    /// Disconnect every client once their queues drain
+    fn close(&mut self) {
+        for client in self.clients.drain(..) {
+            report(&client);
+        }
+    }
+}
+
+fn report(client: &Client) {
+    if client.dropped > 0 {
+        eprintln!(
+            "WebSocketView client {} missed {} messages",
+            client.name, client.dropped
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{
+        node_types::{ConcreteType, DataNode, PVMDataType, PVMDataType::*, SchemaNode},
+        rel_types::{Inf, InfInit, PVMOps},
+        RelGenerable, ID,
+    };
+
+    use uuid::Uuid;
+
+    fn leak_ty(pvm_ty: PVMDataType, name: &'static str) -> &'static ConcreteType {
+        Box::leak(Box::new(ConcreteType {
+            pvm_ty,
+            name,
+            props: HashMap::new(),
+        }))
+    }
+
+    fn rel(id: u64) -> Arc<DBTr> {
+        rel_op(id, PVMOps::Sink)
+    }
+
+    fn rel_op(id: u64, pvm_op: PVMOps) -> Arc<DBTr> {
+        Arc::new(DBTr::CreateRel(Rel::Inf(Inf::new(
+            ID::new(id),
+            ID::new(1),
+            ID::new(2),
+            InfInit {
+                pvm_op,
+                ctx: ID::new(3),
+                bytes_read: 0,
+                bytes_written: 0,
+                event: None,
+            },
+        ))))
+    }
+
+    #[test]
+    fn zero_client_buffer_rejected() {
+        let mut params = ViewParams::new();
+        params.insert_param("bind_addr", "127.0.0.1:0".to_string());
+        params.insert_param("max_client_buffer", 0i64);
+        params.insert_param("replay", 0i64);
+        let (_send, recv) = std::sync::mpsc::channel();
+        match WebSocketView::new(0).create(0, params, ViewStream::new(recv)) {
+            Err(ViewError::InvalidParam(name, _)) if name == "max_client_buffer" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn slow_client_misses_overflow() {
+        let mut hub = Hub::new(2, 0);
+        let slow = hub.register("slow".into(), Filter::default());
+        for n in 0..5 {
+            hub.publish(&rel(n), n.to_string());
+        }
+        assert_eq!(hub.clients[0].dropped, 3);
+        let got: Vec<_> = slow.try_iter().map(|m| m.to_string()).collect();
+        assert_eq!(got, vec!["0", "1"]);
+        drop(slow);
+        hub.publish(&rel(5), "5".into());
+        assert!(hub.clients.is_empty());
+    }
+
+    #[test]
+    fn new_client_gets_recent_nodes() {
+        let ty = leak_ty(Actor, "process");
+        let node = |id| {
+            Arc::new(DBTr::CreateNode(Node::Schema(SchemaNode::from_data(
+                ID::new(id),
+                ty,
+            ))))
+        };
+        let mut hub = Hub::new(4, 2);
+        hub.publish(&node(1), "n1".into());
+        hub.publish(&rel(2), "r2".into());
+        hub.publish(&node(3), "n3".into());
+        hub.publish(&node(4), "n4".into());
+        let late = hub.register("late".into(), Filter::default());
+        hub.publish(&rel(5), "r5".into());
+        let got: Vec<_> = late.try_iter().map(|m| m.to_string()).collect();
+        assert_eq!(got, vec!["n3", "n4", "r5"]);
+    }
+
+    #[test]
+    fn filtered_client_sees_matching_events() {
+        let process = leak_ty(Actor, "process");
+        let file = leak_ty(Store, "file");
+        let data = |id, ty: &'static ConcreteType| {
+            Arc::new(DBTr::CreateNode(Node::Data(DataNode::new(
+                ty.pvm_ty,
+                ty,
+                ID::new(id),
+                Uuid::nil(),
+                ID::new(0),
+                None,
+            ))))
+        };
+        let mut hub = Hub::new(8, 0);
+        let client = hub.register("client".into(), Filter::from_query("types=file&ops=Sink"));
+        hub.publish(&data(1, process), "process".into());
+        hub.publish(&data(2, file), "file".into());
+        hub.publish(&rel_op(3, PVMOps::Source), "source".into());
+        hub.publish(&rel_op(4, PVMOps::Sink), "sink".into());
+        let got: Vec<_> = client.try_iter().map(|m| m.to_string()).collect();
+        assert_eq!(got, vec!["file", "sink"]);
+    }
+}